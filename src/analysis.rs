@@ -0,0 +1,421 @@
+use std::collections::HashMap;
+
+use lopdf::content::Content;
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use tracing::debug;
+
+use crate::geometry::Rect;
+use crate::overlay;
+
+/// What a page's content draws, gathered in a single pass over its content
+/// stream.
+#[derive(Debug, Default)]
+pub struct PageContent {
+    /// Number of operators which paint something: strokes, fills, text,
+    /// images, and shadings.
+    pub painted: usize,
+    /// Whether any color is set in, or any image is drawn in, DeviceRGB.
+    pub uses_rgb: bool,
+    /// The images drawn on the page.
+    pub images: Vec<ImageUse>,
+    /// Names of the fonts in the page's resources which are not embedded.
+    pub unembedded_fonts: Vec<String>,
+    /// The extent of what's painted, in default user space, with that of
+    /// text estimated from its size and length; None if nothing is.
+    pub bounds: Option<Rect>,
+}
+
+/// An image drawn on a page, and the resolution it ends up at.
+#[derive(Debug)]
+pub struct ImageUse {
+    pub name: String,
+    /// Effective resolution in pixels per inch, the lower of the two axes.
+    pub ppi: f64,
+}
+
+/// Content analysis shared between the preflight checks.
+///
+/// Decoding content streams is by far the most expensive part of preflight,
+/// so each page is decoded at most once, the first time any check asks
+/// about it, and the result is kept for the checks which follow. Pages
+/// whose content can't be decoded are skipped rather than failing the job,
+/// since stamping itself never needs to look inside them.
+pub struct Analysis<'a> {
+    doc: &'a Document,
+    pages: HashMap<ObjectId, Option<PageContent>>,
+}
+
+impl<'a> Analysis<'a> {
+    pub fn new(doc: &'a Document) -> Analysis<'a> {
+        Analysis {
+            doc,
+            pages: HashMap::new(),
+        }
+    }
+
+    /// The analysis of the given page, or None if its content couldn't be
+    /// decoded.
+    pub fn page(&mut self, page_id: ObjectId) -> Option<&PageContent> {
+        let doc = self.doc;
+        self.pages
+            .entry(page_id)
+            .or_insert_with(|| match analyze(doc, page_id) {
+                Ok(content) => Some(content),
+                Err(e) => {
+                    debug!(?page_id, "Unable to analyze page content: {}", e);
+                    None
+                }
+            })
+            .as_ref()
+    }
+}
+
+/// Walk a page's content stream once, tracking the transformation matrix so
+/// that the size at which each image is placed is known.
+///
+/// Form XObjects are counted as painting but are not descended into.
+fn analyze(doc: &Document, page_id: ObjectId) -> lopdf::Result<PageContent> {
+    let content = Content::decode(&doc.get_page_content(page_id)?)?;
+
+    let resources = overlay::inherited(doc, page_id, b"Resources")
+        .and_then(|resources| resources.as_dict().ok());
+    let xobjects = resources
+        .and_then(|resources| resources.get(b"XObject").ok())
+        .and_then(|xobjects| dictionary(doc, xobjects));
+
+    let mut result = PageContent::default();
+
+    if let Some(fonts) = resources
+        .and_then(|resources| resources.get(b"Font").ok())
+        .and_then(|fonts| dictionary(doc, fonts))
+    {
+        for (name, font) in fonts.iter() {
+            let Some(font) = dictionary(doc, font) else {
+                continue;
+            };
+            if !is_embedded(doc, font) {
+                let name = font
+                    .get(b"BaseFont")
+                    .and_then(Object::as_name)
+                    .unwrap_or(name.as_slice());
+                result
+                    .unembedded_fonts
+                    .push(String::from_utf8_lossy(name).into_owned());
+            }
+        }
+    }
+    let mut ctm = IDENTITY;
+    let mut stack = Vec::new();
+
+    // The points of the path under construction, in default user space, and
+    // enough of the text state to estimate where text lands
+    let mut path: Vec<(f64, f64)> = Vec::new();
+    let mut text = Text::default();
+
+    for operation in &content.operations {
+        let operands: Vec<f64> = operation.operands.iter().filter_map(number).collect();
+
+        match operation.operator.as_str() {
+            "q" => stack.push(ctm),
+            "Q" => ctm = stack.pop().unwrap_or(ctm),
+            "cm" => {
+                if let [a, b, c, d, e, f] = operands[..] {
+                    ctm = concat(&[a, b, c, d, e, f], &ctm);
+                }
+            }
+            "rg" | "RG" => result.uses_rgb = true,
+            "cs" | "CS"
+                if matches!(
+                    operation.operands.first(),
+                    Some(Object::Name(name)) if name == b"DeviceRGB"
+                ) =>
+            {
+                result.uses_rgb = true
+            }
+            "m" | "l" | "c" | "v" | "y" => {
+                path.extend(
+                    operands
+                        .chunks_exact(2)
+                        .map(|point| transform(&ctm, (point[0], point[1]))),
+                );
+            }
+            "re" => {
+                if let [x, y, width, height] = operands[..] {
+                    path.extend(corners(&ctm, x, y, x + width, y + height));
+                }
+            }
+            "S" | "s" | "f" | "F" | "f*" | "B" | "B*" | "b" | "b*" => {
+                result.painted += 1;
+                extend(&mut result.bounds, path.drain(..));
+            }
+            "n" => path.clear(),
+            "BT" => {
+                text.matrix = IDENTITY;
+                text.line = IDENTITY;
+            }
+            "Tf" => {
+                if let [size] = operands[..] {
+                    text.size = size;
+                }
+            }
+            "TL" => {
+                if let [leading] = operands[..] {
+                    text.leading = leading;
+                }
+            }
+            "Tm" => {
+                if let [a, b, c, d, e, f] = operands[..] {
+                    text.matrix = [a, b, c, d, e, f];
+                    text.line = text.matrix;
+                }
+            }
+            "Td" | "TD" => {
+                if let [x, y] = operands[..] {
+                    if operation.operator == "TD" {
+                        text.leading = -y;
+                    }
+                    text.next_line(x, y);
+                }
+            }
+            "T*" => text.next_line(0.0, -text.leading),
+            "Tj" | "TJ" | "'" | "\"" => {
+                result.painted += 1;
+                if operation.operator != "Tj" && operation.operator != "TJ" {
+                    text.next_line(0.0, -text.leading);
+                }
+
+                let strings = match operation.operands.last() {
+                    Some(Object::Array(items)) => items.as_slice(),
+                    Some(shown) => std::slice::from_ref(shown),
+                    None => &[],
+                };
+                let advance = text.advance(strings);
+
+                let placed = concat(&text.matrix, &ctm);
+                extend(
+                    &mut result.bounds,
+                    corners(
+                        &placed,
+                        0.0,
+                        -DESCENT * text.size,
+                        advance,
+                        ASCENT * text.size,
+                    ),
+                );
+                text.matrix = concat(&[1.0, 0.0, 0.0, 1.0, advance, 0.0], &text.matrix);
+            }
+            "sh" => result.painted += 1,
+            "BI" => {
+                result.painted += 1;
+                extend(&mut result.bounds, corners(&ctm, 0.0, 0.0, 1.0, 1.0));
+            }
+            "Do" => {
+                result.painted += 1;
+
+                let Some(Object::Name(name)) = operation.operands.first() else {
+                    continue;
+                };
+                let Some(xobject) = xobjects
+                    .and_then(|xobjects| xobjects.get(name).ok())
+                    .and_then(|object| object.as_reference().ok())
+                    .and_then(|id| doc.get_object(id).ok())
+                    .and_then(|object| object.as_stream().ok())
+                    .map(|stream| &stream.dict)
+                else {
+                    continue;
+                };
+                let is_image = xobject
+                    .get(b"Subtype")
+                    .and_then(Object::as_name)
+                    .is_ok_and(|subtype| subtype == b"Image");
+
+                // A form paints within its bounding box, in its own space
+                if !is_image {
+                    if let Some([x1, y1, x2, y2]) = numbers(xobject, b"BBox") {
+                        let matrix = numbers(xobject, b"Matrix").unwrap_or(IDENTITY);
+                        extend(
+                            &mut result.bounds,
+                            corners(&concat(&matrix, &ctm), x1, y1, x2, y2),
+                        );
+                    }
+                    continue;
+                }
+                let image = xobject;
+                extend(&mut result.bounds, corners(&ctm, 0.0, 0.0, 1.0, 1.0));
+
+                if image
+                    .get(b"ColorSpace")
+                    .and_then(Object::as_name)
+                    .is_ok_and(|space| space == b"DeviceRGB")
+                {
+                    result.uses_rgb = true;
+                }
+
+                let pixels = |key: &[u8]| image.get(key).ok().and_then(number).unwrap_or(0.0);
+
+                // The image occupies the unit square, so its placed size is
+                // the length of each axis of the transformation
+                let placed_width = ctm[0].hypot(ctm[1]) / 72.0;
+                let placed_height = ctm[2].hypot(ctm[3]) / 72.0;
+                if placed_width > 0.0 && placed_height > 0.0 {
+                    result.images.push(ImageUse {
+                        name: String::from_utf8_lossy(name).into_owned(),
+                        ppi: (pixels(b"Width") / placed_width)
+                            .min(pixels(b"Height") / placed_height),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(result)
+}
+
+const IDENTITY: [f64; 6] = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+
+/// How far above and below the baseline text is taken to reach, and how wide
+/// each character, as fractions of the font size: near enough for the
+/// extent of a block of text without measuring every glyph.
+const ASCENT: f64 = 0.8;
+const DESCENT: f64 = 0.2;
+const CHARACTER_WIDTH: f64 = 0.5;
+
+/// The parts of the text state which move where text is shown.
+struct Text {
+    matrix: [f64; 6],
+    line: [f64; 6],
+    size: f64,
+    leading: f64,
+}
+
+impl Default for Text {
+    fn default() -> Text {
+        Text {
+            matrix: IDENTITY,
+            line: IDENTITY,
+            size: 0.0,
+            leading: 0.0,
+        }
+    }
+}
+
+impl Text {
+    /// Move to the start of the next line, offset from the start of this one.
+    fn next_line(&mut self, x: f64, y: f64) {
+        self.line = concat(&[1.0, 0.0, 0.0, 1.0, x, y], &self.line);
+        self.matrix = self.line;
+    }
+
+    /// Estimated distance, in text space, that showing these strings, and
+    /// the adjustments between them, moves along the line.
+    fn advance(&self, shown: &[Object]) -> f64 {
+        shown
+            .iter()
+            .map(|item| match item {
+                Object::String(bytes, _) => bytes.len() as f64 * CHARACTER_WIDTH * self.size,
+                item => -number(item).unwrap_or(0.0) / 1000.0 * self.size,
+            })
+            .sum()
+    }
+}
+
+/// The product of two transformation matrices: m, then n.
+fn concat(m: &[f64; 6], n: &[f64; 6]) -> [f64; 6] {
+    let [a, b, c, d, e, f] = *m;
+    [
+        a * n[0] + b * n[2],
+        a * n[1] + b * n[3],
+        c * n[0] + d * n[2],
+        c * n[1] + d * n[3],
+        e * n[0] + f * n[2] + n[4],
+        e * n[1] + f * n[3] + n[5],
+    ]
+}
+
+fn transform(m: &[f64; 6], (x, y): (f64, f64)) -> (f64, f64) {
+    (m[0] * x + m[2] * y + m[4], m[1] * x + m[3] * y + m[5])
+}
+
+/// The corners of a rectangle, transformed.
+fn corners(m: &[f64; 6], x1: f64, y1: f64, x2: f64, y2: f64) -> [(f64, f64); 4] {
+    [(x1, y1), (x2, y1), (x1, y2), (x2, y2)].map(|point| transform(m, point))
+}
+
+/// Grow the bounds to take in the given points.
+fn extend(bounds: &mut Option<Rect>, points: impl IntoIterator<Item = (f64, f64)>) {
+    for (x, y) in points {
+        let grown = match *bounds {
+            Some(rect) => Rect {
+                x1: rect.x1.min(x),
+                y1: rect.y1.min(y),
+                x2: rect.x2.max(x),
+                y2: rect.y2.max(y),
+            },
+            None => Rect {
+                x1: x,
+                y1: y,
+                x2: x,
+                y2: y,
+            },
+        };
+        *bounds = Some(grown);
+    }
+}
+
+/// An array of numbers in a dictionary, such as a bounding box or matrix.
+fn numbers<const N: usize>(dict: &Dictionary, key: &[u8]) -> Option<[f64; N]> {
+    let values: Vec<f64> = dict
+        .get(key)
+        .and_then(Object::as_array)
+        .ok()?
+        .iter()
+        .filter_map(number)
+        .collect();
+
+    values.try_into().ok()
+}
+
+fn number(object: &Object) -> Option<f64> {
+    match object {
+        Object::Integer(i) => Some(*i as f64),
+        Object::Real(r) => Some(*r as f64),
+        _ => None,
+    }
+}
+
+/// Resolve an object which may be a reference to a dictionary.
+fn dictionary<'a>(doc: &'a Document, object: &'a Object) -> Option<&'a Dictionary> {
+    match object {
+        Object::Reference(id) => doc.get_dictionary(*id).ok(),
+        other => other.as_dict().ok(),
+    }
+}
+
+/// Whether a font carries its own glyphs, either in an embedded font file or,
+/// for a Type 3 font, as procedures.
+fn is_embedded(doc: &Document, font: &Dictionary) -> bool {
+    let font = match font.get(b"Subtype").and_then(Object::as_name) {
+        Ok(b"Type3") => return true,
+        Ok(b"Type0") => match font
+            .get(b"DescendantFonts")
+            .and_then(Object::as_array)
+            .ok()
+            .and_then(|fonts| fonts.first())
+            .and_then(|font| dictionary(doc, font))
+        {
+            Some(descendant) => descendant,
+            None => return false,
+        },
+        _ => font,
+    };
+
+    font.get(b"FontDescriptor")
+        .ok()
+        .and_then(|descriptor| dictionary(doc, descriptor))
+        .is_some_and(|descriptor| {
+            [b"FontFile".as_slice(), b"FontFile2", b"FontFile3"]
+                .iter()
+                .any(|key| descriptor.has(key))
+        })
+}
@@ -0,0 +1,452 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::exit::Failure;
+use crate::i18n::Message;
+use crate::overlay::Options;
+use crate::template;
+
+/// What became of one manuscript in a batch.
+pub enum Outcome {
+    /// Stamped, with this many pages in the output.
+    Stamped(usize),
+    /// Not stamped, as the manifest shows it already was, into this output.
+    Skipped(PathBuf),
+    /// Not stamped, for this kind of reason and this one in particular.
+    Failed(Failure, String),
+}
+
+/// Expand the manuscripts named on the command line into the files to be
+/// stamped, in the order given.
+///
+/// A shell will usually have expanded any glob already, but one that was
+/// quoted, or given where the shell doesn't, is expanded here: `*` matches
+/// any run of characters within a path component and `?` any one character.
+/// Names without wildcards are passed through as they are, whether or not
+/// they exist, so that a missing one is reported against its own name.
+pub fn expand<'a>(patterns: impl Iterator<Item = &'a PathBuf>) -> Result<Vec<PathBuf>, String> {
+    let mut inputs = Vec::new();
+
+    for pattern in patterns {
+        if !is_glob(pattern) {
+            inputs.push(pattern.clone());
+            continue;
+        }
+
+        let matched = glob(pattern);
+        if matched.is_empty() {
+            return Err(Message::NoMatches { pattern }.to_string());
+        }
+        inputs.extend(matched);
+    }
+
+    Ok(inputs)
+}
+
+fn is_glob(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?'])
+}
+
+/// The files matching a pattern, sorted by name within each directory.
+fn glob(pattern: &Path) -> Vec<PathBuf> {
+    let mut candidates = vec![PathBuf::new()];
+
+    for component in pattern.components() {
+        let Component::Normal(name) = component else {
+            for candidate in &mut candidates {
+                candidate.push(component);
+            }
+            continue;
+        };
+
+        let name = name.to_string_lossy();
+        if !name.contains(['*', '?']) {
+            for candidate in &mut candidates {
+                candidate.push(&*name);
+            }
+            continue;
+        }
+
+        let mut expanded = Vec::new();
+        for candidate in &candidates {
+            let directory = if candidate.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                candidate.as_path()
+            };
+            let Ok(entries) = fs::read_dir(directory) else {
+                continue;
+            };
+
+            let mut names: Vec<String> = entries
+                .filter_map(Result::ok)
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                // Hidden files only match a pattern which asks for them
+                .filter(|entry| !entry.starts_with('.') || name.starts_with('.'))
+                .filter(|entry| wildcard(&name, entry))
+                .collect();
+            names.sort();
+
+            expanded.extend(names.into_iter().map(|entry| candidate.join(entry)));
+        }
+        candidates = expanded;
+    }
+
+    candidates.retain(|candidate| candidate.is_file());
+    candidates
+}
+
+/// Whether a name matches a pattern of `*` and `?` wildcards.
+fn wildcard(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    // Where the last `*` was, and how much of the name it had taken
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some('?') => {
+                p += 1;
+                n += 1;
+            }
+            Some(&c) if c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // Backtrack, letting the last `*` take one more character
+                Some((sp, sn)) => {
+                    star = Some((sp, sn + 1));
+                    p = sp + 1;
+                    n = sn + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Pair each manuscript with the output to write it to.
+///
+/// A single manuscript is written to the output as named, unless that's a
+/// directory or a template. Several are written into the output, which must
+/// then be a directory, each under its own filename, or a template, each
+/// under the name it gives; two manuscripts given the same name, or one
+/// which would be overwritten, are refused.
+pub fn outputs(
+    inputs: &[PathBuf],
+    output: &Path,
+    options: &Options,
+) -> Result<Vec<(PathBuf, PathBuf)>, String> {
+    if template::is_template(output) {
+        let mut seen = HashSet::new();
+        let mut pairs = Vec::new();
+
+        for input in inputs {
+            let target = template::render(output, input, options)?;
+            if !seen.insert(target.clone()) {
+                return Err(Message::DuplicateName {
+                    name: &target.to_string_lossy(),
+                    output,
+                }
+                .to_string());
+            }
+            if same_file(input, &target) {
+                return Err(Message::WouldOverwrite { input, output }.to_string());
+            }
+
+            pairs.push((input.clone(), target));
+        }
+
+        return Ok(pairs);
+    }
+
+    if !output.is_dir() {
+        if inputs.len() > 1 {
+            return Err(Message::OutputNotDirectory { output }.to_string());
+        }
+        return Ok(vec![(inputs[0].clone(), output.to_path_buf())]);
+    }
+
+    let mut seen = HashSet::new();
+    let mut pairs = Vec::new();
+
+    for input in inputs {
+        let Some(name) = input.file_name() else {
+            return Err(Message::NotAFile { path: input }.to_string());
+        };
+        if !seen.insert(name) {
+            let name = name.to_string_lossy();
+            return Err(Message::DuplicateName {
+                name: &name,
+                output,
+            }
+            .to_string());
+        }
+
+        let target = output.join(name);
+        if same_file(input, &target) {
+            return Err(Message::WouldOverwrite { input, output }.to_string());
+        }
+
+        pairs.push((input.clone(), target));
+    }
+
+    Ok(pairs)
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// The manuscripts a batch has stamped so far, kept in the file given with
+/// `--resume` so that a run which was interrupted, or which failed partway,
+/// picks up where it left off when run again.
+///
+/// Each manuscript is recorded as soon as it's stamped, one JSON line to
+/// each, and flushed to disk, so that at most the one being stamped when the
+/// run stopped is stamped again. The file is removed once the whole batch
+/// has gone through.
+pub struct State {
+    path: PathBuf,
+    done: HashMap<(PathBuf, PathBuf), usize>,
+}
+
+/// One line of the state file.
+#[derive(Serialize, Deserialize)]
+struct Done {
+    input: PathBuf,
+    output: PathBuf,
+    pages: usize,
+}
+
+impl State {
+    /// Read what an earlier run recorded in the given file, if there is one.
+    pub fn open(path: &Path) -> Result<State, String> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("Unable to read {}: {}", path.display(), e)),
+        };
+
+        // A line cut short as the run stopped is passed over, leaving its
+        // manuscript to be stamped again
+        let done = text
+            .lines()
+            .filter_map(|line| serde_json::from_str::<Done>(line).ok())
+            .map(|done| ((done.input, done.output), done.pages))
+            .collect();
+
+        Ok(State {
+            path: path.to_path_buf(),
+            done,
+        })
+    }
+
+    /// The pages in the output, if the manuscript was already stamped into
+    /// it and the output is still there.
+    pub fn done(&self, input: &Path, output: &Path) -> Option<usize> {
+        self.done
+            .get(&(input.to_path_buf(), output.to_path_buf()))
+            .copied()
+            .filter(|_| output.is_file())
+    }
+
+    /// Record that the manuscript has been stamped into the output.
+    pub fn record(&mut self, input: &Path, output: &Path, pages: usize) -> io::Result<()> {
+        let done = Done {
+            input: input.to_path_buf(),
+            output: output.to_path_buf(),
+            pages,
+        };
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(&done)?)?;
+        file.sync_all()?;
+
+        self.done.insert((done.input, done.output), done.pages);
+        Ok(())
+    }
+
+    /// Remove the file once the batch has gone through, so that the next
+    /// run starts afresh.
+    pub fn finish(self) -> io::Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A closing summary of a batch: how many manuscripts were stamped, skipped,
+/// and failed, and why each failure failed.
+pub fn summary(results: &[(PathBuf, Outcome)]) -> String {
+    let count = |f: fn(&Outcome) -> bool| results.iter().filter(|(_, outcome)| f(outcome)).count();
+
+    let stamped = count(|outcome| matches!(outcome, Outcome::Stamped(_)));
+    let skipped = count(|outcome| matches!(outcome, Outcome::Skipped(_)));
+    let failed = count(|outcome| matches!(outcome, Outcome::Failed(..)));
+
+    let mut summary = Message::BatchSummary {
+        stamped,
+        total: results.len(),
+        skipped,
+        failed,
+    }
+    .to_string();
+
+    for (input, outcome) in results {
+        if let Outcome::Failed(_, reason) = outcome {
+            summary.push_str(&format!("\n  {}: {}", input.display(), reason));
+        }
+    }
+
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scratch directory holding empty files with the given names.
+    fn scratch(case: &str, names: &[&str]) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("cropped-batch-{}.{}", case, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        for name in names {
+            let path = directory.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        directory
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(wildcard("*.pdf", "chapter-1.pdf"));
+        assert!(wildcard("*.pdf", ".pdf"));
+        assert!(wildcard("chapter-?.pdf", "chapter-1.pdf"));
+        assert!(!wildcard("chapter-?.pdf", "chapter-10.pdf"));
+        assert!(wildcard("c*-*0.pdf", "chapter-10.pdf"));
+        assert!(wildcard("*", ""));
+        assert!(!wildcard("*.pdf", "chapter-1.pdf.bak"));
+        assert!(!wildcard("?", ""));
+    }
+
+    #[test]
+    fn expand_globs_in_order() {
+        let directory = scratch(
+            "expand",
+            &["b.pdf", "a.pdf", "c.txt", ".hidden.pdf", "d/e.pdf"],
+        );
+
+        let patterns = [
+            directory.join("*.pdf"),
+            directory.join("?/*.pdf"),
+            directory.join("missing.pdf"),
+        ];
+        assert_eq!(
+            expand(patterns.iter()).unwrap(),
+            vec![
+                directory.join("a.pdf"),
+                directory.join("b.pdf"),
+                directory.join("d/e.pdf"),
+                // Passed through to be reported missing under its own name
+                directory.join("missing.pdf"),
+            ]
+        );
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn expand_no_matches() {
+        let directory = scratch("no-matches", &["a.txt"]);
+        assert!(expand([directory.join("*.pdf")].iter()).is_err());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn outputs_into_a_directory() {
+        let directory = scratch("outputs", &["in/a.pdf", "in/b.pdf", "other/a.pdf", "out/x"]);
+        let (a, b) = (directory.join("in/a.pdf"), directory.join("in/b.pdf"));
+        let out = directory.join("out");
+        let options = Options::default();
+
+        assert_eq!(
+            outputs(&[a.clone(), b.clone()], &out, &options).unwrap(),
+            vec![
+                (a.clone(), out.join("a.pdf")),
+                (b.clone(), out.join("b.pdf"))
+            ]
+        );
+
+        // The same manuscript twice, or two with the same name, would be
+        // written to the same output
+        assert!(outputs(&[a.clone(), a.clone()], &out, &options).is_err());
+        assert!(outputs(&[a.clone(), directory.join("other/a.pdf")], &out, &options).is_err());
+
+        // Nor may a manuscript be written over itself
+        assert!(outputs(&[a.clone(), b.clone()], &directory.join("in"), &options).is_err());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn outputs_to_a_file() {
+        let options = Options::default();
+        let (a, b) = (PathBuf::from("a.pdf"), PathBuf::from("b.pdf"));
+        let out = Path::new("stamped.pdf");
+
+        assert_eq!(
+            outputs(std::slice::from_ref(&a), out, &options).unwrap(),
+            vec![(a.clone(), out.to_path_buf())]
+        );
+        assert!(outputs(&[a, b], out, &options).is_err());
+    }
+
+    #[test]
+    fn state_resumes_what_was_recorded() {
+        let directory = scratch("state", &["one.pdf", "out/one.pdf"]);
+        let path = directory.join("state.jsonl");
+        let (input, output) = (directory.join("one.pdf"), directory.join("out/one.pdf"));
+        let missing = directory.join("out/two.pdf");
+
+        let mut state = State::open(&path).unwrap();
+        assert_eq!(state.done(&input, &output), None);
+        state.record(&input, &output, 12).unwrap();
+        state.record(&input, &missing, 3).unwrap();
+
+        // A line cut short by the interruption is passed over
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        write!(file, "{{\"input\":").unwrap();
+
+        let state = State::open(&path).unwrap();
+        assert_eq!(state.done(&input, &output), Some(12));
+        assert_eq!(state.done(&input, &missing), None, "output since removed");
+        assert_eq!(state.done(&output, &input), None);
+
+        state.finish().unwrap();
+        assert!(!path.exists());
+        assert!(State::open(&path).unwrap().done(&input, &output).is_none());
+    }
+}
@@ -0,0 +1,127 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use serde::Deserialize;
+use tracing::{debug, info};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Where in the archive each kind of file is put, as a vendor's intake
+/// expects, read from a JSON file given with `--vendor`, such as
+///
+/// ```json
+/// { "pdf": "interior", "checksums": "checksums", "report": "reports" }
+/// ```
+///
+/// Each is a folder within the archive; a kind left out goes at the top,
+/// as every file does without a layout.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Layout {
+    /// Folder for the stamped PDFs.
+    pub pdf: Option<String>,
+    /// Folder for the checksum sidecars and manifests.
+    pub checksums: Option<String>,
+    /// Folder for the JSON report of the run.
+    pub report: Option<String>,
+}
+
+impl Layout {
+    /// Read a layout from the given JSON file, checking that each folder
+    /// stays within the archive.
+    pub fn load(path: &Path) -> Result<Layout, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Unable to read the layout in {}: {}", path.display(), e))?;
+        let layout: Layout = serde_json::from_str(&text)
+            .map_err(|e| format!("Invalid layout in {}: {}", path.display(), e))?;
+
+        for folder in [&layout.pdf, &layout.checksums, &layout.report]
+            .into_iter()
+            .flatten()
+        {
+            let inside = Path::new(folder)
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)));
+            if !inside {
+                return Err(format!(
+                    "Invalid layout in {}: '{}' is not a folder within the archive",
+                    path.display(),
+                    folder
+                ));
+            }
+        }
+
+        Ok(layout)
+    }
+
+    /// The name in the archive of one of the files bundled.
+    fn name(&self, path: &Path, report: Option<&Path>) -> io::Result<String> {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| io::Error::other(format!("Not a file: {}", path.display())))?;
+
+        let folder = if report == Some(path) {
+            &self.report
+        } else if is_pdf(path) {
+            &self.pdf
+        } else {
+            &self.checksums
+        };
+
+        Ok(match folder {
+            Some(folder) => format!("{}/{}", folder.trim_end_matches('/'), name),
+            None => name,
+        })
+    }
+}
+
+/// Package the products of a run into a single zip archive for delivery.
+///
+/// Each file is stored under its own filename, in the folder the layout
+/// gives for its kind, the report being the one named. PDFs are stored
+/// rather than deflated since their streams are already compressed and
+/// printers' intake tools sometimes inspect them in-place; everything else
+/// (checksums, manifests, the report) is deflated.
+pub fn write(
+    bundle_path: &Path,
+    files: &[PathBuf],
+    report: Option<&Path>,
+    layout: &Layout,
+) -> io::Result<()> {
+    let file = File::create(bundle_path)?;
+    let mut archive = ZipWriter::new(file);
+
+    for path in files {
+        let name = layout.name(path, report)?;
+
+        let method = if is_pdf(path) {
+            CompressionMethod::Stored
+        } else {
+            CompressionMethod::Deflated
+        };
+        let options = SimpleFileOptions::default().compression_method(method);
+
+        debug!(?name, ?method);
+
+        archive
+            .start_file(name, options)
+            .map_err(io::Error::other)?;
+
+        let mut source = File::open(path)?;
+        io::copy(&mut source, &mut archive)?;
+    }
+
+    archive.finish().map_err(io::Error::other)?;
+
+    info!("Bundle written to {}", bundle_path.display());
+
+    Ok(())
+}
+
+fn is_pdf(path: &Path) -> bool {
+    path.extension()
+        .map(|extension| extension.eq_ignore_ascii_case("pdf"))
+        .unwrap_or(false)
+}
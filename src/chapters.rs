@@ -0,0 +1,255 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use serde::{Deserialize, Serialize};
+
+/// Where the titles of the chapters printed in the slug come from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Chapters {
+    /// The manuscript's outline, each page being in the chapter of the
+    /// nearest bookmark at or before it.
+    Outline,
+    /// The title of each chapter, by the page it starts on, counting from 1.
+    Map(BTreeMap<usize, String>),
+}
+
+/// Most bookmarks an outline is walked for, in case of a malformed one
+/// which loops.
+const MAX_BOOKMARKS: usize = 10_000;
+
+/// Read a map of chapter titles from a file with one chapter per line, its
+/// first page and then its title:
+///
+/// ```text
+/// 1   Front matter
+/// 9   1. The Harbour
+/// 31  2. Low Water
+/// ```
+///
+/// Blank lines, and lines starting with `#`, are ignored.
+pub fn load(path: &Path) -> Result<Chapters, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut titles = BTreeMap::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (page, title) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let page = match page.parse::<usize>() {
+            Ok(page) if page > 0 && !title.trim().is_empty() => page,
+            _ => {
+                return Err(format!(
+                    "{}, line {}: expected a page number, counting from 1, and a title",
+                    path.display(),
+                    index + 1
+                ));
+            }
+        };
+        titles.insert(page, title.trim().to_string());
+    }
+
+    Ok(Chapters::Map(titles))
+}
+
+/// The title of each chapter of the manuscript by the page it starts on,
+/// from wherever the chapters come from.
+pub fn titles(
+    doc: &Document,
+    page_ids: &[ObjectId],
+    chapters: &Chapters,
+) -> BTreeMap<usize, String> {
+    match chapters {
+        Chapters::Outline => outline(doc, page_ids),
+        Chapters::Map(titles) => titles.clone(),
+    }
+}
+
+/// The title of the chapter a page is in: that of the nearest chapter
+/// starting at or before it.
+pub fn title(titles: &BTreeMap<usize, String>, page_num: usize) -> Option<&str> {
+    titles
+        .range(..=page_num)
+        .next_back()
+        .map(|(_, title)| title.as_str())
+}
+
+/// The bookmarks of the manuscript's outline, at every level, by the page
+/// each points to. Where several point to the same page, the last one in
+/// the outline, usually the most specific, is kept.
+fn outline(doc: &Document, page_ids: &[ObjectId]) -> BTreeMap<usize, String> {
+    let pages: HashMap<ObjectId, usize> = page_ids
+        .iter()
+        .enumerate()
+        .map(|(index, id)| (*id, index + 1))
+        .collect();
+    let mut titles = BTreeMap::new();
+
+    let Some(outlines) = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| catalog.get(b"Outlines").ok())
+        .and_then(|outlines| dictionary(doc, outlines))
+    else {
+        return titles;
+    };
+
+    // Depth first, so that the outline is visited in reading order
+    let mut pending: Vec<ObjectId> = first(outlines).into_iter().collect();
+    let mut seen = HashSet::new();
+    while let Some(id) = pending.pop() {
+        if seen.len() > MAX_BOOKMARKS {
+            break;
+        }
+        if !seen.insert(id) {
+            continue;
+        }
+        let Ok(item) = doc.get_dictionary(id) else {
+            continue;
+        };
+
+        if let Some(next) = item
+            .get(b"Next")
+            .ok()
+            .and_then(|next| next.as_reference().ok())
+        {
+            pending.push(next);
+        }
+        if let Some(child) = first(item) {
+            pending.push(child);
+        }
+
+        let page = destination(doc, item).and_then(|page_id| pages.get(&page_id));
+        let title = item.get(b"Title").ok().and_then(text);
+        if let (Some(&page), Some(title)) = (page, title) {
+            titles.insert(page, title);
+        }
+    }
+
+    titles
+}
+
+fn first(item: &Dictionary) -> Option<ObjectId> {
+    item.get(b"First").ok()?.as_reference().ok()
+}
+
+/// The page a bookmark goes to, whether given directly, as a GoTo action, or
+/// by the name of a destination.
+fn destination(doc: &Document, item: &Dictionary) -> Option<ObjectId> {
+    let target = match item.get(b"Dest") {
+        Ok(dest) => dest,
+        Err(_) => {
+            let action = dictionary(doc, item.get(b"A").ok()?)?;
+            if action.get(b"S").and_then(Object::as_name).ok()? != b"GoTo" {
+                return None;
+            }
+            action.get(b"D").ok()?
+        }
+    };
+
+    let target = resolve(doc, target);
+    let array = match target {
+        Object::Array(array) => array,
+        Object::Name(name) | Object::String(name, _) => named(doc, name)?,
+        _ => return None,
+    };
+
+    array.first()?.as_reference().ok()
+}
+
+/// A named destination's array, from the catalog's `Dests` dictionary or
+/// the `Dests` name tree.
+fn named<'a>(doc: &'a Document, name: &[u8]) -> Option<&'a Vec<Object>> {
+    let catalog = doc.catalog().ok()?;
+
+    let found = catalog
+        .get(b"Dests")
+        .ok()
+        .and_then(|dests| dictionary(doc, dests))
+        .and_then(|dests| dests.get(name).ok())
+        .or_else(|| {
+            let names = dictionary(doc, catalog.get(b"Names").ok()?)?;
+            let tree = dictionary(doc, names.get(b"Dests").ok()?)?;
+            lookup(doc, tree, name, 0)
+        })?;
+
+    // Either the array itself, or a dictionary holding it as D
+    match resolve(doc, found) {
+        Object::Array(array) => Some(array),
+        Object::Dictionary(dict) => match resolve(doc, dict.get(b"D").ok()?) {
+            Object::Array(array) => Some(array),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Find a name in a name tree.
+fn lookup<'a>(
+    doc: &'a Document,
+    node: &'a Dictionary,
+    name: &[u8],
+    depth: usize,
+) -> Option<&'a Object> {
+    if depth > 32 {
+        return None;
+    }
+
+    if let Ok(names) = node.get(b"Names").and_then(Object::as_array) {
+        let found = names
+            .chunks_exact(2)
+            .find(|pair| matches!(resolve(doc, &pair[0]), Object::String(key, _) if key == name));
+        if let Some(pair) = found {
+            return Some(&pair[1]);
+        }
+    }
+
+    node.get(b"Kids")
+        .and_then(Object::as_array)
+        .ok()?
+        .iter()
+        .filter_map(|kid| dictionary(doc, kid))
+        .find_map(|kid| lookup(doc, kid, name, depth + 1))
+}
+
+/// A text string, decoded from UTF-16 if it has a byte order mark, and
+/// otherwise taken as Latin-1, near enough to PDFDocEncoding for a title.
+fn decode(bytes: &[u8]) -> String {
+    match bytes.strip_prefix(&[0xFE, 0xFF]) {
+        Some(utf16) => {
+            let units: Vec<u16> = utf16
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        None => bytes.iter().map(|&byte| byte as char).collect(),
+    }
+}
+
+fn text(object: &Object) -> Option<String> {
+    match object {
+        Object::String(bytes, _) => {
+            let title = decode(bytes);
+            let title = title.trim();
+            (!title.is_empty()).then(|| title.to_string())
+        }
+        _ => None,
+    }
+}
+
+fn resolve<'a>(doc: &'a Document, object: &'a Object) -> &'a Object {
+    match object {
+        Object::Reference(id) => doc.get_object(*id).unwrap_or(object),
+        other => other,
+    }
+}
+
+fn dictionary<'a>(doc: &'a Document, object: &'a Object) -> Option<&'a Dictionary> {
+    resolve(doc, object).as_dict().ok()
+}
@@ -0,0 +1,290 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::lock;
+use crate::overlay::{self, Bleed, Options};
+
+/// The settings a run was performed with, recorded in the manifest so that a
+/// delivered file can be matched back to how it was produced.
+#[derive(Debug, Serialize)]
+pub struct Settings {
+    pub version: String,
+    pub trim_width: f64,
+    pub trim_height: f64,
+    pub bleed: Bleed,
+    pub trim_origin: Option<(f64, f64)>,
+}
+
+/// A single processed document as it appears in the manifest.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub input: PathBuf,
+    pub input_sha256: String,
+    /// The input's trailer ID, both parts in hexadecimal, if it has one: the
+    /// same whatever the file is called or wherever it's copied.
+    #[serde(default)]
+    pub input_id: Option<String>,
+    pub output: PathBuf,
+    pub output_sha256: String,
+    /// Digest of the effective options the input was stamped with.
+    #[serde(default)]
+    pub settings_sha256: String,
+    /// Code printed in the output's slug to trace it, if there was one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_code: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Manifest<'a> {
+    settings: &'a Settings,
+    documents: &'a [Entry],
+}
+
+/// The parts of a manifest already written which are read back.
+#[derive(Deserialize)]
+struct Recorded {
+    documents: Vec<Entry>,
+}
+
+/// Compute the SHA-256 digest of some bytes, returned as a lowercase
+/// hexadecimal string.
+pub fn sha256_bytes(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Compute the SHA-256 digest of the file at the given path, returned as a
+/// lowercase hexadecimal string.
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+
+    loop {
+        let count = file.read(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+        hasher.update(&buffer[..count]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Path of the sidecar checksum file for a given output, which is the output
+/// filename with ".sha256" appended.
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".sha256");
+    PathBuf::from(name)
+}
+
+/// Write a sidecar file next to the output containing its checksum. The
+/// format is the same as that emitted by `sha256sum` so that recipients can
+/// verify delivered files with `sha256sum -c`.
+pub fn write_sidecar(path: &Path, digest: &str) -> io::Result<PathBuf> {
+    let filename = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+
+    // Written atomically, so that a run killed partway never leaves a
+    // truncated checksum to be trusted
+    let sidecar = sidecar_path(path);
+    let line = format!("{}  {}\n", digest, filename);
+    overlay::write_atomically(&sidecar, |file| file.write_all(line.as_bytes()))?;
+
+    Ok(sidecar)
+}
+
+/// Path of the manifest for outputs written into the given directory.
+pub fn manifest_path(directory: &Path) -> PathBuf {
+    directory.join("manifest.json")
+}
+
+/// Read back the documents listed in the manifest in the given directory,
+/// if there is one.
+pub fn read_manifest(directory: &Path) -> Vec<Entry> {
+    fs::read(manifest_path(directory))
+        .ok()
+        .and_then(|json| serde_json::from_slice::<Recorded>(&json).ok())
+        .map(|recorded| recorded.documents)
+        .unwrap_or_default()
+}
+
+/// Write a manifest.json listing every document processed by this run along
+/// with the settings used. Documents listed by an earlier run are kept,
+/// unless this run wrote the same output.
+pub fn write_manifest(
+    directory: &Path,
+    settings: &Settings,
+    entries: Vec<Entry>,
+) -> io::Result<PathBuf> {
+    // Read, merge, and write back the manifest with it locked, so that
+    // another run writing into the same directory can't drop these entries,
+    // nor have its own dropped
+    let _lock = lock::acquire(&manifest_path(directory))?;
+
+    let mut documents = read_manifest(directory);
+    documents.retain(|earlier| !entries.iter().any(|entry| entry.output == earlier.output));
+    documents.extend(entries);
+
+    let manifest = Manifest {
+        settings,
+        documents: &documents,
+    };
+
+    let json = serde_json::to_string_pretty(&manifest).map_err(io::Error::other)?;
+
+    let path = manifest_path(directory);
+    overlay::write_atomically(&path, |file| file.write_all((json + "\n").as_bytes()))?;
+
+    info!("Manifest written to {}", path.display());
+
+    Ok(path)
+}
+
+/// Hash the input and output of a run, write the output's sidecar, and return
+/// the manifest entry describing them.
+pub fn checksum_document(input: &Path, output: &Path, options: &Options) -> io::Result<Entry> {
+    let input_sha256 = sha256_file(input)?;
+    let output_sha256 = sha256_file(output)?;
+
+    let sidecar = write_sidecar(output, &output_sha256)?;
+    info!("Checksum written to {}", sidecar.display());
+
+    Ok(Entry {
+        input: input.to_path_buf(),
+        input_sha256,
+        input_id: trailer_id(input)?,
+        output: output.to_path_buf(),
+        output_sha256,
+        settings_sha256: settings_sha256(options)?,
+        trace_code: options.trace_code.clone(),
+    })
+}
+
+/// Digest of the effective options, for telling whether two runs were made
+/// the same way. The trace code is left out, as it differs for every output
+/// of an otherwise identical run.
+pub fn settings_sha256(options: &Options) -> io::Result<String> {
+    let options = Options {
+        trace_code: None,
+        ..options.clone()
+    };
+    let json = serde_json::to_vec(&options).map_err(io::Error::other)?;
+    Ok(format!("{:x}", Sha256::digest(&json)))
+}
+
+/// How much of the end of a file is searched for its trailer ID.
+const TRAILER_SEARCH: u64 = 64 * 1024;
+
+/// A PDF's trailer ID, both parts in hexadecimal separated by a space, if it
+/// has one. The first part stays the same across revisions of a document,
+/// and the second changes with every one, so together they identify a
+/// particular revision.
+///
+/// Only the end of the file is read, where the trailer (or the dictionary of
+/// a cross-reference stream, which is never compressed) is found, so this is
+/// quick even for a large manuscript.
+pub fn trailer_id(path: &Path) -> io::Result<Option<String>> {
+    let mut file = File::open(path)?;
+    let length = file.metadata()?.len();
+    file.seek(SeekFrom::Start(length.saturating_sub(TRAILER_SEARCH)))?;
+
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail)?;
+    let tail = String::from_utf8_lossy(&tail);
+
+    // The last one, belonging to the most recent revision, as
+    // /ID [<hex> <hex>]
+    let Some(start) = tail.rfind("/ID") else {
+        return Ok(None);
+    };
+    let Some((array, _)) = tail[start..].split_once(']') else {
+        return Ok(None);
+    };
+    let parts: Vec<String> = array
+        .split('<')
+        .skip(1)
+        .filter_map(|part| part.split_once('>'))
+        .map(|(hex, _)| hex.trim().to_ascii_lowercase())
+        .collect();
+
+    let valid = |hex: &String| !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit());
+    if parts.len() != 2 || !parts.iter().all(valid) {
+        return Ok(None);
+    }
+
+    Ok(Some(parts.join(" ")))
+}
+
+/// The output an input with the same trailer ID was already stamped into
+/// with the same options, according to the manifest in the given directory,
+/// so long as that output is still there.
+pub fn already_stamped(
+    directory: &Path,
+    input: &Path,
+    options: &Options,
+) -> io::Result<Option<PathBuf>> {
+    let Some(id) = trailer_id(input)? else {
+        return Ok(None);
+    };
+    let settings = settings_sha256(options)?;
+
+    Ok(read_manifest(directory)
+        .into_iter()
+        .find(|entry| {
+            entry.input_id.as_deref() == Some(id.as_str())
+                && entry.settings_sha256 == settings
+                && entry.output.is_file()
+        })
+        .map(|entry| entry.output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn already_stamped_only_while_the_output_is_there() {
+        let directory =
+            std::env::temp_dir().join(format!("cropped-checksums.{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let input = directory.join("manuscript.pdf");
+        let output = directory.join("stamped.pdf");
+        fs::write(
+            &input,
+            "%PDF-1.7\ntrailer\n<< /ID [<AB12> <CD34>] >>\n%%EOF\n",
+        )
+        .unwrap();
+        fs::write(&output, "%PDF-1.7\n%%EOF\n").unwrap();
+
+        let options = Options::default();
+        let settings = Settings {
+            version: "0.0.0".to_string(),
+            trim_width: options.trim_width,
+            trim_height: options.trim_height,
+            bleed: options.bleed,
+            trim_origin: None,
+        };
+        let entry = checksum_document(&input, &output, &options).unwrap();
+        write_manifest(&directory, &settings, vec![entry]).unwrap();
+
+        assert_eq!(
+            already_stamped(&directory, &input, &options).unwrap(),
+            Some(output.clone())
+        );
+
+        // Deleted since, so to be stamped again
+        fs::remove_file(&output).unwrap();
+        assert_eq!(already_stamped(&directory, &input, &options).unwrap(), None);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,38 @@
+use std::io;
+
+use clap::Command;
+use clap::builder::{PossibleValue, PossibleValuesParser};
+use clap_complete::Shell;
+
+use crate::units::TRIM_PRESETS;
+
+/// Write a script completing cropped's subcommands and options for the given
+/// shell to standard output.
+///
+/// Values the parser works out for itself are offered too: the trim presets,
+/// and the profiles in the configuration files read for this run. Profiles
+/// added later aren't offered until the script is made again.
+pub fn generate(command: Command, shell: Shell, profiles: &[&str]) {
+    let trims = || {
+        let presets = TRIM_PRESETS.iter().map(|&(name, _, _)| name);
+        PossibleValuesParser::new(presets.chain(["auto"]).map(PossibleValue::new))
+    };
+
+    let mut command = command;
+    for name in ["stamp", "check", "check-pair"] {
+        command = command.mut_subcommand(name, |subcommand| {
+            subcommand.mut_arg("trim", |arg| arg.value_parser(trims()))
+        });
+    }
+    if !profiles.is_empty() {
+        let names: Vec<PossibleValue> = profiles
+            .iter()
+            .map(|name| PossibleValue::new(name.to_string()))
+            .collect();
+        command = command.mut_arg("profile", |arg| {
+            arg.value_parser(PossibleValuesParser::new(names))
+        });
+    }
+
+    clap_complete::generate(shell, &mut command, "cropped", &mut io::stdout());
+}
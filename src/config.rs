@@ -0,0 +1,499 @@
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::{Arg, ArgAction, Command};
+use toml::{Table, Value};
+use tracing::info;
+
+use crate::fonts::{self, Weight};
+use crate::i18n::Message;
+
+/// The project's configuration, looked for in the current directory.
+const LOCAL: &str = "cropped.toml";
+
+/// Settings naming files, which are found relative to the configuration
+/// file rather than to wherever cropped happens to be run from.
+const PATHS: [&str; 3] = ["rules", "font", "bold-font"];
+
+/// Settings which aren't options, but are configured here alone.
+const FONTS: [(&str, Weight); 2] = [("font", Weight::Regular), ("bold-font", Weight::Bold)];
+
+/// Settings for finding and installing new releases, which are likewise
+/// configured here alone.
+const UPDATES: [&str; 3] = ["update-url", "update-key", "update-check"];
+
+/// The table of named profiles, each a group of settings.
+const PROFILES: &str = "profile";
+
+/// Defaults for the stamp and check options, read from configuration files.
+///
+/// Each setting is named after the option it gives a default for, so
+///
+/// ```toml
+/// trim = "6x9"
+/// sheet = "a3"
+/// mark-gap = "3mm"
+/// slug-note = "Second pass"
+/// font = "fonts/IBMPlexMono-Regular.ttf"
+/// ```
+///
+/// is as if every run were given `--trim 6x9 --sheet a3` and so on, with
+/// `font` and `bold-font` naming TrueType files for the slug in place of
+/// Inconsolata. Settings for a particular printer can be grouped in a
+/// profile, such as `[profile.kdp-6x9]`, and used only when it's chosen with
+/// `--profile`, winning over the settings outside any profile. Options given
+/// on the command line win over the files.
+pub struct Config {
+    /// Each file read, with its settings, in the order they apply.
+    files: Vec<(PathBuf, Table)>,
+    /// The profile chosen, if any.
+    profile: Option<String>,
+}
+
+/// The configuration files which apply: the one given with `--config` alone,
+/// or otherwise the user's and then the project's, whichever exist, so that
+/// a project's settings win over the user's.
+fn paths(explicit: Option<PathBuf>) -> Vec<PathBuf> {
+    if let Some(path) = explicit {
+        return vec![path];
+    }
+
+    let user = user_directory().map(|dir| dir.join("config.toml"));
+
+    user.into_iter()
+        .chain([PathBuf::from(LOCAL)])
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+/// The directory the user's configuration is kept in, along with anything
+/// else of theirs cropped reads, such as mark packs.
+pub fn user_directory() -> Option<PathBuf> {
+    if cfg!(target_os = "windows") {
+        env::var_os("APPDATA").map(|dir| Path::new(&dir).join("cropped"))
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .map(|dir| dir.join("cropped"))
+    }
+}
+
+/// The value of an option which must be known before the arguments are
+/// parsed, such as `--config`, since the defaults it leads to must be known
+/// to parse them.
+pub fn given(args: &[OsString], option: &str) -> Option<String> {
+    let mut args = args.iter().skip(1);
+    let prefix = format!("--{}=", option);
+
+    while let Some(arg) = args.next() {
+        let arg = arg.to_string_lossy();
+        if arg == "--" {
+            break;
+        }
+        if arg == format!("--{}", option) {
+            return args
+                .next()
+                .map(|value| value.to_string_lossy().into_owned());
+        }
+        if let Some(value) = arg.strip_prefix(&prefix) {
+            return Some(value.to_string());
+        }
+    }
+
+    None
+}
+
+/// Read the configuration files which apply to a run with these arguments,
+/// checking that each setting names one of the given options, or a font,
+/// and that the profile chosen, if any, is in one of them.
+pub fn load(args: &[OsString], options: &[&str]) -> Result<Config, String> {
+    let mut files = Vec::new();
+
+    for path in paths(given(args, "config").map(PathBuf::from)) {
+        let text = fs::read_to_string(&path).map_err(|e| {
+            Message::ConfigUnreadable {
+                path: &path,
+                reason: &e.to_string(),
+            }
+            .to_string()
+        })?;
+        let mut table: Table = toml::from_str(&text).map_err(|e| {
+            Message::ConfigUnreadable {
+                path: &path,
+                reason: &e.to_string(),
+            }
+            .to_string()
+        })?;
+
+        for (key, value) in &mut table {
+            match value {
+                Value::Table(profiles) if key == PROFILES => {
+                    for (name, settings) in profiles.iter_mut() {
+                        let Value::Table(settings) = settings else {
+                            let key = format!("{}.{}", PROFILES, name);
+                            return Err(Message::UnknownSetting {
+                                key: &key,
+                                path: &path,
+                            }
+                            .to_string());
+                        };
+                        check(settings, &path, options)?;
+                    }
+                }
+                _ => check_setting(key, value, &path, options)?,
+            }
+        }
+
+        info!("Configuration read from {}", path.display());
+        files.push((path, table));
+    }
+
+    let config = Config {
+        files,
+        profile: given(args, "profile"),
+    };
+    if let Some(name) = &config.profile {
+        let available = config.profiles();
+        if !available.contains(&name.as_str()) {
+            return Err(Message::UnknownProfile {
+                name,
+                available: &available.join(", "),
+            }
+            .to_string());
+        }
+    }
+
+    Ok(config)
+}
+
+/// Check each of a group of settings.
+fn check(settings: &mut Table, path: &Path, options: &[&str]) -> Result<(), String> {
+    for (key, value) in settings {
+        check_setting(key, value, path, options)?;
+    }
+
+    Ok(())
+}
+
+/// Check that a setting names one of the given options, or a font, and find
+/// any file it names relative to the configuration file.
+fn check_setting(
+    key: &str,
+    value: &mut Value,
+    path: &Path,
+    options: &[&str],
+) -> Result<(), String> {
+    let font = FONTS.iter().any(|(name, _)| *name == key);
+    if !font && !UPDATES.contains(&key) && !options.contains(&key) {
+        return Err(Message::UnknownSetting { key, path }.to_string());
+    }
+
+    match value {
+        Value::String(file) if PATHS.contains(&key) => {
+            let directory = path.parent().unwrap_or(Path::new(""));
+            *file = directory.join(&*file).to_string_lossy().into_owned();
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+impl Config {
+    /// The names of the profiles in the files, in the order they're found.
+    pub fn profiles(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        for (_, table) in &self.files {
+            let Some(profiles) = table.get(PROFILES).and_then(Value::as_table) else {
+                continue;
+            };
+            for name in profiles.keys() {
+                if !names.contains(&name.as_str()) {
+                    names.push(name.as_str());
+                }
+            }
+        }
+        names
+    }
+
+    /// Each group of settings which applies, in the order they apply: those
+    /// outside any profile in each file, then those of the profile chosen.
+    fn layers(&self) -> impl Iterator<Item = (&Path, &Table)> {
+        let general = self
+            .files
+            .iter()
+            .map(|(path, table)| (path.as_path(), table));
+        let profile = self.files.iter().filter_map(|(path, table)| {
+            let name = self.profile.as_ref()?;
+            let settings = table.get(PROFILES)?.get(name)?.as_table()?;
+            Some((path.as_path(), settings))
+        });
+
+        general.chain(profile)
+    }
+
+    /// The value of a setting which isn't an option, such as `update-url`,
+    /// the later one's where several files or the profile give it.
+    #[cfg(feature = "self-update")]
+    pub fn setting(&self, key: &str) -> Option<&Value> {
+        self.layers().filter_map(|(_, table)| table.get(key)).last()
+    }
+
+    /// Give the configured fonts to use for the slug, the later setting's
+    /// where several name one.
+    pub fn configure_fonts(&self) {
+        let mut configured = HashMap::new();
+
+        for (_, table) in self.layers() {
+            for (name, weight) in FONTS {
+                if let Some(Value::String(file)) = table.get(name) {
+                    configured.insert(weight, PathBuf::from(file));
+                }
+            }
+        }
+
+        fonts::configure(configured);
+    }
+
+    /// The arguments with the configured settings put in ahead of those
+    /// given for the subcommand, as options which those given then override.
+    /// The subcommand must let an option be given more than once, the last
+    /// one winning.
+    pub fn apply(
+        &self,
+        command: &Command,
+        mut args: Vec<OsString>,
+    ) -> Result<Vec<OsString>, String> {
+        // The subcommand is the first argument which isn't an option or the
+        // value of one given ahead of it, which only global options can be
+        let globals: Vec<String> = command
+            .get_arguments()
+            .filter(|arg| arg.is_global_set() && arg.get_action().takes_values())
+            .flat_map(|arg| {
+                let long = arg.get_long().map(|long| format!("--{}", long));
+                let short = arg.get_short().map(|short| format!("-{}", short));
+                long.into_iter().chain(short)
+            })
+            .collect();
+
+        let mut position = None;
+        let mut index = 1;
+        while index < args.len() {
+            let arg = args[index].to_string_lossy();
+            if globals.iter().any(|global| *global == arg) {
+                index += 2;
+                continue;
+            }
+            if !arg.starts_with('-') {
+                position = Some(index);
+                break;
+            }
+            index += 1;
+        }
+        let Some(position) = position else {
+            return Ok(args);
+        };
+        let Some(subcommand) = command.find_subcommand(&args[position]) else {
+            return Ok(args);
+        };
+
+        let mut settings = Vec::new();
+        for (path, table) in self.layers() {
+            for (key, value) in table {
+                if key == PROFILES {
+                    continue;
+                }
+                let Some(arg) = subcommand
+                    .get_arguments()
+                    .find(|arg| arg.get_long() == Some(key.as_str()))
+                else {
+                    continue;
+                };
+
+                let flag = !arg.get_action().takes_values();
+                let bad = || Message::BadSetting { key, path, flag }.to_string();
+
+                match value {
+                    Value::Boolean(true) if flag => settings.push(format!("--{}", key)),
+                    // Turning off a flag a file or profile before turned on
+                    Value::Boolean(false) if flag => settings.push(format!("--no-{}", key)),
+                    _ if flag => return Err(bad()),
+                    Value::Array(values) => {
+                        for value in values {
+                            settings.push(format!("--{}={}", key, scalar(value).ok_or_else(bad)?));
+                        }
+                    }
+                    value => settings.push(format!("--{}={}", key, scalar(value).ok_or_else(bad)?)),
+                }
+            }
+        }
+        if !settings.is_empty() {
+            info!("Configured defaults: {}", settings.join(" "));
+        }
+
+        args.splice(
+            position + 1..position + 1,
+            settings.into_iter().map(OsString::from),
+        );
+        Ok(args)
+    }
+}
+
+/// A `--no-` form of each flag among the given options, hidden from the
+/// help, so that one turned on in a configuration file can be turned off
+/// again for a run. Of a flag and its `--no-` form, the last given wins.
+pub fn negations(options: &[Arg]) -> Vec<Arg> {
+    options
+        .iter()
+        .filter(|arg| matches!(arg.get_action(), ArgAction::SetTrue))
+        .filter_map(|arg| {
+            let name = format!("no-{}", arg.get_long()?);
+            Some(
+                Arg::new(name.clone())
+                    .long(name)
+                    .action(ArgAction::SetTrue)
+                    .hide(true)
+                    .overrides_with(arg.get_id().clone()),
+            )
+        })
+        .collect()
+}
+
+/// A setting's value as it would be given on the command line.
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(value) => Some(value.clone()),
+        Value::Integer(value) => Some(value.to_string()),
+        Value::Float(value) => Some(value.to_string()),
+        Value::Boolean(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A command with a global option taking a value, and a subcommand
+    /// taking an option, a flag, and its `--no-` form.
+    fn command() -> Command {
+        let options = vec![
+            Arg::new("mark-gap").long("mark-gap"),
+            Arg::new("strict").long("strict").action(ArgAction::SetTrue),
+            Arg::new("manuscript"),
+        ];
+        let negations = negations(&options);
+
+        Command::new("cropped")
+            .arg(Arg::new("profile").long("profile").global(true))
+            .subcommand(
+                Command::new("check")
+                    .args_override_self(true)
+                    .args(options)
+                    .args(negations),
+            )
+    }
+
+    fn config(files: &[&str], profile: Option<&str>) -> Config {
+        Config {
+            files: files
+                .iter()
+                .enumerate()
+                .map(|(index, text)| {
+                    let path = PathBuf::from(format!("config-{}.toml", index));
+                    (path, toml::from_str(text).unwrap())
+                })
+                .collect(),
+            profile: profile.map(str::to_string),
+        }
+    }
+
+    /// The mark gap and strictness of a run with the given arguments.
+    fn run(config: &Config, args: &[&str]) -> (Option<String>, bool) {
+        let args = args.iter().map(OsString::from).collect();
+        let args = config.apply(&command(), args).unwrap();
+        let matches = command().get_matches_from(args);
+        let (_, check) = matches.subcommand().unwrap();
+        (
+            check.get_one::<String>("mark-gap").cloned(),
+            check.get_flag("strict"),
+        )
+    }
+
+    const GENERAL: &str = r#"
+        mark-gap = "3mm"
+        strict = true
+
+        [profile.kdp]
+        mark-gap = "9pt"
+        strict = false
+    "#;
+
+    #[test]
+    fn the_file_gives_defaults() {
+        let config = config(&[GENERAL], None);
+        assert_eq!(
+            run(&config, &["cropped", "check", "in.pdf"]),
+            (Some("3mm".to_string()), true)
+        );
+    }
+
+    #[test]
+    fn a_later_file_wins() {
+        let config = config(&[GENERAL, r#"mark-gap = "5mm""#], None);
+        assert_eq!(
+            run(&config, &["cropped", "check", "in.pdf"]),
+            (Some("5mm".to_string()), true)
+        );
+    }
+
+    #[test]
+    fn the_profile_wins_over_the_files() {
+        let config = config(&[GENERAL, r#"mark-gap = "5mm""#], Some("kdp"));
+        assert_eq!(
+            run(&config, &["cropped", "--profile", "kdp", "check", "in.pdf"]),
+            (Some("9pt".to_string()), false)
+        );
+    }
+
+    #[test]
+    fn the_command_line_wins_over_the_profile() {
+        let config = config(&[GENERAL], Some("kdp"));
+        assert_eq!(
+            run(
+                &config,
+                &[
+                    "cropped",
+                    "--profile",
+                    "kdp",
+                    "check",
+                    "in.pdf",
+                    "--mark-gap",
+                    "1mm",
+                    "--strict"
+                ]
+            ),
+            (Some("1mm".to_string()), true)
+        );
+    }
+
+    #[test]
+    fn a_configured_flag_can_be_turned_off() {
+        let config = config(&[GENERAL], None);
+        assert_eq!(
+            run(&config, &["cropped", "check", "in.pdf", "--no-strict"]),
+            (Some("3mm".to_string()), false)
+        );
+    }
+
+    #[test]
+    fn profiles_are_listed() {
+        let config = config(&[GENERAL], None);
+        assert_eq!(config.profiles(), vec!["kdp"]);
+    }
+}
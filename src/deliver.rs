@@ -0,0 +1,156 @@
+use std::fs::File;
+use std::io;
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use ssh2::Session;
+use tracing::{info, warn};
+
+/// Number of times an upload is attempted before giving up.
+const ATTEMPTS: u32 = 3;
+
+/// A parsed `sftp://[user@]host[:port]/directory/` delivery target.
+#[derive(Debug)]
+pub struct Target {
+    pub user: String,
+    pub host: String,
+    pub port: u16,
+    pub directory: String,
+}
+
+/// Record of a successful delivery.
+#[derive(Debug)]
+pub struct Receipt {
+    pub target: String,
+    pub files: Vec<String>,
+    pub bytes: u64,
+    pub attempts: u32,
+}
+
+impl Target {
+    pub fn parse(url: &str) -> Result<Target, String> {
+        let rest = url
+            .strip_prefix("sftp://")
+            .ok_or_else(|| format!("Delivery target must be an sftp:// URL, not '{}'", url))?;
+
+        let (authority, directory) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+
+        let (user, hostport) = match authority.rsplit_once('@') {
+            Some((user, hostport)) => (user.to_string(), hostport),
+            None => (
+                std::env::var("USER").unwrap_or_else(|_| "anonymous".to_string()),
+                authority,
+            ),
+        };
+
+        let (host, port) = match hostport.rsplit_once(':') {
+            Some((host, port)) => {
+                let port = port
+                    .parse::<u16>()
+                    .map_err(|_| format!("Invalid port '{}' in delivery target", port))?;
+                (host.to_string(), port)
+            }
+            None => (hostport.to_string(), 22),
+        };
+
+        if host.is_empty() {
+            return Err(format!("No host given in delivery target '{}'", url));
+        }
+
+        Ok(Target {
+            user,
+            host,
+            port,
+            directory: directory.to_string(),
+        })
+    }
+
+    fn remote_path(&self, name: &str) -> PathBuf {
+        Path::new(&self.directory).join(name)
+    }
+}
+
+/// Upload the given files to the printer's drop box.
+///
+/// Authentication is via the user's running ssh-agent. Each file is uploaded
+/// under a temporary ".part" name and renamed once complete so that the
+/// printer's intake never picks up a half-transferred file. Transient
+/// failures are retried with a doubling backoff.
+pub fn deliver(target: &Target, files: &[PathBuf]) -> io::Result<Receipt> {
+    let mut attempt = 1;
+    let mut backoff = Duration::from_secs(2);
+
+    loop {
+        match upload(target, files) {
+            Ok(bytes) => {
+                let receipt = Receipt {
+                    target: format!(
+                        "sftp://{}@{}:{}{}",
+                        target.user, target.host, target.port, target.directory
+                    ),
+                    files: files
+                        .iter()
+                        .filter_map(|path| path.file_name())
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .collect(),
+                    bytes,
+                    attempts: attempt,
+                };
+                info!(?receipt, "Delivery complete");
+                return Ok(receipt);
+            }
+            Err(e) if attempt < ATTEMPTS => {
+                warn!(
+                    "Delivery attempt {} failed: {}; retrying in {:?}",
+                    attempt, e, backoff
+                );
+                thread::sleep(backoff);
+                attempt += 1;
+                backoff *= 2;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn upload(target: &Target, files: &[PathBuf]) -> io::Result<u64> {
+    let tcp = TcpStream::connect((target.host.as_str(), target.port))?;
+
+    let mut session = Session::new().map_err(io::Error::other)?;
+    session.set_tcp_stream(tcp);
+    session.handshake().map_err(io::Error::other)?;
+    session
+        .userauth_agent(&target.user)
+        .map_err(io::Error::other)?;
+
+    let sftp = session.sftp().map_err(io::Error::other)?;
+
+    let mut total = 0;
+
+    for path in files {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| io::Error::other(format!("Not a file: {}", path.display())))?;
+
+        let partial = target.remote_path(&format!(".{}.part", name));
+        let destination = target.remote_path(&name);
+
+        let mut source = File::open(path)?;
+        let mut remote = sftp.create(&partial).map_err(io::Error::other)?;
+        total += io::copy(&mut source, &mut remote)?;
+        drop(remote);
+
+        sftp.rename(&partial, &destination, None)
+            .map_err(io::Error::other)?;
+
+        info!("Uploaded {}", destination.display());
+    }
+
+    Ok(total)
+}
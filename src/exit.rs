@@ -0,0 +1,55 @@
+use std::fmt;
+use std::io;
+
+use owo_colors::OwoColorize;
+
+use crate::i18n;
+
+/// The kinds of failure cropped exits with, each with its own exit status,
+/// so that scripts wrapping it can tell them apart without reading the
+/// messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// Anything not covered below.
+    Other = 1,
+    /// The command line or configuration asks for something which can't be
+    /// done. The same status as clap gives its own usage errors.
+    Usage = 2,
+    /// A manuscript, or another file read, is missing, unreadable, or not a
+    /// PDF.
+    Input = 3,
+    /// The trim doesn't fit on the sheet.
+    Geometry = 4,
+    /// Checking found problems: preflight warnings from check, any at all
+    /// under --strict, or differences found by audit or verify.
+    Preflight = 5,
+    /// The output, or something written alongside it, couldn't be written.
+    Output = 6,
+    /// Interrupted with Ctrl-C.
+    Cancelled = 130,
+}
+
+impl Failure {
+    /// The process exit status for this kind of failure.
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+}
+
+/// The kind of failure an error from reading or writing a PDF is, where
+/// there's no more to go on: a file which isn't there or won't parse is a
+/// problem with the input, and any other I/O error one with the output.
+pub fn classify(error: &lopdf::Error) -> Failure {
+    match error {
+        lopdf::Error::IO(e) if e.kind() == io::ErrorKind::NotFound => Failure::Input,
+        lopdf::Error::IO(e) if e.kind() == io::ErrorKind::Other => Failure::Other,
+        lopdf::Error::IO(_) => Failure::Output,
+        _ => Failure::Input,
+    }
+}
+
+/// Report an error, and exit with the status for its kind of failure.
+pub fn fail(failure: Failure, message: impl fmt::Display) -> ! {
+    eprintln!("{}: {}", i18n::error().bright_red(), message);
+    std::process::exit(failure.code())
+}
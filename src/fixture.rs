@@ -0,0 +1,271 @@
+use std::path::Path;
+
+use lopdf::content::{Content, Operation};
+use lopdf::{Document, Object, ObjectId, Stream, StringFormat, dictionary};
+use tracing::info;
+
+use crate::fonts;
+use crate::units;
+
+/// Depth of the color bars, measured inward from the trim. They also run
+/// out through the bleed to the edge of the page.
+const BAR_DEPTH: f64 = 12.0;
+
+/// The process inks the color bars cycle through, as CMYK.
+const INKS: [[f64; 4]; 4] = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+/// Synthesize a simple manuscript for testing and for calibrating a printer
+/// without risking a real one.
+///
+/// Each page is the trim plus bleed in size, carries a TrimBox, and has
+/// bars of solid color running from inside the trim out to the edge of the
+/// page on all four sides, so that whether the bleed survives trimming can
+/// be seen at a glance. A thin rule marks the trim itself, and the page
+/// number is printed large in the middle.
+pub fn generate(
+    output_path: &Path,
+    pages: usize,
+    trim_width: f64,
+    trim_height: f64,
+    bleed: f64,
+) -> lopdf::Result<()> {
+    let mut doc = Document::with_version("1.5");
+
+    let (font_id, metrics) = fonts::embed_font(&mut doc, fonts::Weight::Regular)?;
+
+    let width = trim_width + 2.0 * bleed;
+    let height = trim_height + 2.0 * bleed;
+
+    let description = format!(
+        "{:.2} x {:.2} in, bleed {:.1} mm",
+        trim_width / units::POINTS_PER_INCH,
+        trim_height / units::POINTS_PER_INCH,
+        bleed / units::POINTS_PER_MM
+    );
+
+    let pages_id = doc.new_object_id();
+    let mut kids = Vec::with_capacity(pages);
+
+    for page_num in 1..=pages {
+        let mut ops = Vec::new();
+
+        // Color bars along each edge, cycling through the inks so that
+        // adjacent pages differ
+        let inner = bleed + BAR_DEPTH;
+        let bars = [
+            (0.0, 0.0, width, inner),
+            (0.0, height - inner, width, inner),
+            (0.0, 0.0, inner, height),
+            (width - inner, 0.0, inner, height),
+        ];
+        for (index, (x, y, w, h)) in bars.into_iter().enumerate() {
+            let [c, m, y_ink, k] = INKS[(page_num + index) % INKS.len()];
+            ops.push(Operation::new(
+                "k",
+                vec![c.into(), m.into(), y_ink.into(), k.into()],
+            ));
+            ops.push(Operation::new(
+                "re",
+                vec![x.into(), y.into(), w.into(), h.into()],
+            ));
+            ops.push(Operation::new("f", vec![]));
+        }
+
+        // Trim rule
+        ops.push(Operation::new(
+            "K",
+            vec![0.into(), 0.into(), 0.into(), 1.into()],
+        ));
+        ops.push(Operation::new("w", vec![0.25.into()]));
+        ops.push(Operation::new(
+            "re",
+            vec![
+                bleed.into(),
+                bleed.into(),
+                trim_width.into(),
+                trim_height.into(),
+            ],
+        ));
+        ops.push(Operation::new("S", vec![]));
+
+        // Page number, large, and a description of the fixture beneath it
+        ops.push(Operation::new(
+            "k",
+            vec![0.into(), 0.into(), 0.into(), 1.into()],
+        ));
+        let centered = |label: &str, size: f64, y: f64| {
+            let x = (width - metrics.measure(label, size)) / 2.0;
+            text(label, size, x, y)
+        };
+        ops.extend(centered(&page_num.to_string(), 72.0, height / 2.0));
+        ops.extend(centered(&description, 10.0, height / 2.0 - 24.0));
+
+        let content = Content { operations: ops };
+        let content_id = doc.add_object(Stream::new(dictionary! {}, content.encode()?));
+
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), width.into(), height.into()],
+            "TrimBox" => vec![
+                bleed.into(),
+                bleed.into(),
+                (bleed + trim_width).into(),
+                (bleed + trim_height).into(),
+            ],
+            "Resources" => dictionary! {
+                "Font" => dictionary! {
+                    "F1" => font_id,
+                },
+            },
+            "Contents" => content_id,
+        });
+        kids.push(Object::Reference(page_id));
+    }
+
+    save(doc, pages_id, kids, output_path)
+}
+
+/// Spacing of the calibration grid, 10mm.
+const GRID: f64 = 10.0 * units::POINTS_PER_MM;
+
+/// Generate an A4 calibration target: a 10mm grid with its diagonals and a
+/// scale along two edges, so that the user can check with a ruler that
+/// their printer isn't scaling output before trusting the position of any
+/// crop marks.
+pub fn calibration(output_path: &Path) -> lopdf::Result<()> {
+    let mut doc = Document::with_version("1.5");
+
+    let (font_id, metrics) = fonts::embed_font(&mut doc, fonts::Weight::Regular)?;
+
+    // The grid is 190 × 270mm, leaving a 10mm margin on the left and bottom
+    let (columns, rows) = (19, 27);
+    let (x1, y1) = (GRID, GRID);
+    let (x2, y2) = (x1 + columns as f64 * GRID, y1 + rows as f64 * GRID);
+
+    let mut ops = Vec::new();
+    ops.push(Operation::new("G", vec![0.into()]));
+
+    // Lines every 10mm, heavier every 50mm
+    for heavy in [false, true] {
+        let weight = if heavy { 0.75 } else { 0.25 };
+        ops.push(Operation::new("w", vec![weight.into()]));
+
+        for i in (0..=columns).filter(|i| (i % 5 == 0) == heavy) {
+            let x = x1 + i as f64 * GRID;
+            ops.push(Operation::new("m", vec![x.into(), y1.into()]));
+            ops.push(Operation::new("l", vec![x.into(), y2.into()]));
+        }
+        for i in (0..=rows).filter(|i| (i % 5 == 0) == heavy) {
+            let y = y1 + i as f64 * GRID;
+            ops.push(Operation::new("m", vec![x1.into(), y.into()]));
+            ops.push(Operation::new("l", vec![x2.into(), y.into()]));
+        }
+        ops.push(Operation::new("S", vec![]));
+    }
+
+    // Diagonals, which show up any difference in scale between the axes
+    ops.push(Operation::new("w", vec![0.25.into()]));
+    ops.push(Operation::new("m", vec![x1.into(), y1.into()]));
+    ops.push(Operation::new("l", vec![x2.into(), y2.into()]));
+    ops.push(Operation::new("m", vec![x1.into(), y2.into()]));
+    ops.push(Operation::new("l", vec![x2.into(), y1.into()]));
+    ops.push(Operation::new("S", vec![]));
+
+    // Scale in millimetres every 50mm along the bottom and left edges
+    ops.push(Operation::new("g", vec![0.into()]));
+    for i in (0..=columns).step_by(5) {
+        let label = format!("{}", i * 10);
+        let x = x1 + i as f64 * GRID - metrics.measure(&label, 6.0) / 2.0;
+        ops.extend(text(&label, 6.0, x, y1 - 8.0));
+    }
+    for i in (5..=rows).step_by(5) {
+        let label = format!("{}", i * 10);
+        let x = x1 - 2.0 - metrics.measure(&label, 6.0);
+        ops.extend(text(&label, 6.0, x, y1 + i as f64 * GRID - 2.0));
+    }
+
+    // Instructions, in the top margin
+    ops.extend(text(
+        "Print at 100% (actual size). Grid squares are 10 mm; the grid is 190 x 270 mm.",
+        7.0,
+        x1,
+        y2 + 12.0,
+    ));
+    ops.extend(text(
+        "If it measures otherwise, the printer is scaling and crop marks will be misplaced.",
+        7.0,
+        x1,
+        y2 + 3.0,
+    ));
+
+    let content = Content { operations: ops };
+    let content_id = doc.add_object(Stream::new(dictionary! {}, content.encode()?));
+
+    let pages_id = doc.new_object_id();
+    let page_id = doc.add_object(dictionary! {
+        "Type" => "Page",
+        "Parent" => pages_id,
+        "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+        "Resources" => dictionary! {
+            "Font" => dictionary! {
+                "F1" => font_id,
+            },
+        },
+        "Contents" => content_id,
+    });
+
+    save(doc, pages_id, vec![Object::Reference(page_id)], output_path)
+}
+
+/// Operations to show a line of text in the embedded font.
+fn text(text: &str, size: f64, x: f64, y: f64) -> Vec<Operation> {
+    vec![
+        Operation::new("BT", vec![]),
+        Operation::new("Tf", vec!["F1".into(), size.into()]),
+        Operation::new("Td", vec![x.into(), y.into()]),
+        Operation::new(
+            "Tj",
+            vec![Object::String(
+                text.as_bytes().to_vec(),
+                StringFormat::Literal,
+            )],
+        ),
+        Operation::new("ET", vec![]),
+    ]
+}
+
+/// Complete the page tree and catalog of a generated document, and save it.
+fn save(
+    mut doc: Document,
+    pages_id: ObjectId,
+    kids: Vec<Object>,
+    output_path: &Path,
+) -> lopdf::Result<()> {
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Count" => kids.len() as i64,
+            "Kids" => kids,
+        }),
+    );
+
+    let catalog_id = doc.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    doc.trailer.set("Root", catalog_id);
+
+    doc.compress();
+
+    info!("Save generated document");
+    doc.save(output_path)?;
+
+    Ok(())
+}
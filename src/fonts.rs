@@ -1,19 +1,292 @@
+use std::collections::HashMap;
+use std::env;
 use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
-use lopdf::{Document, ObjectId, Stream, dictionary};
+use lopdf::content::Operation;
+use lopdf::{Document, Object, ObjectId, Stream, StringFormat, dictionary};
+use tracing::info;
+use ttf_parser::GlyphId;
 
-const FONT_PATH: &str = "/usr/share/fonts/levien-inconsolata/Inconsolata-Regular.ttf";
+/// Weights of Inconsolata the slug can be set in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Weight {
+    #[default]
+    Regular,
+    /// For large-print slugs, where it's installed.
+    Bold,
+}
+
+impl Weight {
+    /// Filename the weight is installed under.
+    fn file(self) -> &'static str {
+        match self {
+            Weight::Regular => "Inconsolata-Regular.ttf",
+            Weight::Bold => "Inconsolata-Bold.ttf",
+        }
+    }
+
+    /// Environment variable naming a TrueType font file to use for the slug
+    /// in place of the installed Inconsolata of this weight.
+    fn variable(self) -> &'static str {
+        match self {
+            Weight::Regular => "CROPPED_FONT",
+            Weight::Bold => "CROPPED_BOLD_FONT",
+        }
+    }
+
+    /// PostScript name the font is embedded under.
+    fn postscript_name(self) -> &'static str {
+        match self {
+            Weight::Regular => "Inconsolata-Regular",
+            Weight::Bold => "Inconsolata-Bold",
+        }
+    }
+}
+
+/// Font files named in a configuration file, for each weight.
+static CONFIGURED: OnceLock<HashMap<Weight, PathBuf>> = OnceLock::new();
+
+/// Use these font files for the slug, in place of the installed Inconsolata,
+/// unless the environment names others. Only the first call has any effect.
+pub fn configure(files: HashMap<Weight, PathBuf>) {
+    let _ = CONFIGURED.set(files);
+}
+
+/// Places Inconsolata of the given weight is installed to on this platform,
+/// system packages first and then the user's own fonts.
+fn font_candidates(weight: Weight) -> Vec<PathBuf> {
+    let mut candidates = Vec::new();
+    let font_file = weight.file();
+
+    if let Some(path) = env::var_os(weight.variable()) {
+        candidates.push(PathBuf::from(path));
+    }
+    if let Some(path) = CONFIGURED.get().and_then(|files| files.get(&weight)) {
+        candidates.push(path.clone());
+    }
+
+    if cfg!(target_os = "windows") {
+        if let Some(dir) = env::var_os("LOCALAPPDATA") {
+            candidates.push(
+                Path::new(&dir)
+                    .join(r"Microsoft\Windows\Fonts")
+                    .join(font_file),
+            );
+        }
+        if let Some(dir) = env::var_os("WINDIR") {
+            candidates.push(Path::new(&dir).join("Fonts").join(font_file));
+        }
+    } else if cfg!(target_os = "macos") {
+        if let Some(home) = env::var_os("HOME") {
+            candidates.push(Path::new(&home).join("Library/Fonts").join(font_file));
+        }
+        candidates.push(Path::new("/Library/Fonts").join(font_file));
+    } else {
+        candidates.push(Path::new("/usr/share/fonts/levien-inconsolata").join(font_file));
+        candidates.push(Path::new("/usr/share/fonts/TTF").join(font_file));
+        candidates.push(Path::new("/usr/share/fonts/truetype/inconsolata").join(font_file));
+        if let Some(home) = env::var_os("HOME") {
+            candidates.push(Path::new(&home).join(".local/share/fonts").join(font_file));
+        }
+    }
+
+    candidates
+}
+
+/// Read the slug font from the first of the candidate locations it's found
+/// in, or explain where it was looked for. If the bold weight isn't
+/// installed the regular is read in its place; the weight actually read is
+/// returned with the font.
+fn read_font(weight: Weight) -> lopdf::Result<(Vec<u8>, Weight)> {
+    let candidates = font_candidates(weight);
+
+    for path in &candidates {
+        if let Ok(data) = fs::read(path) {
+            return Ok((data, weight));
+        }
+    }
+
+    if weight == Weight::Bold {
+        info!("Inconsolata Bold not found; setting the slug in the regular weight");
+        return read_font(Weight::Regular);
+    }
+
+    let searched: Vec<String> = candidates
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    Err(lopdf::Error::IO(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!(
+            "Inconsolata not found; install it or set {} to a TrueType font. Looked in: {}",
+            weight.variable(),
+            searched.join(", ")
+        ),
+    )))
+}
+
+/// First and last character codes given widths in the embedded font,
+/// covering the whole of WinAnsiEncoding from the space up.
+const FIRST_CHAR: u8 = 32;
+const LAST_CHAR: u8 = 255;
+
+/// Characters encoded as 0x80 to 0x9F in WinAnsiEncoding, where it departs
+/// from Latin-1. Codes left undefined are None.
+const WIN_ANSI_HIGH: [Option<char>; 32] = [
+    Some('€'),
+    None,
+    Some('‚'),
+    Some('ƒ'),
+    Some('„'),
+    Some('…'),
+    Some('†'),
+    Some('‡'),
+    Some('ˆ'),
+    Some('‰'),
+    Some('Š'),
+    Some('‹'),
+    Some('Œ'),
+    None,
+    Some('Ž'),
+    None,
+    None,
+    Some('\u{2018}'),
+    Some('\u{2019}'),
+    Some('\u{201C}'),
+    Some('\u{201D}'),
+    Some('•'),
+    Some('–'),
+    Some('—'),
+    Some('˜'),
+    Some('™'),
+    Some('š'),
+    Some('›'),
+    Some('œ'),
+    None,
+    Some('ž'),
+    Some('Ÿ'),
+];
+
+/// Character drawn in place of any that WinAnsiEncoding can't represent.
+const REPLACEMENT: u8 = b'?';
+
+/// The character a WinAnsiEncoding code stands for, if any.
+fn decode(code: u8) -> Option<char> {
+    match code {
+        0x20..=0x7E | 0xA0..=0xFF => Some(code as char),
+        0x80..=0x9F => WIN_ANSI_HIGH[(code - 0x80) as usize],
+        _ => None,
+    }
+}
+
+/// Encode text in WinAnsiEncoding, the encoding of the embedded font, as it
+/// will be written to the content stream. Characters it can't represent
+/// become question marks.
+pub fn encode(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| match c {
+            ' '..='~' | '\u{A0}'..='ÿ' => c as u8,
+            _ => WIN_ANSI_HIGH
+                .iter()
+                .position(|&high| high == Some(c))
+                .map(|index| 0x80 + index as u8)
+                .unwrap_or(REPLACEMENT),
+        })
+        .collect()
+}
+
+/// Measurements of the embedded font at 1pt, for laying out text.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    /// Distance between the baselines of successive lines.
+    pub leading: f64,
+    /// Advance of each code from FIRST_CHAR to LAST_CHAR.
+    widths: Vec<f64>,
+    /// Adjustment to the advance between pairs of codes, from the font's
+    /// kern table. Only pairs with an adjustment are present.
+    kerning: HashMap<(u8, u8), f64>,
+}
+
+impl Metrics {
+    /// Metrics for a monospaced font with no kerning, so that tests needn't
+    /// depend on the font installed.
+    #[cfg(test)]
+    pub fn monospaced(char_width: f64, leading: f64) -> Metrics {
+        Metrics {
+            leading,
+            widths: vec![char_width; (LAST_CHAR - FIRST_CHAR) as usize + 1],
+            kerning: HashMap::new(),
+        }
+    }
+
+    /// Advance of a single code at 1pt.
+    fn advance(&self, code: u8) -> f64 {
+        match code {
+            FIRST_CHAR..=LAST_CHAR => self.widths[(code - FIRST_CHAR) as usize],
+            _ => 0.0,
+        }
+    }
+
+    /// Adjustment to the advance between two adjacent codes at 1pt,
+    /// negative where they are drawn closer together.
+    fn kerning(&self, left: u8, right: u8) -> f64 {
+        self.kerning.get(&(left, right)).copied().unwrap_or(0.0)
+    }
+
+    /// Width of text set at the given size, as drawn by `show`. This is
+    /// measured over the text as encoded, so that characters replaced in
+    /// encoding are measured as they will appear.
+    pub fn measure(&self, text: &str, size: f64) -> f64 {
+        let bytes = encode(text);
+        let advances: f64 = bytes.iter().map(|&code| self.advance(code)).sum();
+        let kerning: f64 = bytes
+            .windows(2)
+            .map(|pair| self.kerning(pair[0], pair[1]))
+            .sum();
+
+        (advances + kerning) * size
+    }
+
+    /// The operation showing text in the embedded font, applying kerning if
+    /// the font has any for the text.
+    pub fn show(&self, text: &str) -> Operation {
+        let bytes = encode(text);
+        let string = |b: &[u8]| Object::String(b.to_vec(), StringFormat::Literal);
+
+        let mut parts = Vec::new();
+        let mut start = 0;
+        for (index, pair) in bytes.windows(2).enumerate() {
+            let kerning = self.kerning(pair[0], pair[1]);
+            if kerning != 0.0 {
+                parts.push(string(&bytes[start..=index]));
+                // TJ adjustments are in thousandths of an em, and positive
+                // moves the next glyph left
+                parts.push((-kerning * 1000.0).into());
+                start = index + 1;
+            }
+        }
+
+        if parts.is_empty() {
+            return Operation::new("Tj", vec![string(&bytes)]);
+        }
+
+        parts.push(string(&bytes[start..]));
+        Operation::new("TJ", vec![Object::Array(parts)])
+    }
+}
 
 /// Embed a TrueType font into the PDF document.
 ///
 /// Creates the necessary font descriptor, font file stream, and font
-/// dictionary objects required for PDF font embedding. Uses WinAnsiEncoding
-/// as the timestamps and page numbers are purely ASCII text.
+/// dictionary objects required for PDF font embedding. Uses WinAnsiEncoding,
+/// which covers the Latin characters likely in filenames and notes.
 ///
-/// Returns the ObjectId of the font dictionary and the width of a monospaced
-/// character at 1pt font size.
-pub fn embed_font(doc: &mut Document) -> lopdf::Result<(ObjectId, f64)> {
-    let font_data = fs::read(FONT_PATH).map_err(|e| lopdf::Error::IO(e))?;
+/// Returns the ObjectId of the font dictionary and the font's metrics.
+pub fn embed_font(doc: &mut Document, weight: Weight) -> lopdf::Result<(ObjectId, Metrics)> {
+    let (font_data, weight) = read_font(weight)?;
 
     let face =
         ttf_parser::Face::parse(&font_data, 0).map_err(|_| lopdf::Error::PageNumberNotFound(0))?;
@@ -23,27 +296,56 @@ pub fn embed_font(doc: &mut Document) -> lopdf::Result<(ObjectId, f64)> {
     let ascender = face.ascender();
     let descender = face.descender();
     let cap_height = face.capital_height().unwrap_or(700);
+    let line_gap = face.line_gap();
 
-    // Get character width for monospaced font (use '0' as representative glyph)
     let units_per_em = face.units_per_em() as f64;
 
-    // Get advance width in font units for the Widths array
-    let advance_width_units = face
-        .glyph_index('0')
-        .and_then(|glyph_id| face.glyph_hor_advance(glyph_id))
-        .unwrap_or(600);
+    // Advance widths in font units for the Widths array, falling back on
+    // that of '0' for anything the font lacks
+    let advance_units = |c: char| {
+        face.glyph_index(c)
+            .and_then(|glyph_id| face.glyph_hor_advance(glyph_id))
+    };
+    let default_units = advance_units('0').unwrap_or(600);
+    let width_units: Vec<u16> = (FIRST_CHAR..=LAST_CHAR)
+        .map(|code| match decode(code) {
+            Some(c) => advance_units(c).unwrap_or(default_units),
+            None => 0,
+        })
+        .collect();
 
-    // Calculate normalized width (0.0 to 1.0) for text positioning
-    let char_width = advance_width_units as f64 / units_per_em;
+    // Kerning between each pair of codes, from the kern table where the font
+    // has one
+    let mut kerning = HashMap::new();
+    if let Some(kern) = face.tables().kern {
+        let glyphs: Vec<(u8, GlyphId)> = (FIRST_CHAR..=LAST_CHAR)
+            .filter_map(|code| Some((code, face.glyph_index(decode(code)?)?)))
+            .collect();
+        for subtable in kern
+            .subtables
+            .into_iter()
+            .filter(|s| s.horizontal && !s.variable && !s.has_cross_stream)
+        {
+            for &(left, left_glyph) in &glyphs {
+                for &(right, right_glyph) in &glyphs {
+                    if let Some(value) = subtable.glyphs_kerning(left_glyph, right_glyph) {
+                        *kerning.entry((left, right)).or_insert(0.0) += value as f64 / units_per_em;
+                    }
+                }
+            }
+        }
+        kerning.retain(|_, value| *value != 0.0);
+    }
 
-    // Build Widths array for characters 32-126 (WinAnsiEncoding range)
-    // Since Inconsolata is monospaced, all characters have the same width
-    const FIRST_CHAR: usize = 32;
-    const LAST_CHAR: usize = 126;
-    let widths = vec![(advance_width_units as i64).into(); LAST_CHAR - FIRST_CHAR + 1];
+    // Distance between baselines of successive lines, as the font designer
+    // intended
+    let leading = (ascender as f64 - descender as f64 + line_gap as f64) / units_per_em;
 
-    // Drop face to release borrow on font_data before moving it
-    drop(face);
+    // Build Widths array for codes 32-255 (WinAnsiEncoding range)
+    let widths: Vec<Object> = width_units
+        .iter()
+        .map(|&units| (units as i64).into())
+        .collect();
 
     // Create font file stream
     let font_stream = Stream::new(
@@ -57,7 +359,7 @@ pub fn embed_font(doc: &mut Document) -> lopdf::Result<(ObjectId, f64)> {
     // Create font descriptor
     let font_descriptor = dictionary! {
         "Type" => "FontDescriptor",
-        "FontName" => "Inconsolata-Regular",
+        "FontName" => weight.postscript_name(),
         "Flags" => 32, // Symbolic font
         "FontBBox" => vec![
             (bbox.x_min as i64).into(),
@@ -78,14 +380,31 @@ pub fn embed_font(doc: &mut Document) -> lopdf::Result<(ObjectId, f64)> {
     let font_dict = dictionary! {
         "Type" => "Font",
         "Subtype" => "TrueType",
-        "BaseFont" => "Inconsolata-Regular",
+        "BaseFont" => weight.postscript_name(),
         "FontDescriptor" => font_descriptor_id,
         "Encoding" => "WinAnsiEncoding",
-        "FirstChar" => 32,
-        "LastChar" => 126,
+        "FirstChar" => FIRST_CHAR as i64,
+        "LastChar" => LAST_CHAR as i64,
         "Widths" => widths,
     };
     let font_id = doc.add_object(font_dict);
 
-    Ok((font_id, char_width))
+    Ok((
+        font_id,
+        Metrics {
+            leading,
+            widths: width_units
+                .iter()
+                .map(|&units| units as f64 / units_per_em)
+                .collect(),
+            kerning,
+        },
+    ))
+}
+
+/// The metrics of the font in the given weight, without embedding it
+/// anywhere, for laying out the slug ahead of stamping.
+pub fn metrics(weight: Weight) -> lopdf::Result<Metrics> {
+    let (_, metrics) = embed_font(&mut Document::new(), weight)?;
+    Ok(metrics)
 }
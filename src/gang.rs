@@ -0,0 +1,382 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use lopdf::content::{Content, Operation};
+use lopdf::{Document, Object, ObjectId, Stream, dictionary};
+use tracing::{debug, info};
+
+use crate::overlay::{self, Edges, MARK_GAP, MARK_LENGTH, MARK_WEIGHT};
+use crate::units;
+
+/// The A4 sheet the jobs are ganged onto, width and height in points.
+pub const SHEET: (f64, f64) = (595.0, 842.0);
+
+/// Margin left clear around the edge of the sheet, 1cm.
+const SHEET_MARGIN: f64 = 28.35;
+
+/// Distance crop marks reach beyond the trim on every side.
+const REACH: f64 = MARK_GAP + MARK_LENGTH;
+
+/// One job to be ganged: the first page of `input`, trimmed to the given
+/// size, repeated `count` times.
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub input: PathBuf,
+    pub trim_width: f64,
+    pub trim_height: f64,
+    pub count: usize,
+}
+
+impl Job {
+    /// Parse a job given on the command line as INPUT:WIDTHxHEIGHT:COUNT,
+    /// for example "cards.pdf:3.5x2in:10".
+    pub fn parse(text: &str) -> Result<Job, String> {
+        let mut parts = text.rsplitn(3, ':');
+
+        let count = parts.next().unwrap_or_default();
+        let trim = parts.next();
+        let input = parts.next();
+
+        let (Some(trim), Some(input)) = (trim, input) else {
+            return Err(format!(
+                "Expected INPUT:WIDTHxHEIGHT:COUNT (e.g. cards.pdf:3.5x2in:10), not '{}'",
+                text
+            ));
+        };
+
+        let (trim_width, trim_height) = units::parse_size(trim)?;
+        let count = match count.parse::<usize>() {
+            Ok(count) if count > 0 => count,
+            _ => {
+                return Err(format!(
+                    "Invalid count '{}'; at least one copy is needed",
+                    count
+                ));
+            }
+        };
+
+        Ok(Job {
+            input: PathBuf::from(input),
+            trim_width,
+            trim_height,
+            count,
+        })
+    }
+}
+
+/// Where one copy of a job lands: the sheet (counting from 0) and the
+/// bottom left corner of its trim.
+#[derive(Debug, Clone, Copy)]
+pub struct Placement {
+    pub job: usize,
+    pub sheet: usize,
+    pub x: f64,
+    pub y: f64,
+}
+
+/// Arrange copies of the jobs onto A4 sheets using simple shelf packing.
+///
+/// Items are sorted tallest first and laid out left to right in rows
+/// ("shelves") from the top of the sheet down, starting a new shelf when a
+/// row is full and a new sheet when the page is. Each item occupies its trim
+/// plus room for its own crop marks on every side.
+pub fn pack(jobs: &[Job]) -> Result<Vec<Placement>, String> {
+    let (sheet_width, sheet_height) = SHEET;
+
+    let mut items: Vec<usize> = jobs
+        .iter()
+        .enumerate()
+        .flat_map(|(index, job)| std::iter::repeat_n(index, job.count))
+        .collect();
+    items.sort_by(|a, b| jobs[*b].trim_height.total_cmp(&jobs[*a].trim_height));
+
+    let mut placements = Vec::with_capacity(items.len());
+
+    let mut sheet = 0;
+    let mut cursor_x = SHEET_MARGIN;
+    let mut shelf_top = sheet_height - SHEET_MARGIN;
+    let mut shelf_height: f64 = 0.0;
+
+    for index in items {
+        let job = &jobs[index];
+        let cell_width = job.trim_width + 2.0 * REACH;
+        let cell_height = job.trim_height + 2.0 * REACH;
+
+        if cell_width > sheet_width - 2.0 * SHEET_MARGIN
+            || cell_height > sheet_height - 2.0 * SHEET_MARGIN
+        {
+            return Err(format!(
+                "{} is too large to fit on the sheet with its crop marks",
+                job.input.display()
+            ));
+        }
+
+        // Start a new shelf if this item doesn't fit on the current one
+        if cursor_x + cell_width > sheet_width - SHEET_MARGIN {
+            shelf_top -= shelf_height;
+            cursor_x = SHEET_MARGIN;
+            shelf_height = 0.0;
+        }
+
+        // Start a new sheet if the shelf runs off the bottom
+        if shelf_top - cell_height < SHEET_MARGIN {
+            sheet += 1;
+            shelf_top = sheet_height - SHEET_MARGIN;
+            cursor_x = SHEET_MARGIN;
+            shelf_height = 0.0;
+        }
+
+        placements.push(Placement {
+            job: index,
+            sheet,
+            x: cursor_x + REACH,
+            y: shelf_top - cell_height + REACH,
+        });
+
+        cursor_x += cell_width;
+        shelf_height = shelf_height.max(cell_height);
+    }
+
+    Ok(placements)
+}
+
+/// Produce a cutting diagram: one line per item giving the sheet, job, and
+/// trim rectangle in millimetres from the sheet's bottom left corner.
+pub fn cutting_diagram(jobs: &[Job], placements: &[Placement]) -> String {
+    let mut diagram = String::new();
+
+    for placement in placements {
+        let job = &jobs[placement.job];
+        diagram.push_str(&format!(
+            "sheet {}: {} at {:.1},{:.1} mm, {:.1}×{:.1} mm\n",
+            placement.sheet + 1,
+            job.input.display(),
+            placement.x / units::POINTS_PER_MM,
+            placement.y / units::POINTS_PER_MM,
+            job.trim_width / units::POINTS_PER_MM,
+            job.trim_height / units::POINTS_PER_MM,
+        ));
+    }
+
+    diagram
+}
+
+/// The first page of a job's input, imported into the output document as a
+/// Form XObject.
+struct Imported {
+    form_id: ObjectId,
+    mediabox: [f64; 4],
+}
+
+/// Copy the first page of the given manuscript into the output document as
+/// a Form XObject. The source objects are renumbered above those already
+/// in the output, so that those the page uses can be moved across as they
+/// are, leaving the rest of the manuscript behind.
+fn import_first_page(output: &mut Document, path: &Path) -> lopdf::Result<Imported> {
+    let mut source = Document::load(path)?;
+    source.renumber_objects_with(output.max_id + 1);
+
+    let page_id = source
+        .page_iter()
+        .next()
+        .ok_or(lopdf::Error::PageNumberNotFound(1))?;
+
+    let content = source.get_page_content(page_id)?;
+    let mediabox = overlay::read_box(
+        overlay::inherited(&source, page_id, b"MediaBox")
+            .ok_or(lopdf::Error::PageNumberNotFound(1))?,
+    )?;
+    let resources = overlay::inherited(&source, page_id, b"Resources")
+        .cloned()
+        .unwrap_or_else(|| Object::Dictionary(dictionary! {}));
+
+    let mut used = BTreeSet::new();
+    referenced(&source, &resources, &mut used);
+    for id in used {
+        if let Some(object) = source.objects.remove(&id) {
+            output.objects.insert(id, object);
+        }
+    }
+    output.max_id = output.max_id.max(source.max_id);
+
+    let form = Stream::new(
+        dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Form",
+            "BBox" => mediabox.iter().map(|v| Object::Real(*v as f32)).collect::<Vec<_>>(),
+            "Resources" => resources,
+        },
+        content,
+    );
+
+    Ok(Imported {
+        form_id: output.add_object(form),
+        mediabox,
+    })
+}
+
+/// Add the objects an object refers to, directly or by way of others, to
+/// those found, leaving out the page tree that a page's resources may link
+/// back to.
+fn referenced(document: &Document, object: &Object, found: &mut BTreeSet<ObjectId>) {
+    match object {
+        Object::Reference(id) => {
+            if found.insert(*id)
+                && let Ok(object) = document.get_object(*id)
+            {
+                referenced(document, object, found);
+            }
+        }
+        Object::Array(items) => {
+            for item in items {
+                referenced(document, item, found);
+            }
+        }
+        Object::Dictionary(dictionary)
+        | Object::Stream(Stream {
+            dict: dictionary, ..
+        }) => {
+            for (key, value) in dictionary.iter() {
+                if key != b"Parent" {
+                    referenced(document, value, found);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Gang the given jobs onto as few A4 sheets as possible, each copy with
+/// its own crop marks, and save the result.
+///
+/// Returns the placements so that the caller can report a cutting diagram.
+pub fn impose(
+    output_path: &Path,
+    jobs: &[Job],
+) -> Result<Vec<Placement>, Box<dyn std::error::Error>> {
+    let placements = pack(jobs)?;
+    if placements.is_empty() {
+        return Err("There are no copies of any job to gang".into());
+    }
+    let sheets = placements.iter().map(|p| p.sheet + 1).max().unwrap_or(0);
+
+    info!("Ganging {} items onto {} sheets", placements.len(), sheets);
+
+    let mut document = Document::with_version("1.5");
+
+    let mut imported = Vec::with_capacity(jobs.len());
+    for job in jobs {
+        imported.push(import_first_page(&mut document, &job.input)?);
+        debug!(input = ?job.input, "Imported");
+    }
+
+    // Every sheet can refer to every job's form
+    let mut xobjects = dictionary! {};
+    for (index, form) in imported.iter().enumerate() {
+        xobjects.set(format!("J{}", index), form.form_id);
+    }
+    let xobjects_id = document.add_object(xobjects);
+
+    let pages_id = document.new_object_id();
+    let mut kids = Vec::with_capacity(sheets);
+
+    for sheet in 0..sheets {
+        let mut ops = Vec::new();
+
+        for placement in placements.iter().filter(|p| p.sheet == sheet) {
+            let job = &jobs[placement.job];
+            let form = &imported[placement.job];
+
+            // Center the page's content on its trim, allowing for a MediaBox
+            // which doesn't start at the origin
+            let [x1, y1, x2, y2] = form.mediabox;
+            let tx = placement.x - ((x2 - x1) - job.trim_width) / 2.0 - x1;
+            let ty = placement.y - ((y2 - y1) - job.trim_height) / 2.0 - y1;
+
+            ops.push(Operation::new("q", vec![]));
+            ops.push(Operation::new(
+                "cm",
+                vec![1.into(), 0.into(), 0.into(), 1.into(), tx.into(), ty.into()],
+            ));
+            ops.push(Operation::new(
+                "Do",
+                vec![Object::Name(format!("J{}", placement.job).into_bytes())],
+            ));
+            ops.push(Operation::new("Q", vec![]));
+
+            let offsets = Edges {
+                left: MARK_GAP,
+                right: MARK_GAP,
+                bottom: MARK_GAP,
+                top: MARK_GAP,
+            };
+            ops.extend(overlay::generate_crop_marks(
+                placement.x,
+                placement.y,
+                job.trim_width,
+                job.trim_height,
+                &offsets,
+                MARK_WEIGHT,
+                false,
+            ));
+        }
+
+        let content = Content { operations: ops };
+        let content_id = document.add_object(Stream::new(dictionary! {}, content.encode()?));
+
+        let page_id = document.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), SHEET.0.into(), SHEET.1.into()],
+            "Resources" => dictionary! {
+                "XObject" => xobjects_id,
+            },
+            "Contents" => content_id,
+        });
+        kids.push(Object::Reference(page_id));
+    }
+
+    document.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => kids,
+            "Count" => sheets as i64,
+        }),
+    );
+
+    let catalog_id = document.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    document.trailer.set("Root", catalog_id);
+
+    // Drop the page trees and catalogs of the imported manuscripts
+    document.prune_objects();
+    document.compress();
+
+    info!("Save output");
+    document.save(output_path)?;
+
+    Ok(placements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_job() {
+        let job = Job::parse("cards.pdf:3.5x2in:10").unwrap();
+        assert_eq!(job.input, PathBuf::from("cards.pdf"));
+        assert_eq!((job.trim_width, job.trim_height), (252.0, 144.0));
+        assert_eq!(job.count, 10);
+    }
+
+    #[test]
+    fn parse_job_without_copies() {
+        assert!(Job::parse("cards.pdf:3.5x2in:0").is_err());
+        assert!(Job::parse("cards.pdf:3.5x2in:-1").is_err());
+        assert!(Job::parse("cards.pdf:3.5x2in").is_err());
+    }
+}
@@ -0,0 +1,547 @@
+use lopdf::Object;
+use serde::Serialize;
+
+use crate::fonts::Metrics;
+use crate::line::{self, Align, Field, Placed};
+use crate::marks::{Fold, FoldAxis, RULER_DEPTH};
+use crate::overlay::{Edges, MARK_LENGTH, MarkStyle, Options};
+use crate::units::POINTS_PER_MM;
+
+/// Distance of the slug (the footer line of timestamp, filename, and page
+/// number) from the edges of the sheet, 1cm.
+pub const SLUG_MARGIN: f64 = 28.35;
+
+/// Font size of the slug text.
+pub const SLUG_SIZE: f64 = 10.0;
+
+/// Smallest font size the slug will be shrunk to when fitting it into a
+/// narrow margin.
+pub const SLUG_MIN_SIZE: f64 = 6.0;
+
+/// Font size of the large-print slug.
+pub const SLUG_LARGE_SIZE: f64 = 14.0;
+
+/// Closest the slug may come to the edge of the sheet, 5mm; most printers
+/// can't print any closer than this.
+pub const SLUG_MIN_EDGE: f64 = 14.17;
+
+/// Length of each arm of a registration target's crosshair, from its
+/// center, 5mm.
+pub const TARGET_ARM: f64 = 5.0 * POINTS_PER_MM;
+
+/// Distance from the trim of the outer line of Japanese-style crop marks
+/// on an edge with no bleed, 3mm, the usual bleed.
+pub const JAPANESE_BLEED: f64 = 3.0 * POINTS_PER_MM;
+
+/// Radius of the circle of a registration target, 3mm.
+pub const TARGET_RADIUS: f64 = 3.0 * POINTS_PER_MM;
+
+/// Vertical placement and size of the slug on the sheet.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Slug {
+    /// Height of the text baseline above the bottom of the sheet.
+    pub baseline: f64,
+    /// Font size.
+    pub size: f64,
+    /// Number of lines: the main line at the bottom, and any notes stacked
+    /// above it.
+    pub lines: usize,
+    /// Distance between baselines, as a multiple of the font size.
+    pub leading: f64,
+}
+
+impl Default for Slug {
+    fn default() -> Slug {
+        Slug {
+            baseline: SLUG_MARGIN,
+            size: SLUG_SIZE,
+            lines: 1,
+            leading: 1.2,
+        }
+    }
+}
+
+impl Slug {
+    /// A slug of the given number of lines and font size in its usual place.
+    pub fn new(lines: usize, leading: f64, size: f64) -> Slug {
+        Slug {
+            lines: lines.max(1),
+            leading,
+            size,
+            ..Slug::default()
+        }
+    }
+
+    /// Height of the band the slug's text occupies: a quarter of the font
+    /// size below the bottom baseline for descenders, the full size above
+    /// the top one, and the leading between each line.
+    pub fn height(&self) -> f64 {
+        self.size * 1.25 + (self.lines - 1) as f64 * self.leading * self.size
+    }
+
+    /// Bottom of the band the slug's text occupies.
+    pub fn bottom(&self) -> f64 {
+        self.baseline - self.size / 4.0
+    }
+
+    /// Top of the band the slug's text occupies.
+    pub fn top(&self) -> f64 {
+        self.bottom() + self.height()
+    }
+
+    /// Baseline of the given line, counting up from the main line at 0.
+    pub fn line_baseline(&self, line: usize) -> f64 {
+        self.baseline + line as f64 * self.leading * self.size
+    }
+
+    /// The same slug moved so that its top is at the given height.
+    pub fn with_top(&self, top: f64) -> Slug {
+        Slug {
+            baseline: self.baseline + top - self.top(),
+            ..*self
+        }
+    }
+
+    /// The same slug reflected to the opposite edge of a sheet of the given
+    /// height, as far from the top as it was from the bottom.
+    pub fn flipped(&self, sheet_height: f64) -> Slug {
+        self.with_top(sheet_height - self.bottom())
+    }
+
+    /// Fit this slug, at the given size, into the band between `low` and
+    /// `high`, centering it vertically and keeping clear of the edges of a
+    /// sheet of the given height.
+    pub fn fit(&self, low: f64, high: f64, size: f64, sheet_height: f64) -> Option<Slug> {
+        let low = low.max(SLUG_MIN_EDGE);
+        let high = high.min(sheet_height - SLUG_MIN_EDGE);
+        let resized = Slug { size, ..*self };
+        let height = resized.height();
+
+        if high - low < height {
+            return None;
+        }
+
+        Some(Slug {
+            baseline: low + (high - low - height) / 2.0 + size / 4.0,
+            ..resized
+        })
+    }
+}
+
+/// An axis-aligned rectangle in sheet coordinates, in points.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Rect {
+    pub x1: f64,
+    pub y1: f64,
+    pub x2: f64,
+    pub y2: f64,
+}
+
+impl Rect {
+    pub fn new(x: f64, y: f64, width: f64, height: f64) -> Rect {
+        Rect {
+            x1: x,
+            y1: y,
+            x2: x + width,
+            y2: y + height,
+        }
+    }
+
+    pub fn width(&self) -> f64 {
+        self.x2 - self.x1
+    }
+
+    pub fn height(&self) -> f64 {
+        self.y2 - self.y1
+    }
+
+    /// Whether the two rectangles overlap. Rectangles which merely touch
+    /// along an edge do not.
+    pub fn intersects(&self, other: &Rect) -> bool {
+        self.x1 < other.x2 && other.x1 < self.x2 && self.y1 < other.y2 && other.y1 < self.y2
+    }
+
+    /// Whether the other rectangle lies entirely within this one.
+    pub fn contains(&self, other: &Rect) -> bool {
+        other.x1 >= self.x1 && other.x2 <= self.x2 && other.y1 >= self.y1 && other.y2 <= self.y2
+    }
+
+    /// The rectangle as a PDF array [x1 y1 x2 y2], as used for page boxes.
+    pub fn to_array(self) -> Vec<Object> {
+        vec![
+            self.x1.into(),
+            self.y1.into(),
+            self.x2.into(),
+            self.y2.into(),
+        ]
+    }
+
+    /// Grow the rectangle outward by the given amount on each edge.
+    pub fn expand(&self, edges: &Edges) -> Rect {
+        Rect {
+            x1: self.x1 - edges.left,
+            y1: self.y1 - edges.bottom,
+            x2: self.x2 + edges.right,
+            y2: self.y2 + edges.top,
+        }
+    }
+}
+
+/// Where everything lands on the sheet for a single page.
+#[derive(Debug, Clone, Serialize)]
+pub struct Layout {
+    pub sheet: Rect,
+    pub trim: Rect,
+    pub bleed: Rect,
+    pub content: Rect,
+    pub marks: Vec<Rect>,
+}
+
+/// Compute the layout of the given page (counting from 1) whose content is
+/// of the given size.
+///
+/// The content is placed so that its trim lands on the trim area. Any
+/// excess beyond the declared bleed is split evenly, which with no declared
+/// bleed means simply centering the content.
+pub fn layout(
+    options: &Options,
+    page_num: usize,
+    content_width: f64,
+    content_height: f64,
+) -> Layout {
+    let (trim_x, trim_y) = options.trim_position();
+    let trim = Rect::new(trim_x, trim_y, options.trim_width, options.trim_height);
+
+    let bleed = options.bleed.edges(page_num);
+
+    let excess_width = content_width - options.trim_width - bleed.left - bleed.right;
+    let excess_height = content_height - options.trim_height - bleed.bottom - bleed.top;
+    let content = Rect::new(
+        trim_x - bleed.left - excess_width / 2.0,
+        trim_y - bleed.bottom - excess_height / 2.0,
+        content_width,
+        content_height,
+    );
+
+    let offsets = mark_offsets(&bleed, options.mark_gap);
+    // Rounded corners are marked on the trim itself, in place of the crop
+    // marks beyond it
+    let mut marks = Vec::new();
+    if options.corner_radius.is_none() {
+        marks.extend(mark_boxes(&trim, &offsets));
+        if options.mark_style == MarkStyle::Japanese {
+            marks.extend(outer_mark_boxes(&trim, &bleed, &offsets));
+        }
+    }
+    if options.center_marks {
+        marks.extend(center_mark_boxes(&trim, &offsets));
+    }
+    let bleed = trim.expand(&bleed);
+    marks.extend(fold_mark_boxes(
+        &trim,
+        &bleed,
+        options.mark_gap,
+        &options.folds,
+    ));
+    if options.rulers.is_some() {
+        marks.extend(ruler_boxes(options, &trim));
+    }
+    if options.registration_marks {
+        marks.extend(
+            target_centers(&bleed, options.mark_gap)
+                .iter()
+                .map(|&(x, y)| {
+                    Rect::new(
+                        x - TARGET_ARM,
+                        y - TARGET_ARM,
+                        2.0 * TARGET_ARM,
+                        2.0 * TARGET_ARM,
+                    )
+                }),
+        );
+    }
+
+    Layout {
+        sheet: Rect::new(0.0, 0.0, options.sheet_width, options.sheet_height),
+        trim,
+        bleed,
+        content,
+        marks,
+    }
+}
+
+/// Gap between each trim edge and the crop marks on that side: marks start
+/// the given gap outside the bleed so they never print into the area that
+/// will be trimmed away.
+pub fn mark_offsets(bleed: &Edges, gap: f64) -> Edges {
+    Edges {
+        left: bleed.left + gap,
+        right: bleed.right + gap,
+        bottom: bleed.bottom + gap,
+        top: bleed.top + gap,
+    }
+}
+
+/// The extent of each of the eight outer strokes of Japanese-style crop
+/// marks, beside those `mark_boxes` gives, in line with the edge of the
+/// bleed, or `JAPANESE_BLEED` beyond the trim on an edge with none.
+pub fn outer_mark_boxes(trim: &Rect, bleed: &Edges, offsets: &Edges) -> Vec<Rect> {
+    let outer = |bleed: f64| if bleed > 0.0 { bleed } else { JAPANESE_BLEED };
+    let mut boxes = Vec::with_capacity(8);
+
+    for y in [trim.y1 - outer(bleed.bottom), trim.y2 + outer(bleed.top)] {
+        // Horizontal marks to the left and right
+        boxes.push(Rect {
+            x1: trim.x1 - offsets.left - MARK_LENGTH,
+            y1: y,
+            x2: trim.x1 - offsets.left,
+            y2: y,
+        });
+        boxes.push(Rect {
+            x1: trim.x2 + offsets.right,
+            y1: y,
+            x2: trim.x2 + offsets.right + MARK_LENGTH,
+            y2: y,
+        });
+    }
+
+    for x in [trim.x1 - outer(bleed.left), trim.x2 + outer(bleed.right)] {
+        // Vertical marks below and above
+        boxes.push(Rect {
+            x1: x,
+            y1: trim.y1 - offsets.bottom - MARK_LENGTH,
+            x2: x,
+            y2: trim.y1 - offsets.bottom,
+        });
+        boxes.push(Rect {
+            x1: x,
+            y1: trim.y2 + offsets.top,
+            x2: x,
+            y2: trim.y2 + offsets.top + MARK_LENGTH,
+        });
+    }
+
+    boxes
+}
+
+/// The extent of each of the four center mark strokes, at the middle of
+/// each edge of the trim.
+pub fn center_mark_boxes(trim: &Rect, offsets: &Edges) -> Vec<Rect> {
+    let middle_x = (trim.x1 + trim.x2) / 2.0;
+    let middle_y = (trim.y1 + trim.y2) / 2.0;
+
+    vec![
+        Rect {
+            x1: middle_x,
+            y1: trim.y1 - offsets.bottom - MARK_LENGTH,
+            x2: middle_x,
+            y2: trim.y1 - offsets.bottom,
+        },
+        Rect {
+            x1: middle_x,
+            y1: trim.y2 + offsets.top,
+            x2: middle_x,
+            y2: trim.y2 + offsets.top + MARK_LENGTH,
+        },
+        Rect {
+            x1: trim.x1 - offsets.left - MARK_LENGTH,
+            y1: middle_y,
+            x2: trim.x1 - offsets.left,
+            y2: middle_y,
+        },
+        Rect {
+            x1: trim.x2 + offsets.right,
+            y1: middle_y,
+            x2: trim.x2 + offsets.right + MARK_LENGTH,
+            y2: middle_y,
+        },
+    ]
+}
+
+/// The extent of each fold mark stroke: one beyond the bleed at each end of
+/// each fold, starting the gap beyond it that the crop marks do.
+pub fn fold_mark_boxes(trim: &Rect, bleed: &Rect, gap: f64, folds: &[Fold]) -> Vec<Rect> {
+    let mut boxes = Vec::with_capacity(folds.len() * 2);
+
+    for fold in folds {
+        match fold.axis {
+            FoldAxis::Vertical => {
+                let x = trim.x1 + fold.at.offset(trim.width());
+                boxes.push(Rect {
+                    x1: x,
+                    y1: bleed.y1 - gap - MARK_LENGTH,
+                    x2: x,
+                    y2: bleed.y1 - gap,
+                });
+                boxes.push(Rect {
+                    x1: x,
+                    y1: bleed.y2 + gap,
+                    x2: x,
+                    y2: bleed.y2 + gap + MARK_LENGTH,
+                });
+            }
+            FoldAxis::Horizontal => {
+                let y = trim.y2 - fold.at.offset(trim.height());
+                boxes.push(Rect {
+                    x1: bleed.x1 - gap - MARK_LENGTH,
+                    y1: y,
+                    x2: bleed.x1 - gap,
+                    y2: y,
+                });
+                boxes.push(Rect {
+                    x1: bleed.x2 + gap,
+                    y1: y,
+                    x2: bleed.x2 + gap + MARK_LENGTH,
+                    y2: y,
+                });
+            }
+        }
+    }
+
+    boxes
+}
+
+/// The extent of the rulers, along the top and left edges of the sheet,
+/// across the trim.
+pub fn ruler_boxes(options: &Options, trim: &Rect) -> Vec<Rect> {
+    let top = options.sheet_height - SLUG_MIN_EDGE;
+
+    vec![
+        Rect {
+            x1: trim.x1,
+            y1: top - RULER_DEPTH,
+            x2: trim.x2,
+            y2: top,
+        },
+        Rect {
+            x1: SLUG_MIN_EDGE,
+            y1: trim.y1,
+            x2: SLUG_MIN_EDGE + RULER_DEPTH,
+            y2: trim.y2,
+        },
+    ]
+}
+
+/// The centers of the registration targets: one at the middle of each edge
+/// of the bleed, the gap beyond it that the crop marks are, far enough out
+/// that its crosshair clears the bleed too.
+pub fn target_centers(bleed: &Rect, gap: f64) -> [(f64, f64); 4] {
+    let middle = ((bleed.x1 + bleed.x2) / 2.0, (bleed.y1 + bleed.y2) / 2.0);
+    let offset = gap + TARGET_ARM;
+
+    [
+        (middle.0, bleed.y2 + offset),
+        (middle.0, bleed.y1 - offset),
+        (bleed.x1 - offset, middle.1),
+        (bleed.x2 + offset, middle.1),
+    ]
+}
+
+/// The extent of each of the eight crop mark strokes around the trim.
+pub fn mark_boxes(trim: &Rect, offsets: &Edges) -> Vec<Rect> {
+    let mut boxes = Vec::with_capacity(8);
+
+    for y in [trim.y1, trim.y2] {
+        // Horizontal marks to the left and right
+        boxes.push(Rect {
+            x1: trim.x1 - offsets.left - MARK_LENGTH,
+            y1: y,
+            x2: trim.x1 - offsets.left,
+            y2: y,
+        });
+        boxes.push(Rect {
+            x1: trim.x2 + offsets.right,
+            y1: y,
+            x2: trim.x2 + offsets.right + MARK_LENGTH,
+            y2: y,
+        });
+    }
+
+    for x in [trim.x1, trim.x2] {
+        // Vertical marks below and above
+        boxes.push(Rect {
+            x1: x,
+            y1: trim.y1 - offsets.bottom - MARK_LENGTH,
+            x2: x,
+            y2: trim.y1 - offsets.bottom,
+        });
+        boxes.push(Rect {
+            x1: x,
+            y1: trim.y2 + offsets.top,
+            x2: x,
+            y2: trim.y2 + offsets.top + MARK_LENGTH,
+        });
+    }
+
+    boxes
+}
+
+/// The slug laid out line by line, each as its baseline and fields. The
+/// main line has the timestamp at left, the filename centered, and the page
+/// number at right; the lines of any note are stacked above it, left
+/// aligned. The lines run across a sheet of the given width.
+pub fn slug_lines(
+    slug: &Slug,
+    sheet_width: f64,
+    metrics: &Metrics,
+    timestamp: &str,
+    filename: &str,
+    page_text: &str,
+    note: &[&str],
+) -> Vec<(f64, Vec<Placed>)> {
+    let measure = |text: &str| metrics.measure(text, slug.size);
+    let (left, right) = (SLUG_MARGIN, sheet_width - SLUG_MARGIN);
+
+    let main = [
+        Field::new(timestamp, Align::Left),
+        Field::new(filename, Align::Center),
+        Field::new(page_text, Align::Right),
+    ];
+    let mut lines = vec![(
+        slug.baseline,
+        line::layout(&main, left, right, slug.size, measure),
+    )];
+
+    for (index, text) in note.iter().rev().enumerate() {
+        let fields = [Field::new(*text, Align::Left)];
+        lines.push((
+            slug.line_baseline(index + 1),
+            line::layout(&fields, left, right, slug.size, measure),
+        ));
+    }
+
+    lines
+}
+
+/// The boxes occupied by the slug's fields.
+pub fn slug_boxes(
+    slug: &Slug,
+    sheet_width: f64,
+    metrics: &Metrics,
+    timestamp: &str,
+    filename: &str,
+    page_text: &str,
+    note: &[&str],
+) -> Vec<Rect> {
+    slug_lines(
+        slug,
+        sheet_width,
+        metrics,
+        timestamp,
+        filename,
+        page_text,
+        note,
+    )
+    .into_iter()
+    .flat_map(|(baseline, fields)| {
+        fields.into_iter().map(move |field| {
+            Rect::new(
+                field.x,
+                baseline - slug.size / 4.0,
+                field.width,
+                slug.size * 1.25,
+            )
+        })
+    })
+    .collect()
+}
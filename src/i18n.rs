@@ -0,0 +1,996 @@
+use std::env;
+use std::fmt;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Languages the command-line messages are available in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Locale {
+    English,
+    French,
+}
+
+static LOCALE: OnceLock<Locale> = OnceLock::new();
+
+impl Locale {
+    /// Parse a language given as `--lang`, either as a bare language code
+    /// or a POSIX locale name such as `fr_CA.UTF-8`.
+    pub fn parse(tag: &str) -> Result<Locale, String> {
+        let language = tag
+            .split(['_', '-', '.', '@'])
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+
+        match language.as_str() {
+            "en" | "c" | "posix" => Ok(Locale::English),
+            "fr" => Ok(Locale::French),
+            _ => Err(format!("Unsupported language {}; expected en or fr", tag)),
+        }
+    }
+
+    /// The locale the environment asks for, by the usual precedence of
+    /// LC_ALL, then LC_MESSAGES, then LANG. Anything unsupported falls back
+    /// to English.
+    fn from_env() -> Locale {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|name| env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|value| Locale::parse(&value).ok())
+            .unwrap_or(Locale::English)
+    }
+}
+
+/// Set the language for the rest of the run: the one given, or else the
+/// one from the environment. Only the first call has any effect.
+pub fn init(locale: Option<Locale>) {
+    let _ = LOCALE.set(locale.unwrap_or_else(Locale::from_env));
+}
+
+/// The language messages are being shown in.
+pub fn locale() -> Locale {
+    *LOCALE.get_or_init(Locale::from_env)
+}
+
+/// Label for an error message.
+pub fn error() -> &'static str {
+    match locale() {
+        Locale::English => "error",
+        Locale::French => "erreur",
+    }
+}
+
+/// Label for a warning message.
+pub fn warning() -> &'static str {
+    match locale() {
+        Locale::English => "warning",
+        Locale::French => "avertissement",
+    }
+}
+
+/// A number to the given number of decimal places, with the decimal
+/// separator of the language.
+pub fn decimal(value: f64, places: usize) -> String {
+    let text = format!("{:.*}", places, value);
+    match locale() {
+        Locale::English => text,
+        Locale::French => text.replace('.', ","),
+    }
+}
+
+/// A length among the options, as named in a message refusing its value.
+#[derive(Debug, Clone, Copy)]
+pub enum Length {
+    TopBleed,
+    BottomBleed,
+    InsideBleed,
+    OutsideBleed,
+    MarkGap,
+    MarkWeight,
+    CornerRadius,
+    SafetyMargin,
+    DrillSpacing,
+}
+
+impl Length {
+    fn english(self) -> &'static str {
+        match self {
+            Length::TopBleed => "top bleed",
+            Length::BottomBleed => "bottom bleed",
+            Length::InsideBleed => "inside bleed",
+            Length::OutsideBleed => "outside bleed",
+            Length::MarkGap => "mark gap",
+            Length::MarkWeight => "mark weight",
+            Length::CornerRadius => "corner radius",
+            Length::SafetyMargin => "safety margin",
+            Length::DrillSpacing => "drill spacing",
+        }
+    }
+
+    fn french(self) -> &'static str {
+        match self {
+            Length::TopBleed => "Le fond perdu en haut",
+            Length::BottomBleed => "Le fond perdu en bas",
+            Length::InsideBleed => "Le fond perdu intérieur",
+            Length::OutsideBleed => "Le fond perdu extérieur",
+            Length::MarkGap => "L'écart des traits de coupe",
+            Length::MarkWeight => "L'épaisseur des traits de coupe",
+            Length::CornerRadius => "Le rayon des coins",
+            Length::SafetyMargin => "La marge de sécurité",
+            Length::DrillSpacing => "L'espacement des trous",
+        }
+    }
+}
+
+/// An option listing pages, as named in a message refusing a page number.
+#[derive(Debug, Clone, Copy)]
+pub enum Pages {
+    Order,
+    Selection,
+    Only,
+}
+
+impl Pages {
+    fn english(self) -> &'static str {
+        match self {
+            Pages::Order => "the page order",
+            Pages::Selection => "a page selection",
+            Pages::Only => "the page to stamp alone",
+        }
+    }
+
+    fn french(self) -> &'static str {
+        match self {
+            Pages::Order => "Ordre des pages",
+            Pages::Selection => "Sélection de pages",
+            Pages::Only => "Page à tamponner seule",
+        }
+    }
+}
+
+/// Messages shown to the user by the command-line tool, rendered in the
+/// current language when displayed.
+///
+/// Preflight warnings and the explanations of their categories come from
+/// the library and are in English regardless.
+pub enum Message<'a> {
+    NoProvenance {
+        path: &'a Path,
+    },
+    ModifiedSinceStamped {
+        path: &'a Path,
+    },
+    NoProvenanceRecord {
+        path: &'a Path,
+    },
+    Differing {
+        attribute: &'a str,
+    },
+    Consistent {
+        files: usize,
+    },
+    FixtureNeedsPages,
+    InputNotFound {
+        path: &'a Path,
+    },
+    Restored {
+        pages: usize,
+    },
+    ContentUnchanged {
+        pages: usize,
+    },
+    NoProblems,
+    Warnings {
+        count: usize,
+    },
+    AlreadyStamped {
+        input: &'a Path,
+        earlier: &'a Path,
+    },
+    Resumed {
+        input: &'a Path,
+        output: &'a Path,
+    },
+    Cancelled,
+    /// The template of the progress bar shown while stamping, in
+    /// indicatif's notation.
+    ProgressBar,
+    #[cfg(feature = "self-update")]
+    UpdateNotConfigured,
+    #[cfg(feature = "self-update")]
+    UpToDate {
+        version: &'a str,
+    },
+    #[cfg(feature = "self-update")]
+    UpdateAvailable {
+        version: &'a str,
+    },
+    #[cfg(feature = "self-update")]
+    Updated {
+        from: &'a str,
+        to: &'a str,
+    },
+    #[cfg(feature = "sftp")]
+    Delivered {
+        files: &'a str,
+        bytes: u64,
+        target: &'a str,
+        attempts: u32,
+    },
+    ManuscriptNotFound,
+    OutputDirectoryMissing {
+        directory: &'a Path,
+    },
+    TrimNotPositive,
+    NotALength {
+        length: Length,
+        value: f64,
+    },
+    SheetOutOfRange {
+        max: f64,
+    },
+    TrimOffSheet {
+        trim_width: f64,
+        trim_height: f64,
+        sheet_width: f64,
+        sheet_height: f64,
+    },
+    GridTooFine {
+        spacing: f64,
+    },
+    NotAPosition {
+        x: f64,
+        y: f64,
+    },
+    FoldOutsideTrim {
+        offset: f64,
+        across: f64,
+    },
+    PagesFromOne {
+        pages: Pages,
+    },
+    BackwardRange {
+        first: usize,
+        last: usize,
+    },
+    NoHoles,
+    DrillDiameter {
+        diameter: f64,
+    },
+    NotAnOffset {
+        seconds: i32,
+    },
+    Hairline {
+        weight: f64,
+    },
+    PlacingOn {
+        sheet: &'a str,
+    },
+    TrimDoesntFit {
+        width: f64,
+        height: f64,
+        sheet: &'a str,
+    },
+    TrimFromPage {
+        trim: &'a str,
+        bleed: f64,
+    },
+    TrimFromContent {
+        trim: &'a str,
+        width: f64,
+        height: f64,
+        consistent: usize,
+        pages: usize,
+    },
+    TrimUnknown {
+        width: f64,
+        height: f64,
+    },
+    NoMatches {
+        pattern: &'a Path,
+    },
+    OutputNotDirectory {
+        output: &'a Path,
+    },
+    DuplicateName {
+        name: &'a str,
+        output: &'a Path,
+    },
+    WouldOverwrite {
+        input: &'a Path,
+        output: &'a Path,
+    },
+    NotAFile {
+        path: &'a Path,
+    },
+    BatchSummary {
+        stamped: usize,
+        total: usize,
+        skipped: usize,
+        failed: usize,
+    },
+    OutputExists {
+        output: &'a Path,
+    },
+    NeedsFiles {
+        option: &'a str,
+    },
+    ReportOverOutput,
+    Stamped {
+        output: &'a Path,
+        pages: usize,
+        usage: &'a str,
+        code: Option<&'a str>,
+    },
+    Usage {
+        sheets: usize,
+        utilization: f64,
+        cost: Option<f64>,
+    },
+    ConfigUnreadable {
+        path: &'a Path,
+        reason: &'a str,
+    },
+    UnknownSetting {
+        key: &'a str,
+        path: &'a Path,
+    },
+    BadSetting {
+        key: &'a str,
+        path: &'a Path,
+        flag: bool,
+    },
+    UnknownProfile {
+        name: &'a str,
+        available: &'a str,
+    },
+}
+
+impl fmt::Display for Message<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match locale() {
+            Locale::English => english(self, f),
+            Locale::French => french(self, f),
+        }
+    }
+}
+
+fn plural(count: usize) -> &'static str {
+    if count == 1 { "" } else { "s" }
+}
+
+/// French counts zero as singular, unlike English.
+fn pluriel(count: usize) -> &'static str {
+    if count > 1 { "s" } else { "" }
+}
+
+fn english(message: &Message, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match *message {
+        Message::NoProvenance { path } => write!(
+            f,
+            "{} has no provenance record; it wasn't stamped by cropped.",
+            path.display()
+        ),
+        Message::ModifiedSinceStamped { path } => {
+            write!(
+                f,
+                "{} has been modified since it was stamped",
+                path.display()
+            )
+        }
+        Message::NoProvenanceRecord { path } => {
+            write!(f, "{} has no provenance record", path.display())
+        }
+        Message::Differing { attribute } => write!(f, "Differing {}:", attribute),
+        Message::Consistent { files } => write!(f, "{} files consistent", files),
+        Message::FixtureNeedsPages => write!(f, "A fixture needs at least one page."),
+        Message::InputNotFound { path } => write!(f, "Input {} not found.", path.display()),
+        Message::Restored { pages } => write!(f, "Restored {} pages", pages),
+        Message::ContentUnchanged { pages } => write!(
+            f,
+            "The text and images of {} page{} are unchanged",
+            pages,
+            plural(pages)
+        ),
+        Message::NoProblems => write!(f, "No problems found"),
+        Message::Warnings { count } => {
+            write!(f, "{} warning{}", count, plural(count))
+        }
+        Message::AlreadyStamped { input, earlier } => write!(
+            f,
+            "{} was already stamped with these settings, into {}; skipping it. Give --force to stamp it again.",
+            input.display(),
+            earlier.display()
+        ),
+        Message::Resumed { input, output } => write!(
+            f,
+            "{} was already stamped into {} by the run being resumed; skipping it.",
+            input.display(),
+            output.display()
+        ),
+        Message::Cancelled => write!(f, "Cancelled; no output written."),
+        Message::ProgressBar => write!(
+            f,
+            "{{msg}} [{{wide_bar}}] {{pos}}/{{len}} pages, {{elapsed}} elapsed, about {{eta}} to go"
+        ),
+        #[cfg(feature = "self-update")]
+        Message::UpdateNotConfigured => write!(
+            f,
+            "No release site is configured; set update-url and update-key in the configuration file."
+        ),
+        #[cfg(feature = "self-update")]
+        Message::UpToDate { version } => write!(f, "cropped {} is the latest release.", version),
+        #[cfg(feature = "self-update")]
+        Message::UpdateAvailable { version } => write!(
+            f,
+            "cropped {} is available; run cropped self-update to install it.",
+            version
+        ),
+        #[cfg(feature = "self-update")]
+        Message::Updated { from, to } => write!(f, "Updated cropped from {} to {}.", from, to),
+        #[cfg(feature = "sftp")]
+        Message::Delivered {
+            files,
+            bytes,
+            target,
+            attempts,
+        } => write!(
+            f,
+            "Delivered {} ({} bytes) to {} after {} attempt(s)",
+            files, bytes, target, attempts
+        ),
+        Message::ManuscriptNotFound => write!(f, "Input manuscript PDF not found."),
+        Message::OutputDirectoryMissing { directory } => write!(
+            f,
+            "Output directory {} does not exist.",
+            directory.display()
+        ),
+        Message::TrimNotPositive => write!(
+            f,
+            "The trim width and height must both be greater than zero."
+        ),
+        Message::NotALength { length, value } => write!(
+            f,
+            "The {} must be a non-negative length, not {}",
+            length.english(),
+            value
+        ),
+        Message::SheetOutOfRange { max } => write!(
+            f,
+            "The sheet must be more than 0 and at most {} pt on each side",
+            max
+        ),
+        Message::TrimOffSheet {
+            trim_width,
+            trim_height,
+            sheet_width,
+            sheet_height,
+        } => write!(
+            f,
+            "The {} x {} pt trim doesn't fit on the {} x {} pt sheet",
+            trim_width, trim_height, sheet_width, sheet_height
+        ),
+        Message::GridTooFine { spacing } => write!(
+            f,
+            "A grid spacing of {} pt is too fine; the least is 1mm",
+            spacing
+        ),
+        Message::NotAPosition { x, y } => {
+            write!(f, "The trim origin {},{} is not a position", x, y)
+        }
+        Message::FoldOutsideTrim { offset, across } => write!(
+            f,
+            "A fold {} pt in falls outside the {} pt trim",
+            offset, across
+        ),
+        Message::PagesFromOne { pages } => {
+            write!(f, "Pages in {} are numbered from 1", pages.english())
+        }
+        Message::BackwardRange { first, last } => {
+            write!(f, "The range {}-{} runs backward", first, last)
+        }
+        Message::NoHoles => write!(f, "There must be at least one hole to drill"),
+        Message::DrillDiameter { diameter } => write!(
+            f,
+            "The drill diameter must be more than 0, not {}",
+            diameter
+        ),
+        Message::NotAnOffset { seconds } => {
+            write!(f, "{} seconds is not an offset from UTC", seconds)
+        }
+        Message::Hairline { weight } => write!(
+            f,
+            "Hairline crop marks render unpredictably across RIPs; drawing them at {}pt",
+            weight
+        ),
+        Message::PlacingOn { sheet } => write!(f, "Placing pages on {} sheets", sheet),
+        Message::TrimFromPage { trim, bleed } => {
+            write!(f, "Taking the trim to be {}, the size of the pages", trim)?;
+            if bleed > 0.0 {
+                write!(f, " less {:.1} mm bleed", bleed)?;
+            }
+            Ok(())
+        }
+        Message::TrimFromContent {
+            trim,
+            width,
+            height,
+            consistent,
+            pages,
+        } => write!(
+            f,
+            "Taking the trim to be {}, the smallest holding the content of {:.1} × {:.1} mm on {} of {} page{}",
+            trim,
+            width,
+            height,
+            consistent,
+            pages,
+            plural(pages)
+        ),
+        Message::TrimUnknown { width, height } => write!(
+            f,
+            "No standard trim fits the manuscript; taking the trim to be the size of its pages, {:.1} × {:.1} mm",
+            width, height
+        ),
+        Message::TrimDoesntFit {
+            width,
+            height,
+            sheet,
+        } => write!(
+            f,
+            "A trim of {:.1} × {:.1} mm doesn't fit on the {} sheet.",
+            width, height, sheet
+        ),
+        Message::NoMatches { pattern } => {
+            write!(f, "No manuscripts match {}.", pattern.display())
+        }
+        Message::OutputNotDirectory { output } => write!(
+            f,
+            "Stamping several manuscripts needs a directory to write them into, which {} is not.",
+            output.display()
+        ),
+        Message::DuplicateName { name, output } => write!(
+            f,
+            "More than one manuscript is named {}; they would overwrite each other in {}.",
+            name,
+            output.display()
+        ),
+        Message::WouldOverwrite { input, output } => write!(
+            f,
+            "Stamping {} into {} would overwrite the manuscript.",
+            input.display(),
+            output.display()
+        ),
+        Message::NotAFile { path } => write!(f, "{} is not a file.", path.display()),
+        Message::BatchSummary {
+            stamped,
+            total,
+            skipped,
+            failed,
+        } => write!(
+            f,
+            "Stamped {} of {} manuscripts; {} skipped, {} failed",
+            stamped, total, skipped, failed
+        ),
+        Message::OutputExists { output } => write!(
+            f,
+            "{} already exists; give --force to write over it.",
+            output.display()
+        ),
+        Message::NeedsFiles { option } => write!(
+            f,
+            "--{} needs files on disk, so can't be used with - for standard input or output.",
+            option
+        ),
+        Message::ReportOverOutput => write!(
+            f,
+            "--report - can't be used with -o -, as the PDF is written to standard output."
+        ),
+        Message::Stamped {
+            output,
+            pages,
+            usage,
+            code,
+        } => {
+            write!(
+                f,
+                "{}: {} page{}, {}",
+                output.display(),
+                pages,
+                plural(pages),
+                usage
+            )?;
+            if let Some(code) = code {
+                write!(f, ", trace code {}", code)?;
+            }
+            Ok(())
+        }
+        Message::Usage {
+            sheets,
+            utilization,
+            cost,
+        } => {
+            write!(
+                f,
+                "{} sheet{}, {:.1}% paper utilization",
+                sheets,
+                plural(sheets),
+                utilization * 100.0
+            )?;
+            if let Some(cost) = cost {
+                write!(f, ", estimated cost {:.2}", cost)?;
+            }
+            Ok(())
+        }
+        Message::ConfigUnreadable { path, reason } => {
+            write!(f, "Can't read configuration {}: {}", path.display(), reason)
+        }
+        Message::UnknownSetting { key, path } => write!(
+            f,
+            "Unknown setting {} in {}; settings are named after the options to stamp and check.",
+            key,
+            path.display()
+        ),
+        Message::BadSetting {
+            key,
+            path,
+            flag: true,
+        } => write!(f, "{} in {} should be true or false.", key, path.display()),
+        Message::BadSetting { key, path, .. } => write!(
+            f,
+            "{} in {} should be a string, a number, or a list of them.",
+            key,
+            path.display()
+        ),
+        Message::UnknownProfile { name, available } if available.is_empty() => {
+            write!(f, "No profile {}; no configuration file defines any.", name)
+        }
+        Message::UnknownProfile { name, available } => {
+            write!(f, "No profile {}; expected one of {}.", name, available)
+        }
+    }
+}
+
+fn french(message: &Message, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match *message {
+        Message::NoProvenance { path } => write!(
+            f,
+            "{} ne comporte aucune trace de provenance ; il n'a pas été produit par cropped.",
+            path.display()
+        ),
+        Message::ModifiedSinceStamped { path } => write!(
+            f,
+            "{} a été modifié depuis son passage dans cropped",
+            path.display()
+        ),
+        Message::NoProvenanceRecord { path } => {
+            write!(
+                f,
+                "{} ne comporte aucune trace de provenance",
+                path.display()
+            )
+        }
+        Message::Differing { attribute } => write!(f, "Valeurs divergentes de {} :", attribute),
+        Message::Consistent { files } => write!(
+            f,
+            "{} fichier{} cohérent{}",
+            files,
+            pluriel(files),
+            pluriel(files)
+        ),
+        Message::FixtureNeedsPages => {
+            write!(f, "Un fichier de test doit compter au moins une page.")
+        }
+        Message::InputNotFound { path } => {
+            write!(f, "Fichier d'entrée {} introuvable.", path.display())
+        }
+        Message::Restored { pages } => write!(
+            f,
+            "{} page{} restaurée{}",
+            pages,
+            pluriel(pages),
+            pluriel(pages)
+        ),
+        Message::ContentUnchanged { pages } => write!(
+            f,
+            "Le texte et les images de {} page{} sont inchangés",
+            pages,
+            pluriel(pages)
+        ),
+        Message::NoProblems => write!(f, "Aucun problème détecté"),
+        Message::Warnings { count } => {
+            write!(f, "{} avertissement{}", count, pluriel(count))
+        }
+        Message::AlreadyStamped { input, earlier } => write!(
+            f,
+            "{} a déjà été traité avec ces réglages, vers {} ; ignoré. Utilisez --force pour le traiter à nouveau.",
+            input.display(),
+            earlier.display()
+        ),
+        Message::Resumed { input, output } => write!(
+            f,
+            "{} a déjà été traité vers {} par l'exécution reprise ; ignoré.",
+            input.display(),
+            output.display()
+        ),
+        Message::Cancelled => write!(f, "Annulé ; aucun fichier écrit."),
+        Message::ProgressBar => write!(
+            f,
+            "{{msg}} [{{wide_bar}}] {{pos}}/{{len}} pages, {{elapsed}} écoulées, encore {{eta}} environ"
+        ),
+        #[cfg(feature = "self-update")]
+        Message::UpdateNotConfigured => write!(
+            f,
+            "Aucun site de publication n'est configuré ; définissez update-url et update-key dans le fichier de configuration."
+        ),
+        #[cfg(feature = "self-update")]
+        Message::UpToDate { version } => {
+            write!(f, "cropped {} est la dernière version.", version)
+        }
+        #[cfg(feature = "self-update")]
+        Message::UpdateAvailable { version } => write!(
+            f,
+            "cropped {} est disponible ; lancez cropped self-update pour l'installer.",
+            version
+        ),
+        #[cfg(feature = "self-update")]
+        Message::Updated { from, to } => {
+            write!(f, "cropped mis à jour de {} vers {}.", from, to)
+        }
+        #[cfg(feature = "sftp")]
+        Message::Delivered {
+            files,
+            bytes,
+            target,
+            attempts,
+        } => write!(
+            f,
+            "{} ({} octets) livré à {} après {} tentative(s)",
+            files, bytes, target, attempts
+        ),
+        Message::ManuscriptNotFound => write!(f, "Manuscrit PDF introuvable."),
+        Message::OutputDirectoryMissing { directory } => write!(
+            f,
+            "Le dossier de sortie {} n'existe pas.",
+            directory.display()
+        ),
+        Message::TrimNotPositive => write!(
+            f,
+            "La largeur et la hauteur du format rogné doivent être supérieures à zéro."
+        ),
+        Message::NotALength { length, value } => write!(
+            f,
+            "{} doit être une longueur positive ou nulle, et non {}",
+            length.french(),
+            decimal(value, 2)
+        ),
+        Message::SheetOutOfRange { max } => write!(
+            f,
+            "La feuille doit mesurer plus de 0 et au plus {} pt de chaque côté",
+            decimal(max, 0)
+        ),
+        Message::TrimOffSheet {
+            trim_width,
+            trim_height,
+            sheet_width,
+            sheet_height,
+        } => write!(
+            f,
+            "Le format rogné de {} x {} pt ne tient pas sur la feuille de {} x {} pt",
+            decimal(trim_width, 1),
+            decimal(trim_height, 1),
+            decimal(sheet_width, 1),
+            decimal(sheet_height, 1)
+        ),
+        Message::GridTooFine { spacing } => write!(
+            f,
+            "Un espacement de grille de {} pt est trop fin ; le minimum est 1 mm",
+            decimal(spacing, 2)
+        ),
+        Message::NotAPosition { x, y } => write!(
+            f,
+            "L'origine du format rogné {} ; {} n'est pas une position",
+            decimal(x, 1),
+            decimal(y, 1)
+        ),
+        Message::FoldOutsideTrim { offset, across } => write!(
+            f,
+            "Un pli à {} pt tombe hors du format rogné de {} pt",
+            decimal(offset, 1),
+            decimal(across, 1)
+        ),
+        Message::PagesFromOne { pages } => write!(
+            f,
+            "{} : les pages sont numérotées à partir de 1",
+            pages.french()
+        ),
+        Message::BackwardRange { first, last } => {
+            write!(f, "La plage {}-{} est à l'envers", first, last)
+        }
+        Message::NoHoles => write!(f, "Il faut au moins un trou à percer"),
+        Message::DrillDiameter { diameter } => write!(
+            f,
+            "Le diamètre de perçage doit être supérieur à 0, et non {}",
+            decimal(diameter, 2)
+        ),
+        Message::NotAnOffset { seconds } => write!(
+            f,
+            "{} secondes n'est pas un décalage par rapport à UTC",
+            seconds
+        ),
+        Message::Hairline { weight } => write!(
+            f,
+            "Les traits de coupe très fins s'impriment de façon imprévisible selon les RIP ; ils sont tracés à {} pt",
+            decimal(weight, 2)
+        ),
+        Message::PlacingOn { sheet } => write!(f, "Pages placées sur des feuilles {}", sheet),
+        Message::TrimFromPage { trim, bleed } => {
+            write!(f, "Format rogné retenu : {}, celui des pages", trim)?;
+            if bleed > 0.0 {
+                write!(f, " moins {} mm de fond perdu", decimal(bleed, 1))?;
+            }
+            Ok(())
+        }
+        Message::TrimFromContent {
+            trim,
+            width,
+            height,
+            consistent,
+            pages,
+        } => write!(
+            f,
+            "Format rogné retenu : {}, le plus petit contenant le contenu de {} × {} mm sur {} page{} sur {}",
+            trim,
+            decimal(width, 1),
+            decimal(height, 1),
+            consistent,
+            pluriel(consistent),
+            pages
+        ),
+        Message::TrimUnknown { width, height } => write!(
+            f,
+            "Aucun format rogné standard ne convient ; format retenu : celui des pages, {} × {} mm",
+            decimal(width, 1),
+            decimal(height, 1)
+        ),
+        Message::TrimDoesntFit {
+            width,
+            height,
+            sheet,
+        } => write!(
+            f,
+            "Un format rogné de {} × {} mm ne tient pas sur une feuille {}.",
+            decimal(width, 1),
+            decimal(height, 1),
+            sheet
+        ),
+        Message::NoMatches { pattern } => {
+            write!(f, "Aucun manuscrit ne correspond à {}.", pattern.display())
+        }
+        Message::OutputNotDirectory { output } => write!(
+            f,
+            "Pour traiter plusieurs manuscrits, il faut un dossier où les écrire, ce que {} n'est pas.",
+            output.display()
+        ),
+        Message::DuplicateName { name, output } => write!(
+            f,
+            "Plusieurs manuscrits s'appellent {} ; ils s'écraseraient dans {}.",
+            name,
+            output.display()
+        ),
+        Message::WouldOverwrite { input, output } => write!(
+            f,
+            "Traiter {} vers {} écraserait le manuscrit.",
+            input.display(),
+            output.display()
+        ),
+        Message::NotAFile { path } => write!(f, "{} n'est pas un fichier.", path.display()),
+        Message::BatchSummary {
+            stamped,
+            total,
+            skipped,
+            failed,
+        } => write!(
+            f,
+            "{} manuscrit{} sur {} traité{} ; {} ignoré{}, {} en échec",
+            stamped,
+            pluriel(stamped),
+            total,
+            pluriel(stamped),
+            skipped,
+            pluriel(skipped),
+            failed
+        ),
+        Message::OutputExists { output } => write!(
+            f,
+            "{} existe déjà ; utilisez --force pour l'écraser.",
+            output.display()
+        ),
+        Message::NeedsFiles { option } => write!(
+            f,
+            "--{} a besoin de fichiers sur disque et ne peut pas servir avec - pour l'entrée ou la sortie standard.",
+            option
+        ),
+        Message::ReportOverOutput => write!(
+            f,
+            "--report - ne peut pas servir avec -o -, car le PDF est écrit sur la sortie standard."
+        ),
+        Message::Stamped {
+            output,
+            pages,
+            usage,
+            code,
+        } => {
+            write!(
+                f,
+                "{} : {} page{}, {}",
+                output.display(),
+                pages,
+                pluriel(pages),
+                usage
+            )?;
+            if let Some(code) = code {
+                write!(f, ", code de traçage {}", code)?;
+            }
+            Ok(())
+        }
+        Message::Usage {
+            sheets,
+            utilization,
+            cost,
+        } => {
+            write!(
+                f,
+                "{} feuille{}, {} % d'utilisation du papier",
+                sheets,
+                pluriel(sheets),
+                decimal(utilization * 100.0, 1)
+            )?;
+            if let Some(cost) = cost {
+                write!(f, ", coût estimé {}", decimal(cost, 2))?;
+            }
+            Ok(())
+        }
+        Message::ConfigUnreadable { path, reason } => write!(
+            f,
+            "Impossible de lire la configuration {} : {}",
+            path.display(),
+            reason
+        ),
+        Message::UnknownSetting { key, path } => write!(
+            f,
+            "Réglage inconnu {} dans {} ; les réglages portent le nom des options de stamp et check.",
+            key,
+            path.display()
+        ),
+        Message::BadSetting {
+            key,
+            path,
+            flag: true,
+        } => write!(
+            f,
+            "{} dans {} doit valoir true ou false.",
+            key,
+            path.display()
+        ),
+        Message::BadSetting { key, path, .. } => write!(
+            f,
+            "{} dans {} doit être une chaîne, un nombre ou une liste de ceux-ci.",
+            key,
+            path.display()
+        ),
+        Message::UnknownProfile { name, available } if available.is_empty() => write!(
+            f,
+            "Aucun profil {} ; aucun fichier de configuration n'en définit.",
+            name
+        ),
+        Message::UnknownProfile { name, available } => {
+            write!(
+                f,
+                "Aucun profil {} ; profils possibles : {}.",
+                name, available
+            )
+        }
+    }
+}
@@ -0,0 +1,133 @@
+/// Where a field sits on its line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// A piece of text to be placed on a line.
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub text: String,
+    pub align: Align,
+}
+
+impl Field {
+    pub fn new(text: impl Into<String>, align: Align) -> Field {
+        Field {
+            text: text.into(),
+            align,
+        }
+    }
+}
+
+/// A field as placed: its text, possibly truncated, and the x position and
+/// width it occupies.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Placed {
+    pub text: String,
+    pub x: f64,
+    pub width: f64,
+}
+
+/// Minimum space kept between neighbouring fields, in multiples of the
+/// font size.
+const GAP: f64 = 1.0;
+
+/// What is put in place of the end of text which doesn't fit. The embedded
+/// font only covers printable ASCII, so not a true ellipsis.
+const ELLIPSIS: &str = "...";
+
+/// Lay out fields along a line running from `left` to `right`.
+///
+/// Right aligned fields are placed first, from the right end inward, then
+/// left aligned fields from the left end, and finally centered fields in
+/// whatever space remains between them, as near to the center as they can
+/// get. Right aligned fields (such as the page number) therefore always
+/// appear in full; anything which would otherwise collide is truncated with
+/// an ellipsis, and dropped altogether if there's no room even for that.
+///
+/// `measure` gives the width of a string at the font size in use.
+pub fn layout(
+    fields: &[Field],
+    left: f64,
+    right: f64,
+    gap_size: f64,
+    measure: impl Fn(&str) -> f64,
+) -> Vec<Placed> {
+    let gap = GAP * gap_size;
+    let mut placed = Vec::new();
+
+    // Right aligned, from the right end leftward
+    let mut right_edge = right;
+    for field in fields.iter().filter(|f| f.align == Align::Right) {
+        let width = measure(&field.text);
+        right_edge -= width;
+        placed.push(Placed {
+            text: field.text.clone(),
+            x: right_edge,
+            width,
+        });
+        right_edge -= gap;
+    }
+
+    // Left aligned, from the left end rightward, up to the right fields
+    let mut left_edge = left;
+    for field in fields.iter().filter(|f| f.align == Align::Left) {
+        let Some((text, width)) = fit(&field.text, right_edge - left_edge, &measure) else {
+            break;
+        };
+        placed.push(Placed {
+            text,
+            x: left_edge,
+            width,
+        });
+        left_edge += width + gap;
+    }
+
+    // Centered, in the space remaining, shifting off center if need be
+    let centered: Vec<&Field> = fields.iter().filter(|f| f.align == Align::Center).collect();
+    if !centered.is_empty() {
+        let available = right_edge - left_edge;
+        let share = (available - gap * (centered.len() - 1) as f64) / centered.len() as f64;
+
+        let fitted: Vec<(String, f64)> = centered
+            .iter()
+            .filter_map(|field| fit(&field.text, share, &measure))
+            .collect();
+        let total = fitted.iter().map(|(_, width)| width).sum::<f64>()
+            + gap * fitted.len().saturating_sub(1) as f64;
+
+        let ideal = (left + right - total) / 2.0;
+        let mut x = ideal.clamp(left_edge, (right_edge - total).max(left_edge));
+        for (text, width) in fitted {
+            placed.push(Placed { text, x, width });
+            x += width + gap;
+        }
+    }
+
+    placed
+}
+
+/// Fit text into the given width, truncating it with an ellipsis if it's too
+/// long. Returns None if not even the ellipsis fits.
+fn fit(text: &str, available: f64, measure: &impl Fn(&str) -> f64) -> Option<(String, f64)> {
+    let width = measure(text);
+    if width <= available {
+        return Some((text.to_string(), width));
+    }
+
+    for end in (0..text.len())
+        .rev()
+        .filter(|end| text.is_char_boundary(*end))
+    {
+        let truncated = format!("{}{}", &text[..end], ELLIPSIS);
+        let width = measure(&truncated);
+        if width <= available {
+            return Some((truncated, width));
+        }
+    }
+
+    None
+}
@@ -0,0 +1,81 @@
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use tracing::info;
+
+/// An advisory lock on a file about to be written, held until dropped, so
+/// that runs sharing an output directory, such as CI jobs or hot folder
+/// workers, take turns at each proof and manifest rather than interleaving
+/// their writes.
+///
+/// The lock is taken on a hidden `.name.lock` file alongside, not on the
+/// file itself, which is replaced rather than written in place. The
+/// operating system releases it if the process dies, so a crashed run never
+/// leaves a stale lock behind; the empty lock file itself is left in place,
+/// as removing it could let two runs each lock a different one.
+pub struct Lock {
+    _file: File,
+}
+
+/// The lock file guarding the given file.
+pub fn lock_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    path.with_file_name(format!(".{}.lock", name))
+}
+
+/// Lock the given file against other runs, waiting for whichever holds it
+/// to finish first.
+pub fn acquire(path: &Path) -> io::Result<Lock> {
+    let lock_path = lock_path(path);
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)?;
+
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(fs::TryLockError::WouldBlock) => {
+            info!("Waiting for another run writing {}", path.display());
+            file.lock()?;
+        }
+        Err(fs::TryLockError::Error(e)) => return Err(e),
+    }
+
+    Ok(Lock { _file: file })
+}
+
+/// What a file looked like at some moment, so that a change by a writer
+/// which didn't take the lock can be noticed before being written over.
+#[derive(Debug, PartialEq)]
+pub struct Snapshot(Option<(u64, Option<SystemTime>)>);
+
+impl Snapshot {
+    /// The file's size and modification time as they are now, or its
+    /// absence.
+    pub fn of(path: &Path) -> Snapshot {
+        Snapshot(
+            fs::metadata(path)
+                .ok()
+                .map(|metadata| (metadata.len(), metadata.modified().ok())),
+        )
+    }
+
+    /// Fail if the file has been created, changed, or removed since.
+    pub fn check(&self, path: &Path) -> io::Result<()> {
+        if Snapshot::of(path) == *self {
+            return Ok(());
+        }
+
+        Err(io::Error::other(format!(
+            "{} was changed by another process while being written; not overwriting it",
+            path.display()
+        )))
+    }
+}
@@ -0,0 +1,158 @@
+use std::ffi::OsString;
+use std::fs::OpenOptions;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use clap::ArgMatches;
+use tracing::info;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{Layer, Registry};
+
+use crate::exit::{self, Failure};
+use crate::{VERSION, checksums, config};
+
+/// Target of the header logged at the start of each run, which goes to the
+/// log file but not the terminal.
+const HEADER: &str = "cropped::header";
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Start logging. The log goes to standard error, leaving standard output
+/// free for a PDF written to a pipeline, as much of it as -v and -q ask for;
+/// with --log-file it's appended to that file too, in full down to debug
+/// detail, whatever the terminal gets.
+pub fn init(args: &[OsString]) {
+    let json = config::given(args, "log-format").as_deref() == Some("json");
+    let layer = |writer: BoxMakeWriter, ansi: bool| -> BoxedLayer {
+        let layer = tracing_subscriber::fmt::layer()
+            .with_writer(writer)
+            .with_ansi(ansi);
+        if json {
+            layer.json().with_current_span(true).boxed()
+        } else {
+            layer.boxed()
+        }
+    };
+
+    let level = verbosity(args);
+    let terminal = Targets::new()
+        .with_default(level)
+        .with_target(HEADER, LevelFilter::OFF);
+    let mut layers = vec![
+        layer(BoxMakeWriter::new(std::io::stderr), true)
+            .with_filter(terminal)
+            .boxed(),
+    ];
+
+    if let Some(path) = config::given(args, "log-file") {
+        let file = match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(file) => file,
+            Err(e) => exit::fail(
+                Failure::Output,
+                format!("Can't open the log file {}: {}", path, e),
+            ),
+        };
+        layers.push(
+            layer(BoxMakeWriter::new(Arc::new(file)), false)
+                .with_filter(level.max(LevelFilter::DEBUG))
+                .boxed(),
+        );
+    }
+
+    tracing_subscriber::registry().with(layers).init();
+}
+
+/// Log the header of this run to the log file, if there is one: cropped's
+/// version, the arguments as the configuration files left them, and the
+/// checksum of each input they name, so that a log can be matched to the
+/// files which produced it.
+pub fn header(args: &[OsString], matches: &ArgMatches) {
+    if matches.get_one::<PathBuf>("log-file").is_none() {
+        return;
+    }
+
+    let line: Vec<String> = args
+        .iter()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    info!(
+        target: HEADER,
+        version = VERSION,
+        "cropped {} run as: {}",
+        VERSION,
+        line.join(" ")
+    );
+
+    let Some((_, sub)) = matches.subcommand() else {
+        return;
+    };
+    for id in sub.ids() {
+        // Anything written is output, even if it was there before the run
+        if id.as_str() == "output" {
+            continue;
+        }
+        let Ok(Some(values)) = sub.try_get_raw(id.as_str()) else {
+            continue;
+        };
+        for path in values.map(PathBuf::from).filter(|path| path.is_file()) {
+            match checksums::sha256_file(&path) {
+                Ok(digest) => {
+                    info!(
+                        target: HEADER,
+                        input = %path.display(),
+                        sha256 = %digest,
+                        "Input {}",
+                        path.display()
+                    );
+                }
+                Err(e) => {
+                    info!(
+                        target: HEADER,
+                        input = %path.display(),
+                        "Input {} unreadable: {}",
+                        path.display(),
+                        e
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// How much to log, from -v and -q. These are picked out of the arguments
+/// before they're parsed, so that reading the configuration files, which
+/// parsing depends on, can be logged too.
+fn verbosity(args: &[OsString]) -> LevelFilter {
+    let mut verbose = 0;
+    let mut quiet = false;
+
+    for arg in args.iter().skip(1) {
+        let arg = arg.to_string_lossy();
+        match arg.as_ref() {
+            "--" => break,
+            "--verbose" => verbose += 1,
+            "--quiet" => quiet = true,
+            flags
+                if flags.starts_with('-')
+                    && !flags.starts_with("--")
+                    && flags.len() > 1
+                    && flags[1..].chars().all(|c| c == 'v' || c == 'q') =>
+            {
+                verbose += flags.matches('v').count();
+                quiet |= flags.contains('q');
+            }
+            _ => {}
+        }
+    }
+
+    match (quiet, verbose) {
+        (true, _) => LevelFilter::ERROR,
+        (false, 0) => LevelFilter::WARN,
+        (false, 1) => LevelFilter::INFO,
+        (false, 2) => LevelFilter::DEBUG,
+        (false, _) => LevelFilter::TRACE,
+    }
+}
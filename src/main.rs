@@ -1,21 +1,146 @@
-use clap::{Arg, ArgAction, Command, value_parser};
+use clap::{Arg, ArgAction, ArgMatches, Command, value_parser};
+use exit::Failure;
+use i18n::Message;
+use indicatif::{ProgressBar, ProgressStyle};
 use owo_colors::OwoColorize;
-use std::path::PathBuf;
-use tracing::{debug, info};
-use tracing_subscriber;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use tracing::{debug, info, info_span};
 
+mod analysis;
+mod batch;
+mod bundle;
+mod chapters;
+mod checksums;
+mod completions;
+mod config;
+#[cfg(feature = "sftp")]
+mod deliver;
+mod exit;
+mod fixture;
 mod fonts;
+mod gang;
+mod geometry;
+mod i18n;
+mod line;
+mod lock;
+mod logging;
+mod marks;
 mod overlay;
+mod pair;
+mod plugins;
+mod preflight;
+mod provenance;
+mod report;
+mod rpc;
+mod rules;
+mod spool;
+mod strip;
+mod template;
+mod trace;
+mod trim;
+mod units;
+#[cfg(feature = "self-update")]
+mod update;
+mod usage;
+mod verify;
 
 const VERSION: &str = concat!("v", env!("CARGO_PKG_VERSION"));
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<OsString> = std::env::args_os().collect();
+
     // Initialize the logging subsystem
-    tracing_subscriber::fmt::init();
+    logging::init(&args);
+
+    // The stamping subcommand, which has more options than the rest
+    let stamp = Command::new("stamp")
+        .about("Place each page of a manuscript onto an A4 sheet, with crop marks and a slug")
+        .args_override_self(true)
+        .arg(output_arg().help("Path for the output PDF, - for standard output, or a directory to write several into. May be a template such as {title}-{trim}-{date}.pdf, filled in for each manuscript from its metadata and the settings."))
+        .args(placement_args())
+        .mut_arg("manuscript", |arg| {
+            arg.num_args(1..)
+                .help("Manuscript PDFs to be placed into the template, a glob such as chapters/*.pdf, or - for standard input. Given several, the output is the directory to write them into.")
+        })
+        .arg(cost_per_sheet_arg())
+        .arg(
+            Arg::new("producer")
+                .long("producer")
+                .value_name("TEXT")
+                .help("Producer to name in the output's metadata, in place of cropped and its version. The manuscript's own producer is kept regardless."),
+        )
+        .arg(
+            Arg::new("write-checksums")
+                .long("write-checksums")
+                .action(ArgAction::SetTrue)
+                .help("Write a .sha256 sidecar for the output and a manifest.json alongside it."),
+        )
+        .arg(
+            Arg::new("report")
+                .long("report")
+                .value_name("PATH")
+                .value_parser(value_parser!(PathBuf))
+                .help("Write a JSON report of the run to this file, or - for standard output: for each document, its input's checksum, pages, trim and sheet, output, warnings, and the time taken, for build systems."),
+        )
+        .arg(
+            Arg::new("resume")
+                .long("resume")
+                .value_name("FILE")
+                .value_parser(value_parser!(PathBuf))
+                .help("Record each manuscript in this file as it's stamped, and pass over those it records, so that a batch which was interrupted or failed partway picks up where it left off when run again. The file is removed once the whole batch has gone through."),
+        )
+        .arg(
+            Arg::new("force")
+                .long("force")
+                .action(ArgAction::SetTrue)
+                .help("Stamp the manuscript even if the manifest shows it was already stamped with the same settings, or the output already exists."),
+        )
+        .arg(
+            Arg::new("trace-code")
+                .long("trace-code")
+                .value_name("CODE")
+                .help("Print a code after the page number in the footer, and record it in the provenance and manifest, so that a leaked proof can be traced. With auto, a short unique code is generated for each output."),
+        )
+        .arg(
+            Arg::new("trace-seed")
+                .long("trace-seed")
+                .value_name("SEED")
+                .requires("trace-code")
+                .help("Derive automatic trace codes from this seed and each output's filename, so that running again gives the same codes."),
+        )
+        .arg(
+            Arg::new("bundle")
+                .long("bundle")
+                .value_name("ZIP")
+                .value_parser(value_parser!(PathBuf))
+                .help("Package the output, any checksums, and the report into a zip archive for delivery."),
+        )
+        .arg(
+            Arg::new("vendor")
+                .long("vendor")
+                .value_name("FILE")
+                .value_parser(value_parser!(PathBuf))
+                .requires("bundle")
+                .help("JSON file of the vendor's layout for the bundle: the folder within it for the PDFs, the checksums, and the report."),
+        );
+
+    #[cfg(feature = "sftp")]
+    let stamp = stamp.arg(
+        Arg::new("deliver")
+            .long("deliver")
+            .value_name("URL")
+            .help("Upload the finished output to an sftp:// drop box after a successful run."),
+    );
 
     // Configure command-line argument parser
-    let matches = Command::new("cropped")
+    let command = Command::new("cropped")
         .version(VERSION)
         .propagate_version(true)
         .author("Andrew Cowie")
@@ -23,6 +148,269 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .disable_help_subcommand(true)
         .disable_help_flag(true)
         .disable_version_flag(true)
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .subcommand(stamp)
+        .subcommand(
+            Command::new("check")
+                .about("Check a manuscript for problems without stamping it")
+                .args_override_self(true)
+                .args(placement_args()),
+        )
+        .subcommand(
+            Command::new("check-pair")
+                .about("Check that a cover fits its interior, in spine width and trim, and check each")
+                .args_override_self(true)
+                .arg(
+                    Arg::new("cover")
+                        .value_name("COVER")
+                        .value_parser(value_parser!(PathBuf))
+                        .index(1)
+                        .required(true)
+                        .help("The cover PDF: back, spine, and front together on one page, with bleed."),
+                )
+                .args(placement_args())
+                .mut_arg("manuscript", |arg| {
+                    arg.index(2)
+                        .value_name("INTERIOR")
+                        .help("The interior PDF the cover is for, checked with the options as check would.")
+                })
+                .arg(
+                    Arg::new("paper")
+                        .long("paper")
+                        .value_name("PAPER")
+                        .value_parser(pair::parse_paper)
+                        .default_value("white")
+                        .help("Paper the interior is printed on, for the spine width: white, cream, or color, or the thickness per page, such as 0.0025in."),
+                )
+                .arg(
+                    Arg::new("cover-bleed")
+                        .long("cover-bleed")
+                        .value_name("LENGTH")
+                        .value_parser(units::parse_length)
+                        .default_value("0.125in")
+                        .help("Bleed the cover carries on every edge."),
+                )
+                .arg(
+                    Arg::new("cover-rules")
+                        .long("cover-rules")
+                        .value_name("FILE")
+                        .value_parser(value_parser!(PathBuf))
+                        .help("JSON file of the vendor's preflight rules for covers, applied to the cover as --rules is to the interior."),
+                ),
+        )
+        .subcommand(
+            Command::new("info")
+                .visible_alias("inspect")
+                .about("Show how a stamped PDF was produced, and whether it has changed since")
+                .arg(
+                    Arg::new("file")
+                        .value_name("FILE")
+                        .value_parser(value_parser!(PathBuf))
+                        .help("Stamped PDF to inspect.")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("audit")
+                .about("Check that a set of stamped PDFs were all produced the same way")
+                .arg(
+                    Arg::new("files")
+                        .value_name("FILE")
+                        .value_parser(value_parser!(PathBuf))
+                        .help("Stamped PDFs making up the print run.")
+                        .num_args(1..)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("explain")
+                .about("Explain the causes of, and fixes for, a warning")
+                .arg(
+                    Arg::new("code")
+                        .value_name("CODE")
+                        .value_parser(preflight::Category::parse)
+                        .help("Code of the warning, e.g. CR001, or its name, e.g. missing-bleed.")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("impose")
+                .about("Gang copies of the first page of several jobs onto shared sheets")
+                .arg(output_arg())
+                .arg(cost_per_sheet_arg())
+                .arg(
+                    Arg::new("jobs")
+                        .value_name("INPUT:WIDTHxHEIGHT:COUNT")
+                        .value_parser(gang::Job::parse)
+                        .num_args(1..)
+                        .required(true)
+                        .help("Gang copies of the first page of several jobs onto shared sheets, Jobs are given as input, trim size, and number of copies, e.g. cards.pdf:3.5x2in:10."),
+                ),
+        )
+        .subcommand(
+            Command::new("strip")
+                .about("Remove the stamp from a PDF stamped by cropped, restoring the manuscript's pages")
+                .arg(output_arg())
+                .arg(
+                    Arg::new("file")
+                        .value_name("FILE")
+                        .value_parser(value_parser!(PathBuf))
+                        .help("Stamped PDF to strip.")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Check that stamping left a manuscript's content alone, comparing the text and images of each page with the stamped PDF's")
+                .arg(
+                    Arg::new("manuscript")
+                        .value_name("MANUSCRIPT")
+                        .value_parser(value_parser!(PathBuf))
+                        .help("The manuscript as it was stamped.")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("file")
+                        .value_name("FILE")
+                        .value_parser(value_parser!(PathBuf))
+                        .help("The PDF stamped from it.")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("rpc").about(
+                "Serve JSON-RPC over stdin and stdout, one message per line, for GUIs and editor plugins",
+            ),
+        )
+        .subcommand(
+            Command::new("calibration")
+                .about("Generate an A4 calibration target to check the printer isn't scaling")
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_name("OUTPUT")
+                        .value_parser(value_parser!(PathBuf))
+                        .help("Path for the calibration PDF.")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("completions")
+                .about("Print a script completing cropped's subcommands and options for a shell")
+                .arg(
+                    Arg::new("shell")
+                        .value_name("SHELL")
+                        .value_parser(["bash", "zsh", "fish", "powershell"])
+                        .required(true)
+                        .help("Shell to complete for: bash, zsh, fish, or powershell."),
+                ),
+        )
+        .subcommand(
+            Command::new("manpage")
+                .about("Print a man page for cropped, in roff, for packagers to install")
+                .hide(true)
+                .arg(
+                    Arg::new("directory")
+                        .value_name("DIRECTORY")
+                        .value_parser(value_parser!(PathBuf))
+                        .help("Write a page for cropped and one for each subcommand into this directory, rather than the one page to standard output."),
+                ),
+        )
+        .subcommand(
+            Command::new("gen-fixture")
+                .about("Generate a simple test manuscript with color bars running into the bleed")
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_name("OUTPUT")
+                        .value_parser(value_parser!(PathBuf))
+                        .help("Path for the fixture PDF.")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("pages")
+                        .long("pages")
+                        .value_name("COUNT")
+                        .value_parser(value_parser!(usize))
+                        .default_value("8")
+                        .help("Number of pages."),
+                )
+                .arg(
+                    Arg::new("size")
+                        .long("size")
+                        .value_name("WIDTHxHEIGHT")
+                        .value_parser(units::parse_size)
+                        .default_value("6x9in")
+                        .help("Trim size, e.g. 6x9in or 148x210mm."),
+                )
+                .arg(
+                    Arg::new("bleed")
+                        .long("bleed")
+                        .value_name("LENGTH")
+                        .value_parser(units::parse_length)
+                        .default_value("3mm")
+                        .help("Bleed beyond the trim on every edge."),
+                ),
+        )
+        .arg(
+            Arg::new("lang")
+                .long("lang")
+                .value_name("LANG")
+                .value_parser(i18n::Locale::parse)
+                .global(true)
+                .help("Language for messages and reports: en or fr. Defaults to the one in LANG."),
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_name("FILE")
+                .value_parser(value_parser!(PathBuf))
+                .global(true)
+                .help("Read defaults for the options to stamp and check from this file, in place of cropped.toml and ~/.config/cropped/config.toml."),
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .value_name("NAME")
+                .global(true)
+                .help("Use the settings of this profile from the configuration files, such as one for a particular printer."),
+        )
+        .arg(
+            Arg::new("log-format")
+                .long("log-format")
+                .value_name("FORMAT")
+                .value_parser(["text", "json"])
+                .default_value("text")
+                .global(true)
+                .help("Format of the log: text, or json for one object per line, with the document, page, and operation as fields, for log aggregation."),
+        )
+        .arg(
+            Arg::new("log-file")
+                .long("log-file")
+                .value_name("PATH")
+                .value_parser(value_parser!(PathBuf))
+                .global(true)
+                .help("Also append the log to this file, with everything down to debug detail and a header naming the version, arguments, and checksums of the inputs, for troubleshooting later."),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .action(ArgAction::Count)
+                .global(true)
+                .help("Log what's being done: -v for each step, -vv for each page as well."),
+        )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .action(ArgAction::SetTrue)
+                .global(true)
+                .help("Print nothing but warnings and errors."),
+        )
         .arg(
             Arg::new("help")
                 .long("help")
@@ -38,73 +426,1421 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .global(true)
                 .hide(true)
                 .action(ArgAction::Version),
-        )
-        .arg(
-            Arg::new("output")
-                .short('o')
-                .long("output")
-                .value_name("OUTPUT")
-                .value_parser(value_parser!(PathBuf))
-                .help("Path for the output PDF.")
-                .required(true),
-        )
-        .arg(
-            Arg::new("size")
-                .short('s')
-                .long("size")
-                .value_name("SIZE")
-                .help("Trim size of the input manuscript.")
-                .default_value("trade"),
-        )
-        .arg(
-            Arg::new("manuscript")
-                .value_name("INPUT")
-                .value_parser(value_parser!(PathBuf))
-                .help("Path to the input manuscript PDF to be placed into the template."),
-        )
-        .get_matches();
+        );
+
+    #[cfg(feature = "self-update")]
+    let command = command.subcommand(
+        Command::new("self-update")
+            .about("Download the latest release, verify its signature, and install it in place of this one")
+            .arg(
+                Arg::new("check")
+                    .long("check")
+                    .action(ArgAction::SetTrue)
+                    .help("Only say whether a newer release is available, without installing it."),
+            ),
+    );
+
+    // Defaults for the options from configuration files, put in ahead of
+    // those given so that those given win
+    let placement = placement_args();
+    let names: Vec<&str> = placement
+        .iter()
+        .filter(|arg| !arg.is_hide_set())
+        .filter_map(Arg::get_long)
+        .collect();
+    let configured = config::load(&args, &names).and_then(|config| {
+        config.configure_fonts();
+        let args = config.apply(&command, args)?;
+        Ok((config, args))
+    });
+    let (config, args) = match configured {
+        Ok(configured) => configured,
+        Err(message) => {
+            exit::fail(Failure::Usage, message);
+        }
+    };
+
+    let matches = command.clone().get_matches_from(&args);
+
+    i18n::init(matches.get_one::<i18n::Locale>("lang").copied());
+    logging::header(&args, &matches);
 
     info!("cropped application started");
 
+    #[cfg(feature = "self-update")]
+    notify_update(&config, &matches);
+
     //
     // Extract command-line arguments
     //
 
+    if let Some(("completions", sub)) = matches.subcommand() {
+        let shell = match sub.get_one::<String>("shell").unwrap().as_str() {
+            "zsh" => clap_complete::Shell::Zsh,
+            "fish" => clap_complete::Shell::Fish,
+            "powershell" => clap_complete::Shell::PowerShell,
+            _ => clap_complete::Shell::Bash,
+        };
+        completions::generate(command, shell, &config.profiles());
+        return Ok(());
+    }
+
+    if let Some(("manpage", sub)) = matches.subcommand() {
+        match sub.get_one::<PathBuf>("directory") {
+            Some(directory) => clap_mangen::generate_to(command, directory)?,
+            None => clap_mangen::Man::new(command).render(&mut std::io::stdout())?,
+        }
+        return Ok(());
+    }
+
+    #[cfg(feature = "self-update")]
+    if let Some(("self-update", sub)) = matches.subcommand() {
+        let Some(source) = update::Source::configured(&config) else {
+            exit::fail(Failure::Usage, Message::UpdateNotConfigured);
+        };
+
+        match update::update(&source, VERSION, !sub.get_flag("check")) {
+            Ok(update::Update::Current) => say(sub, Message::UpToDate { version: VERSION }),
+            Ok(update::Update::Available(version)) => {
+                say(sub, Message::UpdateAvailable { version: &version })
+            }
+            Ok(update::Update::Installed(version)) => say(
+                sub,
+                Message::Updated {
+                    from: VERSION,
+                    to: &version,
+                },
+            ),
+            Err(e) => exit::fail(Failure::Other, e),
+        }
+
+        return Ok(());
+    }
+
+    if let Some(("info", sub)) = matches.subcommand() {
+        let path = sub.get_one::<PathBuf>("file").unwrap();
+
+        match provenance::inspect(path)? {
+            Some(inspection) => print!("{}", inspection),
+            None => {
+                exit::fail(Failure::Input, Message::NoProvenance { path });
+            }
+        }
+
+        return Ok(());
+    }
+
+    if let Some(("audit", sub)) = matches.subcommand() {
+        let mut inspections = Vec::new();
+        let mut problems = false;
+
+        for path in sub.get_many::<PathBuf>("files").unwrap() {
+            match provenance::inspect(path)? {
+                Some(inspection) => {
+                    if inspection.updates > 0 || inspection.pages != inspection.provenance.pages {
+                        eprintln!(
+                            "{}: {}",
+                            i18n::warning().bright_yellow(),
+                            Message::ModifiedSinceStamped { path }
+                        );
+                        problems = true;
+                    }
+                    inspections.push((path.clone(), inspection));
+                }
+                None => {
+                    eprintln!(
+                        "{}: {}",
+                        i18n::warning().bright_yellow(),
+                        Message::NoProvenanceRecord { path }
+                    );
+                    problems = true;
+                }
+            }
+        }
+
+        for drift in provenance::audit(&inspections) {
+            problems = true;
+            println!(
+                "{}",
+                Message::Differing {
+                    attribute: drift.attribute
+                }
+            );
+            for (value, paths) in &drift.values {
+                println!("  {}", value);
+                for path in paths {
+                    println!("    {}", path.display());
+                }
+            }
+        }
+
+        if problems {
+            std::process::exit(Failure::Preflight.code());
+        }
+
+        println!(
+            "{}",
+            Message::Consistent {
+                files: inspections.len()
+            }
+        );
+        return Ok(());
+    }
+
+    if let Some(("explain", sub)) = matches.subcommand() {
+        let category = sub.get_one::<preflight::Category>("code").unwrap();
+
+        println!("{} {}", category.code(), category.name());
+        println!();
+        println!("{}", category.explanation());
+
+        return Ok(());
+    }
+
+    if let Some(("rpc", _)) = matches.subcommand() {
+        rpc::serve(std::io::stdin().lock(), std::io::stdout().lock())?;
+
+        return Ok(());
+    }
+
+    if let Some(("calibration", sub)) = matches.subcommand() {
+        let output_path = sub.get_one::<PathBuf>("output").unwrap();
+
+        fixture::calibration(output_path)?;
+
+        return Ok(());
+    }
+
+    if let Some(("gen-fixture", sub)) = matches.subcommand() {
+        let output_path = sub.get_one::<PathBuf>("output").unwrap();
+        let pages = *sub.get_one::<usize>("pages").unwrap();
+        if pages == 0 {
+            exit::fail(Failure::Usage, Message::FixtureNeedsPages);
+        }
+        let (trim_width, trim_height) = *sub.get_one::<(f64, f64)>("size").unwrap();
+        let bleed = *sub.get_one::<f64>("bleed").unwrap();
+
+        fixture::generate(output_path, pages, trim_width, trim_height, bleed)?;
+
+        return Ok(());
+    }
+
+    if let Some(("impose", sub)) = matches.subcommand() {
+        let output_path = sub.get_one::<PathBuf>("output").unwrap();
+        check_output(output_path);
+
+        let cost_per_sheet = sub.get_one::<f64>("cost-per-sheet").copied();
+
+        let jobs: Vec<gang::Job> = sub
+            .get_many::<gang::Job>("jobs")
+            .unwrap()
+            .cloned()
+            .collect();
+
+        for job in &jobs {
+            if !job.input.exists() {
+                exit::fail(Failure::Input, Message::InputNotFound { path: &job.input });
+            }
+        }
+
+        let placements = gang::impose(output_path, &jobs)?;
+
+        print!("{}", gang::cutting_diagram(&jobs, &placements));
+
+        let sheets = placements.iter().map(|p| p.sheet + 1).max().unwrap_or(0);
+        let trimmed_area: f64 = placements
+            .iter()
+            .map(|p| jobs[p.job].trim_width * jobs[p.job].trim_height)
+            .sum();
+        println!(
+            "{}",
+            usage::estimate(
+                sheets,
+                trimmed_area,
+                gang::SHEET.0 * gang::SHEET.1,
+                cost_per_sheet
+            )
+        );
+
+        return Ok(());
+    }
+
+    if let Some(("strip", sub)) = matches.subcommand() {
+        let input_path = sub.get_one::<PathBuf>("file").unwrap();
+        let output_path = sub.get_one::<PathBuf>("output").unwrap();
+        check_output(output_path);
+
+        match strip::strip(input_path, output_path) {
+            Ok(pages) => println!("{}", Message::Restored { pages }),
+            Err(e) => {
+                exit::fail(exit::classify(&e), e);
+            }
+        }
+
+        return Ok(());
+    }
+
+    if let Some(("verify", sub)) = matches.subcommand() {
+        let manuscript_path = sub.get_one::<PathBuf>("manuscript").unwrap();
+        let stamped_path = sub.get_one::<PathBuf>("file").unwrap();
+
+        let (pages, problems) = verify::verify(manuscript_path, stamped_path)
+            .unwrap_or_else(|e| exit::fail(exit::classify(&e), e));
+        if !problems.is_empty() {
+            for problem in &problems {
+                eprintln!("{}: {}", i18n::error().bright_red(), problem);
+            }
+            std::process::exit(Failure::Preflight.code());
+        }
+
+        println!("{}", Message::ContentUnchanged { pages });
+        return Ok(());
+    }
+
+    if let Some(("check", sub)) = matches.subcommand() {
+        let manuscript_path = manuscript(sub);
+        let options = options(sub);
+        let span = info_span!("check", document = %manuscript_path.display());
+        let _entered = span.enter();
+
+        let mut report = |progress: overlay::Progress| {
+            if let overlay::Progress::Warning(message) = progress {
+                eprintln!("{}: {}", i18n::warning().bright_yellow(), message);
+            }
+        };
+
+        let job = overlay::Job::load(manuscript_path, &options, &mut report)
+            .unwrap_or_else(|e| exit::fail(Failure::Input, e));
+        let preflight = job
+            .preflight(&mut report)
+            .unwrap_or_else(|e| exit::fail(Failure::Preflight, e));
+
+        // Exit with failure if there's anything to look at, for scripts
+        match preflight.warnings.len() {
+            0 => println!("{}", Message::NoProblems),
+            count => {
+                println!("{}", Message::Warnings { count });
+                std::process::exit(Failure::Preflight.code());
+            }
+        }
+
+        return Ok(());
+    }
+
+    if let Some(("check-pair", sub)) = matches.subcommand() {
+        check_pair(sub);
+        return Ok(());
+    }
+
+    // Otherwise, stamping
+    let Some(("stamp", matches)) = matches.subcommand() else {
+        unreachable!("a subcommand is required");
+    };
+
     let output_path = matches.get_one::<PathBuf>("output").unwrap();
 
+    let cost_per_sheet = matches.get_one::<f64>("cost-per-sheet").copied();
+
+    // Checksums, bundles, and deliveries need files on disk to work from
+    let piped = overlay::is_stdio(output_path)
+        || matches
+            .get_many::<PathBuf>("manuscript")
+            .unwrap()
+            .any(|path| overlay::is_stdio(path));
+    let needs_files = [
+        ("write-checksums", matches.get_flag("write-checksums")),
+        ("bundle", matches.contains_id("bundle")),
+        ("resume", matches.contains_id("resume")),
+        (
+            "deliver",
+            matches.try_contains_id("deliver").unwrap_or(false),
+        ),
+    ]
+    .into_iter()
+    .find_map(|(name, given)| given.then_some(name));
+    let report_path = matches.get_one::<PathBuf>("report");
+    if overlay::is_stdio(output_path) && report_path.is_some_and(|path| overlay::is_stdio(path)) {
+        exit::fail(Failure::Usage, Message::ReportOverOutput);
+    }
+    if let Some(option) = needs_files.filter(|_| piped) {
+        exit::fail(Failure::Usage, Message::NeedsFiles { option });
+    }
+
+    let mut options = options(matches);
+    options.producer = matches.get_one::<String>("producer").cloned();
+
+    // Read the bundle's layout before stamping, so that a mistake in it
+    // doesn't waste the run
+    let layout = match matches.get_one::<PathBuf>("vendor") {
+        Some(path) => bundle::Layout::load(path).unwrap_or_else(|e| exit::fail(Failure::Input, e)),
+        None => bundle::Layout::default(),
+    };
+
+    let mut state = matches
+        .get_one::<PathBuf>("resume")
+        .map(|path| batch::State::open(path).unwrap_or_else(|e| exit::fail(Failure::Input, e)));
+
+    let inputs = batch::expand(matches.get_many::<PathBuf>("manuscript").unwrap())
+        .unwrap_or_else(|message| exit::fail(Failure::Input, message));
+    let jobs = batch::outputs(&inputs, output_path, &options)
+        .unwrap_or_else(|message| exit::fail(Failure::Usage, message));
+    for (_, output_path) in &jobs {
+        check_output(output_path);
+    }
+
+    debug!(?output_path);
+    debug!(?jobs);
+    debug!(?options);
+
+    // Cancel cleanly on the first Ctrl-C; exit immediately on the second
+    let cancel = Arc::new(AtomicBool::new(false));
+    {
+        let cancel = cancel.clone();
+        tokio::spawn(async move {
+            while tokio::signal::ctrl_c().await.is_ok() {
+                if cancel.swap(true, Ordering::Relaxed) {
+                    std::process::exit(Failure::Cancelled.code());
+                }
+            }
+        });
+    }
+
+    let mut results = Vec::new();
+    let mut entries = Vec::new();
+    let mut documents = Vec::new();
+
+    // Files produced by this run, in the order they should be delivered
+    let mut products = Vec::new();
+
+    for (manuscript_path, output_path) in &jobs {
+        // Everything logged while stamping names the document it's about
+        let span = info_span!("stamp", document = %manuscript_path.display());
+        let _entered = span.enter();
+
+        // Each output gets its own trace code
+        let options = overlay::Options {
+            trace_code: trace_code(matches, output_path),
+            ..options.clone()
+        };
+
+        // Pass over what the run being resumed already stamped
+        let resumed = state
+            .as_ref()
+            .and_then(|state| state.done(manuscript_path, output_path));
+
+        // Take turns with any other run writing the same output, such as
+        // another hot folder worker, holding it until its sidecar is written
+        let lock = (resumed.is_none() && !overlay::is_stdio(output_path))
+            .then(|| lock::acquire(output_path))
+            .transpose();
+        let started = Instant::now();
+        let mut warnings = Vec::new();
+        let mut phases = report::Phases::default();
+        let outcome = match (resumed, &lock) {
+            (Some(pages), _) => batch::Outcome::Stamped(pages),
+            (None, Ok(_)) => stamp_one(
+                matches,
+                &options,
+                manuscript_path,
+                output_path,
+                &cancel,
+                &mut warnings,
+                &mut phases,
+            ),
+            (None, Err(e)) => batch::Outcome::Failed(Failure::Output, e.to_string()),
+        };
+
+        match &outcome {
+            batch::Outcome::Stamped(_) if resumed.is_some() => {
+                say(
+                    matches,
+                    Message::Resumed {
+                        input: manuscript_path,
+                        output: output_path,
+                    },
+                );
+
+                products.push(output_path.clone());
+            }
+            batch::Outcome::Stamped(pages) => {
+                info!("PDF combination completed successfully");
+
+                let usage = usage::estimate(
+                    *pages,
+                    *pages as f64 * options.trim_width * options.trim_height,
+                    options.sheet_width * options.sheet_height,
+                    cost_per_sheet,
+                );
+                say(
+                    matches,
+                    Message::Stamped {
+                        output: output_path,
+                        pages: *pages,
+                        usage: &usage.to_string(),
+                        code: options.trace_code.as_deref(),
+                    },
+                );
+
+                products.push(output_path.clone());
+            }
+            batch::Outcome::Skipped(earlier) => {
+                say(
+                    matches,
+                    Message::AlreadyStamped {
+                        input: manuscript_path,
+                        earlier,
+                    },
+                );
+            }
+            batch::Outcome::Failed(_, message) => {
+                eprintln!(
+                    "{}: {}: {}",
+                    i18n::error().bright_red(),
+                    manuscript_path.display(),
+                    message
+                );
+            }
+        }
+
+        if resumed.is_none()
+            && let (Some(state), batch::Outcome::Stamped(pages)) = (&mut state, &outcome)
+        {
+            state.record(manuscript_path, output_path, *pages)?;
+        }
+
+        if matches!(outcome, batch::Outcome::Stamped(_)) && matches.get_flag("write-checksums") {
+            let entry = checksums::checksum_document(manuscript_path, output_path, &options)?;
+            entries.push(entry);
+            products.push(checksums::sidecar_path(output_path));
+        }
+
+        if let Some(report_path) = report_path {
+            documents.push(report::Document::new(
+                manuscript_path,
+                output_path,
+                &options,
+                &outcome,
+                warnings,
+                started.elapsed().as_secs_f64(),
+                phases,
+            ));
+
+            // Bring the report up to date after each document, so that a
+            // run which dies partway still leaves one of what it got through
+            if !overlay::is_stdio(report_path) {
+                report::write(report_path, VERSION, &documents, None)?;
+            }
+        }
+
+        results.push((manuscript_path.clone(), outcome));
+    }
+
+    // Reported whether or not the run went through, for the build system to
+    // see what failed
+    if let Some(report_path) = report_path {
+        report::write(report_path, VERSION, &documents, None)?;
+    }
+
+    // A manifest in each directory written into, as a template may have
+    // spread the outputs across several
+    let mut manifests: BTreeMap<PathBuf, Vec<checksums::Entry>> = BTreeMap::new();
+    for entry in entries {
+        manifests
+            .entry(directory(&entry.output).to_path_buf())
+            .or_default()
+            .push(entry);
+    }
+    for (directory, entries) in manifests {
+        let settings = checksums::Settings {
+            version: VERSION.to_string(),
+            trim_width: options.trim_width,
+            trim_height: options.trim_height,
+            bleed: options.bleed,
+            trim_origin: options.trim_origin,
+        };
+
+        let manifest_path = checksums::write_manifest(&directory, &settings, entries)?;
+        products.push(manifest_path);
+    }
+
+    if results.len() > 1 {
+        say(matches, batch::summary(&results));
+    }
+
+    // Only bundle and deliver a run which went through completely, and
+    // otherwise exit as the first failure did
+    let failed = results.iter().find_map(|(_, outcome)| match outcome {
+        batch::Outcome::Failed(failure, _) => Some(*failure),
+        _ => None,
+    });
+    if let Some(failure) = failed {
+        std::process::exit(failure.code());
+    }
+    if products.is_empty() {
+        if let Some(state) = state {
+            state.finish()?;
+        }
+        return Ok(());
+    }
+
+    if let Some(bundle_path) = matches.get_one::<PathBuf>("bundle") {
+        let report_path = report_path.map(PathBuf::as_path);
+        let mut files = products.clone();
+        files.extend(report_path.map(Path::to_path_buf));
+        bundle::write(bundle_path, &files, report_path, &layout)?;
+    }
+
+    #[cfg(feature = "sftp")]
+    if let Some(url) = matches.get_one::<String>("deliver") {
+        let target = match deliver::Target::parse(url) {
+            Ok(target) => target,
+            Err(message) => {
+                exit::fail(Failure::Usage, message);
+            }
+        };
+
+        // Deliver the bundle in place of its contents if one was made
+        let files = match matches.get_one::<PathBuf>("bundle") {
+            Some(bundle_path) => vec![bundle_path.clone()],
+            None => products.clone(),
+        };
+
+        let receipt = deliver::deliver(&target, &files)?;
+
+        println!(
+            "{}",
+            Message::Delivered {
+                files: &receipt.files.join(", "),
+                bytes: receipt.bytes,
+                target: &receipt.target,
+                attempts: receipt.attempts,
+            }
+        );
+
+        // Write the report again with the receipt, for the build system to
+        // see where the run went
+        if let Some(report_path) = report_path {
+            let delivery = report::Delivery {
+                target: receipt.target,
+                files: receipt.files,
+                bytes: receipt.bytes,
+                attempts: receipt.attempts,
+            };
+            report::write(report_path, VERSION, &documents, Some(&delivery))?;
+        }
+    }
+
+    // Only now has the batch gone through, bundle and delivery included
+    if let Some(state) = state {
+        state.finish()?;
+    }
+
+    Ok(())
+}
+
+/// Stamp one manuscript into one output, unless the manifest in the output
+/// directory shows it was already stamped the same way.
+fn stamp_one(
+    matches: &ArgMatches,
+    options: &overlay::Options,
+    manuscript_path: &Path,
+    output_path: &Path,
+    cancel: &AtomicBool,
+    warnings: &mut Vec<String>,
+    phases: &mut report::Phases,
+) -> batch::Outcome {
+    if !overlay::is_stdio(manuscript_path) && !manuscript_path.exists() {
+        return batch::Outcome::Failed(Failure::Input, Message::ManuscriptNotFound.to_string());
+    }
+
+    // Don't stamp the same manuscript the same way into the same place
+    // twice, as when a file is dropped into a hot folder again. There's no
+    // telling with a pipeline.
+    let piped = overlay::is_stdio(manuscript_path) || overlay::is_stdio(output_path);
+    if !matches.get_flag("force") && !piped {
+        match checksums::already_stamped(directory(output_path), manuscript_path, options) {
+            Ok(Some(earlier)) => return batch::Outcome::Skipped(earlier),
+            Ok(None) => {}
+            Err(e) => return batch::Outcome::Failed(Failure::Other, e.to_string()),
+        }
+    }
+
+    // Nor write over an earlier output unless asked to
+    if !matches.get_flag("force") && !overlay::is_stdio(output_path) && output_path.exists() {
+        return batch::Outcome::Failed(
+            Failure::Output,
+            Message::OutputExists {
+                output: output_path,
+            }
+            .to_string(),
+        );
+    }
+
+    let bar = progress_bar(matches, manuscript_path);
+    let mut current = None;
+    let result = overlay::combine(
+        output_path,
+        manuscript_path,
+        options,
+        |progress| match progress {
+            overlay::Progress::Warning(message) => {
+                bar.suspend(|| eprintln!("{}: {}", i18n::warning().bright_yellow(), message));
+                warnings.push(message);
+            }
+            overlay::Progress::PageStarted { page, total } => {
+                bar.set_length(total as u64);
+                debug!(page, total, "Stamping page {}/{}", page, total);
+            }
+            overlay::Progress::PageFinished { page, total } => {
+                bar.inc(1);
+                debug!(page, total, "Stamped page {}/{}", page, total);
+            }
+            overlay::Progress::Phase(phase) => {
+                current = Some(phase);
+                phases.begin(phase);
+                debug!(operation = ?phase);
+            }
+        },
+        cancel,
+    );
+    phases.end();
+    bar.finish_and_clear();
+
+    // Stop the whole batch, not just this manuscript
+    if result.is_err() && cancel.load(Ordering::Relaxed) {
+        exit::fail(Failure::Cancelled, Message::Cancelled);
+    }
+
+    // What failed depends on how far the job got
+    let failure = match current {
+        None | Some(overlay::Phase::Loading) => Failure::Input,
+        Some(overlay::Phase::Checking) => Failure::Preflight,
+        Some(overlay::Phase::Stamping | overlay::Phase::Compressing) => Failure::Other,
+        Some(overlay::Phase::Saving) => Failure::Output,
+    };
+    match result {
+        Ok(pages) => batch::Outcome::Stamped(pages),
+        Err(e) => batch::Outcome::Failed(failure, e.to_string()),
+    }
+}
+
+/// A bar showing the pages of a manuscript stamped so far, since a long one
+/// otherwise leaves the terminal silent until it's done. It's hidden when
+/// standard error isn't a terminal, or with --quiet.
+fn progress_bar(matches: &ArgMatches, manuscript_path: &Path) -> ProgressBar {
+    let quiet = matches.try_get_one::<bool>("quiet").ok().flatten() == Some(&true);
+    if quiet || !std::io::stderr().is_terminal() {
+        return ProgressBar::hidden();
+    }
+
+    let bar = ProgressBar::new(0).with_message(manuscript_path.display().to_string());
+    if let Ok(style) = ProgressStyle::with_template(&Message::ProgressBar.to_string()) {
+        bar.set_style(style.progress_chars("=> "));
+    }
+    bar
+}
+
+/// Report the warnings raised checking a file against it.
+fn report(path: &Path) -> impl FnMut(overlay::Progress) + '_ {
+    move |progress| {
+        if let overlay::Progress::Warning(message) = progress {
+            eprintln!(
+                "{}: {}: {}",
+                i18n::warning().bright_yellow(),
+                path.display(),
+                message
+            );
+        }
+    }
+}
+
+/// Check a cover against its interior, and each of them as check would,
+/// exiting with failure if there's anything to look at.
+fn check_pair(matches: &ArgMatches) {
+    let interior_path = manuscript(matches);
+    let cover_path = matches.get_one::<PathBuf>("cover").unwrap();
+    // The spine is as thick as the whole interior, whatever --only says
+    let options = overlay::Options {
+        only: None,
+        ..options(matches)
+    };
+    let paper = *matches.get_one::<f64>("paper").unwrap();
+    let bleed = *matches.get_one::<f64>("cover-bleed").unwrap();
+    let fail = |failure: Failure, path: &Path, e: String| -> ! {
+        exit::fail(failure, format!("{}: {}", path.display(), e))
+    };
+    let cover_rules = match matches
+        .get_one::<PathBuf>("cover-rules")
+        .map(|path| rules::load(path))
+    {
+        Some(Ok(rules)) => rules,
+        Some(Err(message)) => exit::fail(Failure::Input, message),
+        None => Vec::new(),
+    };
+
+    let mut warnings = 0;
+
+    let interior = overlay::Job::load(interior_path, &options, &mut report(interior_path))
+        .unwrap_or_else(|e| fail(Failure::Input, interior_path, e.to_string()));
+    let interior_pages = interior.pages();
+    match interior.preflight(&mut report(interior_path)) {
+        Ok(preflight) => warnings += preflight.warnings.len(),
+        Err(e) => fail(Failure::Preflight, interior_path, e.to_string()),
+    }
+
+    let document = lopdf::Document::load(cover_path)
+        .unwrap_or_else(|e| fail(Failure::Input, cover_path, e.to_string()));
+    let cover = pair::Cover::measure(&document, options.content_box)
+        .unwrap_or_else(|e| fail(Failure::Input, cover_path, e));
+
+    let problems = pair::check(
+        &cover,
+        interior_pages,
+        options.trim_width,
+        options.trim_height,
+        bleed,
+        paper,
+    );
+    for problem in &problems {
+        report(cover_path)(overlay::Progress::Warning(problem.clone()));
+    }
+    warnings += problems.len();
+
+    let cover_options = pair::cover_options(&options, &cover, bleed, cover_rules);
+    let checked = overlay::Job::from_document(
+        document,
+        cover_path,
+        &cover_options,
+        &mut report(cover_path),
+    )
+    .unwrap_or_else(|e| fail(Failure::Input, cover_path, e.to_string()));
+    match checked.preflight(&mut report(cover_path)) {
+        Ok(preflight) => warnings += preflight.warnings.len(),
+        Err(e) => fail(Failure::Preflight, cover_path, e.to_string()),
+    }
+
+    // Exit with failure if there's anything to look at, for scripts
+    match warnings {
+        0 => println!("{}", Message::NoProblems),
+        count => {
+            println!("{}", Message::Warnings { count });
+            std::process::exit(Failure::Preflight.code());
+        }
+    }
+}
+
+/// The trace code to print on an output, as given or generated for it.
+fn trace_code(matches: &ArgMatches, output_path: &Path) -> Option<String> {
+    let seed = matches.get_one::<String>("trace-seed").map(String::as_str);
+
+    match matches.get_one::<String>("trace-code")?.as_str() {
+        "auto" => Some(trace::generate(seed, output_path)),
+        code => Some(code.to_string()),
+    }
+}
+
+/// Mention a newer release, if the passive check for them is turned on,
+/// except where what's printed is for another program to read.
+#[cfg(feature = "self-update")]
+fn notify_update(config: &config::Config, matches: &ArgMatches) {
+    let Some((name, sub)) = matches.subcommand() else {
+        return;
+    };
+    if sub.get_flag("quiet")
+        || ["self-update", "rpc", "completions", "manpage"].contains(&name)
+        || !update::checks(config)
+    {
+        return;
+    }
+    let Some(source) = update::Source::configured(config) else {
+        return;
+    };
+
+    if let Some(version) = update::notice(&source, VERSION) {
+        eprintln!("{}", Message::UpdateAvailable { version: &version });
+    }
+}
+
+/// Print a message for the user: to standard output, unless that's where
+/// the PDF is being written. Nothing is printed under --quiet.
+fn say(matches: &ArgMatches, message: impl std::fmt::Display) {
+    if matches.try_get_one::<bool>("quiet").ok().flatten() == Some(&true) {
+        return;
+    }
+
+    // Standard output may be taken by the PDF, or by the report
+    let piped = ["output", "report"].into_iter().any(|id| {
+        matches
+            .try_get_one::<PathBuf>(id)
+            .ok()
+            .flatten()
+            .is_some_and(|path| overlay::is_stdio(path))
+    });
+
+    if piped {
+        eprintln!("{}", message);
+    } else {
+        println!("{}", message);
+    }
+}
+
+/// The output path, common to the subcommands which write a PDF.
+fn output_arg() -> Arg {
+    Arg::new("output")
+        .short('o')
+        .long("output")
+        .value_name("OUTPUT")
+        .value_parser(value_parser!(PathBuf))
+        .help("Path for the output PDF, or - for standard output.")
+        .required(true)
+}
+
+fn cost_per_sheet_arg() -> Arg {
+    Arg::new("cost-per-sheet")
+        .long("cost-per-sheet")
+        .value_name("AMOUNT")
+        .value_parser(value_parser!(f64))
+        .help("Cost of one sheet of paper, used to estimate the cost of printing the output.")
+}
+
+/// Arguments describing the manuscript and how it's to be placed on the
+/// sheet, common to stamping and checking.
+fn placement_args() -> Vec<Arg> {
+    let mut args = vec![
+        Arg::new("trim")
+            .short('s')
+            .long("trim")
+            .visible_alias("size")
+            .value_name("PRESET")
+            .value_parser(units::parse_trim)
+            .help("Trim size of the input manuscript: trade or 6x9, digest or 5.5x8.5, 5x8, pocket, or a5. With auto, it is worked out from the pages of the manuscript, or the first of several.")
+            .default_value("trade"),
+        Arg::new("sheet")
+            .long("sheet")
+            .value_name("SHEET")
+            .value_parser(["a4", "a3", "sra3", "letter", "tabloid", "auto"])
+            .default_value("a4")
+            .help("Sheet to place each page on. With auto, the smallest which holds the trim, bleed, and crop marks: A4, A3, or SRA3, or for trims in inches Letter or Tabloid."),
+        Arg::new("landscape")
+            .long("landscape")
+            .action(ArgAction::SetTrue)
+            .help("Turn the sheet on its side, for wide trims such as landscape photo books."),
+        Arg::new("trim-width")
+            .long("trim-width")
+            .value_name("LENGTH")
+            .value_parser(units::parse_length)
+            .requires("trim-height")
+            .help("Width of the trim, e.g. 5.5in or 140mm, overriding --trim."),
+        Arg::new("trim-height")
+            .long("trim-height")
+            .value_name("LENGTH")
+            .value_parser(units::parse_length)
+            .requires("trim-width")
+            .help("Height of the trim, e.g. 8.5in or 216mm, overriding --trim."),
+        Arg::new("bleed")
+            .long("bleed")
+            .value_name("LENGTH")
+            .value_parser(overlay::Bleed::parse)
+            .help("Bleed carried by the manuscript beyond the trim, e.g. 0.125in or 3mm, or per edge as top=3mm,outside=3mm,bottom=3mm,inside=0.")
+            .default_value("0"),
+        Arg::new("mark-gap")
+            .long("mark-gap")
+            .value_name("LENGTH")
+            .value_parser(units::parse_length)
+            .help("Gap between the edge of the bleed and the start of each crop mark.")
+            .default_value("5pt"),
+        Arg::new("mark-weight")
+            .long("mark-weight")
+            .value_name("WEIGHT")
+            .value_parser(["hairline", "0.25", "0.5", "1"])
+            .help("Stroke width of the crop marks, in points. A hairline is drawn at 0.25pt, as zero-width lines render unpredictably across RIPs; 1 is a heavy, high-contrast mark.")
+            .default_value("0.5"),
+        Arg::new("mark-style")
+            .long("mark-style")
+            .value_name("STYLE")
+            .value_parser(["western", "japanese"])
+            .default_value("western")
+            .help("Style of the crop marks: western, a single line at each corner, or japanese, a pair of lines in line with the trim and the bleed (tombo)."),
+        Arg::new("corner-radius")
+            .long("corner-radius")
+            .value_name("RADIUS")
+            .value_parser(units::parse_length)
+            .help("Radius of the rounded corners a die will cut, e.g. 3mm, for cards and postcards. Arcs marking the corners are drawn on the trim in place of the crop marks."),
+        Arg::new("reorder")
+            .long("reorder")
+            .value_name("PAGES")
+            .value_parser(overlay::parse_page_order)
+            .help("Order of the pages in the output, as a comma-separated list of every manuscript page number, e.g. 2,1,4,3."),
+        Arg::new("reverse")
+            .long("reverse")
+            .action(ArgAction::SetTrue)
+            .help("Reverse the order of the pages in the output, after any --reorder, for duplexers which need reverse-collated files."),
+        Arg::new("pages")
+            .long("pages")
+            .value_name("PAGES")
+            .value_parser(overlay::PageSelection::parse)
+            .help("Manuscript pages to stamp, e.g. 1-10,15,20- or odd or even. The rest are left out of the output."),
+        Arg::new("keep-unselected")
+            .long("keep-unselected")
+            .action(ArgAction::SetTrue)
+            .requires("pages")
+            .help("Pass the pages not selected by --pages through untouched, rather than leaving them out."),
+        Arg::new("only")
+            .long("only")
+            .value_name("PAGE")
+            .value_parser(overlay::OnlyPage::parse)
+            .conflicts_with_all(["pages", "reorder", "reverse"])
+            .help("Stamp one page alone, first, last, or a page number, skipping the rest of the manuscript entirely: quicker than --pages for a marked page to attach to a job bag."),
+        Arg::new("no-overlay-pages")
+            .long("no-overlay-pages")
+            .value_name("PAGES")
+            .value_parser(overlay::PageSelection::parse)
+            .help("Manuscript pages to place on the sheet without crop marks or a slug, e.g. 1,2 for scans of letterhead with marks of their own."),
+        Arg::new("content-box")
+            .long("content-box")
+            .value_name("BOX")
+            .value_parser(["mediabox", "cropbox", "trimbox"])
+            .default_value("mediabox")
+            .help("Which of the manuscript's page boxes is its content, for centering and bleed."),
+        Arg::new("extend-bleed")
+            .long("extend-bleed")
+            .value_name("METHOD")
+            .value_parser(["mirror", "clone-edge"])
+            .help("Experimental: make up bleed for pages which lack it, by mirroring the edges or scaling the page. Lossy."),
+        Arg::new("compat")
+            .long("compat")
+            .value_name("MODE")
+            .value_parser(["standard", "legacy"])
+            .default_value("standard")
+            .help("Use legacy to write the plainest PDF possible, for old RIPs that choke on modern constructs."),
+        Arg::new("trim-origin")
+            .long("trim-origin")
+            .value_name("X,Y")
+            .value_parser(units::parse_position)
+            .help("Place the trim's bottom left corner at this distance from the sheet's left and bottom edges, e.g. 20mm,30mm, instead of centering it."),
+        Arg::new("test-strip")
+            .long("test-strip")
+            .value_name("INKS")
+            .value_parser(["gray", "cmyk"])
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value("gray")
+            .help("Draw a strip of 10% to 100% tint patches in the margin, in gray or as CMYK rows."),
+        Arg::new("color-bars")
+            .long("color-bars")
+            .action(ArgAction::SetTrue)
+            .help("Draw a column of color control patches in the left margin: CMYK solids, their overprints, a gray balance patch, and black tints, for checking density on press."),
+        Arg::new("plate-labels")
+            .long("plate-labels")
+            .action(ArgAction::SetTrue)
+            .help("Print C M Y K labels in the margin, each in its own separation, so separated plates are self-identifying."),
+        Arg::new("center-marks")
+            .long("center-marks")
+            .action(ArgAction::SetTrue)
+            .help("Draw a center mark at the middle of each edge of the trim as well as the crop marks at its corners."),
+        Arg::new("rulers")
+            .long("rulers")
+            .value_name("UNITS")
+            .value_parser(["mm", "in"])
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value("mm")
+            .help("Draw numbered rulers along the top and left edges of the sheet, measuring across the trim from its top left corner, in mm or in."),
+        Arg::new("grid")
+            .long("grid")
+            .value_name("SPACING")
+            .value_parser(marks::parse_grid)
+            .help("Draw a faint grid across the trim at this spacing, e.g. 10mm or 0.5in, measured from its top left corner, for checking alignment on proofs."),
+        Arg::new("show-trim-outline")
+            .long("show-trim-outline")
+            .action(ArgAction::SetTrue)
+            .help("Draw a thin dashed magenta outline exactly on the trim, over the page content, to show what will be cut away. For proofs only, since it prints."),
+        Arg::new("center-crosshair")
+            .long("center-crosshair")
+            .action(ArgAction::SetTrue)
+            .help("Draw a small crosshair at the center of the trim, over the page content, for checking the front and back of duplex proofs line up. For proofs only, since it prints."),
+        Arg::new("drill")
+            .long("drill")
+            .value_name("COUNT")
+            .value_parser(value_parser!(u64).range(1..))
+            .help("Mark where this many binding holes will be drilled or punched, with a circle and crosshair over the page content for each, so their positions can be checked before drilling."),
+        Arg::new("drill-diameter")
+            .long("drill-diameter")
+            .value_name("LENGTH")
+            .value_parser(units::parse_length)
+            .default_value("6mm")
+            .requires("drill")
+            .help("Diameter of the drilled holes."),
+        Arg::new("drill-edge")
+            .long("drill-edge")
+            .value_name("EDGE")
+            .value_parser(["inside", "left", "right", "top", "bottom"])
+            .default_value("inside")
+            .requires("drill")
+            .help("Edge of the trim the holes are along: inside, the binding edge, which is the left of odd pages and the right of even ones, or left, right, top, or bottom. Their centers are 12mm in from it."),
+        Arg::new("drill-spacing")
+            .long("drill-spacing")
+            .value_name("LENGTH")
+            .value_parser(units::parse_length)
+            .default_value("80mm")
+            .requires("drill")
+            .help("Distance between the centers of neighbouring holes, such as 80mm for ISO 838 or 4.25in for US three-hole punching."),
+        Arg::new("show-safety")
+            .long("show-safety")
+            .value_name("MARGIN")
+            .value_parser(units::parse_length)
+            .help("Draw a light dashed outline this far inside the trim, e.g. 0.25in, marking the live area critical text must stay within. For proofs only, since it prints."),
+        Arg::new("fold")
+            .long("fold")
+            .value_name("POSITIONS")
+            .value_parser(marks::parse_folds)
+            .help("Draw dashed fold marks beyond the bleed for folds at these positions across the trim, from its left edge, such as 1/3,2/3 for a tri-fold or 99mm, or prefixed with y: for horizontal folds, from its top edge."),
+        Arg::new("registration-marks")
+            .long("registration-marks")
+            .action(ArgAction::SetTrue)
+            .help("Draw a registration target, a circle and crosshair, at the middle of each edge beyond the bleed, on every plate, for aligning plates and the two sides of duplex sheets."),
+        Arg::new("marks")
+            .long("marks")
+            .value_name("PACK")
+            .action(ArgAction::Append)
+            .help("Also draw the marks of an installed mark pack, such as a vendor's registration targets, from the plugins directory beside the configuration or named by CROPPED_PLUGINS. May be repeated."),
+        Arg::new("strict")
+            .long("strict")
+            .action(ArgAction::SetTrue)
+            .help("Treat problems found checking the manuscript as errors rather than warnings."),
+        Arg::new("binding")
+            .long("binding")
+            .value_name("BINDING")
+            .value_parser(["perfect", "saddle"])
+            .help("How the book will be bound, to check its page count suits."),
+        Arg::new("rules")
+            .long("rules")
+            .value_name("FILE")
+            .value_parser(value_parser!(PathBuf))
+            .help("JSON file of additional preflight rules to apply to the manuscript."),
+        Arg::new("allow")
+            .long("allow")
+            .value_name("WARNING")
+            .value_parser(preflight::Category::parse)
+            .action(ArgAction::Append)
+            .help("Suppress a kind of preflight warning known to be acceptable, e.g. rgb-content or missing-bleed. May be repeated."),
+        Arg::new("slug-note")
+            .long("slug-note")
+            .value_name("TEXT")
+            .help("Additional text to print above the slug, such as title and revision. Use \\n to start a new line."),
+        Arg::new("slug-chapter")
+            .long("slug-chapter")
+            .value_name("SOURCE")
+            .help("Print the title of the chapter each page is in after the filename, so that loose sheets can be sorted back by hand: outline to take chapters from the manuscript's bookmarks, or a file giving the first page and title of each chapter, one per line."),
+        Arg::new("slug-fit")
+            .long("slug-fit")
+            .value_name("MODE")
+            .value_parser(["auto", "strict"])
+            .default_value("auto")
+            .help("Whether the footer may be shrunk or moved to another margin when it would overlap the page content."),
+        Arg::new("slug-size")
+            .long("slug-size")
+            .value_name("SIZE")
+            .value_parser(["normal", "large"])
+            .default_value("normal")
+            .help("Size of the footer text. Large is 14pt, in bold where Inconsolata Bold is installed, for reading proofs with impaired sight."),
+        Arg::new("marks-only")
+            .long("marks-only")
+            .action(ArgAction::SetTrue)
+            .help("Print the crop marks alone, without the footer, for the least work and the smallest output."),
+        Arg::new("slug-duplex")
+            .long("slug-duplex")
+            .action(ArgAction::SetTrue)
+            .help("Print the footer at the bottom of odd sheets and the top of even ones, so that when the output is printed duplex the footers all fall on one edge of the stack, to be cut off together."),
+        Arg::new("tz-offset")
+            .long("tz-offset")
+            .value_name("OFFSET")
+            .value_parser(overlay::parse_tz_offset)
+            .allow_hyphen_values(true)
+            .help("Give the time in the footer at this offset from UTC, such as +10:00, in place of the system's timezone, for where the timezone can't be found, as in containers without tzdata."),
+        Arg::new("manuscript")
+            .value_name("INPUT")
+            .value_parser(value_parser!(PathBuf))
+            .required(true)
+            .help("Path to the input manuscript PDF to be placed into the template, or - for standard input."),
+    ];
+
+    let negations = config::negations(&args);
+    args.extend(negations);
+    args
+}
+
+/// The manuscript named on the command line, which must exist.
+fn manuscript(matches: &ArgMatches) -> &PathBuf {
     let manuscript_path = matches.get_one::<PathBuf>("manuscript").unwrap();
 
-    let trim_size = matches.get_one::<String>("size").unwrap();
+    if !overlay::is_stdio(manuscript_path) && !manuscript_path.exists() {
+        exit::fail(Failure::Input, Message::ManuscriptNotFound);
+    }
+
+    manuscript_path
+}
+
+/// The directory an output is written into.
+fn directory(output_path: &Path) -> &Path {
+    output_path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+}
+
+/// Check that the directory an output is to be written into exists, before
+/// any work is done.
+fn check_output(output_path: &Path) {
+    if let Some(directory) = output_path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty() && !parent.is_dir())
+    {
+        exit::fail(
+            Failure::Output,
+            Message::OutputDirectoryMissing { directory },
+        );
+    }
+}
 
-    if !manuscript_path.exists() {
-        eprintln!(
-            "{}: Input manuscript PDF not found.",
-            "error".bright_red()
+/// Work out the trim from the pages of the manuscript, or the first of
+/// several, and say what was chosen and why.
+fn detect_trim(matches: &ArgMatches, which: overlay::ContentBox) -> (f64, f64) {
+    let first = batch::expand(matches.get_many::<PathBuf>("manuscript").unwrap())
+        .map(|inputs| inputs.into_iter().next().unwrap());
+    let manuscript_path = match first {
+        Ok(path) => path,
+        Err(message) => {
+            exit::fail(Failure::Input, message);
+        }
+    };
+    if overlay::is_stdio(&manuscript_path) {
+        exit::fail(
+            Failure::Usage,
+            Message::NeedsFiles {
+                option: "trim auto",
+            },
         );
-        std::process::exit(1);
     }
 
-    // Parse paper size to dimensions (width, height in points)
-    let (trim_width, trim_height) = match trim_size.as_str() {
-        "trade" => (432.0, 648.0), // 6" × 9"
-        _ => {
+    let detected = lopdf::Document::load(&manuscript_path)
+        .map_err(|e| format!("{}: {}", manuscript_path.display(), e))
+        .and_then(|doc| trim::detect(&doc, which));
+    match detected {
+        Ok(detected) => {
+            say(matches, detected.message());
+            (detected.width, detected.height)
+        }
+        Err(message) => {
+            exit::fail(Failure::Input, message);
+        }
+    }
+}
+
+/// Build the options the library takes from the placement arguments.
+fn options(matches: &ArgMatches) -> overlay::Options {
+    let bleed = *matches.get_one::<overlay::Bleed>("bleed").unwrap();
+    let content_box = match matches.get_one::<String>("content-box").unwrap().as_str() {
+        "cropbox" => overlay::ContentBox::Crop,
+        "trimbox" => overlay::ContentBox::Trim,
+        _ => overlay::ContentBox::Media,
+    };
+
+    // Take the trim from the preset, or the manuscript, unless given
+    // explicitly
+    let explicit = (
+        matches.get_one::<f64>("trim-width"),
+        matches.get_one::<f64>("trim-height"),
+    );
+    let (trim_width, trim_height) = match explicit {
+        (Some(&width), Some(&height)) => {
+            if width <= 0.0 || height <= 0.0 {
+                exit::fail(Failure::Usage, Message::TrimNotPositive);
+            }
+            (width, height)
+        }
+        _ => match *matches.get_one::<units::Trim>("trim").unwrap() {
+            units::Trim::Size(width, height) => (width, height),
+            units::Trim::Auto => detect_trim(matches, content_box),
+        },
+    };
+
+    let mut rules = match matches.get_one::<PathBuf>("rules") {
+        Some(path) => rules::load(path).unwrap_or_else(|e| exit::fail(Failure::Input, e)),
+        None => Vec::new(),
+    };
+
+    match matches.get_one::<String>("binding").map(String::as_str) {
+        Some("perfect") => rules.extend(rules::Binding::Perfect.rules()),
+        Some("saddle") => rules.extend(rules::Binding::Saddle.rules()),
+        _ => {}
+    }
+
+    let mark_packs = matches
+        .get_many::<String>("marks")
+        .into_iter()
+        .flatten()
+        .map(|name| plugins::find(name).unwrap_or_else(|e| exit::fail(Failure::Usage, e)))
+        .collect();
+
+    let slug_chapters =
+        matches
+            .get_one::<String>("slug-chapter")
+            .map(|source| match source.as_str() {
+                "outline" => chapters::Chapters::Outline,
+                path => chapters::load(Path::new(path))
+                    .unwrap_or_else(|e| exit::fail(Failure::Input, e)),
+            });
+
+    let mark_weight = match matches.get_one::<String>("mark-weight").unwrap().as_str() {
+        "hairline" => {
             eprintln!(
-                "{}: Unknown paper size '{}'. Supported: trade",
-                "error".bright_red(),
-                trim_size
+                "{}: {}",
+                i18n::warning().bright_yellow(),
+                Message::Hairline {
+                    weight: overlay::HAIRLINE_WEIGHT
+                }
             );
-            std::process::exit(1);
+            overlay::HAIRLINE_WEIGHT
         }
+        weight => weight.parse().unwrap(),
     };
 
-    debug!(?output_path);
-    debug!(?manuscript_path);
-    debug!(?trim_size);
+    let mut options = overlay::Options {
+        trim_width,
+        trim_height,
+        bleed,
+        mark_gap: *matches.get_one::<f64>("mark-gap").unwrap(),
+        mark_weight,
+        corner_radius: matches
+            .get_one::<f64>("corner-radius")
+            .copied()
+            .filter(|&radius| radius > 0.0),
+        mark_style: match matches.get_one::<String>("mark-style").unwrap().as_str() {
+            "japanese" => overlay::MarkStyle::Japanese,
+            _ => overlay::MarkStyle::Western,
+        },
+        page_order: matches.get_one::<Vec<usize>>("reorder").cloned(),
+        reverse: matches.get_flag("reverse"),
+        pages: matches.get_one::<overlay::PageSelection>("pages").cloned(),
+        keep_unselected: matches.get_flag("keep-unselected"),
+        only: matches.get_one::<overlay::OnlyPage>("only").copied(),
+        bare_pages: matches
+            .get_one::<overlay::PageSelection>("no-overlay-pages")
+            .cloned(),
+        trim_origin: matches.get_one::<(f64, f64)>("trim-origin").copied(),
+        strict: matches.get_flag("strict"),
+        slug_fit: match matches.get_one::<String>("slug-fit").unwrap().as_str() {
+            "strict" => overlay::SlugFit::Strict,
+            _ => overlay::SlugFit::Auto,
+        },
+        slug_size: match matches.get_one::<String>("slug-size").unwrap().as_str() {
+            "large" => overlay::SlugSize::Large,
+            _ => overlay::SlugSize::Normal,
+        },
+        slug_duplex: matches.get_flag("slug-duplex"),
+        tz_offset: matches.get_one::<i32>("tz-offset").copied(),
+        marks_only: matches.get_flag("marks-only"),
+        test_strip: matches
+            .get_one::<String>("test-strip")
+            .map(|inks| match inks.as_str() {
+                "cmyk" => marks::StripInks::Cmyk,
+                _ => marks::StripInks::Gray,
+            }),
+        color_bars: matches.get_flag("color-bars"),
+        plate_labels: matches.get_flag("plate-labels"),
+        center_marks: matches.get_flag("center-marks"),
+        trim_outline: matches.get_flag("show-trim-outline"),
+        center_crosshair: matches.get_flag("center-crosshair"),
+        drill: matches.get_one::<u64>("drill").map(|&count| marks::Drill {
+            count: count as usize,
+            diameter: *matches.get_one::<f64>("drill-diameter").unwrap(),
+            edge: match matches.get_one::<String>("drill-edge").unwrap().as_str() {
+                "left" => marks::DrillEdge::Left,
+                "right" => marks::DrillEdge::Right,
+                "top" => marks::DrillEdge::Top,
+                "bottom" => marks::DrillEdge::Bottom,
+                _ => marks::DrillEdge::Inside,
+            },
+            spacing: *matches.get_one::<f64>("drill-spacing").unwrap(),
+        }),
+        safety_margin: matches.get_one::<f64>("show-safety").copied(),
+        grid: matches.get_one::<f64>("grid").copied(),
+        rulers: matches
+            .get_one::<String>("rulers")
+            .map(|units| match units.as_str() {
+                "in" => marks::RulerUnits::In,
+                _ => marks::RulerUnits::Mm,
+            }),
+        folds: matches
+            .get_one::<Vec<marks::Fold>>("fold")
+            .cloned()
+            .unwrap_or_default(),
+        registration_marks: matches.get_flag("registration-marks"),
+        mark_packs,
+        content_box,
+        rules,
+        extend_bleed: matches.get_one::<String>("extend-bleed").map(|method| {
+            match method.as_str() {
+                "clone-edge" => overlay::BleedExtension::CloneEdge,
+                _ => overlay::BleedExtension::Mirror,
+            }
+        }),
+        compat: match matches.get_one::<String>("compat").unwrap().as_str() {
+            "legacy" => overlay::Compat::Legacy,
+            _ => overlay::Compat::Standard,
+        },
+        slug_note: matches
+            .get_one::<String>("slug-note")
+            .map(|note| note.replace("\\n", "\n")),
+        slug_chapters,
+        allow: matches
+            .get_many::<preflight::Category>("allow")
+            .map(|categories| categories.copied().collect())
+            .unwrap_or_default(),
+        ..overlay::Options::default()
+    };
 
-    // Combine the PDFs
-    overlay::combine(output_path, manuscript_path, trim_width, trim_height)?;
+    let landscape = matches.get_flag("landscape");
+    let sheet = match matches.get_one::<String>("sheet").unwrap().as_str() {
+        "auto" => {
+            let sheet = overlay::choose_sheet(&options, landscape);
+            say(
+                matches,
+                Message::PlacingOn {
+                    sheet: &sheet.0.to_uppercase(),
+                },
+            );
+            sheet
+        }
+        name => {
+            let (width, height) = units::sheet_size(name).unwrap();
+            if landscape {
+                (name, height, width)
+            } else {
+                (name, width, height)
+            }
+        }
+    };
+    let (name, sheet_width, sheet_height) = sheet;
+    options.sheet_width = sheet_width;
+    options.sheet_height = sheet_height;
 
-    info!("PDF combination completed successfully");
+    if trim_width > sheet_width || trim_height > sheet_height {
+        exit::fail(
+            Failure::Geometry,
+            Message::TrimDoesntFit {
+                width: trim_width / units::POINTS_PER_MM,
+                height: trim_height / units::POINTS_PER_MM,
+                sheet: &name.to_uppercase(),
+            },
+        );
+    }
 
-    Ok(())
+    // The same checks as options arriving over RPC are put through
+    if let Err(message) = options.validate() {
+        exit::fail(Failure::Usage, message);
+    }
+
+    options
 }
@@ -0,0 +1,823 @@
+use lopdf::content::Operation;
+use lopdf::{Object, StringFormat, dictionary};
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::{self, Layout, Rect, SLUG_MARGIN, SLUG_MIN_EDGE, TARGET_ARM, TARGET_RADIUS};
+use crate::plugins::KAPPA;
+use crate::units::{self, POINTS_PER_INCH, POINTS_PER_MM};
+
+/// Something drawn into the overlay on every page in addition to the crop
+/// marks and slug, such as a test strip.
+pub trait MarkRenderer {
+    /// Generate the PDF operations to draw this mark for a page laid out as
+    /// given. Text may be set in the overlay's font, resource "F1".
+    fn render(&self, layout: &Layout) -> Vec<Operation>;
+
+    /// Named color spaces the operations refer to, to be added to the
+    /// overlay's resources.
+    fn color_spaces(&self) -> Vec<(&'static str, Object)> {
+        Vec::new()
+    }
+}
+
+/// Colorants the test strip patches are printed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StripInks {
+    /// A single row of gray tints.
+    Gray,
+    /// One row each of cyan, magenta, yellow, and black tints.
+    Cmyk,
+}
+
+/// A row of tint patches from 10% to 100% in 10% steps, printed in the top
+/// margin so that operators can check their printer's tone reproduction on
+/// every proof.
+pub struct TestStrip {
+    pub inks: StripInks,
+}
+
+/// Size of each test strip patch, 5mm square.
+const PATCH_SIZE: f64 = 5.0 * POINTS_PER_MM;
+
+impl MarkRenderer for TestStrip {
+    fn render(&self, layout: &Layout) -> Vec<Operation> {
+        let mut ops = Vec::new();
+
+        let rows: &[Option<usize>] = match self.inks {
+            StripInks::Gray => &[None],
+            StripInks::Cmyk => &[Some(0), Some(1), Some(2), Some(3)],
+        };
+
+        let left = SLUG_MARGIN;
+        let top = layout.sheet.y2 - SLUG_MARGIN;
+
+        ops.push(Operation::new("q", vec![]));
+
+        for (row, channel) in rows.iter().enumerate() {
+            let y = top - (row + 1) as f64 * PATCH_SIZE;
+
+            for step in 1..=10 {
+                let tint = step as f64 / 10.0;
+                let x = left + (step - 1) as f64 * PATCH_SIZE;
+
+                match channel {
+                    // DeviceGray is additive, so 100% ink is 0.0
+                    None => ops.push(Operation::new("g", vec![(1.0 - tint).into()])),
+                    Some(channel) => {
+                        let mut components = vec![0.0.into(); 4];
+                        components[*channel] = tint.into();
+                        ops.push(Operation::new("k", components));
+                    }
+                }
+
+                ops.push(Operation::new(
+                    "re",
+                    vec![x.into(), y.into(), PATCH_SIZE.into(), PATCH_SIZE.into()],
+                ));
+                ops.push(Operation::new("f", vec![]));
+            }
+        }
+
+        ops.push(Operation::new("Q", vec![]));
+
+        ops
+    }
+}
+
+/// A column of control patches printed down the left margin, beside the
+/// trim: solids of each process ink, their two-color overprints, a gray
+/// balance patch of cyan, magenta, and yellow, and tints of black, so that
+/// press operators can check density and gray balance on every proof.
+pub struct ColorBars;
+
+/// The color bar's patches from the top, as DeviceCMYK.
+const COLOR_BAR_PATCHES: [[f64; 4]; 12] = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+    [1.0, 1.0, 0.0, 0.0],
+    [1.0, 0.0, 1.0, 0.0],
+    [0.0, 1.0, 1.0, 0.0],
+    [0.5, 0.4, 0.4, 0.0],
+    [0.0, 0.0, 0.0, 0.75],
+    [0.0, 0.0, 0.0, 0.5],
+    [0.0, 0.0, 0.0, 0.25],
+    [0.0, 0.0, 0.0, 0.0],
+];
+
+impl MarkRenderer for ColorBars {
+    fn render(&self, layout: &Layout) -> Vec<Operation> {
+        let mut ops = vec![Operation::new("q", vec![])];
+
+        let left = SLUG_MARGIN;
+        let height = COLOR_BAR_PATCHES.len() as f64 * PATCH_SIZE;
+        let top = (layout.sheet.y1 + layout.sheet.y2 + height) / 2.0;
+
+        for (index, cmyk) in COLOR_BAR_PATCHES.iter().enumerate() {
+            let y = top - (index + 1) as f64 * PATCH_SIZE;
+
+            ops.push(Operation::new(
+                "k",
+                cmyk.iter().map(|&v| v.into()).collect(),
+            ));
+            ops.push(Operation::new(
+                "re",
+                vec![left.into(), y.into(), PATCH_SIZE.into(), PATCH_SIZE.into()],
+            ));
+            ops.push(Operation::new("f", vec![]));
+        }
+
+        // Outline the column, so that the paper white patch shows where it is
+        ops.push(Operation::new(
+            "K",
+            vec![0.into(), 0.into(), 0.into(), 1.into()],
+        ));
+        ops.push(Operation::new("w", vec![0.25.into()]));
+        ops.push(Operation::new(
+            "re",
+            vec![
+                left.into(),
+                (top - height).into(),
+                PATCH_SIZE.into(),
+                height.into(),
+            ],
+        ));
+        ops.push(Operation::new("S", vec![]));
+
+        ops.push(Operation::new("Q", vec![]));
+        ops
+    }
+}
+
+/// A fold in a folded product, such as a tri-fold brochure.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Fold {
+    pub axis: FoldAxis,
+    pub at: FoldPosition,
+}
+
+/// Which way a fold runs across the trim.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FoldAxis {
+    /// From top to bottom, measured from the left edge of the trim.
+    Vertical,
+    /// From side to side, measured from the top edge of the trim.
+    Horizontal,
+}
+
+/// How far across the trim a fold falls.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FoldPosition {
+    /// A fraction of the way across, such as a third.
+    Fraction(f64),
+    /// A distance, in points.
+    Length(f64),
+}
+
+impl FoldPosition {
+    /// Distance of the fold from the edge it's measured from, across a trim
+    /// of the given length.
+    pub fn offset(&self, length: f64) -> f64 {
+        match self {
+            FoldPosition::Fraction(fraction) => fraction * length,
+            FoldPosition::Length(distance) => *distance,
+        }
+    }
+}
+
+/// Parse a list of folds separated by commas, each a fraction of the way
+/// across the trim such as "1/3" or a distance such as "99mm", from its left
+/// edge, or prefixed with "y:" for a horizontal fold, from its top edge.
+pub fn parse_folds(text: &str) -> Result<Vec<Fold>, String> {
+    text.split(',')
+        .map(|item| {
+            let item = item.trim();
+            let (axis, position) = match item.strip_prefix("y:") {
+                Some(position) => (FoldAxis::Horizontal, position),
+                None => (FoldAxis::Vertical, item.strip_prefix("x:").unwrap_or(item)),
+            };
+
+            let at = match position.split_once('/') {
+                Some((numerator, denominator)) => {
+                    let invalid =
+                        || format!("'{}' is not a fraction of the trim, such as 1/3", item);
+                    let numerator: f64 = numerator.trim().parse().map_err(|_| invalid())?;
+                    let denominator: f64 = denominator.trim().parse().map_err(|_| invalid())?;
+                    if !(numerator > 0.0 && numerator < denominator) {
+                        return Err(invalid());
+                    }
+                    FoldPosition::Fraction(numerator / denominator)
+                }
+                None => {
+                    let distance = units::parse_length(position)?;
+                    if distance <= 0.0 {
+                        return Err(format!("'{}' must be a distance into the trim", item));
+                    }
+                    FoldPosition::Length(distance)
+                }
+            };
+
+            Ok(Fold { axis, at })
+        })
+        .collect()
+}
+
+/// Dashed marks beyond the bleed at each end of each fold, so that a folded
+/// product can be proofed and folded by hand along them.
+pub struct FoldMarks {
+    pub folds: Vec<Fold>,
+    /// Gap between the bleed and the marks, the same as the crop marks'.
+    pub gap: f64,
+    /// Width of the strokes, the same as the crop marks'.
+    pub weight: f64,
+}
+
+impl MarkRenderer for FoldMarks {
+    fn render(&self, layout: &Layout) -> Vec<Operation> {
+        let mut ops = vec![
+            Operation::new("q", vec![]),
+            Operation::new("K", vec![0.into(), 0.into(), 0.into(), 1.into()]),
+            Operation::new("w", vec![self.weight.into()]),
+            Operation::new("d", vec![Object::Array(vec![3.into(), 2.into()]), 0.into()]),
+        ];
+
+        for mark in geometry::fold_mark_boxes(&layout.trim, &layout.bleed, self.gap, &self.folds) {
+            ops.push(Operation::new("m", vec![mark.x1.into(), mark.y1.into()]));
+            ops.push(Operation::new("l", vec![mark.x2.into(), mark.y2.into()]));
+            ops.push(Operation::new("S", vec![]));
+        }
+
+        ops.push(Operation::new("Q", vec![]));
+        ops
+    }
+}
+
+/// A thin dashed rectangle exactly on the trim, over the page content, so
+/// that what will be cut away can be seen without a physical proof. It's
+/// printed in magenta, as guides usually are, to stand out from the
+/// content.
+pub struct TrimOutline;
+
+/// Stroke width of the trim and safety outlines.
+const OUTLINE_WEIGHT: f64 = 0.25;
+
+impl MarkRenderer for TrimOutline {
+    fn render(&self, layout: &Layout) -> Vec<Operation> {
+        dashed_outline(&layout.trim, [0.0, 1.0, 0.0, 0.0])
+    }
+}
+
+/// A light dashed rectangle inset from the trim by a margin, marking the
+/// live area inside which text and anything else critical must stay, such
+/// as the 0.25in many print-on-demand services ask for.
+pub struct SafetyOutline {
+    /// Distance of the live area inside the trim, in points.
+    pub margin: f64,
+}
+
+impl MarkRenderer for SafetyOutline {
+    fn render(&self, layout: &Layout) -> Vec<Operation> {
+        let trim = &layout.trim;
+        let live = Rect {
+            x1: trim.x1 + self.margin,
+            y1: trim.y1 + self.margin,
+            x2: trim.x2 - self.margin,
+            y2: trim.y2 - self.margin,
+        };
+        if live.width() <= 0.0 || live.height() <= 0.0 {
+            return Vec::new();
+        }
+
+        // Half-strength cyan, lighter than the trim outline
+        dashed_outline(&live, [0.5, 0.0, 0.0, 0.0])
+    }
+}
+
+/// A faint grid across the trim, at a regular spacing from its top left
+/// corner, for checking the alignment of elements on printed proofs.
+pub struct Grid {
+    /// Distance between the lines, in points.
+    pub spacing: f64,
+}
+
+/// Stroke width of the grid lines, as fine as will reliably print.
+const GRID_WEIGHT: f64 = 0.1;
+
+/// Closest the grid lines may be, 1mm, so that a slip such as 1pt for 1in
+/// doesn't fill the page with ink.
+pub const GRID_MIN_SPACING: f64 = POINTS_PER_MM;
+
+/// Parse the spacing of the grid, a length of at least 1mm.
+pub fn parse_grid(text: &str) -> Result<f64, String> {
+    let spacing = units::parse_length(text)?;
+    if spacing < GRID_MIN_SPACING {
+        return Err(format!("'{}' is too fine a grid; the least is 1mm", text));
+    }
+    Ok(spacing)
+}
+
+impl MarkRenderer for Grid {
+    fn render(&self, layout: &Layout) -> Vec<Operation> {
+        let trim = &layout.trim;
+        // Options are validated before they get here, but a grid too fine
+        // would take unbounded time and memory to draw, so never go finer
+        let spacing = self.spacing.max(GRID_MIN_SPACING);
+        if !spacing.is_finite() {
+            return Vec::new();
+        }
+
+        let mut ops = vec![
+            Operation::new("q", vec![]),
+            Operation::new("K", vec![0.0.into(), 0.0.into(), 0.0.into(), 0.3.into()]),
+            Operation::new("w", vec![GRID_WEIGHT.into()]),
+        ];
+
+        let mut x = trim.x1 + spacing;
+        while x < trim.x2 {
+            ops.push(Operation::new("m", vec![x.into(), trim.y1.into()]));
+            ops.push(Operation::new("l", vec![x.into(), trim.y2.into()]));
+            x += spacing;
+        }
+        let mut y = trim.y2 - spacing;
+        while y > trim.y1 {
+            ops.push(Operation::new("m", vec![trim.x1.into(), y.into()]));
+            ops.push(Operation::new("l", vec![trim.x2.into(), y.into()]));
+            y -= spacing;
+        }
+        ops.push(Operation::new("S", vec![]));
+
+        ops.push(Operation::new("Q", vec![]));
+        ops
+    }
+}
+
+/// A small crosshair at the exact center of the trim, over the page content,
+/// so that holding a duplex proof up to the light shows whether the front
+/// and back line up.
+pub struct CenterCrosshair {
+    /// Width of the strokes, the same as the crop marks'.
+    pub weight: f64,
+}
+
+/// Length of each arm of the center crosshair, from its center, 3mm.
+const CROSSHAIR_ARM: f64 = 3.0 * POINTS_PER_MM;
+
+impl MarkRenderer for CenterCrosshair {
+    fn render(&self, layout: &Layout) -> Vec<Operation> {
+        let x = (layout.trim.x1 + layout.trim.x2) / 2.0;
+        let y = (layout.trim.y1 + layout.trim.y2) / 2.0;
+
+        vec![
+            Operation::new("q", vec![]),
+            Operation::new("K", vec![0.into(), 0.into(), 0.into(), 1.into()]),
+            Operation::new("w", vec![self.weight.into()]),
+            Operation::new("m", vec![(x - CROSSHAIR_ARM).into(), y.into()]),
+            Operation::new("l", vec![(x + CROSSHAIR_ARM).into(), y.into()]),
+            Operation::new("m", vec![x.into(), (y - CROSSHAIR_ARM).into()]),
+            Operation::new("l", vec![x.into(), (y + CROSSHAIR_ARM).into()]),
+            Operation::new("S", vec![]),
+            Operation::new("Q", vec![]),
+        ]
+    }
+}
+
+/// Holes to be drilled or punched through the sheets for binding.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Drill {
+    /// Number of holes.
+    pub count: usize,
+    /// Diameter of each hole, in points.
+    pub diameter: f64,
+    /// The edge of the trim the holes are along.
+    pub edge: DrillEdge,
+    /// Distance between the centers of neighbouring holes, in points.
+    pub spacing: f64,
+}
+
+/// Which edge of the trim holes are drilled along.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DrillEdge {
+    /// The binding edge: the left of recto pages, and the right of versos.
+    Inside,
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+/// Distance of the centers of drilled holes from the edge of the trim,
+/// 12mm, as ISO 838 has it.
+pub const DRILL_INSET: f64 = 12.0 * POINTS_PER_MM;
+
+/// A circle with a crosshair through it where each hole will be drilled, over
+/// the page content, so that hole positions can be checked against it
+/// before drilling. They're printed in magenta, like the other guides.
+pub struct DrillHoles {
+    pub drill: Drill,
+    /// The edge the holes are along on this page, inside resolved to left
+    /// or right.
+    pub edge: DrillEdge,
+    /// Width of the strokes, the same as the crop marks'.
+    pub weight: f64,
+}
+
+impl MarkRenderer for DrillHoles {
+    fn render(&self, layout: &Layout) -> Vec<Operation> {
+        let trim = &layout.trim;
+        let middle = ((trim.x1 + trim.x2) / 2.0, (trim.y1 + trim.y2) / 2.0);
+        let r = self.drill.diameter / 2.0;
+        let arm = r + POINTS_PER_MM;
+
+        let mut ops = vec![
+            Operation::new("q", vec![]),
+            Operation::new("K", vec![0.into(), 1.into(), 0.into(), 0.into()]),
+            Operation::new("w", vec![self.weight.into()]),
+        ];
+
+        // The holes are centered along the edge, and drawn from left to
+        // right or bottom to top, whichever edge they're on
+        for hole in 0..self.drill.count {
+            let along = (hole as f64 - (self.drill.count - 1) as f64 / 2.0) * self.drill.spacing;
+            let (x, y) = match self.edge {
+                DrillEdge::Right => (trim.x2 - DRILL_INSET, middle.1 + along),
+                DrillEdge::Top => (middle.0 + along, trim.y2 - DRILL_INSET),
+                DrillEdge::Bottom => (middle.0 + along, trim.y1 + DRILL_INSET),
+                DrillEdge::Left | DrillEdge::Inside => (trim.x1 + DRILL_INSET, middle.1 + along),
+            };
+
+            // Crosshair
+            ops.push(Operation::new("m", vec![(x - arm).into(), y.into()]));
+            ops.push(Operation::new("l", vec![(x + arm).into(), y.into()]));
+            ops.push(Operation::new("m", vec![x.into(), (y - arm).into()]));
+            ops.push(Operation::new("l", vec![x.into(), (y + arm).into()]));
+
+            ops.extend(circle(x, y, r));
+            ops.push(Operation::new("S", vec![]));
+        }
+
+        ops.push(Operation::new("Q", vec![]));
+        ops
+    }
+}
+
+/// A circle about (x, y) as four Bézier curves, to be stroked or filled.
+fn circle(x: f64, y: f64, r: f64) -> Vec<Operation> {
+    let k = r * KAPPA;
+
+    let mut ops = vec![Operation::new("m", vec![(x + r).into(), y.into()])];
+    for [x1, y1, x2, y2, x3, y3] in [
+        [x + r, y + k, x + k, y + r, x, y + r],
+        [x - k, y + r, x - r, y + k, x - r, y],
+        [x - r, y - k, x - k, y - r, x, y - r],
+        [x + k, y - r, x + r, y - k, x + r, y],
+    ] {
+        ops.push(Operation::new(
+            "c",
+            vec![
+                x1.into(),
+                y1.into(),
+                x2.into(),
+                y2.into(),
+                x3.into(),
+                y3.into(),
+            ],
+        ));
+    }
+    ops
+}
+
+/// Units the rulers are marked in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RulerUnits {
+    /// Ticks every millimetre, labeled every 10mm.
+    Mm,
+    /// Ticks every eighth of an inch, labeled every inch.
+    In,
+}
+
+impl RulerUnits {
+    /// The distance between ticks, in points, and the number of ticks to
+    /// each labeled unit and to each half of one.
+    fn ticks(self) -> (f64, usize, usize) {
+        match self {
+            RulerUnits::Mm => (POINTS_PER_MM, 10, 5),
+            RulerUnits::In => (POINTS_PER_INCH / 8.0, 8, 4),
+        }
+    }
+}
+
+/// Rulers along the top and left edges of the sheet, measuring across the
+/// trim from its top left corner, so that positions can be measured on
+/// printed proofs.
+pub struct Rulers {
+    pub units: RulerUnits,
+    /// Whether to number the rulers, which needs the overlay's font.
+    pub labels: bool,
+}
+
+/// Lengths of the rulers' ticks: each unit, each half unit, and the rest.
+const TICK_MAJOR: f64 = 3.0 * POINTS_PER_MM;
+const TICK_HALF: f64 = 2.0 * POINTS_PER_MM;
+const TICK_MINOR: f64 = POINTS_PER_MM;
+
+/// Font size of the rulers' numbers.
+const RULER_LABEL_SIZE: f64 = 5.0;
+
+/// How far a ruler reaches in from its edge of the sheet, with its numbers.
+pub const RULER_DEPTH: f64 = TICK_MAJOR + 1.0 + RULER_LABEL_SIZE;
+
+impl MarkRenderer for Rulers {
+    fn render(&self, layout: &Layout) -> Vec<Operation> {
+        let (sheet, trim) = (&layout.sheet, &layout.trim);
+        let (step, major, half) = self.units.ticks();
+        let length = |tick: usize| {
+            if tick.is_multiple_of(major) {
+                TICK_MAJOR
+            } else if tick.is_multiple_of(half) {
+                TICK_HALF
+            } else {
+                TICK_MINOR
+            }
+        };
+        let label = |tick: usize| match self.units {
+            RulerUnits::Mm => tick.to_string(),
+            RulerUnits::In => (tick / major).to_string(),
+        };
+        let across = (trim.width() / step + 1e-6).floor() as usize;
+        let down = (trim.height() / step + 1e-6).floor() as usize;
+
+        let mut ops = vec![
+            Operation::new("q", vec![]),
+            Operation::new("K", vec![0.into(), 0.into(), 0.into(), 1.into()]),
+            Operation::new("k", vec![0.into(), 0.into(), 0.into(), 1.into()]),
+            Operation::new("w", vec![0.25.into()]),
+        ];
+
+        // Along the top, from the left of the trim, ticks hanging down
+        let top = sheet.y2 - SLUG_MIN_EDGE;
+        ops.push(Operation::new("m", vec![trim.x1.into(), top.into()]));
+        ops.push(Operation::new("l", vec![trim.x2.into(), top.into()]));
+        for tick in 0..=across {
+            let x = trim.x1 + tick as f64 * step;
+            ops.push(Operation::new("m", vec![x.into(), top.into()]));
+            ops.push(Operation::new(
+                "l",
+                vec![x.into(), (top - length(tick)).into()],
+            ));
+        }
+
+        // Down the left, from the top of the trim, ticks pointing in
+        let left = sheet.x1 + SLUG_MIN_EDGE;
+        ops.push(Operation::new("m", vec![left.into(), trim.y2.into()]));
+        ops.push(Operation::new("l", vec![left.into(), trim.y1.into()]));
+        for tick in 0..=down {
+            let y = trim.y2 - tick as f64 * step;
+            ops.push(Operation::new("m", vec![left.into(), y.into()]));
+            ops.push(Operation::new(
+                "l",
+                vec![(left + length(tick)).into(), y.into()],
+            ));
+        }
+        ops.push(Operation::new("S", vec![]));
+
+        if self.labels {
+            let numbered = |count: usize| (major..=count).step_by(major);
+            let mut number = |x: f64, y: f64, text: String| {
+                ops.push(Operation::new("BT", vec![]));
+                ops.push(Operation::new(
+                    "Tf",
+                    vec!["F1".into(), RULER_LABEL_SIZE.into()],
+                ));
+                ops.push(Operation::new("Td", vec![x.into(), y.into()]));
+                ops.push(Operation::new(
+                    "Tj",
+                    vec![Object::String(text.into_bytes(), StringFormat::Literal)],
+                ));
+                ops.push(Operation::new("ET", vec![]));
+            };
+
+            // Each number beside its tick, inside the end of it
+            for tick in numbered(across) {
+                let x = trim.x1 + tick as f64 * step;
+                number(x + 1.0, top - RULER_DEPTH, label(tick));
+            }
+            for tick in numbered(down) {
+                let y = trim.y2 - tick as f64 * step;
+                number(left + TICK_MAJOR + 1.0, y + 1.0, label(tick));
+            }
+        }
+
+        ops.push(Operation::new("Q", vec![]));
+        ops
+    }
+}
+
+/// Operations stroking a rectangle with a thin dashed line of the given
+/// DeviceCMYK color.
+fn dashed_outline(rect: &Rect, cmyk: [f64; 4]) -> Vec<Operation> {
+    vec![
+        Operation::new("q", vec![]),
+        Operation::new("K", cmyk.iter().map(|&v| v.into()).collect()),
+        Operation::new("w", vec![OUTLINE_WEIGHT.into()]),
+        Operation::new("d", vec![Object::Array(vec![2.into(), 2.into()]), 0.into()]),
+        Operation::new(
+            "re",
+            vec![
+                rect.x1.into(),
+                rect.y1.into(),
+                rect.width().into(),
+                rect.height().into(),
+            ],
+        ),
+        Operation::new("S", vec![]),
+        Operation::new("Q", vec![]),
+    ]
+}
+
+/// Process inks, by their resource name, Separation colorant name, and
+/// DeviceCMYK equivalent.
+const PROCESS_INKS: [(&str, &str, [f64; 4]); 4] = [
+    ("C", "Cyan", [1.0, 0.0, 0.0, 0.0]),
+    ("M", "Magenta", [0.0, 1.0, 0.0, 0.0]),
+    ("Y", "Yellow", [0.0, 0.0, 1.0, 0.0]),
+    ("K", "Black", [0.0, 0.0, 0.0, 1.0]),
+];
+
+/// The letters "C M Y K", each printed in its own Separation color space,
+/// so that when a RIP produces separations each film or plate carries only
+/// its own letter and is self-identifying.
+pub struct PlateLabels;
+
+/// Font size of the plate labels.
+const LABEL_SIZE: f64 = 10.0;
+
+impl MarkRenderer for PlateLabels {
+    fn render(&self, layout: &Layout) -> Vec<Operation> {
+        let mut ops = Vec::new();
+
+        let baseline = layout.sheet.y2 - SLUG_MARGIN - LABEL_SIZE;
+        let spacing = LABEL_SIZE * 1.5;
+        let left = layout.sheet.x2 - SLUG_MARGIN - spacing * PROCESS_INKS.len() as f64;
+
+        for (index, (name, _, _)) in PROCESS_INKS.iter().enumerate() {
+            ops.push(Operation::new("BT", vec![]));
+            ops.push(Operation::new("Tf", vec!["F1".into(), LABEL_SIZE.into()]));
+            ops.push(Operation::new("cs", vec![color_space_name(name).into()]));
+            ops.push(Operation::new("scn", vec![1.into()]));
+            ops.push(Operation::new(
+                "Td",
+                vec![(left + index as f64 * spacing).into(), baseline.into()],
+            ));
+            ops.push(Operation::new(
+                "Tj",
+                vec![Object::String(
+                    name.as_bytes().to_vec(),
+                    StringFormat::Literal,
+                )],
+            ));
+            ops.push(Operation::new("ET", vec![]));
+        }
+
+        ops
+    }
+
+    fn color_spaces(&self) -> Vec<(&'static str, Object)> {
+        PROCESS_INKS
+            .iter()
+            .map(|(name, colorant, cmyk)| {
+                // Tint transform mapping the single tint component linearly
+                // onto the equivalent process color
+                let transform = dictionary! {
+                    "FunctionType" => 2,
+                    "Domain" => vec![0.into(), 1.into()],
+                    "C0" => vec![0.into(), 0.into(), 0.into(), 0.into()],
+                    "C1" => cmyk.iter().map(|v| Object::Real(*v as f32)).collect::<Vec<_>>(),
+                    "N" => 1,
+                };
+                let space = Object::Array(vec![
+                    "Separation".into(),
+                    (*colorant).into(),
+                    "DeviceCMYK".into(),
+                    Object::Dictionary(transform),
+                ]);
+                (color_space_name(name), space)
+            })
+            .collect()
+    }
+}
+
+/// Registration targets, a circle with a crosshair through it, at the
+/// middle of each edge beyond the bleed, for lining up plates with one
+/// another and the back of a duplex sheet with its front. They're printed in
+/// the Separation color All, so that they appear on every plate.
+pub struct RegistrationMarks {
+    /// Gap between the bleed and the targets, the same as the crop marks'.
+    pub gap: f64,
+    /// Width of the strokes, the same as the crop marks'.
+    pub weight: f64,
+}
+
+impl MarkRenderer for RegistrationMarks {
+    fn render(&self, layout: &Layout) -> Vec<Operation> {
+        let mut ops = vec![
+            Operation::new("q", vec![]),
+            Operation::new("CS", vec!["CSAll".into()]),
+            Operation::new("SCN", vec![1.into()]),
+            Operation::new("w", vec![self.weight.into()]),
+        ];
+
+        for (x, y) in geometry::target_centers(&layout.bleed, self.gap) {
+            // Crosshair
+            ops.push(Operation::new("m", vec![(x - TARGET_ARM).into(), y.into()]));
+            ops.push(Operation::new("l", vec![(x + TARGET_ARM).into(), y.into()]));
+            ops.push(Operation::new("m", vec![x.into(), (y - TARGET_ARM).into()]));
+            ops.push(Operation::new("l", vec![x.into(), (y + TARGET_ARM).into()]));
+
+            ops.extend(circle(x, y, TARGET_RADIUS));
+            ops.push(Operation::new("S", vec![]));
+        }
+
+        ops.push(Operation::new("Q", vec![]));
+        ops
+    }
+
+    fn color_spaces(&self) -> Vec<(&'static str, Object)> {
+        let transform = dictionary! {
+            "FunctionType" => 2,
+            "Domain" => vec![0.into(), 1.into()],
+            "C0" => vec![0.into(), 0.into(), 0.into(), 0.into()],
+            "C1" => vec![1.into(), 1.into(), 1.into(), 1.into()],
+            "N" => 1,
+        };
+        let space = Object::Array(vec![
+            "Separation".into(),
+            "All".into(),
+            "DeviceCMYK".into(),
+            Object::Dictionary(transform),
+        ]);
+        vec![("CSAll", space)]
+    }
+}
+
+/// Resource name under which the Separation color space for the given
+/// plate letter is registered.
+fn color_space_name(letter: &str) -> &'static str {
+    match letter {
+        "C" => "CSC",
+        "M" => "CSM",
+        "Y" => "CSY",
+        _ => "CSK",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_folds_fractions_and_lengths() {
+        assert_eq!(
+            parse_folds("1/3, y:99mm, x:2/3").unwrap(),
+            vec![
+                Fold {
+                    axis: FoldAxis::Vertical,
+                    at: FoldPosition::Fraction(1.0 / 3.0),
+                },
+                Fold {
+                    axis: FoldAxis::Horizontal,
+                    at: FoldPosition::Length(99.0 * POINTS_PER_MM),
+                },
+                Fold {
+                    axis: FoldAxis::Vertical,
+                    at: FoldPosition::Fraction(2.0 / 3.0),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_folds_outside_the_trim() {
+        // A fraction must fall within the trim; a length can only be
+        // checked against it once the trim is known
+        assert!(parse_folds("3/2").is_err());
+        assert!(parse_folds("1/1").is_err());
+        assert!(parse_folds("0/3").is_err());
+        assert!(parse_folds("0mm").is_err());
+        assert!(parse_folds("-5mm").is_err());
+    }
+
+    #[test]
+    fn parse_folds_malformed() {
+        assert!(parse_folds("").is_err());
+        assert!(parse_folds("1/3,").is_err());
+        assert!(parse_folds("third").is_err());
+        assert!(parse_folds("1/x").is_err());
+        assert!(parse_folds("z:1/3").is_err());
+    }
+}
@@ -1,87 +1,1770 @@
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use chrono::{Local, TimeZone};
+use chrono::{FixedOffset, Utc};
 use chrono_tz::Tz;
 use lopdf::content::{Content, Operation};
-use lopdf::{Document, Object, ObjectId, Stream, dictionary};
+use lopdf::xref::XrefType;
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream, dictionary};
+use serde::{Deserialize, Serialize};
 use tracing::info;
 
-use crate::fonts;
+use crate::chapters::{self, Chapters};
+use crate::checksums;
+use crate::fonts::{self, Metrics};
+use crate::geometry::{self, Layout, Rect, SLUG_LARGE_SIZE, SLUG_MIN_SIZE, SLUG_SIZE, Slug};
+use crate::i18n::{Length, Message, Pages};
+use crate::line::Placed;
+use crate::lock;
+use crate::marks::{
+    CenterCrosshair, ColorBars, Drill, DrillEdge, DrillHoles, Fold, FoldAxis, FoldMarks,
+    GRID_MIN_SPACING, Grid, MarkRenderer, PlateLabels, RegistrationMarks, RulerUnits, Rulers,
+    SafetyOutline, StripInks, TestStrip, TrimOutline,
+};
+use crate::plugins::{KAPPA, MarkPack};
+use crate::preflight::{self, Category};
+use crate::provenance::{self, Provenance};
+use crate::rules::Rule;
+use crate::spool;
+use crate::strip;
+use crate::units;
+
+/// Stages of a stamping job, reported through the progress callback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Loading,
+    Checking,
+    Stamping,
+    Compressing,
+    Saving,
+}
+
+/// Progress events emitted by combine() so that callers can keep users
+/// informed during long jobs.
+#[derive(Debug, Clone)]
+pub enum Progress {
+    /// A new phase of the job has begun.
+    Phase(Phase),
+    /// Stamping of the given page (counting from 1) has begun.
+    PageStarted { page: usize, total: usize },
+    /// Stamping of the given page (counting from 1) is complete.
+    PageFinished { page: usize, total: usize },
+    /// Something the user should know about, but which doesn't stop the job.
+    Warning(String),
+}
+
+/// Settings controlling how each manuscript page is placed and marked.
+///
+/// Any settings left out when deserializing take their defaults, those of
+/// the command line.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Options {
+    /// Width of the finished (trimmed) page, in points.
+    pub trim_width: f64,
+    /// Height of the finished (trimmed) page, in points.
+    pub trim_height: f64,
+    /// Width of the sheet each page is placed on, in points.
+    pub sheet_width: f64,
+    /// Height of the sheet each page is placed on, in points.
+    pub sheet_height: f64,
+    /// Bleed the manuscript is expected to carry beyond the trim, in points.
+    /// Crop marks are placed outside this area.
+    pub bleed: Bleed,
+    /// Gap between the edge of the bleed and the start of each crop mark,
+    /// in points.
+    pub mark_gap: f64,
+    /// Stroke width of the crop marks, in points.
+    pub mark_weight: f64,
+    /// Whether the crop marks are single or double lines.
+    pub mark_style: MarkStyle,
+    /// Radius of the rounded corners a die will cut, in points, marked with
+    /// an arc at each corner of the trim in place of the crop marks.
+    pub corner_radius: Option<f64>,
+    /// Order of the pages in the output, as manuscript page numbers counting
+    /// from 1. If not given, the manuscript's own order.
+    pub page_order: Option<Vec<usize>>,
+    /// Reverse the order of the pages in the output, after any reordering,
+    /// for duplexers which need reverse-collated files.
+    pub reverse: bool,
+    /// Which pages of the manuscript to stamp. If not given, all of them.
+    pub pages: Option<PageSelection>,
+    /// Pass the pages not selected through untouched, rather than leaving
+    /// them out of the output.
+    pub keep_unselected: bool,
+    /// Stamp this page alone, dropping the rest of the manuscript as soon as
+    /// it's loaded, so that none of it is checked or written. Quicker than
+    /// selecting the page with `pages` on a large manuscript.
+    pub only: Option<OnlyPage>,
+    /// Pages to place on the sheet without crop marks or a slug, such as
+    /// scans of letterhead whose own marks extra ones would be confused
+    /// with. If not given, every page is overlaid.
+    pub bare_pages: Option<PageSelection>,
+    /// Position of the bottom left corner of the trim on the sheet, in
+    /// points from the sheet's left and bottom edges. If not given, the trim
+    /// is centered on the sheet.
+    pub trim_origin: Option<(f64, f64)>,
+    /// Treat problems found when checking the manuscript as errors rather
+    /// than warnings.
+    pub strict: bool,
+    /// Whether the slug may be shrunk or moved to clear the page content.
+    pub slug_fit: SlugFit,
+    /// Size of the slug text: normal, or large print.
+    pub slug_size: SlugSize,
+    /// Print the slug at the top of every other sheet, so that once the
+    /// output is printed duplex the slugs all fall on the same edge of the
+    /// stack and come off in one cut.
+    pub slug_duplex: bool,
+    /// Print the crop marks alone, with no slug, so that no font is read or
+    /// embedded.
+    pub marks_only: bool,
+    /// Draw a strip of tint patches in the margin.
+    pub test_strip: Option<StripInks>,
+    /// Draw a column of color control patches in the left margin.
+    pub color_bars: bool,
+    /// Print "C M Y K" labels, each in its own separation, in the margin.
+    pub plate_labels: bool,
+    /// Draw a center mark at the middle of each edge of the trim as well as
+    /// the crop marks at its corners.
+    pub center_marks: bool,
+    /// Draw a dashed outline on the trim, over the page content.
+    pub trim_outline: bool,
+    /// Draw a small crosshair at the center of the trim, over the page
+    /// content.
+    pub center_crosshair: bool,
+    /// Holes to mark where they'll be drilled, over the page content.
+    pub drill: Option<Drill>,
+    /// Draw a dashed outline this far inside the trim, in points, marking
+    /// the live area.
+    pub safety_margin: Option<f64>,
+    /// Draw a faint grid across the trim at this spacing, in points.
+    pub grid: Option<f64>,
+    /// Draw rulers along the top and left edges of the sheet, in these
+    /// units.
+    pub rulers: Option<RulerUnits>,
+    /// Folds to draw dashed fold marks for, beyond the bleed at each end.
+    pub folds: Vec<Fold>,
+    /// Draw registration targets at the middle of each edge, beyond the
+    /// bleed.
+    pub registration_marks: bool,
+    /// Marks from installed mark packs, drawn as well as the crop marks.
+    pub mark_packs: Vec<MarkPack>,
+    /// Which of the manuscript's page boxes is its content.
+    pub content_box: ContentBox,
+    /// Additional preflight rules supplied by the user.
+    pub rules: Vec<Rule>,
+    /// Synthesize bleed for pages which lack it. Experimental, and lossy.
+    pub extend_bleed: Option<BleedExtension>,
+    /// Which PDF constructs the output may use.
+    pub compat: Compat,
+    /// Kinds of preflight warning known to be acceptable for this job, which
+    /// are suppressed.
+    pub allow: Vec<Category>,
+    /// Additional text printed above the slug, over as many lines as it has.
+    pub slug_note: Option<String>,
+    /// Where to find the title of the chapter each page is in, to print in
+    /// the slug after the filename, if it's to be.
+    pub slug_chapters: Option<Chapters>,
+    /// Producer to name in the output's metadata, in place of cropped's own
+    /// version.
+    pub producer: Option<String>,
+    /// Code printed after the page number in the slug, identifying this
+    /// output so that a leaked copy can be traced.
+    pub trace_code: Option<String>,
+    /// Offset from UTC, in seconds east, to give the slug's time in, in
+    /// place of the system's timezone.
+    pub tz_offset: Option<i32>,
+}
+
+/// How conservative to be in the PDF written, for the benefit of older RIPs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compat {
+    /// Whatever the manuscript and this program find convenient.
+    #[default]
+    Standard,
+    /// PDF 1.4 with a plain cross-reference table, no object streams, a
+    /// single content stream per page, and the marks drawn directly on the
+    /// page rather than in a Form XObject.
+    Legacy,
+}
+
+/// How to make up bleed for a page produced without it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BleedExtension {
+    /// Reflect the content along each edge out into the bleed, as if a
+    /// mirror were stood on the trim line. The corners of the bleed are
+    /// left empty.
+    Mirror,
+    /// Scale the page up just enough that its edge content fills the bleed,
+    /// losing a sliver of the content at the trim.
+    CloneEdge,
+}
+
+/// The page box of the manuscript which is taken to be its content, for
+/// placing it on the sheet and for checking bleed. Anything outside it is
+/// clipped away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ContentBox {
+    #[default]
+    #[serde(rename = "mediabox")]
+    Media,
+    #[serde(rename = "cropbox")]
+    Crop,
+    #[serde(rename = "trimbox")]
+    Trim,
+}
+
+/// How the crop marks are drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MarkStyle {
+    /// A single line beyond each edge at each corner, in line with the
+    /// trim.
+    #[default]
+    Western,
+    /// A pair of lines beyond each edge at each corner, one in line with the
+    /// trim and one with the edge of the bleed, as usual in Japan and much
+    /// of Asia (tombo).
+    Japanese,
+}
+
+/// What to do when the slug doesn't fit in the margin below the content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SlugFit {
+    /// Reduce the slug's font size and/or move it to the widest margin.
+    #[default]
+    Auto,
+    /// Leave the slug where it is and report the problem.
+    Strict,
+}
+
+/// Size of the slug text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SlugSize {
+    #[default]
+    Normal,
+    /// Large print, for reading proofs at arm's length or with impaired
+    /// sight: bigger, in the bold weight where it's installed, and never
+    /// shrunk to fit.
+    Large,
+}
+
+impl SlugSize {
+    /// Font size the slug is set at.
+    pub fn size(self) -> f64 {
+        match self {
+            SlugSize::Normal => SLUG_SIZE,
+            SlugSize::Large => SLUG_LARGE_SIZE,
+        }
+    }
+
+    /// Smallest font size the slug may be shrunk to when fitting it into a
+    /// narrow margin. Large print isn't shrunk at all, only moved.
+    pub fn min_size(self) -> f64 {
+        match self {
+            SlugSize::Normal => SLUG_MIN_SIZE,
+            SlugSize::Large => SLUG_LARGE_SIZE,
+        }
+    }
+
+    /// Weight of the font the slug is set in.
+    pub fn weight(self) -> fonts::Weight {
+        match self {
+            SlugSize::Normal => fonts::Weight::Regular,
+            SlugSize::Large => fonts::Weight::Bold,
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            trim_width: 432.0,
+            trim_height: 648.0,
+            sheet_width: 595.0,
+            sheet_height: 842.0,
+            bleed: Bleed::default(),
+            mark_gap: MARK_GAP,
+            mark_weight: MARK_WEIGHT,
+            mark_style: MarkStyle::default(),
+            corner_radius: None,
+            page_order: None,
+            reverse: false,
+            pages: None,
+            keep_unselected: false,
+            only: None,
+            bare_pages: None,
+            trim_origin: None,
+            strict: false,
+            slug_fit: SlugFit::default(),
+            slug_size: SlugSize::default(),
+            slug_duplex: false,
+            marks_only: false,
+            test_strip: None,
+            color_bars: false,
+            plate_labels: false,
+            center_marks: false,
+            trim_outline: false,
+            center_crosshair: false,
+            drill: None,
+            safety_margin: None,
+            grid: None,
+            rulers: None,
+            folds: Vec::new(),
+            registration_marks: false,
+            mark_packs: Vec::new(),
+            content_box: ContentBox::default(),
+            rules: Vec::new(),
+            extend_bleed: None,
+            compat: Compat::default(),
+            allow: Vec::new(),
+            slug_note: None,
+            slug_chapters: None,
+            producer: None,
+            trace_code: None,
+            tz_offset: None,
+        }
+    }
+}
+
+impl Options {
+    /// The page number as printed at the right of the slug, with any trace
+    /// code after it.
+    pub fn page_text(&self, page_num: usize, total_pages: usize) -> String {
+        match &self.trace_code {
+            Some(code) => format!("{}/{} · {}", page_num, total_pages, code),
+            None => format!("{}/{}", page_num, total_pages),
+        }
+    }
+
+    /// The lines of the slug note, top first.
+    pub fn slug_note_lines(&self) -> Vec<&str> {
+        self.slug_note
+            .as_deref()
+            .map(|note| note.lines().collect())
+            .unwrap_or_default()
+    }
+
+    /// Check for settings no run could be made with, such as a negative
+    /// bleed or a trim larger than the sheet. The command line refuses most
+    /// of these as it parses them, but options can also arrive whole, as in
+    /// a JSON-RPC request, so they're checked again here for every caller.
+    pub fn validate(&self) -> Result<(), String> {
+        let length = |length: Length, value: f64| {
+            if value.is_finite() && value >= 0.0 {
+                Ok(())
+            } else {
+                Err(Message::NotALength { length, value }.to_string())
+            }
+        };
+        let page_num = |pages: Pages, page_num: usize| {
+            if page_num > 0 {
+                Ok(())
+            } else {
+                Err(Message::PagesFromOne { pages }.to_string())
+            }
+        };
+
+        let positive = |value: f64| value > 0.0 && value.is_finite();
+        if !(positive(self.trim_width) && positive(self.trim_height)) {
+            return Err(Message::TrimNotPositive.to_string());
+        }
+        let sheet = |value: f64| value > 0.0 && value <= MAX_SHEET_SIZE;
+        if !(sheet(self.sheet_width) && sheet(self.sheet_height)) {
+            return Err(Message::SheetOutOfRange {
+                max: MAX_SHEET_SIZE,
+            }
+            .to_string());
+        }
+        if self.trim_width > self.sheet_width || self.trim_height > self.sheet_height {
+            return Err(Message::TrimOffSheet {
+                trim_width: self.trim_width,
+                trim_height: self.trim_height,
+                sheet_width: self.sheet_width,
+                sheet_height: self.sheet_height,
+            }
+            .to_string());
+        }
+
+        length(Length::TopBleed, self.bleed.top)?;
+        length(Length::BottomBleed, self.bleed.bottom)?;
+        length(Length::InsideBleed, self.bleed.inside)?;
+        length(Length::OutsideBleed, self.bleed.outside)?;
+        length(Length::MarkGap, self.mark_gap)?;
+        length(Length::MarkWeight, self.mark_weight)?;
+        if let Some(radius) = self.corner_radius {
+            length(Length::CornerRadius, radius)?;
+        }
+        if let Some(margin) = self.safety_margin {
+            length(Length::SafetyMargin, margin)?;
+        }
+        if let Some(spacing) = self.grid
+            && !(spacing >= GRID_MIN_SPACING && spacing.is_finite())
+        {
+            return Err(Message::GridTooFine { spacing }.to_string());
+        }
+        if let Some((x, y)) = self.trim_origin
+            && !(x.is_finite() && y.is_finite())
+        {
+            return Err(Message::NotAPosition { x, y }.to_string());
+        }
+
+        for fold in &self.folds {
+            let across = match fold.axis {
+                FoldAxis::Vertical => self.trim_width,
+                FoldAxis::Horizontal => self.trim_height,
+            };
+            let offset = fold.at.offset(across);
+            if !(offset > 0.0 && offset < across) {
+                return Err(Message::FoldOutsideTrim { offset, across }.to_string());
+            }
+        }
+
+        for &number in self.page_order.iter().flatten() {
+            page_num(Pages::Order, number)?;
+        }
+        for selection in [&self.pages, &self.bare_pages].into_iter().flatten() {
+            for range in &selection.0 {
+                if let PageRange::Span(first, last) = *range {
+                    page_num(Pages::Selection, first)?;
+                    if let Some(last) = last.filter(|&last| last < first) {
+                        return Err(Message::BackwardRange { first, last }.to_string());
+                    }
+                }
+            }
+        }
+        if let Some(OnlyPage::Page(number)) = self.only {
+            page_num(Pages::Only, number)?;
+        }
+
+        if let Some(drill) = self.drill {
+            if drill.count == 0 {
+                return Err(Message::NoHoles.to_string());
+            }
+            if !positive(drill.diameter) {
+                return Err(Message::DrillDiameter {
+                    diameter: drill.diameter,
+                }
+                .to_string());
+            }
+            length(Length::DrillSpacing, drill.spacing)?;
+        }
+
+        if let Some(seconds) = self.tz_offset
+            && seconds.abs() > 14 * 3600
+        {
+            return Err(Message::NotAnOffset { seconds }.to_string());
+        }
+
+        Ok(())
+    }
+
+    /// The additional marks to draw on the given page, counting from 1, as
+    /// selected by the options.
+    pub fn renderers(&self, page_num: usize) -> Vec<Box<dyn MarkRenderer>> {
+        let mut renderers: Vec<Box<dyn MarkRenderer>> = Vec::new();
+
+        if let Some(inks) = self.test_strip {
+            renderers.push(Box::new(TestStrip { inks }));
+        }
+        if self.color_bars {
+            renderers.push(Box::new(ColorBars));
+        }
+        if self.plate_labels {
+            renderers.push(Box::new(PlateLabels));
+        }
+        if let Some(units) = self.rulers {
+            renderers.push(Box::new(Rulers {
+                units,
+                labels: !self.marks_only,
+            }));
+        }
+        if let Some(spacing) = self.grid {
+            renderers.push(Box::new(Grid { spacing }));
+        }
+        if self.trim_outline {
+            renderers.push(Box::new(TrimOutline));
+        }
+        if self.center_crosshair {
+            renderers.push(Box::new(CenterCrosshair {
+                weight: self.mark_weight,
+            }));
+        }
+        if let Some(drill) = self.drill {
+            // The binding edge is on the left of recto pages
+            let edge = match drill.edge {
+                DrillEdge::Inside if page_num % 2 == 1 => DrillEdge::Left,
+                DrillEdge::Inside => DrillEdge::Right,
+                edge => edge,
+            };
+            renderers.push(Box::new(DrillHoles {
+                drill,
+                edge,
+                weight: self.mark_weight,
+            }));
+        }
+        if let Some(margin) = self.safety_margin {
+            renderers.push(Box::new(SafetyOutline { margin }));
+        }
+        if !self.folds.is_empty() {
+            renderers.push(Box::new(FoldMarks {
+                folds: self.folds.clone(),
+                gap: self.mark_gap,
+                weight: self.mark_weight,
+            }));
+        }
+        if self.registration_marks {
+            renderers.push(Box::new(RegistrationMarks {
+                gap: self.mark_gap,
+                weight: self.mark_weight,
+            }));
+        }
+        for pack in &self.mark_packs {
+            renderers.push(Box::new(pack.clone()));
+        }
+
+        renderers
+    }
+
+    /// Position of the bottom left corner of the trim area on the sheet.
+    pub fn trim_position(&self) -> (f64, f64) {
+        match self.trim_origin {
+            Some(origin) => origin,
+            None => (
+                (self.sheet_width - self.trim_width) / 2.0,
+                (self.sheet_height - self.trim_height) / 2.0,
+            ),
+        }
+    }
+
+    /// Whether the given page of the manuscript, counting from 1, is to be
+    /// stamped.
+    pub fn selected(&self, page_num: usize) -> bool {
+        self.pages
+            .as_ref()
+            .is_none_or(|selection| selection.contains(page_num))
+    }
+
+    /// Whether the given page, counting from 1, gets crop marks and a slug,
+    /// rather than only being placed on the sheet.
+    pub fn overlaid(&self, page_num: usize) -> bool {
+        self.bare_pages
+            .as_ref()
+            .is_none_or(|selection| !selection.contains(page_num))
+    }
+
+    /// Whether the trim area and its crop marks fit on the sheet.
+    pub fn marks_fit(&self) -> bool {
+        // Check both a recto and a verso page, since asymmetric bleed moves
+        // the marks between them
+        [1, 2].into_iter().all(|page_num| {
+            let layout = geometry::layout(self, page_num, self.trim_width, self.trim_height);
+            layout.marks.iter().all(|mark| layout.sheet.contains(mark))
+        })
+    }
+}
+
+/// Sheets automatic selection steps up through, smallest first: ISO sizes
+/// for metric trims, and US sizes for trims in whole eighths of an inch.
+const METRIC_SHEETS: [&str; 3] = ["a4", "a3", "sra3"];
+const US_SHEETS: [&str; 2] = ["letter", "tabloid"];
+
+/// Choose the smallest sheet on which the trim area and crop marks of every
+/// page fit, in the given orientation, returning its name, width, and
+/// height. If none is large enough the largest is chosen, and preflight will
+/// report the marks off the sheet.
+pub fn choose_sheet(options: &Options, landscape: bool) -> (&'static str, f64, f64) {
+    let eighths = |length: f64| {
+        let eighths = length / (units::POINTS_PER_INCH / 8.0);
+        (eighths - eighths.round()).abs() < 0.01
+    };
+    let series: &[&str] = if eighths(options.trim_width) && eighths(options.trim_height) {
+        &US_SHEETS
+    } else {
+        &METRIC_SHEETS
+    };
+
+    let sized = |name: &'static str| {
+        let (width, height) = units::sheet_size(name).unwrap();
+        if landscape {
+            (name, height, width)
+        } else {
+            (name, width, height)
+        }
+    };
+
+    series
+        .iter()
+        .map(|name| sized(name))
+        .find(|&(_, sheet_width, sheet_height)| {
+            Options {
+                sheet_width,
+                sheet_height,
+                ..options.clone()
+            }
+            .marks_fit()
+        })
+        .unwrap_or_else(|| sized(series[series.len() - 1]))
+}
+
+/// Bleed on each edge of the trim.
+///
+/// The inside and outside edges are relative to the spine: on recto (odd)
+/// pages the inside is the left edge, on verso (even) pages it is the right.
+/// Perfect-bound interiors commonly have no bleed on the inside edge.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Bleed {
+    pub top: f64,
+    pub bottom: f64,
+    pub inside: f64,
+    pub outside: f64,
+}
+
+/// A value for each edge of a rectangle on the physical page.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Edges {
+    pub left: f64,
+    pub right: f64,
+    pub bottom: f64,
+    pub top: f64,
+}
 
-/// Add crop marks to a manuscript PDF by expanding pages to A4 and drawing lines.
+impl Bleed {
+    /// The same bleed on every edge.
+    pub fn uniform(length: f64) -> Bleed {
+        Bleed {
+            top: length,
+            bottom: length,
+            inside: length,
+            outside: length,
+        }
+    }
+
+    /// Parse a bleed given on the command line. Either a single length
+    /// applying to all edges ("3mm") or a comma separated list of per-edge
+    /// lengths ("top=3mm,outside=3mm,bottom=3mm,inside=0"); edges not
+    /// mentioned in the list have no bleed.
+    pub fn parse(text: &str) -> Result<Bleed, String> {
+        if !text.contains('=') {
+            return Ok(Bleed::uniform(units::parse_length(text)?));
+        }
+
+        let mut bleed = Bleed::default();
+
+        for part in text.split(',') {
+            let (edge, length) = part
+                .split_once('=')
+                .ok_or_else(|| format!("Expected EDGE=LENGTH, not '{}'", part))?;
+            let length = units::parse_length(length)?;
+
+            match edge.trim() {
+                "top" => bleed.top = length,
+                "bottom" => bleed.bottom = length,
+                "inside" => bleed.inside = length,
+                "outside" => bleed.outside = length,
+                other => {
+                    return Err(format!(
+                        "Unknown edge '{}'. Supported: top, bottom, inside, outside",
+                        other
+                    ));
+                }
+            }
+        }
+
+        Ok(bleed)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        *self == Bleed::default()
+    }
+
+    /// Resolve inside and outside to left and right for the given page,
+    /// counting from 1.
+    pub fn edges(&self, page_num: usize) -> Edges {
+        let recto = page_num % 2 == 1;
+        Edges {
+            left: if recto { self.inside } else { self.outside },
+            right: if recto { self.outside } else { self.inside },
+            bottom: self.bottom,
+            top: self.top,
+        }
+    }
+}
+
+/// Name under which the slug font is added to a page's own resources in
+/// legacy mode, chosen not to collide with the manuscript's font names.
+pub const LEGACY_FONT_NAME: &str = "FCropped";
+
+/// Default gap between the edge of the bleed area and the start of each
+/// crop mark.
+pub const MARK_GAP: f64 = 5.0;
+
+/// Length of each crop mark.
+pub const MARK_LENGTH: f64 = 20.0;
+
+/// Default stroke width of the crop marks.
+pub const MARK_WEIGHT: f64 = 0.5;
+
+/// Stroke width used in place of a zero-width hairline, which each RIP
+/// renders as thin as its device allows: invisibly on a platesetter.
+pub const HAIRLINE_WEIGHT: f64 = 0.25;
+
+/// Largest sheet a page may be placed on, along either side: 200 inches,
+/// the most a PDF page can be.
+pub const MAX_SHEET_SIZE: f64 = 14400.0;
+
+/// The manuscript or output path standing for standard input or output, so
+/// that stamping can sit in a pipeline.
+pub const STDIO: &str = "-";
+
+/// Whether a path stands for standard input or output.
+pub fn is_stdio(path: &Path) -> bool {
+    path == Path::new(STDIO)
+}
+
+/// Save a document by way of a temporary file in the same directory, renamed
+/// over the output only once it's complete, so that an interrupted run
+/// never leaves a truncated PDF behind in place of the output.
+pub fn save_atomically(document: &mut Document, output_path: &Path) -> lopdf::Result<()> {
+    write_atomically(output_path, |file| {
+        let mut writer = BufWriter::new(file);
+        document
+            .save_to(&mut writer)
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        writer.flush()
+    })
+    .map_err(lopdf::Error::IO)
+}
+
+/// Write a file by way of a temporary file in the same directory, renamed
+/// over it once complete. Should the file be changed by some other process
+/// in the meantime, the rename is abandoned rather than losing that change.
+pub fn write_atomically(
+    path: &Path,
+    write: impl FnOnce(&mut fs::File) -> io::Result<()>,
+) -> io::Result<()> {
+    let directory = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let Some(name) = path.file_name() else {
+        return Err(io::Error::other(format!(
+            "{} is not a file",
+            path.display()
+        )));
+    };
+    let temporary = directory.join(format!(
+        ".{}.{}.tmp",
+        name.to_string_lossy(),
+        std::process::id()
+    ));
+
+    let before = lock::Snapshot::of(path);
+
+    let result = fs::File::create(&temporary)
+        .and_then(|mut file| {
+            write(&mut file)?;
+            file.sync_all()
+        })
+        .and_then(|()| before.check(path))
+        .and_then(|()| fs::rename(&temporary, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+
+    result
+}
+
+/// Add crop marks to a manuscript PDF by expanding pages to the sheet and
+/// drawing lines.
 ///
 /// Uses a "stamping" approach: the manuscript document is the primary file,
 /// preserving its structure, metadata, and page tree. For each manuscript
 /// page we:
 ///
-/// - Expand the MediaBox to A4
+/// - Expand the MediaBox to the sheet, A4 unless another was chosen
 /// - Wrap the original content in a transformation to center it
 /// - Draw crop marks at the trim size corners
 ///
 /// The original manuscript's content streams (i.e. individual pages) are
-/// never decoded or modified, minimizing risk of corruption. Crop marks are
-/// generated programmatically via native PDF drawing operations.
+/// never modified, and only read by preflight, minimizing risk of
+/// corruption. Crop marks are generated programmatically via native PDF
+/// drawing operations.
 ///
 /// The trim size (e.g., 6"×9") defines where crop marks are placed. The actual
 /// content may be larger (with bleed) and will be centered accordingly.
+///
+/// The `on_progress` callback is invoked as each phase begins and as each
+/// page is stamped. Setting `cancel` aborts the job at the next page
+/// boundary; nothing is written to the output path if the job is cancelled
+/// before saving begins.
+///
+/// Returns the number of pages stamped.
 pub fn combine(
     output_path: &Path,
     manuscript_path: &Path,
-    trim_width: f64,
-    trim_height: f64,
-) -> lopdf::Result<()> {
-    let mut manuscript_document = Document::load(manuscript_path)?;
+    options: &Options,
+    mut on_progress: impl FnMut(Progress),
+    cancel: &AtomicBool,
+) -> lopdf::Result<usize> {
+    let job = Job::load(manuscript_path, options, &mut on_progress)?;
+    let preflight = job.preflight(&mut on_progress)?;
+    if !preflight.warnings.is_empty() {
+        info!("Preflight raised {} warning(s)", preflight.warnings.len());
+    }
+    let stamped = job.stamp(&preflight, &mut on_progress, cancel)?;
+    stamped.save(output_path, &mut on_progress, cancel)
+}
+
+/// A manuscript loaded and ready to be checked and stamped, the first stage
+/// of combine().
+///
+/// Each stage takes the previous one's result, so that a caller can show
+/// the preflight results before committing to the transformation, or check
+/// one manuscript against several sets of options without reloading it.
+pub struct Job<'a> {
+    options: &'a Options,
+    manuscript_path: PathBuf,
+    /// Digest of the manuscript as read, where it was read whole rather
+    /// than handed over already loaded.
+    manuscript_sha256: Option<String>,
+    document: Document,
+    page_ids: Vec<ObjectId>,
+    /// Number in the manuscript of the first page loaded, and how many pages
+    /// the manuscript has, for numbering the pages; 1 and the number loaded
+    /// unless only one page was kept.
+    numbering: (usize, usize),
+    /// The slug's font, as embedded, and its metrics, unless there's to be
+    /// no slug.
+    font: Option<(ObjectId, Metrics)>,
+    timestamp: String,
+    filename: String,
+    /// The title of each chapter, by the page it starts on, if they're to be
+    /// printed in the slug.
+    chapters: BTreeMap<usize, String>,
+}
+
+/// The results of checking a loaded manuscript: where the slug will go,
+/// the order of the output pages, and any warnings raised.
+#[derive(Debug, Clone, Serialize)]
+pub struct Preflight {
+    /// Where the slug goes, unless there's to be none.
+    pub slug: Option<Slug>,
+    /// Whether each page lands on the back of a sheet, where a duplex slug
+    /// is flipped to the top.
+    pub backs: Vec<bool>,
+    pub order: Option<Vec<usize>>,
+    pub warnings: Vec<String>,
+}
+
+/// Where everything lands on the sheet for one page, for drawing a preview
+/// of the stamp without making it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Geometry {
+    #[serde(flatten)]
+    pub layout: Layout,
+    pub slug: Option<Slug>,
+    /// The boxes occupied by the fields of the slug.
+    pub slug_boxes: Vec<Rect>,
+}
+
+/// The slug as printed on a page: where it goes, what it says, and the font
+/// it's set in.
+pub struct SlugText<'a> {
+    pub slug: Slug,
+    pub font_id: ObjectId,
+    pub metrics: &'a Metrics,
+    pub timestamp: &'a str,
+    /// The text at its center, the manuscript's filename and perhaps the
+    /// chapter.
+    pub center: &'a str,
+}
+
+/// A stamped document, ready to be saved.
+pub struct Stamped {
+    document: Document,
+    pages: usize,
+}
+
+impl<'a> Job<'a> {
+    /// Load the manuscript and prepare it for stamping. A manuscript path of
+    /// `-` reads it from standard input.
+    pub fn load(
+        manuscript_path: &Path,
+        options: &'a Options,
+        on_progress: &mut impl FnMut(Progress),
+    ) -> lopdf::Result<Job<'a>> {
+        on_progress(Progress::Phase(Phase::Loading));
+
+        // A manuscript from standard input may be large, and is read in a
+        // stream; one from a file is small enough to read whole
+        let (document, sha256) = if is_stdio(manuscript_path) {
+            let spooled = spool::stdin().map_err(lopdf::Error::IO)?;
+            (spooled.load()?, spooled.sha256.clone())
+        } else {
+            let bytes = fs::read(manuscript_path).map_err(lopdf::Error::IO)?;
+            (Document::load_mem(&bytes)?, checksums::sha256_bytes(&bytes))
+        };
+
+        info!("Manuscript loaded");
+
+        let mut job = Job::from_document(document, manuscript_path, options, on_progress)?;
+        job.manuscript_sha256 = Some(sha256);
+        Ok(job)
+    }
+
+    /// Prepare a manuscript already loaded, such as a copy of one kept open
+    /// to be stamped repeatedly with different options.
+    pub fn from_document(
+        mut document: Document,
+        manuscript_path: &Path,
+        options: &'a Options,
+        on_progress: &mut impl FnMut(Progress),
+    ) -> lopdf::Result<Job<'a>> {
+        // Chapters are found by page in the manuscript as a whole
+        let page_ids: Vec<ObjectId> = document.page_iter().collect();
+        let chapters = chapter_titles(&document, &page_ids, options);
+
+        // Drop every page but the one asked for, before any work is done on
+        // them, and everything only they used
+        let mut numbering = (1, page_ids.len());
+        if let Some(only) = options.only {
+            let page_num = only.page_num(page_ids.len());
+            if page_num == 0 || page_num > page_ids.len() {
+                return Err(lopdf::Error::IO(io::Error::other(format!(
+                    "No page {}; the manuscript has {} pages",
+                    page_num,
+                    page_ids.len()
+                ))));
+            }
+            let others: Vec<u32> = (1..=page_ids.len() as u32)
+                .filter(|&other| other as usize != page_num)
+                .collect();
+            document.delete_pages(&others);
+            document.prune_objects();
+            numbering.0 = page_num;
+            info!("Kept page {} alone", page_num);
+        }
+
+        // Embed Inconsolata font once for all pages (TrueType with
+        // WinAnsiEncoding), unless there's no slug to set in it
+        let font = if options.marks_only {
+            None
+        } else {
+            let font = fonts::embed_font(&mut document, options.slug_size.weight())?;
+            info!("Font embedded");
+            Some(font)
+        };
+
+        // Report where the marks start, for checking against the printer's
+        // requirements
+        let offsets = geometry::mark_offsets(&options.bleed.edges(1), options.mark_gap);
+        info!(
+            "Crop marks start {:.2}mm beyond the bleed: {:.2}mm left, {:.2}mm right, {:.2}mm bottom, {:.2}mm top of the trim",
+            options.mark_gap / units::POINTS_PER_MM,
+            offsets.left / units::POINTS_PER_MM,
+            offsets.right / units::POINTS_PER_MM,
+            offsets.bottom / units::POINTS_PER_MM,
+            offsets.top / units::POINTS_PER_MM,
+        );
+
+        // Calculate timestamp once for all pages
+        let timestamp = timestamp(options, on_progress);
+
+        let filename = manuscript_path
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap_or("-")
+            .to_string();
+
+        let page_ids: Vec<ObjectId> = document.page_iter().collect();
+
+        Ok(Job {
+            options,
+            manuscript_path: manuscript_path.to_path_buf(),
+            manuscript_sha256: None,
+            document,
+            page_ids,
+            numbering,
+            font,
+            timestamp,
+            filename,
+            chapters,
+        })
+    }
+
+    /// Number of pages in the manuscript.
+    pub fn pages(&self) -> usize {
+        self.page_ids.len()
+    }
+
+    /// Check the manuscript against the options, placing the slug. Warnings
+    /// are reported through the progress callback as they're found, and
+    /// collected in the result; under strict mode the first is an error.
+    pub fn preflight(&self, on_progress: &mut impl FnMut(Progress)) -> lopdf::Result<Preflight> {
+        on_progress(Progress::Phase(Phase::Checking));
+
+        let order = output_order(self.options, self.pages())?;
+
+        // Place the slug, and check it with the longest text it will have
+        // at its center
+        let placed = match &self.font {
+            Some((font_id, metrics)) => {
+                let slug = place_slug(&self.document, &self.page_ids, self.options, metrics)?;
+                let (first, _) = self.numbering;
+                let widest_center = (first..first + self.pages())
+                    .map(|page_num| slug_center(&self.filename, &self.chapters, page_num))
+                    .max_by(|a, b| metrics.measure(a, 1.0).total_cmp(&metrics.measure(b, 1.0)))
+                    .unwrap_or_else(|| self.filename.clone());
+                Some((slug, *font_id, metrics, widest_center))
+            }
+            None => None,
+        };
+        let text = placed
+            .as_ref()
+            .map(|(slug, font_id, metrics, center)| SlugText {
+                slug: *slug,
+                font_id: *font_id,
+                metrics,
+                timestamp: &self.timestamp,
+                center,
+            });
+
+        let mut warnings = Vec::new();
+        preflight::check(
+            &self.document,
+            &self.page_ids,
+            self.options,
+            text.as_ref(),
+            &mut |progress| {
+                if let Progress::Warning(message) = &progress {
+                    warnings.push(message.clone());
+                }
+                on_progress(progress);
+            },
+        )?;
+
+        Ok(Preflight {
+            slug: placed.map(|(slug, ..)| slug),
+            backs: backs(self.options, self.pages())?,
+            order,
+            warnings,
+        })
+    }
+
+    /// Stamp each page of the manuscript as the preflight placed it.
+    pub fn stamp(
+        mut self,
+        preflight: &Preflight,
+        on_progress: &mut impl FnMut(Progress),
+        cancel: &AtomicBool,
+    ) -> lopdf::Result<Stamped> {
+        on_progress(Progress::Phase(Phase::Stamping));
+
+        let options = self.options;
+        let total_pages = self.pages();
+        let (first, manuscript_pages) = self.numbering;
+
+        let mut stamped = 0;
+        let reservations = reserve_page_objects(&mut self.document, total_pages, options);
+        for (index, (page_id, reserved)) in self
+            .page_ids
+            .iter()
+            .zip(reservations)
+            .enumerate()
+            .filter(|(index, _)| options.selected(first + index))
+        {
+            check_cancelled(cancel)?;
+
+            let page_num = first + index;
+            on_progress(Progress::PageStarted {
+                page: page_num,
+                total: manuscript_pages,
+            });
+
+            let center = slug_center(&self.filename, &self.chapters, page_num);
+            let text = preflight
+                .slug
+                .zip(self.font.as_ref())
+                .map(|(slug, (font_id, metrics))| SlugText {
+                    slug: slug_on(&slug, &preflight.backs, options, index),
+                    font_id: *font_id,
+                    metrics,
+                    timestamp: &self.timestamp,
+                    center: &center,
+                });
+
+            stamp_page(
+                &mut self.document,
+                *page_id,
+                reserved,
+                options,
+                text.as_ref(),
+                page_num,
+                manuscript_pages,
+            )?;
+
+            on_progress(Progress::PageFinished {
+                page: page_num,
+                total: manuscript_pages,
+            });
+            stamped += 1;
+        }
+
+        check_cancelled(cancel)?;
+
+        if let Some(order) = &preflight.order {
+            reorder_pages(&mut self.document, &self.page_ids, order)?;
+        }
+
+        let output_pages = preflight.order.as_ref().map_or(total_pages, Vec::len);
+        let manuscript_sha256 = match self.manuscript_sha256 {
+            Some(digest) => digest,
+            None => checksums::sha256_file(&self.manuscript_path).map_err(lopdf::Error::IO)?,
+        };
+        let provenance = Provenance::new(
+            &self.manuscript_path,
+            manuscript_sha256,
+            options,
+            &self.timestamp,
+            output_pages,
+        )?;
+        provenance::record(&mut self.document, &provenance)?;
+
+        let producer = options
+            .producer
+            .clone()
+            .unwrap_or_else(provenance::default_producer);
+        provenance::record_producer(&mut self.document, &producer)?;
+
+        if options.compat == Compat::Legacy {
+            downgrade(&mut self.document);
+        }
+
+        Ok(Stamped {
+            document: self.document,
+            pages: stamped,
+        })
+    }
+}
+
+impl Stamped {
+    /// Compress and save the document, to standard output if the path is
+    /// `-`. Returns the number of pages stamped.
+    pub fn save(
+        mut self,
+        output_path: &Path,
+        on_progress: &mut impl FnMut(Progress),
+        cancel: &AtomicBool,
+    ) -> lopdf::Result<usize> {
+        on_progress(Progress::Phase(Phase::Compressing));
+        self.document.compress();
+
+        check_cancelled(cancel)?;
+
+        on_progress(Progress::Phase(Phase::Saving));
+
+        info!("Save output");
+        if is_stdio(output_path) {
+            let mut stdout = BufWriter::new(io::stdout().lock());
+            self.document.save_to(&mut stdout)?;
+            stdout.flush().map_err(lopdf::Error::IO)?;
+        } else {
+            save_atomically(&mut self.document, output_path)?;
+        }
+
+        Ok(self.pages)
+    }
+}
+
+/// The time now, as printed in the slug.
+///
+/// The time is worked out from UTC in the timezone database built into
+/// chrono-tz, rather than the system's, which containers often lack, so it
+/// is right wherever the zone's name can be found. Where it can't, the time
+/// is given in UTC, labeled as such, with a notice, unless an offset is
+/// given.
+pub fn timestamp(options: &Options, on_progress: &mut impl FnMut(Progress)) -> String {
+    // Format: YYYY-MM-DD HH:MM:SS ZZZZ (where ZZZZ is timezone abbreviation like AEDT)
+    let now = Utc::now();
+
+    if let Some(offset) = options.tz_offset.and_then(FixedOffset::east_opt) {
+        return format!(
+            "{} UTC{}",
+            now.with_timezone(&offset).format("%Y-%m-%d %H:%M:%S"),
+            offset
+        );
+    }
+
+    let tz: Tz = match iana_time_zone::get_timezone() {
+        Ok(tz_name) => tz_name.parse().unwrap_or_else(|_| {
+            preflight::notice(
+                options,
+                on_progress,
+                Category::UnknownTimezone,
+                format!(
+                    "Unrecognized timezone '{}', using UTC; give --tz-offset for local time",
+                    tz_name
+                ),
+            );
+            chrono_tz::UTC
+        }),
+        Err(e) => {
+            preflight::notice(
+                options,
+                on_progress,
+                Category::UnknownTimezone,
+                format!(
+                    "Couldn't find the system's timezone ({}), using UTC; give --tz-offset for local time",
+                    e
+                ),
+            );
+            chrono_tz::UTC
+        }
+    };
+    let local = now.with_timezone(&tz);
+
+    format!(
+        "{} {}",
+        local.format("%Y-%m-%d %H:%M:%S"),
+        local.format("%Z")
+    )
+}
+
+/// Parse an offset from UTC, such as "+10:00", "-0530", or "+9", into
+/// seconds east.
+pub fn parse_tz_offset(text: &str) -> Result<i32, String> {
+    let invalid = || {
+        format!(
+            "Expected an offset from UTC such as +10:00 or -05:30, not '{}'",
+            text
+        )
+    };
+    let text = text.trim();
+
+    let (sign, rest) = if let Some(rest) = text.strip_prefix('+') {
+        (1, rest)
+    } else if let Some(rest) = text.strip_prefix('-') {
+        (-1, rest)
+    } else {
+        return Err(invalid());
+    };
+    let (hours, minutes) = match rest.split_once(':') {
+        Some((hours, minutes)) => (hours, minutes),
+        None if rest.len() == 4 => rest.split_at(2),
+        None => (rest, "0"),
+    };
+    let hours: i32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: i32 = minutes.parse().map_err(|_| invalid())?;
+    if !(0..=14).contains(&hours) || !(0..60).contains(&minutes) {
+        return Err(invalid());
+    }
+
+    Ok(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Work out where everything will land on the sheet for the given page of
+/// a manuscript, counting from 1, without checking or stamping anything.
+/// This is quick enough to do on every change of options, for a live
+/// preview.
+///
+/// `metrics` are those of the slug's font, and `filename` and `timestamp`
+/// what the slug will show.
+pub fn geometry(
+    document: &Document,
+    options: &Options,
+    metrics: &Metrics,
+    filename: &str,
+    timestamp: &str,
+    page_num: usize,
+) -> lopdf::Result<Geometry> {
+    let page_ids: Vec<ObjectId> = document.page_iter().collect();
+    let Some(&page_id) = page_num
+        .checked_sub(1)
+        .and_then(|index| page_ids.get(index))
+    else {
+        return Err(lopdf::Error::IO(io::Error::other(format!(
+            "No page {}; the manuscript has {} pages",
+            page_num,
+            page_ids.len()
+        ))));
+    };
+
+    let source = content_box(document, page_id, options.content_box)?;
+    let layout = geometry::layout(options, page_num, source.width(), source.height());
+    if options.marks_only || !options.overlaid(page_num) {
+        return Ok(Geometry {
+            layout,
+            slug: None,
+            slug_boxes: Vec::new(),
+        });
+    }
+
+    let slug = place_slug(document, &page_ids, options, metrics)?;
+    let slug = slug_on(
+        &slug,
+        &backs(options, page_ids.len())?,
+        options,
+        page_num - 1,
+    );
+    let page_text = options.page_text(page_num, page_ids.len());
+    let chapters = chapter_titles(document, &page_ids, options);
+    let slug_boxes = geometry::slug_boxes(
+        &slug,
+        options.sheet_width,
+        metrics,
+        timestamp,
+        &slug_center(filename, &chapters, page_num),
+        &page_text,
+        &options.slug_note_lines(),
+    );
+
+    Ok(Geometry {
+        layout,
+        slug: Some(slug),
+        slug_boxes,
+    })
+}
+
+/// The title of each chapter of the manuscript by the page it starts on, if
+/// they're to be printed in the slug.
+fn chapter_titles(
+    document: &Document,
+    page_ids: &[ObjectId],
+    options: &Options,
+) -> BTreeMap<usize, String> {
+    options
+        .slug_chapters
+        .as_ref()
+        .map(|source| chapters::titles(document, page_ids, source))
+        .unwrap_or_default()
+}
+
+/// The text at the center of the slug on a page: the manuscript's filename,
+/// followed by the title of the chapter the page is in, if there is one.
+fn slug_center(filename: &str, chapters: &BTreeMap<usize, String>, page_num: usize) -> String {
+    match chapters::title(chapters, page_num) {
+        Some(title) => format!("{} · {}", filename, title),
+        None => filename.to_string(),
+    }
+}
+
+/// Whether each page of the manuscript, by index, lands on the back of a
+/// sheet: on an even sheet of the output, as reordered and selected, rather
+/// than at an even page of the manuscript. None do unless the slug is
+/// duplex, and nor do pages left out of the output.
+pub fn backs(options: &Options, total_pages: usize) -> lopdf::Result<Vec<bool>> {
+    let mut backs = vec![false; total_pages];
+    if !options.slug_duplex {
+        return Ok(backs);
+    }
+
+    let order = output_order(options, total_pages)?.unwrap_or_else(|| (0..total_pages).collect());
+    for (sheet, index) in order.into_iter().enumerate() {
+        backs[index] = sheet % 2 == 1;
+    }
+    Ok(backs)
+}
+
+/// The slug as placed on the page at the given index: flipped to the top of
+/// the sheet if the page lands on the back of one.
+fn slug_on(slug: &Slug, backs: &[bool], options: &Options, index: usize) -> Slug {
+    if backs.get(index).copied().unwrap_or(false) {
+        slug.flipped(options.sheet_height)
+    } else {
+        *slug
+    }
+}
+
+/// Place the slug, with room for any note, clear of the content of every
+/// page if it can be.
+fn place_slug(
+    document: &Document,
+    page_ids: &[ObjectId],
+    options: &Options,
+    metrics: &Metrics,
+) -> lopdf::Result<Slug> {
+    let slug = Slug::new(
+        options.slug_note_lines().len() + 1,
+        metrics.leading,
+        options.slug_size.size(),
+    );
+    preflight::fit_slug(document, page_ids, options, slug)
+}
+
+/// The one page of the manuscript to stamp with `--only`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnlyPage {
+    First,
+    Last,
+    /// A page number, counting from 1.
+    Page(usize),
+}
+
+impl OnlyPage {
+    /// Parse the page given on the command line: first, last, or a number.
+    pub fn parse(text: &str) -> Result<OnlyPage, String> {
+        match text.trim() {
+            "first" => Ok(OnlyPage::First),
+            "last" => Ok(OnlyPage::Last),
+            number => match number.parse::<usize>() {
+                Ok(page_num) if page_num > 0 => Ok(OnlyPage::Page(page_num)),
+                _ => Err(format!(
+                    "'{}' is not first, last, or a page number counting from 1",
+                    number
+                )),
+            },
+        }
+    }
+
+    /// The number of the page, counting from 1, in a manuscript of the
+    /// given number of pages.
+    fn page_num(self, total_pages: usize) -> usize {
+        match self {
+            OnlyPage::First => 1,
+            OnlyPage::Last => total_pages,
+            OnlyPage::Page(page_num) => page_num,
+        }
+    }
+}
+
+/// A selection of manuscript pages, such as "1-10,15,20-", "odd", or
+/// "even", as a list of the ranges making it up.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PageSelection(pub Vec<PageRange>);
+
+/// One part of a page selection.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PageRange {
+    Odd,
+    Even,
+    /// Pages from the first to the last, inclusive, counting from 1; with no
+    /// last page, to the end of the manuscript.
+    Span(usize, Option<usize>),
+}
+
+impl PageSelection {
+    /// Parse a selection given on the command line.
+    pub fn parse(text: &str) -> Result<PageSelection, String> {
+        let number = |text: &str| match text.trim().parse::<usize>() {
+            Ok(page_num) if page_num > 0 => Ok(page_num),
+            _ => Err(format!(
+                "'{}' is not a page number, counting from 1",
+                text.trim()
+            )),
+        };
+
+        text.split(',')
+            .map(|part| match part.trim() {
+                "odd" => Ok(PageRange::Odd),
+                "even" => Ok(PageRange::Even),
+                part => match part.split_once('-') {
+                    Some((first, "")) => Ok(PageRange::Span(number(first)?, None)),
+                    Some((first, last)) => {
+                        let (first, last) = (number(first)?, number(last)?);
+                        if last < first {
+                            return Err(format!("The range {} runs backward", part));
+                        }
+                        Ok(PageRange::Span(first, Some(last)))
+                    }
+                    None => {
+                        let page_num = number(part)?;
+                        Ok(PageRange::Span(page_num, Some(page_num)))
+                    }
+                },
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(PageSelection)
+    }
+
+    /// Whether the given page, counting from 1, is selected.
+    pub fn contains(&self, page_num: usize) -> bool {
+        self.0.iter().any(|range| match *range {
+            PageRange::Odd => page_num % 2 == 1,
+            PageRange::Even => page_num.is_multiple_of(2),
+            PageRange::Span(first, last) => {
+                page_num >= first && last.is_none_or(|last| page_num <= last)
+            }
+        })
+    }
+}
+
+/// Parse a page order given on the command line, such as "2,1,4,3", into
+/// page numbers counting from 1.
+pub fn parse_page_order(text: &str) -> Result<Vec<usize>, String> {
+    text.split(',')
+        .map(|number| match number.trim().parse::<usize>() {
+            Ok(page_num) if page_num > 0 => Ok(page_num),
+            _ => Err(format!("'{}' is not a page number", number.trim())),
+        })
+        .collect()
+}
+
+/// The order in which the stamped pages are to appear in the output, as
+/// indices into the manuscript's pages, or None to leave them as they are.
+pub fn output_order(options: &Options, total_pages: usize) -> lopdf::Result<Option<Vec<usize>>> {
+    let mut order: Vec<usize> = match &options.page_order {
+        Some(page_order) => {
+            let mut seen = vec![false; total_pages];
+            for &page_num in page_order {
+                if page_num > total_pages {
+                    return Err(lopdf::Error::IO(io::Error::other(format!(
+                        "Page {} in --reorder is beyond the end of the {}-page manuscript",
+                        page_num, total_pages
+                    ))));
+                }
+                if std::mem::replace(&mut seen[page_num - 1], true) {
+                    return Err(lopdf::Error::IO(io::Error::other(format!(
+                        "Page {} appears more than once in --reorder",
+                        page_num
+                    ))));
+                }
+            }
+            if let Some(missing) = seen.iter().position(|seen| !seen) {
+                return Err(lopdf::Error::IO(io::Error::other(format!(
+                    "Page {} is missing from --reorder, which must list every page",
+                    missing + 1
+                ))));
+            }
+            page_order.iter().map(|page_num| page_num - 1).collect()
+        }
+        None if options.reverse || options.pages.is_some() => (0..total_pages).collect(),
+        None => return Ok(None),
+    };
+
+    if options.reverse {
+        order.reverse();
+    }
+
+    if !(1..=total_pages).any(|page_num| options.selected(page_num)) {
+        return Err(lopdf::Error::IO(io::Error::other(format!(
+            "--pages selects none of the {} pages of the manuscript",
+            total_pages
+        ))));
+    }
+
+    // Pages not selected are left out, unless they're to be kept as they are
+    if !options.keep_unselected {
+        order.retain(|index| options.selected(index + 1));
+    }
+
+    Ok(Some(order))
+}
+
+/// Rewrite the page tree so that the pages appear in the given order, as
+/// indices into `page_ids`.
+///
+/// The tree is flattened into a single Pages node. Attributes the pages
+/// inherited from intermediate nodes are copied onto each page first, so
+/// every page keeps its content, resources, and boxes exactly as before.
+fn reorder_pages(doc: &mut Document, page_ids: &[ObjectId], order: &[usize]) -> lopdf::Result<()> {
+    let pages_id = doc.catalog()?.get(b"Pages")?.as_reference()?;
+
+    for &page_id in page_ids {
+        for key in [&b"Resources"[..], b"MediaBox", b"CropBox", b"Rotate"] {
+            if doc.get_dictionary(page_id)?.has(key) {
+                continue;
+            }
+
+            // Find the value as stored, so that shared resources stay
+            // shared rather than being copied onto every page
+            let mut value = None;
+            let mut node = doc.get_dictionary(page_id)?;
+            for _ in 0..64 {
+                let Ok(parent) = node.get(b"Parent").and_then(Object::as_reference) else {
+                    break;
+                };
+                node = doc.get_dictionary(parent)?;
+                if let Ok(found) = node.get(key) {
+                    value = Some(found.clone());
+                    break;
+                }
+            }
+
+            if let Some(value) = value {
+                doc.get_dictionary_mut(page_id)?.set(key, value);
+            }
+        }
+        doc.get_dictionary_mut(page_id)?.set("Parent", pages_id);
+    }
+
+    let kids: Vec<Object> = order
+        .iter()
+        .map(|&index| Object::Reference(page_ids[index]))
+        .collect();
+    let pages = doc.get_dictionary_mut(pages_id)?;
+    pages.set("Count", kids.len() as i64);
+    pages.set("Kids", kids);
+
+    // Intermediate nodes of the old tree are no longer referenced
+    doc.prune_objects();
+
+    Ok(())
+}
+
+/// Bring the document down to what a PDF 1.4 reader expects: no object or
+/// cross-reference streams, and a version header to match. The content
+/// streams the flattened pages no longer refer to are dropped too.
+fn downgrade(doc: &mut Document) {
+    doc.version = "1.4".to_string();
+    doc.reference_table.cross_reference_type = XrefType::CrossReferenceTable;
+    doc.trailer.remove(b"XRefStm");
+
+    doc.objects.retain(|_, object| {
+        !matches!(
+            object.as_stream().and_then(|stream| stream.dict.get(b"Type")),
+            Ok(Object::Name(name)) if name == b"ObjStm" || name == b"XRef"
+        )
+    });
+
+    doc.prune_objects();
+}
+
+/// Read the box of a page which is to be treated as its content.
+///
+/// Missing boxes default as the PDF specification describes: the CropBox to
+/// the MediaBox, and the TrimBox to the CropBox.
+pub fn content_box(doc: &Document, page_id: ObjectId, which: ContentBox) -> lopdf::Result<Rect> {
+    let keys: &[&[u8]] = match which {
+        ContentBox::Media => &[b"MediaBox"],
+        ContentBox::Crop => &[b"CropBox", b"MediaBox"],
+        ContentBox::Trim => &[b"TrimBox", b"CropBox", b"MediaBox"],
+    };
 
-    info!("Manuscript loaded");
+    for key in keys {
+        if let Some(object) = inherited(doc, page_id, key) {
+            let [x1, y1, x2, y2] = read_box(object)?;
+            return Ok(Rect { x1, y1, x2, y2 });
+        }
+    }
 
-    // Embed Inconsolata font once for all pages (TrueType with WinAnsiEncoding)
-    let (font_id, char_width) = fonts::embed_font(&mut manuscript_document)?;
-    info!("Font embedded");
+    Err(lopdf::Error::PageNumberNotFound(0))
+}
 
-    // Calculate timestamp once for all pages
-    // Format: YYYY-MM-DD HH:MM:SS ZZZZ (where ZZZZ is timezone abbreviation like AEDT)
-    let now = Local::now();
-
-    // Get timezone abbreviation using chrono-tz
-    // Parse the system timezone name and use it to get proper abbreviation
-    let tz_name = iana_time_zone::get_timezone().unwrap_or_else(|_| "UTC".to_string());
-    let tz: Tz = tz_name.parse().unwrap_or(chrono_tz::UTC);
-    let now_with_tz = tz
-        .from_local_datetime(&now.naive_local())
-        .single()
-        .unwrap_or_else(|| tz.from_utc_datetime(&now.naive_utc()));
-    let tz_abbrev = now_with_tz.format("%Z").to_string();
-
-    let timestamp = format!("{} {}", now.format("%Y-%m-%d %H:%M:%S"), tz_abbrev);
-
-    let filename = manuscript_path.file_name().unwrap().to_str().unwrap_or("-");
-
-    // Process each manuscript page
-    let page_ids: Vec<ObjectId> = manuscript_document.page_iter().collect();
-    let total_pages = page_ids.len();
-    for (index, page_id) in page_ids.iter().enumerate() {
-        stamp_page(
-            &mut manuscript_document,
-            *page_id,
-            trim_width,
-            trim_height,
-            font_id,
-            char_width,
-            &timestamp,
-            filename,
-            index + 1,
-            total_pages,
-        )?;
+/// Read a rectangle such as a MediaBox, in the form [x1, y1, x2, y2].
+///
+/// The specification allows any two opposite corners to be given, so boxes
+/// such as [0 842 595 0] turn up in the wild; the result is normalized so
+/// that x1 ≤ x2 and y1 ≤ y2.
+pub fn read_box(object: &Object) -> lopdf::Result<[f64; 4]> {
+    match object {
+        Object::Array(arr) if arr.len() == 4 => {
+            // Convert to f64 handling both Integer and Real types
+            let to_f64 = |obj: &Object| -> lopdf::Result<f64> {
+                match obj {
+                    Object::Integer(i) => Ok(*i as f64),
+                    Object::Real(r) => Ok(*r as f64),
+                    _ => Err(lopdf::Error::PageNumberNotFound(0)),
+                }
+            };
+            let (ax, ay) = (to_f64(&arr[0])?, to_f64(&arr[1])?);
+            let (bx, by) = (to_f64(&arr[2])?, to_f64(&arr[3])?);
+            Ok([ax.min(bx), ay.min(by), ax.max(bx), ay.max(by)])
+        }
+        _ => Err(lopdf::Error::PageNumberNotFound(0)),
     }
+}
+
+/// Look up an attribute of a page, following the Parent chain for
+/// attributes such as Resources and MediaBox which may be inherited from
+/// the page tree.
+pub fn inherited<'a>(doc: &'a Document, page_id: ObjectId, key: &[u8]) -> Option<&'a Object> {
+    let mut node = doc.get_dictionary(page_id).ok()?;
+
+    // Bounded, in case of a malformed page tree with a cycle in it
+    for _ in 0..64 {
+        if let Ok(value) = node.get(key) {
+            return match value {
+                Object::Reference(id) => doc.get_object(*id).ok(),
+                _ => Some(value),
+            };
+        }
 
-    manuscript_document.compress();
+        let parent = node.get(b"Parent").and_then(Object::as_reference).ok()?;
+        node = doc.get_dictionary(parent).ok()?;
+    }
 
-    info!("Save output");
-    manuscript_document.save(output_path)?;
+    None
+}
 
+/// Returns an Interrupted error if the job has been cancelled.
+fn check_cancelled(cancel: &AtomicBool) -> lopdf::Result<()> {
+    if cancel.load(Ordering::Relaxed) {
+        info!("Cancelled");
+        return Err(lopdf::Error::IO(io::Error::new(
+            io::ErrorKind::Interrupted,
+            "Stamping cancelled",
+        )));
+    }
     Ok(())
 }
 
+/// Object IDs set aside for the new objects created when stamping a single
+/// page.
+///
+/// Every page needs the same fixed set of objects, so rather than allocating
+/// IDs as each page is stamped (which would make the numbering depend on the
+/// order in which pages are processed) we reserve a contiguous block per page
+/// up front. Page N always receives the same IDs regardless of when or on
+/// which thread it is stamped, keeping output byte-for-byte reproducible.
+struct PageObjects {
+    font_resources: ObjectId,
+    overlay: ObjectId,
+    xobjects: ObjectId,
+    start: ObjectId,
+    end: ObjectId,
+    /// Wrapper streams for the reflected copies of the content along each
+    /// edge, when extending bleed by mirroring.
+    mirrors: Vec<ObjectId>,
+}
+
+/// Number of objects stamp_page() creates for each page, not counting those
+/// needed to extend bleed.
+const OBJECTS_PER_PAGE: u32 = 5;
+
+/// Reserve object IDs for the given number of pages, in page order, above
+/// the highest ID currently in use in the document.
+fn reserve_page_objects(doc: &mut Document, count: usize, options: &Options) -> Vec<PageObjects> {
+    let base = doc.max_id + 1;
+
+    let mirrors = match options.extend_bleed {
+        Some(BleedExtension::Mirror) => 4,
+        _ => 0,
+    };
+    let per_page = OBJECTS_PER_PAGE + mirrors;
+
+    let reservations = (0..count as u32)
+        .map(|index| {
+            let first = base + index * per_page;
+            PageObjects {
+                font_resources: (first, 0),
+                overlay: (first + 1, 0),
+                xobjects: (first + 2, 0),
+                start: (first + 3, 0),
+                end: (first + 4, 0),
+                mirrors: (0..mirrors)
+                    .map(|i| (first + OBJECTS_PER_PAGE + i, 0))
+                    .collect(),
+            }
+        })
+        .collect();
+
+    doc.max_id = base + count as u32 * per_page - 1;
+
+    reservations
+}
+
 /// Generate PDF operations to draw crop marks at the corners of the given
 /// content area.
 ///
@@ -89,24 +1772,41 @@ pub fn combine(
 /// * `content_y` - Bottom edge of content area
 /// * `content_width` - Width of content area
 /// * `content_height` - Height of content area
+/// * `offsets` - Gap between each content edge and the start of the crop
+///   marks on that side
+/// * `weight` - Stroke width of the marks; a zero-width hairline is drawn
+///   at HAIRLINE_WEIGHT instead
+/// * `center` - Whether to draw a center mark at the middle of each edge as
+///   well
 ///
-fn generate_crop_marks(
+pub fn generate_crop_marks(
     content_x: f64,
     content_y: f64,
     content_width: f64,
     content_height: f64,
+    offsets: &Edges,
+    weight: f64,
+    center: bool,
 ) -> Vec<Operation> {
     let mut ops = Vec::new();
 
-    // Set crop line width (0.5 pt is reasonably standard)
-    ops.push(Operation::new("w", vec![0.5.into()]));
+    // Set crop line width, never a true hairline
+    let weight = if weight > 0.0 {
+        weight
+    } else {
+        HAIRLINE_WEIGHT
+    };
+    ops.push(Operation::new("w", vec![weight.into()]));
 
-    // Set stroke color to black (in gray scale)
-    ops.push(Operation::new("G", vec![0.into()]));
+    // Set stroke color to solid black, 100% K. Never a tint or gray, which
+    // would be screened and print as a broken line
+    ops.push(Operation::new(
+        "K",
+        vec![0.into(), 0.into(), 0.into(), 1.into()],
+    ));
 
     // Crop mark length extending outside content area
-    let mark_length = 20.0;
-    let mark_offset = 5.0; // Gap between content edge and crop mark
+    let mark_length = MARK_LENGTH;
 
     // Calculate corner positions
     let left = content_x;
@@ -118,21 +1818,21 @@ fn generate_crop_marks(
     // Horizontal mark (left side)
     ops.push(Operation::new(
         "m",
-        vec![(left - mark_offset - mark_length).into(), bottom.into()],
+        vec![(left - offsets.left - mark_length).into(), bottom.into()],
     ));
     ops.push(Operation::new(
         "l",
-        vec![(left - mark_offset).into(), bottom.into()],
+        vec![(left - offsets.left).into(), bottom.into()],
     ));
     ops.push(Operation::new("S", vec![]));
     // Vertical mark (bottom side)
     ops.push(Operation::new(
         "m",
-        vec![left.into(), (bottom - mark_offset - mark_length).into()],
+        vec![left.into(), (bottom - offsets.bottom - mark_length).into()],
     ));
     ops.push(Operation::new(
         "l",
-        vec![left.into(), (bottom - mark_offset).into()],
+        vec![left.into(), (bottom - offsets.bottom).into()],
     ));
     ops.push(Operation::new("S", vec![]));
 
@@ -140,21 +1840,21 @@ fn generate_crop_marks(
     // Horizontal mark (right side)
     ops.push(Operation::new(
         "m",
-        vec![(right + mark_offset).into(), bottom.into()],
+        vec![(right + offsets.right).into(), bottom.into()],
     ));
     ops.push(Operation::new(
         "l",
-        vec![(right + mark_offset + mark_length).into(), bottom.into()],
+        vec![(right + offsets.right + mark_length).into(), bottom.into()],
     ));
     ops.push(Operation::new("S", vec![]));
     // Vertical mark (bottom side)
     ops.push(Operation::new(
         "m",
-        vec![right.into(), (bottom - mark_offset - mark_length).into()],
+        vec![right.into(), (bottom - offsets.bottom - mark_length).into()],
     ));
     ops.push(Operation::new(
         "l",
-        vec![right.into(), (bottom - mark_offset).into()],
+        vec![right.into(), (bottom - offsets.bottom).into()],
     ));
     ops.push(Operation::new("S", vec![]));
 
@@ -162,21 +1862,21 @@ fn generate_crop_marks(
     // Horizontal mark (left side)
     ops.push(Operation::new(
         "m",
-        vec![(left - mark_offset - mark_length).into(), top.into()],
+        vec![(left - offsets.left - mark_length).into(), top.into()],
     ));
     ops.push(Operation::new(
         "l",
-        vec![(left - mark_offset).into(), top.into()],
+        vec![(left - offsets.left).into(), top.into()],
     ));
     ops.push(Operation::new("S", vec![]));
     // Vertical mark (top side)
     ops.push(Operation::new(
         "m",
-        vec![left.into(), (top + mark_offset).into()],
+        vec![left.into(), (top + offsets.top).into()],
     ));
     ops.push(Operation::new(
         "l",
-        vec![left.into(), (top + mark_offset + mark_length).into()],
+        vec![left.into(), (top + offsets.top + mark_length).into()],
     ));
     ops.push(Operation::new("S", vec![]));
 
@@ -184,159 +1884,223 @@ fn generate_crop_marks(
     // Horizontal mark (right side)
     ops.push(Operation::new(
         "m",
-        vec![(right + mark_offset).into(), top.into()],
+        vec![(right + offsets.right).into(), top.into()],
     ));
     ops.push(Operation::new(
         "l",
-        vec![(right + mark_offset + mark_length).into(), top.into()],
+        vec![(right + offsets.right + mark_length).into(), top.into()],
     ));
     ops.push(Operation::new("S", vec![]));
     // Vertical mark (top side)
     ops.push(Operation::new(
         "m",
-        vec![right.into(), (top + mark_offset).into()],
+        vec![right.into(), (top + offsets.top).into()],
     ));
     ops.push(Operation::new(
         "l",
-        vec![right.into(), (top + mark_offset + mark_length).into()],
+        vec![right.into(), (top + offsets.top + mark_length).into()],
     ));
     ops.push(Operation::new("S", vec![]));
 
+    if !center {
+        return ops;
+    }
+
+    // Center marks, at the middle of each edge, pointing away from it
+    let middle_x = (left + right) / 2.0;
+    let middle_y = (bottom + top) / 2.0;
+    for (x1, y1, x2, y2) in [
+        (
+            middle_x,
+            bottom - offsets.bottom,
+            middle_x,
+            bottom - offsets.bottom - mark_length,
+        ),
+        (
+            middle_x,
+            top + offsets.top,
+            middle_x,
+            top + offsets.top + mark_length,
+        ),
+        (
+            left - offsets.left,
+            middle_y,
+            left - offsets.left - mark_length,
+            middle_y,
+        ),
+        (
+            right + offsets.right,
+            middle_y,
+            right + offsets.right + mark_length,
+            middle_y,
+        ),
+    ] {
+        ops.push(Operation::new("m", vec![x1.into(), y1.into()]));
+        ops.push(Operation::new("l", vec![x2.into(), y2.into()]));
+        ops.push(Operation::new("S", vec![]));
+    }
+
     ops
 }
 
-/// Generate PDF operations to draw a date/time footer.
+/// Generate PDF operations to draw Japanese-style crop marks (tombo) around
+/// the trim: at each corner, the lines `generate_crop_marks` draws, in line
+/// with the trim, and beside each another in line with the edge of the
+/// bleed, or 3mm out where there's none.
 ///
-/// * `timestamp` - The pre-formatted timestamp string
-/// * `font_name` - The resource name for the font (e.g., "F1")
+/// * `trim` - The trim area
+/// * `bleed` - Bleed beyond each edge of the trim
+/// * `offsets` - Gap between each trim edge and the start of the crop marks
+///   on that side
+/// * `weight` - Stroke width of the marks; a zero-width hairline is drawn
+///   at HAIRLINE_WEIGHT instead
+/// * `center` - Whether to draw a center mark at the middle of each edge as
+///   well
 ///
-/// The date/time is positioned at bottom left, 1cm from both edges.
-fn generate_datetime(timestamp: &str, font_name: &str) -> Vec<Operation> {
-    let mut ops = Vec::new();
-
-    // Position 1cm from bottom and left edges (28.35 points)
-    let y_pos = 28.35;
-    let x_pos = 28.35;
-
-    // Begin text object
-    ops.push(Operation::new("BT", vec![]));
-
-    // Set font (Inconsolata at 10pt)
-    ops.push(Operation::new("Tf", vec![font_name.into(), 10.into()]));
-
-    // Position text at bottom left
-    ops.push(Operation::new("Td", vec![x_pos.into(), y_pos.into()]));
-
-    // Show text
-    ops.push(Operation::new(
-        "Tj",
-        vec![Object::String(
-            timestamp.as_bytes().to_vec(),
-            lopdf::StringFormat::Literal,
-        )],
-    ));
-
-    // End text object
-    ops.push(Operation::new("ET", vec![]));
+pub fn generate_japanese_marks(
+    trim: &Rect,
+    bleed: &Edges,
+    offsets: &Edges,
+    weight: f64,
+    center: bool,
+) -> Vec<Operation> {
+    let weight = if weight > 0.0 {
+        weight
+    } else {
+        HAIRLINE_WEIGHT
+    };
+    let mut ops = vec![
+        Operation::new("w", vec![weight.into()]),
+        Operation::new("K", vec![0.into(), 0.into(), 0.into(), 1.into()]),
+    ];
+
+    let mut strokes = geometry::mark_boxes(trim, offsets);
+    strokes.extend(geometry::outer_mark_boxes(trim, bleed, offsets));
+    if center {
+        strokes.extend(geometry::center_mark_boxes(trim, offsets));
+    }
+    for stroke in strokes {
+        ops.push(Operation::new(
+            "m",
+            vec![stroke.x1.into(), stroke.y1.into()],
+        ));
+        ops.push(Operation::new(
+            "l",
+            vec![stroke.x2.into(), stroke.y2.into()],
+        ));
+        ops.push(Operation::new("S", vec![]));
+    }
 
     ops
 }
 
-/// Generate PDF operations to draw a page number footer.
+/// Generate PDF operations to draw the rounded corners a die will cut, in
+/// place of crop marks: a quarter circle at each corner of the trim, over
+/// the page content.
 ///
-/// * `page_num` - The page number to display
-/// * `page_width` - Width of the page (typically 595 for A4)
-/// * `font_name` - The resource name for the font (e.g., "F1")
-/// * `char_width` - Character width at 1pt font size
+/// * `trim` - The trim area
+/// * `radius` - Radius of the corners, no more than half the trim's width
+///   or height
+/// * `offsets` - Gap between each trim edge and the start of any center
+///   marks on that side
+/// * `weight` - Stroke width of the marks; a zero-width hairline is drawn
+///   at HAIRLINE_WEIGHT instead
+/// * `center` - Whether to draw a center mark at the middle of each edge as
+///   well
 ///
-/// The page number is positioned at bottom right, 1cm from both edges.
-fn generate_page_number(
-    page_num: usize,
-    total_pages: usize,
-    page_width: f64,
-    font_name: &str,
-    char_width: f64,
+pub fn generate_corner_arcs(
+    trim: &Rect,
+    radius: f64,
+    offsets: &Edges,
+    weight: f64,
+    center: bool,
 ) -> Vec<Operation> {
-    let mut ops = Vec::new();
-
-    // Position 1cm from bottom and right edges (28.35 points)
-    let y_pos = 28.35;
-
-    let text = format!("{}/{}", page_num, total_pages);
-
-    // Calculate x position to right-align using actual font metrics
-    let font_size = 10.0;
-    let text_width = text.len() as f64 * char_width * font_size;
-    let x_pos = page_width - 28.35 - text_width;
-
-    // Begin text object
-    ops.push(Operation::new("BT", vec![]));
-
-    // Set font (Inconsolata at 10pt)
-    ops.push(Operation::new("Tf", vec![font_name.into(), 10.into()]));
-
-    // Position text at bottom right
-    ops.push(Operation::new("Td", vec![x_pos.into(), y_pos.into()]));
-
-    // Show text
-    ops.push(Operation::new(
-        "Tj",
-        vec![Object::String(
-            text.into_bytes(),
-            lopdf::StringFormat::Literal,
-        )],
-    ));
+    let weight = if weight > 0.0 {
+        weight
+    } else {
+        HAIRLINE_WEIGHT
+    };
+    let mut ops = vec![
+        Operation::new("w", vec![weight.into()]),
+        Operation::new("K", vec![0.into(), 0.into(), 0.into(), 1.into()]),
+    ];
+
+    let r = radius.min(trim.width() / 2.0).min(trim.height() / 2.0);
+    let k = r * KAPPA;
+
+    // Each corner, and the directions along the trim's edges away from it
+    for (x, y, dx, dy) in [
+        (trim.x1, trim.y1, 1.0, 1.0),
+        (trim.x2, trim.y1, -1.0, 1.0),
+        (trim.x1, trim.y2, 1.0, -1.0),
+        (trim.x2, trim.y2, -1.0, -1.0),
+    ] {
+        ops.push(Operation::new("m", vec![x.into(), (y + dy * r).into()]));
+        ops.push(Operation::new(
+            "c",
+            vec![
+                x.into(),
+                (y + dy * (r - k)).into(),
+                (x + dx * (r - k)).into(),
+                y.into(),
+                (x + dx * r).into(),
+                y.into(),
+            ],
+        ));
+        ops.push(Operation::new("S", vec![]));
+    }
 
-    // End text object
-    ops.push(Operation::new("ET", vec![]));
+    if center {
+        for stroke in geometry::center_mark_boxes(trim, offsets) {
+            ops.push(Operation::new(
+                "m",
+                vec![stroke.x1.into(), stroke.y1.into()],
+            ));
+            ops.push(Operation::new(
+                "l",
+                vec![stroke.x2.into(), stroke.y2.into()],
+            ));
+            ops.push(Operation::new("S", vec![]));
+        }
+    }
 
     ops
 }
 
-/// Generate PDF operations to draw a filename footer.
+/// Generate PDF operations to draw the slug's fields, as laid out along its
+/// line.
 ///
-/// * `filename` - The filename to display
-/// * `page_width` - Width of the page (we expect 595 for A4)
+/// * `fields` - The fields, already positioned
 /// * `font_name` - The resource name for the font (we expect "F1")
-/// * `char_width` - Character width at 1pt font size
+/// * `size` - Font size of the footer
+/// * `baseline` - Baseline of this line of the footer
+/// * `metrics` - Metrics of the font, for kerning
 ///
-/// The filename is positioned at bottom center, 1cm from bottom edge.
-fn generate_filename(
-    filename: &str,
-    page_width: f64,
+fn generate_slug(
+    fields: &[Placed],
     font_name: &str,
-    char_width: f64,
+    size: f64,
+    baseline: f64,
+    metrics: &Metrics,
 ) -> Vec<Operation> {
     let mut ops = Vec::new();
 
-    // Position 1cm from bottom edge (28.35 points)
-    let y_pos = 28.35;
+    for field in fields {
+        // Begin text object
+        ops.push(Operation::new("BT", vec![]));
 
-    // Calculate x position to center-align using actual font metrics
-    let font_size = 10.0;
-    let text_width = filename.len() as f64 * char_width * font_size;
-    let x_pos = (page_width - text_width) / 2.0;
+        // Set font (Inconsolata, normally at 10pt)
+        ops.push(Operation::new("Tf", vec![font_name.into(), size.into()]));
 
-    // Begin text object
-    ops.push(Operation::new("BT", vec![]));
+        // Position text on the line's baseline
+        ops.push(Operation::new("Td", vec![field.x.into(), baseline.into()]));
 
-    // Set font (Inconsolata at 10pt)
-    ops.push(Operation::new("Tf", vec![font_name.into(), 10.into()]));
-
-    // Position text at bottom center
-    ops.push(Operation::new("Td", vec![x_pos.into(), y_pos.into()]));
-
-    // Show text
-    ops.push(Operation::new(
-        "Tj",
-        vec![Object::String(
-            filename.as_bytes().to_vec(),
-            lopdf::StringFormat::Literal,
-        )],
-    ));
+        // Show text
+        ops.push(metrics.show(&field.text));
 
-    // End text object
-    ops.push(Operation::new("ET", vec![]));
+        // End text object
+        ops.push(Operation::new("ET", vec![]));
+    }
 
     ops
 }
@@ -347,66 +2111,133 @@ fn generate_filename(
 /// completely isolated from the page's Resources. This avoids the need to manipulate
 /// the page's Font dictionary.
 ///
-/// Returns the ObjectId of the created Form XObject.
+/// The Form XObject and its font resources are stored at the reserved IDs
+/// given.
 fn create_overlay_xobject(
     doc: &mut Document,
+    reserved: &PageObjects,
     page_num: usize,
     total_pages: usize,
-    trim_x: f64,
-    trim_y: f64,
-    trim_width: f64,
-    trim_height: f64,
-    font_id: ObjectId,
-    char_width: f64,
-    timestamp: &str,
-    filename: &str,
-) -> lopdf::Result<ObjectId> {
-    let mut ops = Vec::new();
-
-    // Draw crop marks
-    ops.extend(generate_crop_marks(trim_x, trim_y, trim_width, trim_height));
-
-    // Draw date/time at bottom left
+    layout: &Layout,
+    options: &Options,
+    slug: Option<&SlugText>,
+) -> lopdf::Result<()> {
     let font_name = "F1";
-    ops.extend(generate_datetime(timestamp, font_name));
-
-    // Draw filename at bottom center
-    ops.extend(generate_filename(filename, 595.0, font_name, char_width));
-
-    // Draw page number at bottom right
-    ops.extend(generate_page_number(
-        page_num,
-        total_pages,
-        595.0,
-        font_name,
-        char_width,
-    ));
+    let (ops, color_spaces) =
+        overlay_operations(page_num, total_pages, layout, options, font_name, slug);
 
     // Create the Form XObject's content
     let content = Content { operations: ops };
 
-    // Create Resources dictionary for the Form XObject with Inconsolata font
-    let mut font_dict = dictionary! {};
-    font_dict.set(font_name.as_bytes(), font_id);
-    let font_dict_id = doc.add_object(font_dict);
-
-    let resources = dictionary! {
-        "Font" => font_dict_id,
-    };
+    // Create Resources dictionary for the Form XObject with Inconsolata font,
+    // if there's a slug to set in it
+    let mut resources = dictionary! {};
+    if let Some(slug) = slug {
+        let mut font_dict = dictionary! {};
+        font_dict.set(font_name.as_bytes(), slug.font_id);
+        doc.objects
+            .insert(reserved.font_resources, Object::Dictionary(font_dict));
+        resources.set("Font", reserved.font_resources);
+    }
+    if !color_spaces.is_empty() {
+        resources.set("ColorSpace", color_spaces);
+    }
 
     // Create the Form XObject
-    // BBox covers the entire A4 page so crop marks and page number can be anywhere
+    // BBox covers the entire sheet so crop marks and page number can be anywhere
     let xobject_stream = Stream::new(
         dictionary! {
             "Type" => "XObject",
             "Subtype" => "Form",
-            "BBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+            "BBox" => layout.sheet.to_array(),
             "Resources" => Object::Dictionary(resources),
         },
         content.encode()?,
     );
 
-    Ok(doc.add_object(xobject_stream))
+    doc.objects
+        .insert(reserved.overlay, Object::Stream(xobject_stream));
+
+    Ok(())
+}
+
+/// Generate the operations drawing everything on the overlay: crop marks,
+/// any additional marks, and the slug. Returns them along with the color
+/// spaces they need, by name.
+fn overlay_operations(
+    page_num: usize,
+    total_pages: usize,
+    layout: &Layout,
+    options: &Options,
+    font_name: &str,
+    slug: Option<&SlugText>,
+) -> (Vec<Operation>, Dictionary) {
+    let mut ops = Vec::new();
+
+    // Draw crop marks, starting outside the bleed so they never print into
+    // the area that will be trimmed away
+    let offsets = geometry::mark_offsets(&options.bleed.edges(page_num), options.mark_gap);
+    ops.extend(match (options.corner_radius, options.mark_style) {
+        (Some(radius), _) => generate_corner_arcs(
+            &layout.trim,
+            radius,
+            &offsets,
+            options.mark_weight,
+            options.center_marks,
+        ),
+        (None, MarkStyle::Western) => generate_crop_marks(
+            layout.trim.x1,
+            layout.trim.y1,
+            options.trim_width,
+            options.trim_height,
+            &offsets,
+            options.mark_weight,
+            options.center_marks,
+        ),
+        (None, MarkStyle::Japanese) => generate_japanese_marks(
+            &layout.trim,
+            &options.bleed.edges(page_num),
+            &offsets,
+            options.mark_weight,
+            options.center_marks,
+        ),
+    });
+
+    // Draw any additional marks, collecting the color spaces they use
+    let mut color_spaces = dictionary! {};
+    for renderer in options.renderers(page_num) {
+        ops.extend(renderer.render(layout));
+        for (name, space) in renderer.color_spaces() {
+            color_spaces.set(name, space);
+        }
+    }
+
+    // Draw date/time at left, filename at center, and page number at right,
+    // with any note above
+    let Some(text) = slug else {
+        return (ops, color_spaces);
+    };
+    let page_text = options.page_text(page_num, total_pages);
+    let note = options.slug_note_lines();
+    for (baseline, fields) in geometry::slug_lines(
+        &text.slug,
+        layout.sheet.width(),
+        text.metrics,
+        text.timestamp,
+        text.center,
+        &page_text,
+        &note,
+    ) {
+        ops.extend(generate_slug(
+            &fields,
+            font_name,
+            text.slug.size,
+            baseline,
+            text.metrics,
+        ));
+    }
+
+    (ops, color_spaces)
 }
 
 /// Adds crop marks and page number to a single manuscript page.
@@ -436,139 +2267,206 @@ fn create_overlay_xobject(
 /// minimizing the risk of corrupting the input document's content.
 ///
 /// The trim size defines where crop marks are placed. The actual content (which
-/// may include bleed) is read from the original MediaBox and centered accordingly.
+/// may include bleed) is read from the original MediaBox, or the box chosen with
+/// `--content-box`, and centered accordingly.
 fn stamp_page(
     doc: &mut Document,
     page_id: ObjectId,
-    trim_width: f64,
-    trim_height: f64,
-    font_id: ObjectId,
-    char_width: f64,
-    timestamp: &str,
-    filename: &str,
+    reserved: PageObjects,
+    options: &Options,
+    slug: Option<&SlugText>,
     page_num: usize,
     total_pages: usize,
 ) -> lopdf::Result<()> {
     // Clone the page dictionary once so we can mutate doc
     let page = doc.get_object(page_id)?.as_dict()?.clone();
 
-    // Read original MediaBox to get actual content dimensions
-    let original_mediabox = page.get(b"MediaBox")?;
-    let (actual_width, actual_height) = match original_mediabox {
-        Object::Array(arr) if arr.len() == 4 => {
-            // MediaBox format: [x1, y1, x2, y2]
-            // Convert to f64 handling both Integer and Real types
-            let to_f64 = |obj: &Object| -> lopdf::Result<f64> {
-                match obj {
-                    Object::Integer(i) => Ok(*i as f64),
-                    Object::Real(r) => Ok(*r as f64),
-                    _ => Err(lopdf::Error::PageNumberNotFound(0)),
-                }
-            };
-            let x1 = to_f64(&arr[0])?;
-            let y1 = to_f64(&arr[1])?;
-            let x2 = to_f64(&arr[2])?;
-            let y2 = to_f64(&arr[3])?;
-            (x2 - x1, y2 - y1)
-        }
-        _ => return Err(lopdf::Error::PageNumberNotFound(0)),
-    };
+    // Read original MediaBox (or whichever box is the content) to get
+    // actual content dimensions
+    let source = content_box(doc, page_id, options.content_box)?;
+    let (actual_width, actual_height) = (source.width(), source.height());
 
+    let legacy = options.compat == Compat::Legacy;
+    // Bare pages are still placed on the sheet, but draw nothing over them
+    let overlaid = options.overlaid(page_num);
+
+    // Keep what stamping replaces, so that the stamp can be stripped again.
+    // Legacy output flattens the content, so there's nothing to go back to.
     let mut new_page = page;
+    if !legacy {
+        let original = strip::record(doc, &new_page);
+        new_page.set(strip::ORIGINAL, original);
+    }
+
+    // Every inheritable attribute ends up on the page itself. The boxes and
+    // resources are rewritten below from whatever the page inherited, and
+    // the rotation is copied down too, so that the page no longer depends
+    // on the tree around it.
+    if let Some(rotate) = inherited(doc, page_id, b"Rotate").cloned() {
+        new_page.set("Rotate", rotate);
+    }
+
+    // Change MediaBox to the sheet
+    new_page.set(
+        "MediaBox",
+        vec![
+            0.into(),
+            0.into(),
+            options.sheet_width.into(),
+            options.sheet_height.into(),
+        ],
+    );
 
-    // Change MediaBox to A4 (595×842)
-    new_page.set("MediaBox", vec![0.into(), 0.into(), 595.into(), 842.into()]);
+    // Calculate trim area position (centered on A4 unless placed explicitly)
+    // and where the content goes relative to it
+    let layout = geometry::layout(options, page_num, actual_width, actual_height);
 
-    // Calculate trim area position (centered on A4)
-    let trim_x: f64 = (595.0 - trim_width) / 2.0;
-    let trim_y: f64 = (842.0 - trim_height) / 2.0;
+    // Record the trim and bleed in sheet coordinates so downstream tools
+    // know where the finished page is. Any boxes the manuscript carried were
+    // in its own coordinate system and no longer apply.
+    new_page.set("TrimBox", layout.trim.to_array());
+    new_page.set("BleedBox", layout.bleed.to_array());
+    new_page.set("CropBox", layout.sheet.to_array());
+    new_page.remove(b"ArtBox");
 
     // Create Form XObject containing crop marks and page number with its own Resources
-    let overlay_xobject_id = create_overlay_xobject(
-        doc,
-        page_num,
-        total_pages,
-        trim_x,
-        trim_y,
-        trim_width,
-        trim_height,
-        font_id,
-        char_width,
-        timestamp,
-        filename,
-    )?;
+    if !legacy && overlaid {
+        create_overlay_xobject(
+            doc,
+            &reserved,
+            page_num,
+            total_pages,
+            &layout,
+            options,
+            slug,
+        )?;
+    }
 
     // Add the overlay XObject to page Resources
     let xobject_name = "Overlay";
 
-    let res_dict = match new_page.get(b"Resources") {
-        Ok(Object::Dictionary(d)) => Some(d.clone()),
-        Ok(Object::Reference(id)) => match doc.get_object(*id) {
-            Ok(obj) => obj.as_dict().ok().cloned(),
-            Err(_) => None,
-        },
-        _ => None,
-    };
+    // The page's resources, wherever in the page tree they come from; the
+    // page gets its own copy with the overlay added
+    let res_dict = inherited(doc, page_id, b"Resources")
+        .and_then(|resources| resources.as_dict().ok())
+        .cloned();
 
-    // Build XObject dictionary with existing XObjects + our overlay
-    let mut xobject_dict = dictionary! {};
+    // Build new Resources dictionary
+    let mut new_resources = dictionary! {};
 
     if let Some(ref rd) = res_dict {
-        if let Ok(xobj_obj) = rd.get(b"XObject") {
-            let existing_xobjects = match xobj_obj {
-                Object::Dictionary(d) => Some(d),
-                Object::Reference(id) => match doc.get_object(*id) {
-                    Ok(obj) => obj.as_dict().ok(),
-                    Err(_) => None,
-                },
-                _ => None,
-            };
+        new_resources.extend(rd);
+    }
 
-            if let Some(xobjects) = existing_xobjects {
-                xobject_dict.extend(xobjects);
-            }
+    // For old RIPs the overlay is drawn directly in the page's own content,
+    // so its font and color spaces join the page's resources instead
+    let mut overlay_ops = Vec::new();
+    if legacy && overlaid {
+        let (ops, color_spaces) = overlay_operations(
+            page_num,
+            total_pages,
+            &layout,
+            options,
+            LEGACY_FONT_NAME,
+            slug,
+        );
+        overlay_ops = ops;
+
+        if let Some(slug) = slug {
+            let mut fonts = sub_dictionary(doc, res_dict.as_ref(), b"Font");
+            fonts.set(LEGACY_FONT_NAME, slug.font_id);
+            new_resources.set("Font", fonts);
+        }
+
+        if !color_spaces.is_empty() {
+            let mut spaces = sub_dictionary(doc, res_dict.as_ref(), b"ColorSpace");
+            spaces.extend(&color_spaces);
+            new_resources.set("ColorSpace", spaces);
         }
     }
 
-    xobject_dict.set(xobject_name.as_bytes(), overlay_xobject_id);
-    let xobject_dict_id = doc.add_object(xobject_dict);
+    // Build XObject dictionary with existing XObjects + our overlay
+    let mut xobject_dict = dictionary! {};
 
-    // Build new Resources dictionary
-    let mut new_resources = dictionary! {};
+    if let Some(ref rd) = res_dict
+        && let Ok(xobj_obj) = rd.get(b"XObject")
+    {
+        let existing_xobjects = match xobj_obj {
+            Object::Dictionary(d) => Some(d),
+            Object::Reference(id) => match doc.get_object(*id) {
+                Ok(obj) => obj.as_dict().ok(),
+                Err(_) => None,
+            },
+            _ => None,
+        };
+
+        if let Some(xobjects) = existing_xobjects {
+            xobject_dict.extend(xobjects);
+        }
+    }
 
-    if let Some(ref rd) = res_dict {
-        new_resources.extend(rd);
+    if !legacy {
+        if overlaid {
+            xobject_dict.set(xobject_name.as_bytes(), reserved.overlay);
+        }
+        doc.objects
+            .insert(reserved.xobjects, Object::Dictionary(xobject_dict));
+
+        new_resources.set("XObject", reserved.xobjects);
     }
 
-    new_resources.set("XObject", xobject_dict_id);
     new_page.set("Resources", Object::Dictionary(new_resources));
 
-    // Center actual content on A4
-    let content_x: f64 = (595.0 - actual_width) / 2.0;
-    let content_y: f64 = (842.0 - actual_height) / 2.0;
+    // Position actual content on A4, allowing for a content box whose
+    // origin isn't at zero
+    let mut scale: f64 = 1.0;
+    let mut content_x: f64 = layout.content.x1 - source.x1;
+    let mut content_y: f64 = layout.content.y1 - source.y1;
+
+    // Where the page lacks bleed, and making it up was asked for, either
+    // scale the content to cover the bleed or note the edges to mirror
+    let lacks_bleed = !layout.content.contains(&layout.bleed);
+    if lacks_bleed && options.extend_bleed == Some(BleedExtension::CloneEdge) {
+        scale = (layout.bleed.width() / actual_width).max(layout.bleed.height() / actual_height);
+        let center_x = (layout.bleed.x1 + layout.bleed.x2) / 2.0;
+        let center_y = (layout.bleed.y1 + layout.bleed.y2) / 2.0;
+        content_x = center_x - scale * (source.x1 + actual_width / 2.0);
+        content_y = center_y - scale * (source.y1 + actual_height / 2.0);
+    }
 
     // Create wrapper stream: invoke overlay XObject + transformation start
     let mut start_ops = Vec::new();
     // Invoke the overlay XObject (draws crop marks and page number)
-    start_ops.push(Operation::new("Do", vec![xobject_name.into()]));
+    if legacy {
+        if overlaid {
+            start_ops.push(Operation::new("q", vec![]));
+            start_ops.extend(overlay_ops);
+            start_ops.push(Operation::new("Q", vec![]));
+        }
+    } else if overlaid {
+        start_ops.push(Operation::new("Do", vec![xobject_name.into()]));
+    }
     start_ops.push(Operation::new("q", vec![]));
     start_ops.push(Operation::new(
         "cm",
         vec![
-            1.into(),
+            scale.into(),
             0.into(),
             0.into(),
-            1.into(),
+            scale.into(),
             content_x.into(),
             content_y.into(),
         ],
     ));
+    // Clip to the content box, as a viewer would have
+    start_ops.extend(clip(&source));
 
     let start_content = Content {
         operations: start_ops,
     };
     let start_stream = Stream::new(dictionary! {}, start_content.encode()?);
-    let start_id = doc.add_object(start_stream);
+    doc.objects
+        .insert(reserved.start, Object::Stream(start_stream));
 
     // Create wrapper stream: transformation end
     let end_ops = vec![Operation::new("Q", vec![])];
@@ -576,35 +2474,660 @@ fn stamp_page(
         operations: end_ops,
     };
     let end_stream = Stream::new(dictionary! {}, end_content.encode()?);
-    let end_id = doc.add_object(end_stream);
+    doc.objects.insert(reserved.end, Object::Stream(end_stream));
 
     // Build Contents array preserving original content objects
     // Per PDF spec, Contents can be a single stream Reference or an Array of References.
     // We convert to Array format to sandwich the original content between our wrappers.
     // This is harmless - viewers simply concatenate streams in order.
-    let mut contents_array = vec![Object::Reference(start_id)];
+    let mut original = Vec::new();
 
     if let Ok(original_contents) = new_page.get(b"Contents") {
-        match original_contents {
-            Object::Reference(_) => {
-                // Single stream (typical case for Typst PDFs) - add as-is
-                contents_array.push(original_contents.clone());
-            }
-            Object::Array(arr) => {
-                // Already an array - preserve all elements
-                contents_array.extend(arr.iter().cloned());
-            }
-            _ => {
-                // Unexpected type, but handle gracefully (blank page)
+        // A single stream (typical case for Typst PDFs) or an array of them,
+        // less anything in it which isn't a stream
+        original.extend(content_streams(doc, original_contents));
+    }
+
+    let mut contents_array = vec![Object::Reference(reserved.start)];
+    contents_array.extend(original.iter().cloned());
+    contents_array.push(Object::Reference(reserved.end));
+
+    // Mirroring draws the original content again, once for each edge,
+    // reflected about that edge and clipped to the strip of bleed beyond it.
+    // The same streams are simply referenced again.
+    if lacks_bleed && !reserved.mirrors.is_empty() {
+        let content = layout.content;
+        let bleed = layout.bleed;
+        let reflections = [
+            // Left, reflecting about the content's left edge
+            (
+                Rect {
+                    x2: content.x1,
+                    ..bleed
+                },
+                [-1.0, 1.0, 2.0 * content.x1 - content_x, content_y],
+            ),
+            // Right
+            (
+                Rect {
+                    x1: content.x2,
+                    ..bleed
+                },
+                [-1.0, 1.0, 2.0 * content.x2 - content_x, content_y],
+            ),
+            // Bottom
+            (
+                Rect {
+                    y2: content.y1,
+                    ..bleed
+                },
+                [1.0, -1.0, content_x, 2.0 * content.y1 - content_y],
+            ),
+            // Top
+            (
+                Rect {
+                    y1: content.y2,
+                    ..bleed
+                },
+                [1.0, -1.0, content_x, 2.0 * content.y2 - content_y],
+            ),
+        ];
+
+        for ((strip, [sx, sy, tx, ty]), id) in reflections.into_iter().zip(&reserved.mirrors) {
+            if strip.width() <= 0.0 || strip.height() <= 0.0 {
+                continue;
             }
+
+            let mut ops = vec![Operation::new("q", vec![])];
+            ops.extend(clip(&strip));
+            ops.push(Operation::new(
+                "cm",
+                vec![
+                    sx.into(),
+                    0.into(),
+                    0.into(),
+                    sy.into(),
+                    tx.into(),
+                    ty.into(),
+                ],
+            ));
+            ops.extend(clip(&source));
+
+            let stream = Stream::new(dictionary! {}, Content { operations: ops }.encode()?);
+            doc.objects.insert(*id, Object::Stream(stream));
+
+            contents_array.push(Object::Reference(*id));
+            contents_array.extend(original.iter().cloned());
+            contents_array.push(Object::Reference(reserved.end));
         }
     }
 
-    contents_array.push(Object::Reference(end_id));
-    new_page.set("Contents", Object::Array(contents_array));
+    if legacy {
+        // Old RIPs may not cope with an array of streams, so concatenate
+        // everything into the one stream, in place of the wrapper
+        let mut flattened = Vec::new();
+        for item in &contents_array {
+            let Object::Reference(id) = item else {
+                continue;
+            };
+            flattened.extend(doc.get_object(*id)?.as_stream()?.get_plain_content()?);
+            flattened.push(b'\n');
+        }
+        doc.objects.remove(&reserved.end);
+        for id in &reserved.mirrors {
+            doc.objects.remove(id);
+        }
+
+        doc.objects.insert(
+            reserved.start,
+            Object::Stream(Stream::new(dictionary! {}, flattened)),
+        );
+        new_page.set("Contents", reserved.start);
+    } else {
+        new_page.set("Contents", Object::Array(contents_array));
+    }
 
     // Replace page in document
     doc.objects.insert(page_id, Object::Dictionary(new_page));
 
     Ok(())
 }
+
+/// The entries of a page's Contents which are references to content
+/// streams. Malformed files sometimes have inline dictionaries, nulls, or
+/// dangling references among them, which are left out.
+pub fn content_streams(doc: &Document, contents: &Object) -> Vec<Object> {
+    let entries = match contents {
+        Object::Array(items) => items.as_slice(),
+        other => std::slice::from_ref(other),
+    };
+
+    entries
+        .iter()
+        .filter(|entry| match entry {
+            Object::Reference(id) => doc.get_object(*id).and_then(Object::as_stream).is_ok(),
+            _ => false,
+        })
+        .cloned()
+        .collect()
+}
+
+/// Look up a dictionary within a page's resources, such as its fonts,
+/// returning a copy to be added to, or an empty one if there isn't one.
+fn sub_dictionary(doc: &Document, resources: Option<&Dictionary>, key: &[u8]) -> Dictionary {
+    match resources.and_then(|rd| rd.get(key).ok()) {
+        Some(Object::Dictionary(d)) => d.clone(),
+        Some(Object::Reference(id)) => doc
+            .get_dictionary(*id)
+            .cloned()
+            .unwrap_or_else(|_| dictionary! {}),
+        _ => dictionary! {},
+    }
+}
+
+/// Operations to clip subsequent drawing to the given rectangle.
+fn clip(area: &Rect) -> Vec<Operation> {
+    vec![
+        Operation::new(
+            "re",
+            vec![
+                area.x1.into(),
+                area.y1.into(),
+                area.width().into(),
+                area.height().into(),
+            ],
+        ),
+        Operation::new("W", vec![]),
+        Operation::new("n", vec![]),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verify;
+
+    fn array(values: [f64; 4]) -> Object {
+        Object::Array(values.iter().map(|v| Object::Real(*v as f32)).collect())
+    }
+
+    #[test]
+    fn parse_tz_offsets() {
+        assert_eq!(parse_tz_offset("+10:00"), Ok(36000));
+        assert_eq!(parse_tz_offset("-05:30"), Ok(-19800));
+        assert_eq!(parse_tz_offset("+0930"), Ok(34200));
+        assert_eq!(parse_tz_offset("+9"), Ok(32400));
+        assert!(parse_tz_offset("10:00").is_err());
+        assert!(parse_tz_offset("+15:00").is_err());
+        assert!(parse_tz_offset("+10:75").is_err());
+    }
+
+    #[test]
+    fn validate_refuses_what_the_command_line_would() {
+        assert_eq!(Options::default().validate(), Ok(()));
+
+        let refused = [
+            Options {
+                bleed: Bleed::uniform(-9.0),
+                ..Options::default()
+            },
+            Options {
+                trim_width: 0.0,
+                ..Options::default()
+            },
+            Options {
+                trim_width: 1000.0,
+                ..Options::default()
+            },
+            Options {
+                sheet_width: f64::INFINITY,
+                ..Options::default()
+            },
+            Options {
+                pages: Some(PageSelection(vec![PageRange::Span(0, None)])),
+                ..Options::default()
+            },
+            Options {
+                page_order: Some(vec![2, 0]),
+                ..Options::default()
+            },
+            Options {
+                mark_weight: f64::NAN,
+                ..Options::default()
+            },
+            Options {
+                grid: Some(0.0),
+                ..Options::default()
+            },
+            Options {
+                folds: crate::marks::parse_folds("500mm").unwrap(),
+                ..Options::default()
+            },
+        ];
+        for options in refused {
+            assert!(options.validate().is_err(), "{:?}", options);
+        }
+    }
+
+    #[test]
+    fn bleed_parse_single_length() {
+        assert_eq!(Bleed::parse("9pt"), Ok(Bleed::uniform(9.0)));
+        assert_eq!(Bleed::parse("0"), Ok(Bleed::default()));
+    }
+
+    #[test]
+    fn bleed_parse_per_edge() {
+        assert_eq!(
+            Bleed::parse("top=9,bottom=9"),
+            Ok(Bleed {
+                top: 9.0,
+                bottom: 9.0,
+                ..Bleed::default()
+            })
+        );
+        assert_eq!(
+            Bleed::parse("top=9pt, outside=9pt, bottom=9pt, inside=0"),
+            Ok(Bleed {
+                inside: 0.0,
+                ..Bleed::uniform(9.0)
+            })
+        );
+    }
+
+    #[test]
+    fn bleed_parse_invalid() {
+        assert!(Bleed::parse("").is_err());
+        assert!(Bleed::parse("-3mm").is_err());
+        assert!(Bleed::parse("left=3mm").is_err());
+        assert!(Bleed::parse("top=3mm,bottom").is_err());
+        assert!(Bleed::parse("top=wide").is_err());
+    }
+
+    #[test]
+    fn read_box_ordered() {
+        assert_eq!(
+            read_box(&array([0.0, 0.0, 595.0, 842.0])).unwrap(),
+            [0.0, 0.0, 595.0, 842.0]
+        );
+    }
+
+    #[test]
+    fn read_box_inverted_vertically() {
+        assert_eq!(
+            read_box(&array([0.0, 842.0, 595.0, 0.0])).unwrap(),
+            [0.0, 0.0, 595.0, 842.0]
+        );
+    }
+
+    #[test]
+    fn read_box_inverted_both_axes() {
+        assert_eq!(
+            read_box(&array([595.0, 842.0, 0.0, 0.0])).unwrap(),
+            [0.0, 0.0, 595.0, 842.0]
+        );
+    }
+
+    #[test]
+    fn read_box_negative_origin() {
+        assert_eq!(
+            read_box(&array([-9.0, -9.0, 441.0, 657.0])).unwrap(),
+            [-9.0, -9.0, 441.0, 657.0]
+        );
+        assert_eq!(
+            read_box(&array([441.0, 657.0, -9.0, -9.0])).unwrap(),
+            [-9.0, -9.0, 441.0, 657.0]
+        );
+    }
+
+    #[test]
+    fn read_box_integers() {
+        let object = Object::Array(vec![612.into(), 792.into(), 0.into(), 0.into()]);
+        assert_eq!(read_box(&object).unwrap(), [0.0, 0.0, 612.0, 792.0]);
+    }
+
+    #[test]
+    fn read_box_malformed() {
+        assert!(read_box(&Object::Array(vec![0.into(), 0.into(), 595.into()])).is_err());
+        assert!(
+            read_box(&Object::Array(vec![
+                0.into(),
+                0.into(),
+                595.into(),
+                "x".into()
+            ]))
+            .is_err()
+        );
+        assert!(read_box(&Object::Null).is_err());
+    }
+
+    #[test]
+    fn write_atomically_replaces_or_leaves_alone() {
+        let directory = std::env::temp_dir().join(format!("cropped-atomic.{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("output.pdf");
+        fs::write(&path, "earlier").unwrap();
+
+        // A failed write leaves the earlier file as it was
+        let failed = write_atomically(&path, |file| {
+            file.write_all(b"partial")?;
+            Err(io::Error::other("interrupted"))
+        });
+        assert!(failed.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "earlier");
+
+        write_atomically(&path, |file| file.write_all(b"later")).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "later");
+
+        // With no temporary file left behind either way
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 1);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    /// Options for a 6" × 9" trim with the given bleed, otherwise defaults.
+    fn options(bleed: Bleed) -> Options {
+        Options {
+            bleed,
+            ..Options::default()
+        }
+    }
+
+    /// Stamp one page of a manuscript carrying exactly the bleed asked for,
+    /// returning the operations of its overlay and of the wrapper drawn
+    /// before its content, decoded from the streams written.
+    fn stamp(options: &Options, page_num: usize, total_pages: usize) -> (String, String) {
+        let edges = options.bleed.edges(page_num);
+        let width = options.trim_width + edges.left + edges.right;
+        let height = options.trim_height + edges.bottom + edges.top;
+
+        let mut doc = Document::with_version("1.5");
+        let font_id = doc.add_object(dictionary! { "Type" => "Font" });
+        let content_id = doc.add_object(Stream::new(dictionary! {}, b"0 0 m".to_vec()));
+        let pages_id = doc.new_object_id();
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), width.into(), height.into()],
+            "Contents" => content_id,
+        });
+
+        let reserved = reserve_page_objects(&mut doc, 1, options).remove(0);
+        let (overlay, start) = (reserved.overlay, reserved.start);
+        stamp_page(
+            &mut doc,
+            page_id,
+            reserved,
+            options,
+            Some(&SlugText {
+                slug: Slug::default(),
+                font_id,
+                metrics: &Metrics::monospaced(0.5, 1.2),
+                timestamp: "2024-01-02 03:04:05 UTC",
+                center: "book.pdf",
+            }),
+            page_num,
+            total_pages,
+        )
+        .unwrap();
+
+        let decode = |id| {
+            let stream = doc.get_object(id).unwrap().as_stream().unwrap();
+            render(&Content::decode(&stream.content).unwrap().operations)
+        };
+        (decode(overlay), decode(start))
+    }
+
+    /// Stamp the only page of a manuscript whose page tree has an
+    /// intermediate node, from which the page inherits its resources and
+    /// rotation, returning the stamped page.
+    fn stamp_tree(options: &Options) -> (Document, Dictionary) {
+        let mut doc = Document::with_version("1.5");
+        let font_id = doc.add_object(dictionary! { "Type" => "Font" });
+        let manuscript_font_id = doc.add_object(dictionary! { "Type" => "Font" });
+        let resources_id = doc.add_object(dictionary! {
+            "Font" => dictionary! { "F9" => manuscript_font_id },
+        });
+        let content_id = doc.add_object(Stream::new(dictionary! {}, b"0 0 m".to_vec()));
+
+        let root_id = doc.new_object_id();
+        let node_id = doc.new_object_id();
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => node_id,
+            "Contents" => content_id,
+        });
+        doc.objects.insert(
+            node_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Parent" => root_id,
+                "Kids" => vec![page_id.into()],
+                "Count" => 1,
+                "Resources" => resources_id,
+                "Rotate" => 90,
+            }),
+        );
+        doc.objects.insert(
+            root_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![node_id.into()],
+                "Count" => 1,
+                "MediaBox" => vec![0.into(), 0.into(), 450.into(), 666.into()],
+            }),
+        );
+
+        let reserved = reserve_page_objects(&mut doc, 1, options).remove(0);
+        stamp_page(
+            &mut doc,
+            page_id,
+            reserved,
+            options,
+            Some(&SlugText {
+                slug: Slug::default(),
+                font_id,
+                metrics: &Metrics::monospaced(0.5, 1.2),
+                timestamp: "2024-01-02 03:04:05 UTC",
+                center: "book.pdf",
+            }),
+            1,
+            1,
+        )
+        .unwrap();
+
+        let page = doc.get_dictionary(page_id).unwrap().clone();
+        (doc, page)
+    }
+
+    #[test]
+    fn stamp_keeps_inherited_resources() {
+        let (doc, page) = stamp_tree(&options(Bleed::uniform(9.0)));
+
+        let resources = page.get(b"Resources").unwrap().as_dict().unwrap();
+        let fonts = resources.get(b"Font").unwrap().as_dict().unwrap();
+        assert!(fonts.has(b"F9"));
+
+        let xobjects = resources.get(b"XObject").unwrap().as_reference().unwrap();
+        assert!(doc.get_dictionary(xobjects).unwrap().has(b"Overlay"));
+    }
+
+    #[test]
+    fn stamp_bare_page_draws_no_overlay() {
+        let (doc, page) = stamp_tree(&Options {
+            bare_pages: Some(PageSelection::parse("1").unwrap()),
+            ..options(Bleed::uniform(9.0))
+        });
+
+        let resources = page.get(b"Resources").unwrap().as_dict().unwrap();
+        let xobjects = resources.get(b"XObject").unwrap().as_reference().unwrap();
+        assert!(!doc.get_dictionary(xobjects).unwrap().has(b"Overlay"));
+        assert!(page.has(b"TrimBox"));
+    }
+
+    #[test]
+    fn fold_marks_fall_on_the_folds() {
+        let options = Options {
+            folds: crate::marks::parse_folds("1/3,y:72pt").unwrap(),
+            ..options(Bleed::uniform(9.0))
+        };
+        let layout = geometry::layout(&options, 1, 450.0, 666.0);
+
+        let folds = &layout.marks[8..];
+        assert_eq!(folds.len(), 4);
+        assert!((folds[0].x1 - layout.trim.x1 - 144.0).abs() < 1e-9);
+        assert_eq!(folds[2].y1, layout.trim.y2 - 72.0);
+        assert!(folds.iter().all(|mark| !mark.intersects(&layout.bleed)));
+        assert!(crate::marks::parse_folds("3/2").is_err());
+    }
+
+    #[test]
+    fn registration_targets_clear_the_bleed() {
+        let options = Options {
+            registration_marks: true,
+            ..options(Bleed::uniform(9.0))
+        };
+        let layout = geometry::layout(&options, 1, 450.0, 666.0);
+
+        assert_eq!(layout.marks.len(), 12);
+        for target in &layout.marks[8..] {
+            assert!(!target.intersects(&layout.bleed));
+            assert!(layout.sheet.contains(target));
+        }
+    }
+
+    #[test]
+    fn stamp_leaves_content_unchanged() {
+        for compat in [Compat::Standard, Compat::Legacy] {
+            let mut doc = Document::with_version("1.5");
+            let font_id = doc.add_object(dictionary! { "Type" => "Font" });
+            let image_id = doc.add_object(Stream::new(
+                dictionary! {
+                    "Type" => "XObject",
+                    "Subtype" => "Image",
+                    "Width" => 1,
+                    "Height" => 1,
+                    "ColorSpace" => "DeviceGray",
+                    "BitsPerComponent" => 8,
+                },
+                vec![0x80],
+            ));
+            let content_id = doc.add_object(Stream::new(
+                dictionary! {},
+                b"BT /F1 12 Tf (Chapter One) Tj ET q 72 0 0 72 0 0 cm /Im1 Do Q".to_vec(),
+            ));
+            let pages_id = doc.new_object_id();
+            let page_id = doc.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "MediaBox" => vec![0.into(), 0.into(), 450.into(), 666.into()],
+                "Resources" => dictionary! {
+                    "Font" => dictionary! { "F1" => font_id },
+                    "XObject" => dictionary! { "Im1" => image_id },
+                },
+                "Contents" => content_id,
+            });
+            let before = verify::fingerprint(&doc, page_id, false).unwrap();
+
+            let options = Options {
+                compat,
+                ..options(Bleed::uniform(9.0))
+            };
+            let reserved = reserve_page_objects(&mut doc, 1, &options).remove(0);
+            stamp_page(
+                &mut doc,
+                page_id,
+                reserved,
+                &options,
+                Some(&SlugText {
+                    slug: Slug::default(),
+                    font_id,
+                    metrics: &Metrics::monospaced(0.5, 1.2),
+                    timestamp: "2024-01-02 03:04:05 UTC",
+                    center: "book.pdf",
+                }),
+                1,
+                1,
+            )
+            .unwrap();
+
+            assert_eq!(before.text, vec![b"Chapter One".to_vec()]);
+            assert_eq!(before.images.len(), 1);
+            assert_eq!(verify::fingerprint(&doc, page_id, true).unwrap(), before);
+        }
+    }
+
+    #[test]
+    fn stamp_materializes_inherited_attributes() {
+        let (_, page) = stamp_tree(&options(Bleed::uniform(9.0)));
+
+        assert_eq!(page.get(b"Rotate").unwrap().as_i64().unwrap(), 90);
+        assert_eq!(
+            read_box(page.get(b"MediaBox").unwrap()).unwrap(),
+            [0.0, 0.0, 595.0, 842.0]
+        );
+        assert!(page.has(b"CropBox"));
+    }
+
+    #[test]
+    fn stamp_records_inherited_attributes_for_strip() {
+        let (_, page) = stamp_tree(&options(Bleed::uniform(9.0)));
+
+        let original = page
+            .get(strip::ORIGINAL.as_bytes())
+            .unwrap()
+            .as_dict()
+            .unwrap();
+        assert!(original.get(b"Resources").unwrap().as_reference().is_ok());
+        assert_eq!(
+            read_box(original.get(b"MediaBox").unwrap()).unwrap(),
+            [0.0, 0.0, 450.0, 666.0]
+        );
+        assert!(matches!(original.get(b"TrimBox"), Ok(Object::Null)));
+    }
+
+    /// One line per operation, operands first as in the content stream.
+    /// Every number is given to three places, so that integer and real
+    /// operands read alike.
+    fn render(operations: &[Operation]) -> String {
+        operations
+            .iter()
+            .map(|operation| {
+                let mut parts: Vec<String> = operation.operands.iter().map(operand).collect();
+                parts.push(operation.operator.clone());
+                parts.join(" ")
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn operand(object: &Object) -> String {
+        match object {
+            Object::Integer(value) => format!("{:.3}", *value as f64),
+            Object::Real(value) => format!("{:.3}", value),
+            Object::Name(name) => format!("/{}", String::from_utf8_lossy(name)),
+            Object::String(text, _) => format!("({})", String::from_utf8_lossy(text)),
+            Object::Array(items) => format!(
+                "[{}]",
+                items.iter().map(operand).collect::<Vec<_>>().join(" ")
+            ),
+            other => format!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn snapshot_recto_uniform_bleed() {
+        let (overlay, wrapper) = stamp(&options(Bleed::uniform(9.0)), 1, 2);
+        insta::assert_snapshot!("recto_uniform_bleed_overlay", overlay);
+        insta::assert_snapshot!("recto_uniform_bleed_wrapper", wrapper);
+    }
+
+    #[test]
+    fn snapshot_verso_no_inside_bleed() {
+        let bleed = Bleed {
+            inside: 0.0,
+            ..Bleed::uniform(9.0)
+        };
+        let (overlay, wrapper) = stamp(&options(bleed), 2, 2);
+        insta::assert_snapshot!("verso_no_inside_bleed_overlay", overlay);
+        insta::assert_snapshot!("verso_no_inside_bleed_wrapper", wrapper);
+    }
+}
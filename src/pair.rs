@@ -0,0 +1,144 @@
+use lopdf::Document;
+use tracing::info;
+
+use crate::geometry::SLUG_MARGIN;
+use crate::overlay::{self, Bleed, ContentBox, MARK_LENGTH, Options};
+use crate::rules::Rule;
+use crate::units::{self, POINTS_PER_INCH, POINTS_PER_MM};
+
+/// Thickness added to the spine by each page of the interior, for the
+/// papers print on demand vendors commonly offer.
+pub const PAPERS: [(&str, f64); 3] = [
+    ("white", 0.002252 * POINTS_PER_INCH),
+    ("cream", 0.0025 * POINTS_PER_INCH),
+    ("color", 0.002347 * POINTS_PER_INCH),
+];
+
+/// How far the cover may be from the size the interior calls for: a
+/// millimetre, about what paper varies by between batches.
+const TOLERANCE: f64 = POINTS_PER_MM;
+
+/// Parse the paper an interior is printed on, by name or as the thickness
+/// per page, such as 0.0025in.
+pub fn parse_paper(text: &str) -> Result<f64, String> {
+    let name = text.trim().to_ascii_lowercase();
+
+    match PAPERS.iter().find(|(paper, _)| *paper == name) {
+        Some(&(_, thickness)) => Ok(thickness),
+        None => units::parse_length(text).map_err(|_| {
+            let names: Vec<&str> = PAPERS.iter().map(|(paper, _)| *paper).collect();
+            format!(
+                "Unknown paper '{}'. Papers: {}, or the thickness per page, such as 0.0025in",
+                text,
+                names.join(", ")
+            )
+        }),
+    }
+}
+
+/// The size of a cover, from its first page, and how many pages it has.
+pub struct Cover {
+    pub width: f64,
+    pub height: f64,
+    pub pages: usize,
+}
+
+impl Cover {
+    pub fn measure(doc: &Document, which: ContentBox) -> Result<Cover, String> {
+        let pages = doc.get_pages();
+        let Some(&page_id) = pages.values().next() else {
+            return Err("The cover has no pages".to_string());
+        };
+        let page = overlay::content_box(doc, page_id, which).map_err(|e| e.to_string())?;
+
+        Ok(Cover {
+            width: page.width(),
+            height: page.height(),
+            pages: pages.len(),
+        })
+    }
+}
+
+/// Check that a cover fits an interior of the given page count and trim,
+/// printed on paper of the given thickness per page: that it's one page,
+/// the height of the trim, and as wide as the back, front, and a spine as
+/// thick as the interior, with the given bleed all round. Each problem
+/// found is described.
+pub fn check(
+    cover: &Cover,
+    interior_pages: usize,
+    trim_width: f64,
+    trim_height: f64,
+    bleed: f64,
+    paper: f64,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+    let describe = |points: f64| {
+        format!(
+            "{:.2}mm ({:.3}in)",
+            points / POINTS_PER_MM,
+            points / POINTS_PER_INCH
+        )
+    };
+
+    if cover.pages != 1 {
+        problems.push(format!(
+            "The cover has {} pages, but printers expect the back, spine, and front together on one",
+            cover.pages
+        ));
+    }
+
+    let height = cover.height - 2.0 * bleed;
+    if (height - trim_height).abs() > TOLERANCE {
+        problems.push(format!(
+            "The cover is for a trim {} high, but the interior's trim is {} high",
+            describe(height),
+            describe(trim_height)
+        ));
+    }
+
+    let spine = interior_pages as f64 * paper;
+    let cover_spine = cover.width - 2.0 * bleed - 2.0 * trim_width;
+    info!(
+        "Spine for {} pages is {}; the cover's is {}",
+        interior_pages,
+        describe(spine),
+        describe(cover_spine)
+    );
+    if (cover_spine - spine).abs() > TOLERANCE {
+        problems.push(format!(
+            "The cover's spine is {}, but {} pages of this paper make a spine {}; was the cover made for another page count or paper?",
+            describe(cover_spine),
+            interior_pages,
+            describe(spine)
+        ));
+    }
+
+    problems
+}
+
+/// Options for checking the cover as a manuscript of its own: its trim the
+/// whole spread, on a sheet large enough to hold it, with only the vendor's
+/// cover rules, and no slug to clear.
+pub fn cover_options(interior: &Options, cover: &Cover, bleed: f64, rules: Vec<Rule>) -> Options {
+    let margin = 2.0 * (bleed + interior.mark_gap + MARK_LENGTH + SLUG_MARGIN);
+
+    Options {
+        trim_width: cover.width - 2.0 * bleed,
+        trim_height: cover.height - 2.0 * bleed,
+        sheet_width: cover.width + margin,
+        sheet_height: cover.height + margin,
+        bleed: Bleed::uniform(bleed),
+        page_order: None,
+        reverse: false,
+        pages: None,
+        only: None,
+        bare_pages: None,
+        folds: Vec::new(),
+        trim_origin: None,
+        rules,
+        extend_bleed: None,
+        marks_only: true,
+        ..interior.clone()
+    }
+}
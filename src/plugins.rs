@@ -0,0 +1,373 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use lopdf::content::Operation;
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::geometry::{Layout, Rect};
+use crate::marks::MarkRenderer;
+use crate::units::{POINTS_PER_INCH, POINTS_PER_MM};
+
+/// Environment variable naming the directory mark packs are found in, in
+/// place of the user's.
+const DIRECTORY_VARIABLE: &str = "CROPPED_PLUGINS";
+
+/// How far a circle's control points are from its ends, as a fraction of the
+/// radius, for four Bézier curves to make a circle.
+pub const KAPPA: f64 = 0.552_284_8;
+
+/// A set of marks drawn on every sheet as well as the crop marks, read from a
+/// data file so that marks a vendor asks for, such as registration targets
+/// or fold marks, can be shared without changes to the program.
+///
+/// A mark pack is a TOML file in the plugins directory, named for the file:
+///
+/// ```toml
+/// description = "Registration targets at the middle of each edge"
+/// units = "mm"
+/// weight = 0.25
+///
+/// [[shape]]
+/// at = ["top", "bottom", "left", "right"]
+/// path = "M -4 3 L 4 3 M 0 -1 L 0 7"
+///
+/// [[shape]]
+/// at = ["top", "bottom", "left", "right"]
+/// circle = [0, 3, 2.5]
+/// ```
+///
+/// Each shape is drawn at each of its anchors: a corner or the middle of an
+/// edge of the bleed, so that the marks stay clear of it however much bleed
+/// the job has. Its path is given in a frame whose origin is the anchor and
+/// whose y axis points away from the page, so that one path serves every
+/// edge; at a corner, x points away from the page too.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MarkPack {
+    pub name: String,
+    pub description: String,
+    /// Width of stroked paths, in points.
+    pub weight: f64,
+    pub color: Color,
+    pub shapes: Vec<Shape>,
+}
+
+/// One shape of a mark pack, in points.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Shape {
+    pub at: Vec<Anchor>,
+    pub path: Vec<Segment>,
+    pub paint: Paint,
+}
+
+/// Where on the bleed a shape is drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Anchor {
+    Top,
+    Bottom,
+    Left,
+    Right,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Segment {
+    Move(f64, f64),
+    Line(f64, f64),
+    Curve(f64, f64, f64, f64, f64, f64),
+    Close,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Paint {
+    #[default]
+    Stroke,
+    Fill,
+}
+
+/// The ink marks are printed in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Color {
+    /// All four process inks, so that the mark shows on every plate.
+    #[default]
+    Registration,
+    Black,
+}
+
+/// A mark pack as written in its file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PackFile {
+    #[serde(default)]
+    description: String,
+    #[serde(default = "default_units")]
+    units: String,
+    #[serde(default = "default_weight")]
+    weight: f64,
+    #[serde(default)]
+    color: Color,
+    #[serde(default)]
+    shape: Vec<ShapeFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ShapeFile {
+    at: Vec<Anchor>,
+    path: Option<String>,
+    circle: Option<[f64; 3]>,
+    #[serde(default)]
+    paint: Paint,
+}
+
+fn default_units() -> String {
+    "mm".to_string()
+}
+
+fn default_weight() -> f64 {
+    0.25
+}
+
+/// The directory mark packs are found in: the one named by
+/// `CROPPED_PLUGINS`, or otherwise `plugins` beside the user's
+/// configuration.
+fn directory() -> Option<PathBuf> {
+    env::var_os(DIRECTORY_VARIABLE)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| config::user_directory().map(|dir| dir.join("plugins")))
+}
+
+/// The names of the mark packs installed, in order.
+pub fn available() -> Vec<String> {
+    let Some(entries) = directory().and_then(|dir| fs::read_dir(dir).ok()) else {
+        return Vec::new();
+    };
+
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .filter_map(|path| Some(path.file_stem()?.to_string_lossy().into_owned()))
+        .collect();
+    names.sort();
+    names
+}
+
+/// Find and read the mark pack of the given name.
+pub fn find(name: &str) -> Result<MarkPack, String> {
+    let path = directory()
+        .map(|dir| dir.join(format!("{}.toml", name)))
+        .filter(|path| path.is_file());
+
+    match path {
+        Some(path) => load(name, &path),
+        None => {
+            let mut available = available().join(", ");
+            if available.is_empty() {
+                available = "none".to_string();
+            }
+            let directory = directory()
+                .map(|dir| dir.display().to_string())
+                .unwrap_or_else(|| "the plugins directory".to_string());
+            Err(format!(
+                "No mark pack '{}' in {}. Installed: {}",
+                name, directory, available
+            ))
+        }
+    }
+}
+
+/// Read a mark pack from the given file.
+pub fn load(name: &str, path: &Path) -> Result<MarkPack, String> {
+    let invalid = |reason: String| format!("Invalid mark pack {}: {}", path.display(), reason);
+
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read mark pack {}: {}", path.display(), e))?;
+    let file: PackFile = toml::from_str(&text).map_err(|e| invalid(e.to_string()))?;
+
+    let scale = match file.units.as_str() {
+        "mm" => POINTS_PER_MM,
+        "pt" => 1.0,
+        "in" => POINTS_PER_INCH,
+        other => {
+            return Err(invalid(format!(
+                "unknown units '{}'; use mm, pt, or in",
+                other
+            )));
+        }
+    };
+
+    let mut shapes = Vec::new();
+    for (index, shape) in file.shape.into_iter().enumerate() {
+        let mut path = match &shape.path {
+            Some(path) => parse_path(path, scale)
+                .map_err(|reason| invalid(format!("shape {}: {}", index + 1, reason)))?,
+            None => Vec::new(),
+        };
+        if let Some([x, y, radius]) = shape.circle {
+            path.extend(circle(x * scale, y * scale, radius * scale));
+        }
+        if path.is_empty() {
+            return Err(invalid(format!(
+                "shape {} has neither a path nor a circle",
+                index + 1
+            )));
+        }
+
+        shapes.push(Shape {
+            at: shape.at,
+            path,
+            paint: shape.paint,
+        });
+    }
+
+    Ok(MarkPack {
+        name: name.to_string(),
+        description: file.description,
+        weight: file.weight,
+        color: file.color,
+        shapes,
+    })
+}
+
+/// Parse a path of the commands M, L, and C, each followed by its
+/// coordinates, and Z, as in SVG but with y upward.
+fn parse_path(text: &str, scale: f64) -> Result<Vec<Segment>, String> {
+    let mut tokens = text.split_whitespace();
+    let mut segments = Vec::new();
+
+    while let Some(command) = tokens.next() {
+        let count = match command {
+            "M" | "L" => 2,
+            "C" => 6,
+            "Z" => 0,
+            other => return Err(format!("unknown path command '{}'", other)),
+        };
+
+        let mut numbers = Vec::with_capacity(count);
+        for _ in 0..count {
+            let number = tokens
+                .next()
+                .ok_or_else(|| format!("{} needs {} numbers", command, count))?;
+            let number: f64 = number
+                .parse()
+                .map_err(|_| format!("'{}' is not a number", number))?;
+            numbers.push(number * scale);
+        }
+
+        segments.push(match numbers[..] {
+            [x, y] if command == "M" => Segment::Move(x, y),
+            [x, y] => Segment::Line(x, y),
+            [x1, y1, x2, y2, x, y] => Segment::Curve(x1, y1, x2, y2, x, y),
+            _ => Segment::Close,
+        });
+    }
+
+    match segments.first() {
+        Some(Segment::Move(..)) | None => Ok(segments),
+        Some(_) => Err("a path must start with M".to_string()),
+    }
+}
+
+/// A circle as four Bézier curves.
+fn circle(x: f64, y: f64, radius: f64) -> Vec<Segment> {
+    let k = radius * KAPPA;
+
+    vec![
+        Segment::Move(x + radius, y),
+        Segment::Curve(x + radius, y + k, x + k, y + radius, x, y + radius),
+        Segment::Curve(x - k, y + radius, x - radius, y + k, x - radius, y),
+        Segment::Curve(x - radius, y - k, x - k, y - radius, x, y - radius),
+        Segment::Curve(x + k, y - radius, x + radius, y - k, x + radius, y),
+        Segment::Close,
+    ]
+}
+
+impl Anchor {
+    /// The anchor's origin on the bleed, and the directions on the sheet of
+    /// the x and y axes of its frame.
+    fn frame(&self, bleed: &Rect) -> ((f64, f64), (f64, f64), (f64, f64)) {
+        let middle = ((bleed.x1 + bleed.x2) / 2.0, (bleed.y1 + bleed.y2) / 2.0);
+
+        match self {
+            Anchor::Top => ((middle.0, bleed.y2), (1.0, 0.0), (0.0, 1.0)),
+            Anchor::Bottom => ((middle.0, bleed.y1), (-1.0, 0.0), (0.0, -1.0)),
+            Anchor::Left => ((bleed.x1, middle.1), (0.0, 1.0), (-1.0, 0.0)),
+            Anchor::Right => ((bleed.x2, middle.1), (0.0, -1.0), (1.0, 0.0)),
+            Anchor::TopLeft => ((bleed.x1, bleed.y2), (-1.0, 0.0), (0.0, 1.0)),
+            Anchor::TopRight => ((bleed.x2, bleed.y2), (1.0, 0.0), (0.0, 1.0)),
+            Anchor::BottomLeft => ((bleed.x1, bleed.y1), (-1.0, 0.0), (0.0, -1.0)),
+            Anchor::BottomRight => ((bleed.x2, bleed.y1), (1.0, 0.0), (0.0, -1.0)),
+        }
+    }
+}
+
+impl MarkRenderer for MarkPack {
+    fn render(&self, layout: &Layout) -> Vec<Operation> {
+        let mut ops = vec![Operation::new("q", vec![])];
+
+        match self.color {
+            Color::Registration => {
+                ops.push(Operation::new(
+                    "K",
+                    vec![1.into(), 1.into(), 1.into(), 1.into()],
+                ));
+                ops.push(Operation::new(
+                    "k",
+                    vec![1.into(), 1.into(), 1.into(), 1.into()],
+                ));
+            }
+            Color::Black => {
+                ops.push(Operation::new(
+                    "K",
+                    vec![0.into(), 0.into(), 0.into(), 1.into()],
+                ));
+                ops.push(Operation::new(
+                    "k",
+                    vec![0.into(), 0.into(), 0.into(), 1.into()],
+                ));
+            }
+        }
+        ops.push(Operation::new("w", vec![self.weight.into()]));
+
+        for shape in &self.shapes {
+            for anchor in &shape.at {
+                let ((x0, y0), (xx, xy), (yx, yy)) = anchor.frame(&layout.bleed);
+                let point = |x: f64, y: f64| -> [lopdf::Object; 2] {
+                    [(x0 + x * xx + y * yx).into(), (y0 + x * xy + y * yy).into()]
+                };
+
+                for segment in &shape.path {
+                    ops.push(match *segment {
+                        Segment::Move(x, y) => Operation::new("m", point(x, y).to_vec()),
+                        Segment::Line(x, y) => Operation::new("l", point(x, y).to_vec()),
+                        Segment::Curve(x1, y1, x2, y2, x, y) => {
+                            let mut operands = point(x1, y1).to_vec();
+                            operands.extend(point(x2, y2));
+                            operands.extend(point(x, y));
+                            Operation::new("c", operands)
+                        }
+                        Segment::Close => Operation::new("h", vec![]),
+                    });
+                }
+
+                let paint = match shape.paint {
+                    Paint::Stroke => "S",
+                    Paint::Fill => "f",
+                };
+                ops.push(Operation::new(paint, vec![]));
+            }
+        }
+
+        ops.push(Operation::new("Q", vec![]));
+        ops
+    }
+}
@@ -0,0 +1,705 @@
+use std::io;
+
+use lopdf::{Document, Object, ObjectId};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use crate::analysis::Analysis;
+use crate::geometry::{self, Rect, SLUG_MARGIN, Slug};
+use crate::overlay::{self, BleedExtension, Options, Progress, SlugFit, SlugText};
+use crate::rules::{Check, Rule, Severity};
+
+/// Tolerance when comparing page dimensions, to allow for rounding in the
+/// typesetting software that produced the manuscript.
+const TOLERANCE: f64 = 0.5;
+
+/// Lowest effective image resolution accepted without a warning. Printers
+/// ask for 300ppi but softness isn't usually visible above about 225ppi.
+const MINIMUM_RESOLUTION: f64 = 225.0;
+
+/// Run the checks on the manuscript's geometry before stamping.
+///
+/// Problems are reported as warnings through the progress callback, unless
+/// strict mode is on in which case the first problem found is returned as
+/// an error and stamping does not proceed.
+pub fn check(
+    doc: &Document,
+    page_ids: &[ObjectId],
+    options: &Options,
+    slug: Option<&SlugText>,
+    on_progress: &mut impl FnMut(Progress),
+) -> lopdf::Result<()> {
+    let mut analysis = Analysis::new(doc);
+
+    check_bleed(doc, page_ids, options, on_progress)?;
+    check_sheet_fit(options, on_progress)?;
+    check_clearance(doc, page_ids, options, slug, on_progress)?;
+    check_color(&mut analysis, page_ids, options, on_progress)?;
+    check_resolution(&mut analysis, page_ids, options, on_progress)?;
+    check_blank(&mut analysis, page_ids, options, on_progress);
+    check_contents(doc, page_ids, options, on_progress);
+    check_rules(doc, &mut analysis, page_ids, options, on_progress)?;
+    Ok(())
+}
+
+/// Decide where the slug goes.
+///
+/// Normally it sits 1cm from the bottom of the sheet. If that would collide
+/// with the content, bleed, or crop marks on any page and --slug-fit is
+/// auto, it is moved to whichever of the bottom and top margins is wider,
+/// shrinking the font if necessary, though never a large-print slug. If it
+/// can't be made to fit at all it is left in place for the clearance check
+/// to report.
+///
+/// With a duplex slug, the pages on the backs of sheets are considered
+/// upside down, since their slug is flipped to the opposite margin.
+pub fn fit_slug(
+    doc: &Document,
+    page_ids: &[ObjectId],
+    options: &Options,
+    default: Slug,
+) -> lopdf::Result<Slug> {
+    if options.slug_fit == SlugFit::Strict {
+        return Ok(default);
+    }
+
+    // Find the clear bands below and above everything on every page
+    let backs = overlay::backs(options, page_ids.len())?;
+    let mut below: f64 = options.sheet_height;
+    let mut above: f64 = 0.0;
+    for (index, page_id) in page_ids.iter().enumerate() {
+        let source = overlay::content_box(doc, *page_id, options.content_box)?;
+        let (width, height) = (source.width(), source.height());
+        let layout = geometry::layout(options, index + 1, width, height);
+
+        for area in layout.marks.iter().chain([&layout.content, &layout.bleed]) {
+            let (y1, y2) = if backs[index] {
+                (
+                    options.sheet_height - area.y2,
+                    options.sheet_height - area.y1,
+                )
+            } else {
+                (area.y1, area.y2)
+            };
+            below = below.min(y1);
+            above = above.max(y2);
+        }
+    }
+
+    if default.top() <= below {
+        return Ok(default);
+    }
+
+    // Try the full size at the top first, then shrink into the wider margin
+    let at_top = default.with_top(options.sheet_height - SLUG_MARGIN);
+    if at_top.bottom() >= above {
+        info!("Slug moved to the top margin to clear the page content");
+        return Ok(at_top);
+    }
+
+    let (low, high, edge) = if below >= options.sheet_height - above {
+        (0.0, below, "bottom")
+    } else {
+        (above, options.sheet_height, "top")
+    };
+
+    let mut size = default.size;
+    while size >= options.slug_size.min_size() {
+        if let Some(slug) = default.fit(low, high, size, options.sheet_height) {
+            info!(
+                "Slug reduced to {}pt in the {} margin to clear the page content",
+                size, edge
+            );
+            return Ok(slug);
+        }
+        size -= 0.5;
+    }
+
+    Ok(default)
+}
+
+/// The kinds of problem preflight reports, so that those known to be
+/// acceptable for a particular job can be allowed with --allow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Category {
+    MissingBleed,
+    BleedSynthesized,
+    OffSheet,
+    SlugOverlapsContent,
+    SlugInBleed,
+    MarksOverlapContent,
+    RgbContent,
+    LowResolution,
+    BlankPage,
+    Rule,
+    UnknownTimezone,
+    MalformedContents,
+}
+
+impl Category {
+    pub const ALL: [Category; 12] = [
+        Category::MissingBleed,
+        Category::BleedSynthesized,
+        Category::OffSheet,
+        Category::SlugOverlapsContent,
+        Category::SlugInBleed,
+        Category::MarksOverlapContent,
+        Category::RgbContent,
+        Category::LowResolution,
+        Category::BlankPage,
+        Category::Rule,
+        Category::UnknownTimezone,
+        Category::MalformedContents,
+    ];
+
+    /// The name used for the category on the command line.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::MissingBleed => "missing-bleed",
+            Category::BleedSynthesized => "bleed-synthesized",
+            Category::OffSheet => "off-sheet",
+            Category::SlugOverlapsContent => "slug-overlaps-content",
+            Category::SlugInBleed => "slug-in-bleed",
+            Category::MarksOverlapContent => "marks-overlap-content",
+            Category::RgbContent => "rgb-content",
+            Category::LowResolution => "low-resolution",
+            Category::BlankPage => "blank-page",
+            Category::Rule => "rule",
+            Category::UnknownTimezone => "unknown-timezone",
+            Category::MalformedContents => "malformed-contents",
+        }
+    }
+
+    /// The stable code identifying the category in messages, such as CR001.
+    /// Codes are never reused or renumbered.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Category::MissingBleed => "CR001",
+            Category::BleedSynthesized => "CR002",
+            Category::OffSheet => "CR003",
+            Category::SlugOverlapsContent => "CR004",
+            Category::SlugInBleed => "CR005",
+            Category::MarksOverlapContent => "CR006",
+            Category::RgbContent => "CR007",
+            Category::LowResolution => "CR008",
+            Category::BlankPage => "CR009",
+            Category::Rule => "CR010",
+            Category::UnknownTimezone => "CR011",
+            Category::MalformedContents => "CR012",
+        }
+    }
+
+    /// A paragraph on what causes problems of this kind and how to fix them.
+    pub fn explanation(&self) -> &'static str {
+        match self {
+            Category::MissingBleed => {
+                "The manuscript's pages are smaller than the trim plus the bleed given with --bleed. Artwork that is meant to run off the edge of the page must extend past the trim so that a slightly misaligned cut doesn't leave a white sliver. Re-export the manuscript with bleed from your typesetting software, or if the design has nothing running off the edge, leave out --bleed."
+            }
+            Category::BleedSynthesized => {
+                "Pages lacking bleed had it made up by --extend-bleed, either by mirroring the content at each edge or by enlarging the page slightly. Both are approximations: check the proof closely at the edges, and prefer a manuscript re-exported with real bleed where possible."
+            }
+            Category::OffSheet => {
+                "The trim area and its crop marks don't fit on the sheet. The trim size is too large for the sheet, or --trim-origin places it too close to an edge. Use a smaller trim or move it onto the sheet."
+            }
+            Category::SlugOverlapsContent => {
+                "The slug (the line giving the time, filename, and page number) would print over the page content, because the margin around the content is too narrow to hold it. Leave --slug-fit at auto to let the slug move and shrink, or use a smaller trim."
+            }
+            Category::SlugInBleed => {
+                "The slug would print inside the bleed area, where it could show on the finished page if the cut drifts. This happens with a large trim or bleed on a small sheet. Leave --slug-fit at auto, or reduce the bleed."
+            }
+            Category::MarksOverlapContent => {
+                "The crop marks would print over the page content. This happens when the manuscript's pages are larger than the trim plus the bleed given, so the content extends into where the marks go. Give the bleed the manuscript actually carries with --bleed, or choose the right page box with --content-box."
+            }
+            Category::RgbContent => {
+                "The manuscript uses RGB color, either set directly or in images. Presses print in CMYK, and the conversion they apply may shift colors noticeably, especially saturated blues and greens. Convert to CMYK with the printer's profile before sending, or allow this for proofs where color doesn't matter."
+            }
+            Category::LowResolution => {
+                "Images are placed at too low a resolution to print cleanly, and will look soft or pixelated. Printers ask for 300ppi at the size the image is placed. Replace them with higher resolution originals, or place them smaller."
+            }
+            Category::BlankPage => {
+                "Pages have nothing on them. Books often have intentionally blank pages, so this is only ever a warning, but an unexpected one can mean content failed to export."
+            }
+            Category::Rule => {
+                "A preflight rule given with --rules failed. The message names the rule; see the rules file for what it requires."
+            }
+            Category::UnknownTimezone => {
+                "The system's timezone couldn't be found, as in containers without tzdata, or isn't one the timezone database knows, so the slug's timestamp is given in UTC instead, labeled as such. Set the system's timezone to a zone such as Australia/Sydney, or give --tz-offset +10:00, to get local time."
+            }
+            Category::MalformedContents => {
+                "Pages list things other than content streams among their contents, such as inline dictionaries, nulls, or references to objects which don't exist. Viewers generally skip them, so they were dropped from the stamped pages rather than passed on to trip up a RIP. Nothing visible should be lost, but the software that produced the manuscript is writing malformed files."
+            }
+        }
+    }
+
+    /// Parse a category given by name, or by code.
+    pub fn parse(text: &str) -> Result<Category, String> {
+        Category::ALL
+            .into_iter()
+            .find(|category| category.name() == text || category.code().eq_ignore_ascii_case(text))
+            .ok_or_else(|| {
+                let names: Vec<&str> = Category::ALL.iter().map(Category::name).collect();
+                format!(
+                    "Unknown warning '{}'; expected one of {}",
+                    text,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// Report a problem, either as a warning or, under --strict, as an error.
+/// Problems in a category the user has allowed are only logged.
+fn report(
+    options: &Options,
+    on_progress: &mut impl FnMut(Progress),
+    category: Category,
+    message: String,
+) -> lopdf::Result<()> {
+    if options.allow.contains(&category) {
+        debug!("Allowed {}: {}", category.name(), message);
+        return Ok(());
+    }
+    let message = format!("[{}] {}", category.code(), message);
+    if options.strict {
+        return Err(lopdf::Error::IO(io::Error::other(message)));
+    }
+    on_progress(Progress::Warning(message));
+    Ok(())
+}
+
+/// Report something worth knowing about which isn't a problem as such, and
+/// so is a warning even under --strict.
+pub fn notice(
+    options: &Options,
+    on_progress: &mut impl FnMut(Progress),
+    category: Category,
+    message: String,
+) {
+    if options.allow.contains(&category) {
+        debug!("Allowed {}: {}", category.name(), message);
+        return;
+    }
+    on_progress(Progress::Warning(format!(
+        "[{}] {}",
+        category.code(),
+        message
+    )));
+}
+
+/// Describe which pages a problem affects.
+fn describe(pages: &[usize], total: usize) -> String {
+    format!(
+        "{} of {} pages, first is page {}",
+        pages.len(),
+        total,
+        pages[0]
+    )
+}
+
+/// Check that every page carries the bleed the user said it would, warning
+/// about those that don't.
+///
+/// A page without enough bleed will show a white sliver at the edge if the
+/// guillotine drifts, which is worth knowing about before it goes to press.
+fn check_bleed(
+    doc: &Document,
+    page_ids: &[ObjectId],
+    options: &Options,
+    on_progress: &mut impl FnMut(Progress),
+) -> lopdf::Result<()> {
+    if options.bleed.is_zero() {
+        return Ok(());
+    }
+
+    let mut short = Vec::new();
+    for (index, page_id) in page_ids.iter().enumerate() {
+        let bleed = options.bleed.edges(index + 1);
+        let required_width = options.trim_width + bleed.left + bleed.right;
+        let required_height = options.trim_height + bleed.bottom + bleed.top;
+
+        let source = overlay::content_box(doc, *page_id, options.content_box)?;
+        let (width, height) = (source.width(), source.height());
+        if width + TOLERANCE < required_width || height + TOLERANCE < required_height {
+            short.push(index + 1);
+        }
+    }
+
+    if short.is_empty() {
+        return Ok(());
+    }
+
+    // Having asked for the bleed to be made up, the user knows it's missing;
+    // all that's left is to be clear that what they get is an approximation
+    if let Some(extension) = options.extend_bleed {
+        let method = match extension {
+            BleedExtension::Mirror => "mirroring",
+            BleedExtension::CloneEdge => "scaling",
+        };
+        notice(
+            options,
+            on_progress,
+            Category::BleedSynthesized,
+            format!(
+                "Bleed synthesized by {} for pages lacking it; this is lossy, check the proof ({})",
+                method,
+                describe(&short, page_ids.len())
+            ),
+        );
+    } else {
+        report(
+            options,
+            on_progress,
+            Category::MissingBleed,
+            format!(
+                "Pages lack the requested bleed ({})",
+                describe(&short, page_ids.len())
+            ),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Check that the trim area and its crop marks fit on the sheet. This
+/// matters most when the trim has been placed explicitly rather than
+/// centered.
+fn check_sheet_fit(options: &Options, on_progress: &mut impl FnMut(Progress)) -> lopdf::Result<()> {
+    if !options.marks_fit() {
+        return report(
+            options,
+            on_progress,
+            Category::OffSheet,
+            "Trim area and crop marks extend beyond the edge of the sheet".to_string(),
+        );
+    }
+
+    Ok(())
+}
+
+/// Check that the slug and crop marks stay clear of the manuscript content
+/// and of the bleed area. With a large trim on a small sheet the margins can
+/// be too narrow to hold them, and marks or text printed into the live area
+/// spoil the proof.
+fn check_clearance(
+    doc: &Document,
+    page_ids: &[ObjectId],
+    options: &Options,
+    slug: Option<&SlugText>,
+    on_progress: &mut impl FnMut(Progress),
+) -> lopdf::Result<()> {
+    let total = page_ids.len();
+    let note = options.slug_note_lines();
+    let boxes = |slug: &Slug, text: &SlugText| {
+        // Digits needn't all be the same width, so measure every page number
+        let widest_page_text = (1..=total)
+            .map(|page_num| options.page_text(page_num, total))
+            .max_by(|a, b| {
+                text.metrics
+                    .measure(a, 1.0)
+                    .total_cmp(&text.metrics.measure(b, 1.0))
+            })
+            .unwrap_or_default();
+        geometry::slug_boxes(
+            slug,
+            options.sheet_width,
+            text.metrics,
+            text.timestamp,
+            text.center,
+            &widest_page_text,
+            &note,
+        )
+    };
+    // With no slug, there are no boxes for it to overlap
+    let front = slug.map(|text| boxes(&text.slug, text)).unwrap_or_default();
+    let back = slug
+        .map(|text| boxes(&text.slug.flipped(options.sheet_height), text))
+        .unwrap_or_default();
+    let backs = overlay::backs(options, total)?;
+
+    let overlaps = |boxes: &[Rect], area: &Rect| boxes.iter().any(|b| b.intersects(area));
+
+    let mut slug_in_content = Vec::new();
+    let mut slug_in_bleed = Vec::new();
+    let mut marks_in_content = Vec::new();
+
+    for (index, page_id) in page_ids.iter().enumerate() {
+        let page_num = index + 1;
+        // Nothing is drawn on bare pages to get in the way
+        if !options.overlaid(page_num) {
+            continue;
+        }
+        let source = overlay::content_box(doc, *page_id, options.content_box)?;
+        let (width, height) = (source.width(), source.height());
+        let layout = geometry::layout(options, page_num, width, height);
+        let slug = if backs[index] { &back } else { &front };
+
+        if overlaps(slug, &layout.content) {
+            slug_in_content.push(page_num);
+        }
+        if overlaps(slug, &layout.bleed) {
+            slug_in_bleed.push(page_num);
+        }
+        if overlaps(&layout.marks, &layout.content) {
+            marks_in_content.push(page_num);
+        }
+    }
+
+    if !slug_in_content.is_empty() {
+        report(
+            options,
+            on_progress,
+            Category::SlugOverlapsContent,
+            format!(
+                "Slug overlaps the page content ({})",
+                describe(&slug_in_content, total)
+            ),
+        )?;
+    }
+    if !slug_in_bleed.is_empty() {
+        report(
+            options,
+            on_progress,
+            Category::SlugInBleed,
+            format!(
+                "Slug falls inside the bleed area ({})",
+                describe(&slug_in_bleed, total)
+            ),
+        )?;
+    }
+    if !marks_in_content.is_empty() {
+        report(
+            options,
+            on_progress,
+            Category::MarksOverlapContent,
+            format!(
+                "Crop marks overlap the page content ({})",
+                describe(&marks_in_content, total)
+            ),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Check for RGB color, which the press will convert to CMYK with
+/// unpredictable results.
+fn check_color(
+    analysis: &mut Analysis,
+    page_ids: &[ObjectId],
+    options: &Options,
+    on_progress: &mut impl FnMut(Progress),
+) -> lopdf::Result<()> {
+    let mut rgb = Vec::new();
+    for (index, page_id) in page_ids.iter().enumerate() {
+        if analysis.page(*page_id).is_some_and(|page| page.uses_rgb) {
+            rgb.push(index + 1);
+        }
+    }
+
+    if !rgb.is_empty() {
+        report(
+            options,
+            on_progress,
+            Category::RgbContent,
+            format!("Pages use RGB color ({})", describe(&rgb, page_ids.len())),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Check that images have been placed at a resolution high enough to print
+/// cleanly.
+fn check_resolution(
+    analysis: &mut Analysis,
+    page_ids: &[ObjectId],
+    options: &Options,
+    on_progress: &mut impl FnMut(Progress),
+) -> lopdf::Result<()> {
+    let mut low = Vec::new();
+    let mut lowest = f64::INFINITY;
+    for (index, page_id) in page_ids.iter().enumerate() {
+        let Some(page) = analysis.page(*page_id) else {
+            continue;
+        };
+        for image in &page.images {
+            if image.ppi < MINIMUM_RESOLUTION {
+                debug!(
+                    "Image {} on page {} is {:.0}ppi",
+                    image.name,
+                    index + 1,
+                    image.ppi
+                );
+                if low.last() != Some(&(index + 1)) {
+                    low.push(index + 1);
+                }
+                lowest = lowest.min(image.ppi);
+            }
+        }
+    }
+
+    if !low.is_empty() {
+        report(
+            options,
+            on_progress,
+            Category::LowResolution,
+            format!(
+                "Images below {:.0}ppi, lowest {:.0}ppi ({})",
+                MINIMUM_RESOLUTION,
+                lowest,
+                describe(&low, page_ids.len())
+            ),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Point out pages with nothing on them. Books have intentionally blank
+/// pages, so this is only ever a warning, even under --strict.
+fn check_blank(
+    analysis: &mut Analysis,
+    page_ids: &[ObjectId],
+    options: &Options,
+    on_progress: &mut impl FnMut(Progress),
+) {
+    let mut blank = Vec::new();
+    for (index, page_id) in page_ids.iter().enumerate() {
+        if analysis
+            .page(*page_id)
+            .is_some_and(|page| page.painted == 0)
+        {
+            blank.push(index + 1);
+        }
+    }
+
+    if !blank.is_empty() {
+        notice(
+            options,
+            on_progress,
+            Category::BlankPage,
+            format!("Pages are blank ({})", describe(&blank, page_ids.len())),
+        );
+    }
+}
+
+/// Check that each page's contents are all content streams. Anything else
+/// is dropped when the page is stamped.
+fn check_contents(
+    doc: &Document,
+    page_ids: &[ObjectId],
+    options: &Options,
+    on_progress: &mut impl FnMut(Progress),
+) {
+    let mut malformed = Vec::new();
+    for (index, page_id) in page_ids.iter().enumerate() {
+        let Ok(contents) = doc
+            .get_dictionary(*page_id)
+            .and_then(|page| page.get(b"Contents"))
+        else {
+            continue;
+        };
+
+        let entries = match contents {
+            Object::Array(items) => items.len(),
+            Object::Null => 0,
+            _ => 1,
+        };
+        if overlay::content_streams(doc, contents).len() != entries {
+            malformed.push(index + 1);
+        }
+    }
+
+    if !malformed.is_empty() {
+        notice(
+            options,
+            on_progress,
+            Category::MalformedContents,
+            format!(
+                "Page contents include entries which aren't content streams, which will be dropped ({})",
+                describe(&malformed, page_ids.len())
+            ),
+        );
+    }
+}
+
+/// Apply the user's own rules, if any were given with --rules.
+fn check_rules(
+    doc: &Document,
+    analysis: &mut Analysis,
+    page_ids: &[ObjectId],
+    options: &Options,
+    on_progress: &mut impl FnMut(Progress),
+) -> lopdf::Result<()> {
+    let total = page_ids.len();
+
+    for rule in &options.rules {
+        let problem = match rule.check {
+            Check::PageCountMultiple(n) if n > 0 && !total.is_multiple_of(n) => {
+                Some(format!("{} pages is not a multiple of {}", total, n))
+            }
+            Check::MinPages(n) if total < n => {
+                Some(format!("{} pages, at least {} required", total, n))
+            }
+            Check::MaxPages(n) if total > n => {
+                Some(format!("{} pages, at most {} allowed", total, n))
+            }
+            Check::PageCountMultiple(_) | Check::MinPages(_) | Check::MaxPages(_) => None,
+            _ => {
+                let mut failed = Vec::new();
+                for (index, page_id) in page_ids.iter().enumerate() {
+                    let page_num = index + 1;
+                    if rule.pages.includes(page_num, total)
+                        && !page_passes(doc, analysis, *page_id, options, rule)?
+                    {
+                        failed.push(page_num);
+                    }
+                }
+                (!failed.is_empty()).then(|| describe(&failed, total))
+            }
+        };
+
+        if let Some(problem) = problem {
+            let message = format!("Rule '{}' failed ({})", rule.name, problem);
+            match rule.severity {
+                Severity::Error => {
+                    return Err(lopdf::Error::IO(io::Error::other(format!(
+                        "[{}] {}",
+                        Category::Rule.code(),
+                        message
+                    ))));
+                }
+                Severity::Warning => report(options, on_progress, Category::Rule, message)?,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether a single page satisfies a rule. Pages whose content couldn't be
+/// analyzed pass the checks that need it.
+fn page_passes(
+    doc: &Document,
+    analysis: &mut Analysis,
+    page_id: ObjectId,
+    options: &Options,
+    rule: &Rule,
+) -> lopdf::Result<bool> {
+    Ok(match rule.check {
+        Check::MinBleed(bleed) => {
+            let source = overlay::content_box(doc, page_id, options.content_box)?;
+            source.width() + TOLERANCE >= options.trim_width + 2.0 * bleed
+                && source.height() + TOLERANCE >= options.trim_height + 2.0 * bleed
+        }
+        Check::NoRgb => analysis.page(page_id).is_none_or(|page| !page.uses_rgb),
+        Check::FontsEmbedded => analysis
+            .page(page_id)
+            .is_none_or(|page| page.unembedded_fonts.is_empty()),
+        Check::MinResolution(ppi) => analysis
+            .page(page_id)
+            .is_none_or(|page| page.images.iter().all(|image| image.ppi >= ppi)),
+        Check::PageCountMultiple(_) | Check::MinPages(_) | Check::MaxPages(_) => true,
+    })
+}
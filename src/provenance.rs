@@ -0,0 +1,356 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use lopdf::{Document, Object, Stream, StringFormat, dictionary};
+use serde::{Deserialize, Serialize};
+
+use crate::overlay::Options;
+
+/// Key in the document catalog under which the provenance record is kept.
+/// Readers ignore catalog entries they don't recognize.
+pub const KEY: &str = "CroppedProvenance";
+
+/// Key in the document information dictionary under which the manuscript's
+/// own producer, normally its typesetter, is kept.
+pub const ORIGINAL_PRODUCER: &str = "CroppedOriginalProducer";
+
+/// Version of lopdf cropped is built with, kept in step with Cargo.toml.
+const LOPDF_VERSION: &str = "0.38";
+
+/// An empty XMP packet, for documents which don't have one already.
+const EMPTY_XMP: &str = "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>
+<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">
+<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">
+</rdf:RDF>
+</x:xmpmeta>
+<?xpacket end=\"w\"?>";
+
+/// How a stamped PDF was produced: enough to answer "exactly how was this
+/// made?" long after the fact.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Provenance {
+    /// Version of cropped which stamped the file.
+    pub version: String,
+    /// When it was stamped, as printed in the slug.
+    pub stamped: String,
+    /// Filename of the manuscript.
+    pub source: String,
+    pub source_sha256: String,
+    /// Number of pages in the stamped file.
+    pub pages: usize,
+    /// Width and height of the sheet, in points.
+    pub sheet: (f64, f64),
+    /// The effective options the manuscript was stamped with.
+    pub settings: serde_json::Value,
+    /// Code printed in the slug to trace this copy, if there was one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_code: Option<String>,
+}
+
+impl Provenance {
+    pub fn new(
+        manuscript_path: &Path,
+        source_sha256: String,
+        options: &Options,
+        timestamp: &str,
+        pages: usize,
+    ) -> lopdf::Result<Provenance> {
+        Ok(Provenance {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            stamped: timestamp.to_string(),
+            source: manuscript_path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            source_sha256,
+            pages,
+            sheet: (options.sheet_width, options.sheet_height),
+            settings: serde_json::to_value(options).map_err(json_error)?,
+            trace_code: options.trace_code.clone(),
+        })
+    }
+}
+
+/// Record the provenance in the document's catalog, as a JSON string.
+pub fn record(doc: &mut Document, provenance: &Provenance) -> lopdf::Result<()> {
+    let json = serde_json::to_string(provenance).map_err(json_error)?;
+
+    doc.catalog_mut()?.set(
+        KEY,
+        Object::String(json.into_bytes(), StringFormat::Literal),
+    );
+
+    Ok(())
+}
+
+/// The producer named in stamped PDFs unless another is given.
+pub fn default_producer() -> String {
+    format!(
+        "cropped v{} (lopdf {})",
+        env!("CARGO_PKG_VERSION"),
+        LOPDF_VERSION
+    )
+}
+
+/// Name the producer of the document, in its information dictionary and as
+/// the creator tool and producer in its XMP metadata. The manuscript's own
+/// producer is kept alongside, so that both the typesetter and the stamping
+/// are known when a proof misbehaves.
+pub fn record_producer(doc: &mut Document, producer: &str) -> lopdf::Result<()> {
+    let info_id = match doc.trailer.get(b"Info").and_then(Object::as_reference) {
+        Ok(id) => id,
+        Err(_) => {
+            let id = doc.add_object(dictionary! {});
+            doc.trailer.set("Info", id);
+            id
+        }
+    };
+
+    let info = doc.get_dictionary_mut(info_id)?;
+    let original = info
+        .get(b"Producer")
+        .ok()
+        .cloned()
+        .filter(|_| !info.has(ORIGINAL_PRODUCER.as_bytes()));
+    if let Some(original) = original {
+        info.set(ORIGINAL_PRODUCER, original);
+    }
+    info.set("Producer", Object::string_literal(producer));
+
+    // Update the XMP metadata to match, or add some if there's none
+    let metadata_id = doc
+        .catalog()?
+        .get(b"Metadata")
+        .and_then(Object::as_reference)
+        .ok();
+    let xmp = metadata_id
+        .and_then(|id| {
+            doc.get_object(id)
+                .ok()?
+                .as_stream()
+                .ok()?
+                .get_plain_content()
+                .ok()
+        })
+        .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+        .unwrap_or_else(|| EMPTY_XMP.to_string());
+
+    let xmp = set_xmp_property(
+        &xmp,
+        "xmp",
+        "http://ns.adobe.com/xap/1.0/",
+        "CreatorTool",
+        producer,
+    );
+    let xmp = set_xmp_property(
+        &xmp,
+        "pdf",
+        "http://ns.adobe.com/pdf/1.3/",
+        "Producer",
+        producer,
+    );
+
+    // Left uncompressed, so that tools which don't parse PDF can find it
+    let mut stream = Stream::new(
+        dictionary! {
+            "Type" => "Metadata",
+            "Subtype" => "XML",
+        },
+        xmp.into_bytes(),
+    );
+    stream.allows_compression = false;
+
+    match metadata_id {
+        Some(id) => {
+            doc.objects.insert(id, Object::Stream(stream));
+        }
+        None => {
+            let id = doc.add_object(stream);
+            doc.catalog_mut()?.set("Metadata", id);
+        }
+    }
+
+    Ok(())
+}
+
+/// Set a simple property in an XMP packet, whether it's written as an
+/// element or as an attribute of its description, adding a description for
+/// it if it isn't there at all.
+fn set_xmp_property(xmp: &str, prefix: &str, namespace: &str, name: &str, value: &str) -> String {
+    let value = value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;");
+    let qualified = format!("{}:{}", prefix, name);
+
+    // As an element, <xmp:CreatorTool>...</xmp:CreatorTool>
+    let open = format!("<{}>", qualified);
+    let close = format!("</{}>", qualified);
+    if let Some(start) = xmp.find(&open) {
+        let start = start + open.len();
+        if let Some(end) = xmp[start..].find(&close) {
+            return format!("{}{}{}", &xmp[..start], value, &xmp[start + end..]);
+        }
+    }
+
+    // As an attribute, xmp:CreatorTool="..."
+    let attribute = format!("{}=\"", qualified);
+    if let Some(start) = xmp.find(&attribute) {
+        let start = start + attribute.len();
+        if let Some(end) = xmp[start..].find('"') {
+            return format!("{}{}{}", &xmp[..start], value, &xmp[start + end..]);
+        }
+    }
+
+    // Neither, so in a description of its own
+    match xmp.find("</rdf:RDF>") {
+        Some(end) => format!(
+            "{}<rdf:Description rdf:about=\"\" xmlns:{}=\"{}\"><{}>{}</{}></rdf:Description>\n{}",
+            &xmp[..end],
+            prefix,
+            namespace,
+            qualified,
+            value,
+            qualified,
+            &xmp[end..]
+        ),
+        None => xmp.to_string(),
+    }
+}
+
+pub fn json_error(e: serde_json::Error) -> lopdf::Error {
+    lopdf::Error::IO(io::Error::other(e))
+}
+
+/// What was found on inspecting a stamped PDF.
+#[derive(Debug)]
+pub struct Inspection {
+    pub provenance: Provenance,
+    /// Number of pages the file has now.
+    pub pages: usize,
+    /// Number of incremental updates appended to the file since it was
+    /// written. Files are written whole when stamped, so any at all mean
+    /// something has modified it since.
+    pub updates: usize,
+}
+
+/// Read back the provenance record from a stamped PDF, if it has one.
+pub fn inspect(path: &Path) -> lopdf::Result<Option<Inspection>> {
+    let bytes = fs::read(path).map_err(lopdf::Error::IO)?;
+    let doc = Document::load_mem(&bytes)?;
+
+    let Ok(Object::String(json, _)) = doc.catalog()?.get(KEY.as_bytes()) else {
+        return Ok(None);
+    };
+    let provenance: Provenance = serde_json::from_slice(json).map_err(json_error)?;
+
+    // Each revision of a PDF ends with its own end-of-file marker
+    let markers = bytes
+        .windows(5)
+        .filter(|window| *window == b"%%EOF")
+        .count();
+
+    Ok(Some(Inspection {
+        provenance,
+        pages: doc.get_pages().len(),
+        updates: markers.saturating_sub(1),
+    }))
+}
+
+impl fmt::Display for Inspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let provenance = &self.provenance;
+
+        writeln!(f, "Stamped by cropped v{}", provenance.version)?;
+        writeln!(f, "Stamped at {}", provenance.stamped)?;
+        writeln!(f, "Source {}", provenance.source)?;
+        writeln!(f, "Source SHA-256 {}", provenance.source_sha256)?;
+        writeln!(
+            f,
+            "Sheet {} x {} pt, {} pages",
+            provenance.sheet.0, provenance.sheet.1, provenance.pages
+        )?;
+        if let Some(code) = &provenance.trace_code {
+            writeln!(f, "Trace code {}", code)?;
+        }
+
+        if self.updates > 0 {
+            writeln!(
+                f,
+                "Modified after stamping: {} incremental update{}",
+                self.updates,
+                if self.updates == 1 { "" } else { "s" }
+            )?;
+        } else if self.pages != provenance.pages {
+            writeln!(f, "Modified after stamping: now {} pages", self.pages)?;
+        } else {
+            writeln!(f, "Not modified after stamping")?;
+        }
+
+        writeln!(f, "Settings:")?;
+        let settings =
+            serde_json::to_string_pretty(&provenance.settings).map_err(|_| fmt::Error)?;
+        for line in settings.lines() {
+            writeln!(f, "  {}", line)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// An attribute which differs across files which should all have been
+/// produced the same way.
+#[derive(Debug)]
+pub struct Drift {
+    pub attribute: &'static str,
+    /// Each value seen, with the files that had it.
+    pub values: BTreeMap<String, Vec<PathBuf>>,
+}
+
+/// An attribute compared by `audit`, by name and how to read it.
+type Attribute = (&'static str, fn(&Provenance) -> String);
+
+/// Compare the provenance of a set of stamped files, returning each
+/// attribute that isn't the same across all of them.
+pub fn audit(inspections: &[(PathBuf, Inspection)]) -> Vec<Drift> {
+    let attributes: [Attribute; 5] = [
+        ("tool version", |p| p.version.clone()),
+        ("source", |p| {
+            format!(
+                "{} ({})",
+                p.source,
+                &p.source_sha256[..12.min(p.source_sha256.len())]
+            )
+        }),
+        ("trim", |p| {
+            format!(
+                "{} x {} pt",
+                p.settings["trim_width"], p.settings["trim_height"]
+            )
+        }),
+        ("bleed", |p| p.settings["bleed"].to_string()),
+        ("sheet", |p| format!("{} x {} pt", p.sheet.0, p.sheet.1)),
+    ];
+
+    let mut drift = Vec::new();
+
+    for (attribute, value) in attributes {
+        let mut values: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+        for (path, inspection) in inspections {
+            values
+                .entry(value(&inspection.provenance))
+                .or_default()
+                .push(path.clone());
+        }
+
+        if values.len() > 1 {
+            drift.push(Drift { attribute, values });
+        }
+    }
+
+    drift
+}
@@ -0,0 +1,382 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use serde::Serialize;
+use tracing::info;
+
+use crate::batch::Outcome;
+use crate::checksums;
+use crate::overlay::{self, Phase};
+
+/// What became of one document in a run, for build systems to read.
+#[derive(Debug, Serialize)]
+pub struct Document {
+    pub input: PathBuf,
+    /// None when the input can't be read to be hashed: when it was read from
+    /// standard input, or wasn't there to be stamped.
+    pub input_sha256: Option<String>,
+    pub output: PathBuf,
+    /// One of stamped, skipped, or failed.
+    pub outcome: &'static str,
+    /// Pages in the output, if it was stamped.
+    pub pages: Option<usize>,
+    /// Why the document wasn't stamped, if it failed, or the earlier output
+    /// it was already stamped into, if it was skipped.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// The trim and sheet, in points.
+    pub trim_width: f64,
+    pub trim_height: f64,
+    pub sheet_width: f64,
+    pub sheet_height: f64,
+    pub warnings: Vec<String>,
+    /// Time taken over the document, in seconds.
+    pub seconds: f64,
+    pub phases: Phases,
+    /// Pages stamped each second, over the whole time taken, if it was
+    /// stamped.
+    pub pages_per_second: Option<f64>,
+}
+
+/// Seconds spent in each phase of stamping a document, timed from the
+/// progress it reports. Phases it didn't reach are left at zero.
+#[derive(Debug, Default, Serialize)]
+pub struct Phases {
+    pub load: f64,
+    pub preflight: f64,
+    pub stamp: f64,
+    pub compress: f64,
+    pub save: f64,
+    #[serde(skip)]
+    current: Option<(Phase, Instant)>,
+}
+
+impl Phases {
+    /// Note that a phase has begun, ending the one before.
+    pub fn begin(&mut self, phase: Phase) {
+        self.end();
+        self.current = Some((phase, Instant::now()));
+    }
+
+    /// End the phase under way, if there is one.
+    pub fn end(&mut self) {
+        let Some((phase, started)) = self.current.take() else {
+            return;
+        };
+        let total = match phase {
+            Phase::Loading => &mut self.load,
+            Phase::Checking => &mut self.preflight,
+            Phase::Stamping => &mut self.stamp,
+            Phase::Compressing => &mut self.compress,
+            Phase::Saving => &mut self.save,
+        };
+        *total += started.elapsed().as_secs_f64();
+    }
+}
+
+impl Document {
+    pub fn new(
+        input: &Path,
+        output: &Path,
+        options: &overlay::Options,
+        outcome: &Outcome,
+        warnings: Vec<String>,
+        seconds: f64,
+        phases: Phases,
+    ) -> Document {
+        let input_sha256 = if overlay::is_stdio(input) {
+            None
+        } else {
+            checksums::sha256_file(input).ok()
+        };
+        let (name, pages, reason) = match outcome {
+            Outcome::Stamped(pages) => ("stamped", Some(*pages), None),
+            Outcome::Skipped(earlier) => ("skipped", None, Some(earlier.display().to_string())),
+            Outcome::Failed(_, message) => ("failed", None, Some(message.clone())),
+        };
+
+        Document {
+            input: input.to_path_buf(),
+            input_sha256,
+            output: output.to_path_buf(),
+            outcome: name,
+            pages,
+            reason,
+            trim_width: options.trim_width,
+            trim_height: options.trim_height,
+            sheet_width: options.sheet_width,
+            sheet_height: options.sheet_height,
+            warnings,
+            seconds,
+            phases,
+            pages_per_second: pages.map(|pages| per_second(pages, seconds)),
+        }
+    }
+}
+
+/// Where the products of a run were delivered, once they have been.
+#[derive(Debug, Serialize)]
+pub struct Delivery {
+    pub target: String,
+    pub files: Vec<String>,
+    pub bytes: u64,
+    pub attempts: u32,
+}
+
+/// How many documents a run had, and what became of them.
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct Totals {
+    documents: usize,
+    stamped: usize,
+    skipped: usize,
+    failed: usize,
+    pages: usize,
+    warnings: usize,
+}
+
+/// The documents which raised the most warnings of one category, most
+/// first, for a large batch's worst to be looked at first.
+#[derive(Debug, PartialEq, Serialize)]
+struct Offenders {
+    /// The warning's code, such as CR006.
+    category: String,
+    /// How many documents raised it at all.
+    documents: usize,
+    worst: Vec<Offender>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct Offender {
+    input: PathBuf,
+    warnings: usize,
+}
+
+/// How many of the worst offenders are listed in each category.
+const WORST_OFFENDERS: usize = 5;
+
+#[derive(Serialize)]
+struct Report<'a> {
+    version: &'a str,
+    /// The most memory the run had resident at once, in bytes, where the
+    /// system says.
+    peak_rss_bytes: Option<u64>,
+    /// Pages stamped each second, over every document.
+    pages_per_second: f64,
+    totals: Totals,
+    worst_offenders: Vec<Offenders>,
+    /// Those which failed first, the rest in the order they were stamped.
+    documents: Vec<&'a Document>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delivery: Option<&'a Delivery>,
+}
+
+fn totals(documents: &[Document]) -> Totals {
+    let mut totals = Totals {
+        documents: documents.len(),
+        ..Totals::default()
+    };
+    for document in documents {
+        match document.outcome {
+            "stamped" => totals.stamped += 1,
+            "skipped" => totals.skipped += 1,
+            _ => totals.failed += 1,
+        }
+        totals.pages += document.pages.unwrap_or(0);
+        totals.warnings += document.warnings.len();
+    }
+    totals
+}
+
+/// The worst offenders in each category of warning raised, by the code
+/// each warning begins with.
+fn worst_offenders(documents: &[Document]) -> Vec<Offenders> {
+    let mut categories: BTreeMap<&str, Vec<Offender>> = BTreeMap::new();
+
+    for document in documents {
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for warning in &document.warnings {
+            let category = warning
+                .strip_prefix('[')
+                .and_then(|rest| rest.split_once(']'))
+                .map_or("other", |(code, _)| code);
+            *counts.entry(category).or_default() += 1;
+        }
+        for (category, warnings) in counts {
+            categories.entry(category).or_default().push(Offender {
+                input: document.input.clone(),
+                warnings,
+            });
+        }
+    }
+
+    categories
+        .into_iter()
+        .map(|(category, mut offenders)| {
+            // Stable, so that ties keep the order they were stamped in
+            offenders.sort_by_key(|offender| Reverse(offender.warnings));
+            let documents = offenders.len();
+            offenders.truncate(WORST_OFFENDERS);
+            Offenders {
+                category: category.to_string(),
+                documents,
+                worst: offenders,
+            }
+        })
+        .collect()
+}
+
+/// Write the report of a run as JSON, to standard output if the path is `-`,
+/// with the receipt for its delivery if it's been delivered.
+///
+/// A file is written atomically, so that a run which dies while writing it
+/// leaves the report it had written before.
+pub fn write(
+    path: &Path,
+    version: &str,
+    documents: &[Document],
+    delivery: Option<&Delivery>,
+) -> io::Result<()> {
+    let pages = documents.iter().filter_map(|document| document.pages).sum();
+    let seconds = documents.iter().map(|document| document.seconds).sum();
+    let mut ordered: Vec<&Document> = documents.iter().collect();
+    ordered.sort_by_key(|document| document.outcome != "failed");
+    let report = Report {
+        version,
+        peak_rss_bytes: peak_rss(),
+        pages_per_second: per_second(pages, seconds),
+        totals: totals(documents),
+        worst_offenders: worst_offenders(documents),
+        documents: ordered,
+        delivery,
+    };
+    let mut json = serde_json::to_vec_pretty(&report).map_err(io::Error::other)?;
+    json.push(b'\n');
+
+    if overlay::is_stdio(path) {
+        io::stdout().write_all(&json)
+    } else {
+        overlay::write_atomically(path, |file| file.write_all(&json))?;
+        info!("Report written to {}", path.display());
+        Ok(())
+    }
+}
+
+fn per_second(pages: usize, seconds: f64) -> f64 {
+    if seconds > 0.0 {
+        pages as f64 / seconds
+    } else {
+        0.0
+    }
+}
+
+/// The peak resident set size of this process, in bytes: its high water
+/// mark, from /proc on Linux. None elsewhere.
+fn peak_rss() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(input: &str, outcome: &'static str, warnings: &[&str]) -> Document {
+        Document {
+            input: PathBuf::from(input),
+            input_sha256: None,
+            output: PathBuf::from("out").join(input),
+            outcome,
+            pages: (outcome == "stamped").then_some(4),
+            reason: None,
+            trim_width: 432.0,
+            trim_height: 648.0,
+            sheet_width: 595.0,
+            sheet_height: 842.0,
+            warnings: warnings.iter().map(|warning| warning.to_string()).collect(),
+            seconds: 1.0,
+            phases: Phases::default(),
+            pages_per_second: None,
+        }
+    }
+
+    #[test]
+    fn aggregates_a_batch() {
+        let documents = [
+            document("a.pdf", "stamped", &["[CR006] Crop marks overlap"]),
+            document(
+                "b.pdf",
+                "stamped",
+                &[
+                    "[CR001] Bleed short",
+                    "[CR006] Crop marks overlap",
+                    "[CR006] Again",
+                ],
+            ),
+            document("c.pdf", "failed", &["Unlabelled"]),
+            document("d.pdf", "skipped", &[]),
+        ];
+
+        assert_eq!(
+            totals(&documents),
+            Totals {
+                documents: 4,
+                stamped: 2,
+                skipped: 1,
+                failed: 1,
+                pages: 8,
+                warnings: 5,
+            }
+        );
+
+        let worst = worst_offenders(&documents);
+        let categories: Vec<&str> = worst.iter().map(|o| o.category.as_str()).collect();
+        assert_eq!(categories, ["CR001", "CR006", "other"]);
+        assert_eq!(worst[1].documents, 2);
+        assert_eq!(
+            worst[1].worst,
+            [
+                Offender {
+                    input: PathBuf::from("b.pdf"),
+                    warnings: 2,
+                },
+                Offender {
+                    input: PathBuf::from("a.pdf"),
+                    warnings: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_failures_first() {
+        let directory = std::env::temp_dir().join(format!("cropped-report.{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("report.json");
+
+        let documents = [
+            document("a.pdf", "stamped", &[]),
+            document("b.pdf", "failed", &[]),
+            document("c.pdf", "stamped", &[]),
+        ];
+        write(&path, "0.0.0", &documents, None).unwrap();
+
+        let report: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        let inputs: Vec<&str> = report["documents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|document| document["input"].as_str().unwrap())
+            .collect();
+        assert_eq!(inputs, ["b.pdf", "a.pdf", "c.pdf"]);
+        assert_eq!(report["totals"]["failed"], 1);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
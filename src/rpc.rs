@@ -0,0 +1,370 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::time::SystemTime;
+
+use lopdf::Document;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tracing::debug;
+
+use crate::fonts::{self, Metrics, Weight};
+use crate::overlay::{self, Job, Options, Phase, Preflight, Progress};
+
+// Error codes, those below -32000 as defined by JSON-RPC 2.0
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const NO_DOCUMENT: i64 = -32001;
+const JOB_FAILED: i64 = -32002;
+
+/// A request as it arrives on a line of input.
+#[derive(Debug, Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenParams {
+    path: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+struct PreflightParams {
+    #[serde(default)]
+    options: Options,
+}
+
+#[derive(Debug, Deserialize)]
+struct GeometryParams {
+    #[serde(default)]
+    options: Options,
+    #[serde(default = "first_page")]
+    page: usize,
+}
+
+fn first_page() -> usize {
+    1
+}
+
+#[derive(Debug, Deserialize)]
+struct StampParams {
+    #[serde(default)]
+    options: Options,
+    output: PathBuf,
+}
+
+/// A manuscript held open between requests, so that it can be checked and
+/// stamped with one set of options after another without loading it again.
+/// It is only reloaded if the file changes on disk.
+struct Open {
+    path: PathBuf,
+    document: Document,
+    /// When the file was last modified, as of loading it.
+    modified: Option<SystemTime>,
+    /// Those of the slug's font in each weight asked for so far, for
+    /// geometry queries.
+    metrics: HashMap<Weight, Metrics>,
+    /// The last preflight, and the options it was run with, so that
+    /// stamping with the same options needn't check the manuscript again.
+    preflight: Option<(Value, Preflight)>,
+}
+
+impl Open {
+    fn load(path: PathBuf) -> Result<Open, Failure> {
+        let modified = modified(&path);
+        let document = Document::load(&path).map_err(failed)?;
+        let metrics = fonts::metrics(Weight::Regular).map_err(failed)?;
+
+        Ok(Open {
+            path,
+            document,
+            modified,
+            metrics: HashMap::from([(Weight::Regular, metrics)]),
+            preflight: None,
+        })
+    }
+
+    /// Reload the manuscript if the file has changed since it was loaded.
+    fn refresh(&mut self) -> Result<(), Failure> {
+        if modified(&self.path) == self.modified {
+            return Ok(());
+        }
+
+        debug!(path = ?self.path, "Manuscript changed, reloading");
+        *self = Open::load(self.path.clone())?;
+        Ok(())
+    }
+
+    /// The metrics of the slug's font in the given weight, loading them the
+    /// first time they're asked for.
+    fn metrics(&mut self, weight: Weight) -> Result<&Metrics, Failure> {
+        match self.metrics.entry(weight) {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => Ok(entry.insert(fonts::metrics(weight).map_err(failed)?)),
+        }
+    }
+
+    /// Check the manuscript with the given options, unless it was last
+    /// checked with the same ones.
+    fn preflight(
+        &mut self,
+        job: &Job,
+        options: &Options,
+        on_progress: &mut impl FnMut(Progress),
+    ) -> Result<Preflight, Failure> {
+        let key =
+            serde_json::to_value(options).map_err(|e| Failure::new(JOB_FAILED, e.to_string()))?;
+
+        if let Some((_, preflight)) = self.preflight.as_ref().filter(|(last, _)| *last == key) {
+            debug!("Reusing preflight");
+            return Ok(preflight.clone());
+        }
+
+        let preflight = job.preflight(on_progress).map_err(failed)?;
+        self.preflight = Some((key, preflight.clone()));
+        Ok(preflight)
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    path.metadata()
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// An error to be returned in place of a result.
+struct Failure {
+    code: i64,
+    message: String,
+}
+
+impl Failure {
+    fn new(code: i64, message: impl Into<String>) -> Failure {
+        Failure {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Serve newline-delimited JSON-RPC 2.0 requests from `input`, writing
+/// responses, and progress notifications while stamping, to `output`, until
+/// the input ends.
+///
+/// The methods are:
+///
+/// - `open` with `{"path": ...}`: load a manuscript, returning its page count
+/// - `preflight` with `{"options": {...}}`: check the open manuscript,
+///   returning where the slug goes and any warnings
+/// - `geometry` with `{"options": {...}, "page": ...}`: work out where the
+///   content, trim, bleed, crop marks, and slug would land on the sheet for
+///   the given page of the open manuscript, counting from 1, without
+///   checking or stamping anything; quick enough for a live preview
+/// - `stamp` with `{"options": {...}, "output": ...}`: stamp the open
+///   manuscript and save the result, returning the number of pages, with a
+///   `progress` notification as each phase begins and each page is done
+///
+/// Options are given as their fields in the library, in points, and any
+/// left out take the command line's defaults. The open manuscript is left
+/// untouched by each job, so can be stamped again with different options;
+/// it's reloaded only if the file changes, and checked again only if the
+/// options do.
+pub fn serve(input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    let mut open: Option<Open> = None;
+
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let request: Request = match serde_json::from_str::<Value>(&line) {
+            Err(e) => {
+                respond(
+                    &mut output,
+                    Value::Null,
+                    Err(Failure::new(PARSE_ERROR, e.to_string())),
+                )?;
+                continue;
+            }
+            Ok(value) => match serde_json::from_value(value) {
+                Ok(request) => request,
+                Err(e) => {
+                    respond(
+                        &mut output,
+                        Value::Null,
+                        Err(Failure::new(INVALID_REQUEST, e.to_string())),
+                    )?;
+                    continue;
+                }
+            },
+        };
+        debug!(method = %request.method, "RPC request");
+
+        let result = match request.method.as_str() {
+            "open" => params(request.params).and_then(|p: OpenParams| open_document(&mut open, p)),
+            "preflight" => params(request.params).and_then(|p: PreflightParams| {
+                p.options.validate().map_err(invalid)?;
+                let open = open.as_mut().ok_or_else(no_document)?;
+                open.refresh()?;
+                preflight(open, &p.options)
+            }),
+            "geometry" => params(request.params).and_then(|p: GeometryParams| {
+                p.options.validate().map_err(invalid)?;
+                let open = open.as_mut().ok_or_else(no_document)?;
+                open.refresh()?;
+                geometry(open, &p.options, p.page)
+            }),
+            "stamp" => params(request.params).and_then(|p: StampParams| {
+                p.options.validate().map_err(invalid)?;
+                let open = open.as_mut().ok_or_else(no_document)?;
+                open.refresh()?;
+                stamp(open, &p.options, &p.output, &mut output)
+            }),
+            other => Err(Failure::new(
+                METHOD_NOT_FOUND,
+                format!("Unknown method '{}'", other),
+            )),
+        };
+
+        respond(&mut output, request.id, result)?;
+    }
+
+    Ok(())
+}
+
+fn params<T: for<'de> Deserialize<'de>>(params: Value) -> Result<T, Failure> {
+    serde_json::from_value(params).map_err(|e| Failure::new(INVALID_PARAMS, e.to_string()))
+}
+
+/// Options which can't be used, refused as invalid params as the command
+/// line would refuse them.
+fn invalid(message: String) -> Failure {
+    Failure::new(INVALID_PARAMS, message)
+}
+
+fn no_document() -> Failure {
+    Failure::new(NO_DOCUMENT, "No manuscript is open")
+}
+
+fn failed(e: lopdf::Error) -> Failure {
+    Failure::new(JOB_FAILED, e.to_string())
+}
+
+fn open_document(open: &mut Option<Open>, params: OpenParams) -> Result<Value, Failure> {
+    let loaded = Open::load(params.path)?;
+    let pages = loaded.document.get_pages().len();
+
+    *open = Some(loaded);
+
+    Ok(json!({ "pages": pages }))
+}
+
+fn preflight(open: &mut Open, options: &Options) -> Result<Value, Failure> {
+    let job = Job::from_document(open.document.clone(), &open.path, options, &mut |_| {})
+        .map_err(failed)?;
+    let preflight = open.preflight(&job, options, &mut |_| {})?;
+
+    serde_json::to_value(&preflight).map_err(|e| Failure::new(JOB_FAILED, e.to_string()))
+}
+
+fn geometry(open: &mut Open, options: &Options, page_num: usize) -> Result<Value, Failure> {
+    let filename = open
+        .path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let timestamp = overlay::timestamp(options, &mut |_| {});
+    let metrics = open.metrics(options.slug_size.weight())?.clone();
+
+    let geometry = overlay::geometry(
+        &open.document,
+        options,
+        &metrics,
+        &filename,
+        &timestamp,
+        page_num,
+    )
+    .map_err(failed)?;
+
+    serde_json::to_value(&geometry).map_err(|e| Failure::new(JOB_FAILED, e.to_string()))
+}
+
+fn stamp(
+    open: &mut Open,
+    options: &Options,
+    output_path: &Path,
+    output: &mut impl Write,
+) -> Result<Value, Failure> {
+    // Notifications are best effort; a failure to write one will show up
+    // when the response is written
+    let mut on_progress = |progress: Progress| {
+        let _ = notify(output, progress);
+    };
+
+    // Nothing cancels a job over RPC; the client closes the pipe instead
+    let cancel = AtomicBool::new(false);
+
+    let job = Job::from_document(open.document.clone(), &open.path, options, &mut on_progress)
+        .map_err(failed)?;
+    let preflight = open.preflight(&job, options, &mut on_progress)?;
+    let stamped = job
+        .stamp(&preflight, &mut on_progress, &cancel)
+        .map_err(failed)?;
+    let pages = stamped
+        .save(output_path, &mut on_progress, &cancel)
+        .map_err(failed)?;
+
+    Ok(json!({ "pages": pages, "warnings": preflight.warnings }))
+}
+
+/// Send a progress notification.
+fn notify(output: &mut impl Write, progress: Progress) -> io::Result<()> {
+    let params = match progress {
+        Progress::Phase(phase) => json!({ "phase": phase_name(phase) }),
+        Progress::PageStarted { page, total } => {
+            json!({ "page_started": page, "total": total })
+        }
+        Progress::PageFinished { page, total } => {
+            json!({ "page_finished": page, "total": total })
+        }
+        Progress::Warning(message) => json!({ "warning": message }),
+    };
+
+    let notification = json!({ "jsonrpc": "2.0", "method": "progress", "params": params });
+    writeln!(output, "{}", notification)?;
+    output.flush()
+}
+
+fn phase_name(phase: Phase) -> &'static str {
+    match phase {
+        Phase::Loading => "loading",
+        Phase::Checking => "checking",
+        Phase::Stamping => "stamping",
+        Phase::Compressing => "compressing",
+        Phase::Saving => "saving",
+    }
+}
+
+/// Write the response to a request.
+fn respond(output: &mut impl Write, id: Value, result: Result<Value, Failure>) -> io::Result<()> {
+    let response = match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(failure) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": failure.code, "message": failure.message },
+        }),
+    };
+
+    writeln!(output, "{}", response)?;
+    output.flush()
+}
@@ -0,0 +1,148 @@
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Deserializer, Serialize, de};
+
+use crate::units;
+
+/// A preflight rule supplied by the user, so that a shop can encode a
+/// vendor's requirements without changes to the program.
+///
+/// Rules are read from a JSON file holding an array of them, for example:
+///
+/// ```json
+/// [
+///   { "name": "Even page count", "severity": "error", "check": { "page_count_multiple": 2 } },
+///   { "name": "No RGB", "check": "no_rgb" },
+///   { "name": "Odd pages carry bleed", "pages": "odd", "check": { "min_bleed": "3mm" } }
+/// ]
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// Shown in the message when the rule fails.
+    pub name: String,
+    /// Which pages the rule applies to. Ignored by checks on the document
+    /// as a whole, such as page count.
+    #[serde(default)]
+    pub pages: Selector,
+    #[serde(default)]
+    pub severity: Severity,
+    pub check: Check,
+}
+
+/// Which pages a rule applies to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Selector {
+    #[default]
+    All,
+    Odd,
+    Even,
+    First,
+    Last,
+}
+
+impl Selector {
+    /// Whether the given page (counting from 1) of a document of `total`
+    /// pages is selected.
+    pub fn includes(&self, page_num: usize, total: usize) -> bool {
+        match self {
+            Selector::All => true,
+            Selector::Odd => page_num % 2 == 1,
+            Selector::Even => page_num.is_multiple_of(2),
+            Selector::First => page_num == 1,
+            Selector::Last => page_num == total,
+        }
+    }
+}
+
+/// What happens when a rule fails. A warning still fails the job under
+/// --strict.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    #[default]
+    Warning,
+    Error,
+}
+
+/// The condition a rule checks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Check {
+    /// The page count must be a multiple of this.
+    PageCountMultiple(usize),
+    /// There must be at least this many pages.
+    MinPages(usize),
+    /// There must be no more than this many pages.
+    MaxPages(usize),
+    /// The content box must extend at least this far beyond the trim on
+    /// every edge, given as a length such as "3mm".
+    MinBleed(#[serde(deserialize_with = "length")] f64),
+    /// No color may be set in, nor image drawn in, DeviceRGB.
+    NoRgb,
+    /// Every font used must be embedded.
+    FontsEmbedded,
+    /// Every image must be placed at no less than this many pixels per inch.
+    MinResolution(f64),
+}
+
+/// How the finished book is bound, which constrains its page count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    /// Perfect binding glues single leaves at the spine, so needs an even
+    /// page count and enough pages to give the spine something to grip.
+    Perfect,
+    /// Saddle stitching folds whole sheets, four pages to a sheet, and
+    /// won't close flat beyond about 96 pages.
+    Saddle,
+}
+
+impl Binding {
+    /// The page count rules for this kind of binding.
+    pub fn rules(&self) -> Vec<Rule> {
+        let rule = |name: &str, check| Rule {
+            name: name.to_string(),
+            pages: Selector::All,
+            severity: Severity::Warning,
+            check,
+        };
+
+        match self {
+            Binding::Perfect => vec![
+                rule(
+                    "Perfect binding needs an even page count",
+                    Check::PageCountMultiple(2),
+                ),
+                rule(
+                    "Perfect binding needs at least 24 pages",
+                    Check::MinPages(24),
+                ),
+            ],
+            Binding::Saddle => vec![
+                rule(
+                    "Saddle stitching needs a multiple of 4 pages",
+                    Check::PageCountMultiple(4),
+                ),
+                rule(
+                    "Saddle stitching allows at most 96 pages",
+                    Check::MaxPages(96),
+                ),
+            ],
+        }
+    }
+}
+
+/// Read rules from the given JSON file.
+pub fn load(path: &Path) -> Result<Vec<Rule>, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read rules from {}: {}", path.display(), e))?;
+
+    serde_json::from_str(&text).map_err(|e| format!("Invalid rules in {}: {}", path.display(), e))
+}
+
+fn length<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    units::parse_length(&text).map_err(de::Error::custom)
+}
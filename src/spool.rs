@@ -0,0 +1,141 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+use lopdf::Document;
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+/// How much of standard input is kept in memory before the rest is spooled
+/// to a temporary file instead.
+const THRESHOLD: usize = 64 * 1024 * 1024;
+
+/// How far from the end of a PDF its `%%EOF` marker may be, as readers allow.
+const TAIL: usize = 1024;
+
+/// A manuscript read from standard input, held in memory if it was small
+/// enough and in a temporary file if not, which is removed once dropped.
+pub struct Spooled {
+    held: Held,
+    /// The SHA-256 digest of everything read, worked out as it arrived.
+    pub sha256: String,
+}
+
+enum Held {
+    Memory(Vec<u8>),
+    File(PathBuf, File),
+}
+
+/// Read a manuscript from standard input in chunks, hashing it as it
+/// arrives.
+///
+/// Reading no faster than it's stamped leaves the writer at the other end of
+/// the pipe, such as `curl`, waiting rather than filling memory, and a PDF
+/// of several hundred megabytes costs no more than the threshold in memory
+/// until it's parsed. Input which ends without the `%%EOF` marker every PDF
+/// ends with is refused, as a pipe cut short by a dropped connection would
+/// otherwise produce a confusing error from the parser, or a proof missing
+/// pages.
+pub fn stdin() -> io::Result<Spooled> {
+    let mut stdin = io::stdin().lock();
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+
+    // Held from the start, so that any temporary file is removed should
+    // reading fail part way
+    let mut spooled = Spooled {
+        held: Held::Memory(Vec::new()),
+        sha256: String::new(),
+    };
+    let mut tail: Vec<u8> = Vec::new();
+    let mut length = 0u64;
+
+    loop {
+        let count = match stdin.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(count) => count,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!(
+                        "Reading standard input failed after {} bytes: {}",
+                        length, e
+                    ),
+                ));
+            }
+        };
+        let chunk = &buffer[..count];
+        hasher.update(chunk);
+        length += count as u64;
+
+        tail.extend_from_slice(chunk);
+        if tail.len() > TAIL {
+            tail.drain(..tail.len() - TAIL);
+        }
+
+        match &mut spooled.held {
+            Held::File(_, file) => file.write_all(chunk)?,
+            Held::Memory(memory) if memory.len() + count > THRESHOLD => {
+                let path =
+                    std::env::temp_dir().join(format!("cropped-stdin.{}.pdf", std::process::id()));
+                info!(
+                    "Spooling standard input to {} past {} MB",
+                    path.display(),
+                    THRESHOLD / (1024 * 1024)
+                );
+
+                let mut file = OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&path)?;
+                let memory = std::mem::take(memory);
+                spooled.held = Held::File(path, file.try_clone()?);
+                file.write_all(&memory)?;
+                file.write_all(chunk)?;
+            }
+            Held::Memory(memory) => memory.extend_from_slice(chunk),
+        }
+    }
+
+    if length == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Nothing was read from standard input",
+        ));
+    }
+    if !tail.windows(5).any(|window| window == b"%%EOF") {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!(
+                "Standard input ended after {} bytes without the end of a PDF; was the pipe cut short?",
+                length
+            ),
+        ));
+    }
+
+    if let Held::File(_, file) = &mut spooled.held {
+        file.flush()?;
+    }
+    spooled.sha256 = format!("{:x}", hasher.finalize());
+
+    Ok(spooled)
+}
+
+impl Spooled {
+    /// Parse the manuscript read.
+    pub fn load(&self) -> lopdf::Result<Document> {
+        match &self.held {
+            Held::Memory(bytes) => Document::load_mem(bytes),
+            Held::File(path, _) => Document::load(path),
+        }
+    }
+}
+
+impl Drop for Spooled {
+    fn drop(&mut self) {
+        if let Held::File(path, _) = &self.held {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
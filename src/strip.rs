@@ -0,0 +1,112 @@
+use std::io;
+use std::path::Path;
+
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use tracing::info;
+
+use crate::overlay;
+use crate::provenance;
+
+/// Key in each stamped page's dictionary under which the entries stamping
+/// replaced are kept, so that the stamp can be removed again.
+pub const ORIGINAL: &str = "CroppedOriginal";
+
+/// Entries which a page may inherit from its ancestors in the page tree.
+const INHERITABLE: [&[u8]; 3] = [b"Resources", b"MediaBox", b"CropBox"];
+
+/// Entries which only the page itself may carry.
+const LOCAL: [&[u8]; 4] = [b"TrimBox", b"BleedBox", b"ArtBox", b"Contents"];
+
+/// The entries of a page which stamping replaces, as they were before.
+///
+/// Inheritable entries are recorded as stored wherever in the page tree the
+/// page found them, so that they survive the tree being flattened. Entries
+/// the page lacked are recorded as null.
+pub fn record(doc: &Document, page: &Dictionary) -> Dictionary {
+    let mut original = Dictionary::new();
+
+    for key in INHERITABLE {
+        let value = stored(doc, page, key).cloned().unwrap_or(Object::Null);
+        original.set(key, value);
+    }
+    for key in LOCAL {
+        let value = page.get(key).cloned().unwrap_or(Object::Null);
+        original.set(key, value);
+    }
+
+    original
+}
+
+/// Find an entry of a page as stored, on the page or its nearest ancestor
+/// carrying it, without following the reference if it is one.
+fn stored<'a>(doc: &'a Document, page: &'a Dictionary, key: &[u8]) -> Option<&'a Object> {
+    let mut node = page;
+
+    // Bounded, in case of a malformed page tree with a cycle in it
+    for _ in 0..64 {
+        if let Ok(value) = node.get(key) {
+            return Some(value);
+        }
+
+        let parent = node.get(b"Parent").and_then(Object::as_reference).ok()?;
+        node = doc.get_dictionary(parent).ok()?;
+    }
+
+    None
+}
+
+/// Remove the stamp from a PDF stamped by cropped, putting each page's
+/// boxes, resources, and content back as they were in the manuscript, and
+/// save the result. Returns the number of pages restored.
+///
+/// The pages stay in the order they were stamped in, and files stamped with
+/// `--compat legacy` can't be stripped, as their content was flattened.
+pub fn strip(input_path: &Path, output_path: &Path) -> lopdf::Result<usize> {
+    let mut doc = Document::load(input_path)?;
+
+    let page_ids: Vec<ObjectId> = doc.page_iter().collect();
+    let mut restored = 0;
+
+    for page_id in page_ids {
+        let page = doc.get_dictionary_mut(page_id)?;
+        let Some(Object::Dictionary(original)) = page.remove(ORIGINAL.as_bytes()) else {
+            continue;
+        };
+
+        for (key, value) in original.iter() {
+            match value {
+                Object::Null => {
+                    page.remove(key);
+                }
+                _ => page.set(key.clone(), value.clone()),
+            }
+        }
+        restored += 1;
+    }
+
+    if restored == 0 {
+        return Err(lopdf::Error::IO(io::Error::other(format!(
+            "{} has no record of its pages before stamping",
+            input_path.display()
+        ))));
+    }
+
+    doc.catalog_mut()?.remove(provenance::KEY.as_bytes());
+
+    // Give the manuscript back its own producer
+    if let Ok(info_id) = doc.trailer.get(b"Info").and_then(Object::as_reference) {
+        let info = doc.get_dictionary_mut(info_id)?;
+        if let Some(original) = info.remove(provenance::ORIGINAL_PRODUCER.as_bytes()) {
+            info.set("Producer", original);
+        }
+    }
+
+    // The overlays and wrappers are no longer referenced by any page
+    doc.prune_objects();
+    doc.compress();
+
+    info!("Save stripped document");
+    overlay::save_atomically(&mut doc, output_path)?;
+
+    Ok(restored)
+}
@@ -0,0 +1,181 @@
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+use lopdf::{Document, Object};
+
+use crate::overlay::{self, Options};
+use crate::units::{self, POINTS_PER_MM};
+
+/// Placeholders an output path may contain, each in braces.
+const PLACEHOLDERS: [&str; 6] = ["title", "author", "name", "trim", "sheet", "date"];
+
+/// Characters which can't appear in a filename on one platform or another.
+const HOSTILE: [char; 9] = ['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+/// Longest a single substituted value may be, in characters, keeping well
+/// inside filesystems' limits on the length of a name.
+const MAX_VALUE: usize = 80;
+
+/// Whether an output path is a template, naming each output from its
+/// manuscript and the settings rather than given literally.
+pub fn is_template(path: &Path) -> bool {
+    path.to_string_lossy().contains('{')
+}
+
+/// The output path for a manuscript, filling in each placeholder in the
+/// template:
+///
+/// - `{title}` and `{author}` from the manuscript's document information,
+///   falling back on its filename if it has none
+/// - `{name}`, the manuscript's filename without its extension
+/// - `{trim}` and `{sheet}`, their preset names, or their size in mm
+/// - `{date}`, today's date, as 2024-03-01
+///
+/// Substituted values have characters which filesystems reject replaced
+/// with hyphens, so that a title such as "Either/Or" can't make a
+/// directory of its own.
+pub fn render(
+    template: &Path,
+    manuscript_path: &Path,
+    options: &Options,
+) -> Result<PathBuf, String> {
+    let template = template.to_string_lossy();
+
+    // Only read the manuscript if its metadata is wanted
+    let wants_info = ["{title}", "{author}"]
+        .iter()
+        .any(|placeholder| template.contains(placeholder));
+    let document = if wants_info && !overlay::is_stdio(manuscript_path) {
+        Some(
+            Document::load(manuscript_path)
+                .map_err(|e| format!("{}: {}", manuscript_path.display(), e))?,
+        )
+    } else {
+        None
+    };
+
+    let name = if overlay::is_stdio(manuscript_path) {
+        "stdin".to_string()
+    } else {
+        manuscript_path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+    let info = |key: &[u8]| {
+        document
+            .as_ref()
+            .and_then(|document| info_string(document, key))
+            .filter(|value| !value.trim().is_empty())
+            .unwrap_or_else(|| name.clone())
+    };
+
+    let mut output = String::new();
+    let mut rest = &*template;
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+
+        let Some(length) = rest[start..].find('}') else {
+            return Err(format!("Unclosed placeholder in output {}", template));
+        };
+        let key = &rest[start + 1..start + length];
+        let value = match key {
+            "title" => info(b"Title"),
+            "author" => info(b"Author"),
+            "name" => name.clone(),
+            "trim" => size_name(
+                &units::TRIM_PRESETS,
+                options.trim_width,
+                options.trim_height,
+            ),
+            "sheet" => size_name(
+                &units::SHEET_PRESETS,
+                options.sheet_width,
+                options.sheet_height,
+            ),
+            "date" => Local::now().format("%Y-%m-%d").to_string(),
+            _ => {
+                return Err(format!(
+                    "Unknown placeholder {{{}}} in output; expected one of {}",
+                    key,
+                    PLACEHOLDERS.join(", ")
+                ));
+            }
+        };
+        output.push_str(&sanitize(&value));
+
+        rest = &rest[start + length + 1..];
+    }
+    output.push_str(rest);
+
+    Ok(PathBuf::from(output))
+}
+
+/// A string from the document information dictionary, decoded from UTF-16
+/// if it has a byte order mark, and otherwise taken as Latin-1, near enough
+/// to PDFDocEncoding for a filename.
+fn info_string(document: &Document, key: &[u8]) -> Option<String> {
+    let info = match document.trailer.get(b"Info").ok()? {
+        Object::Reference(id) => document.get_dictionary(*id).ok()?,
+        Object::Dictionary(info) => info,
+        _ => return None,
+    };
+    let Object::String(bytes, _) = info.get(key).ok()? else {
+        return None;
+    };
+
+    match bytes.strip_prefix(&[0xFE, 0xFF]) {
+        Some(utf16) => {
+            let units: Vec<u16> = utf16
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            Some(String::from_utf16_lossy(&units))
+        }
+        None => Some(bytes.iter().map(|&byte| byte as char).collect()),
+    }
+}
+
+/// The name of the preset of the given size, in either orientation, or the
+/// size in whole millimetres.
+fn size_name(presets: &[(&str, f64, f64)], width: f64, height: f64) -> String {
+    let matches = |a: f64, b: f64| (a - b).abs() < 0.5;
+
+    presets
+        .iter()
+        .find(|&&(_, w, h)| {
+            (matches(w, width) && matches(h, height)) || (matches(w, height) && matches(h, width))
+        })
+        .map(|(name, _, _)| name.to_string())
+        .unwrap_or_else(|| {
+            format!(
+                "{:.0}x{:.0}mm",
+                width / POINTS_PER_MM,
+                height / POINTS_PER_MM
+            )
+        })
+}
+
+/// Make a value safe to put in a filename: characters filesystems reject,
+/// and control characters, become hyphens; leading and trailing spaces and
+/// dots are dropped; and it's cut short if very long.
+fn sanitize(value: &str) -> String {
+    let cleaned: String = value
+        .chars()
+        .map(|c| {
+            if HOSTILE.contains(&c) || c.is_control() {
+                '-'
+            } else {
+                c
+            }
+        })
+        .take(MAX_VALUE)
+        .collect();
+
+    let cleaned = cleaned.trim_matches(|c: char| c.is_whitespace() || c == '.');
+    if cleaned.is_empty() {
+        "untitled".to_string()
+    } else {
+        cleaned.to_string()
+    }
+}
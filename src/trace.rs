@@ -0,0 +1,52 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::path::Path;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+
+/// Crockford's base 32, which leaves out I, L, O, and U so that a code read
+/// back off a printout can't be mistaken for another.
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Generate a short code to print in the slug of an output, so that a
+/// leaked proof can be traced back to whoever it was sent to.
+///
+/// Each output gets its own code. Unseeded, the code is random and differs
+/// from run to run; given a seed, it's derived from the seed and the
+/// output's filename, so that the same run gives the same codes every time.
+pub fn generate(seed: Option<&str>, output_path: &Path) -> String {
+    let mut hasher = Sha256::new();
+
+    match seed {
+        Some(seed) => hasher.update(seed.as_bytes()),
+        None => {
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_nanos())
+                .unwrap_or_default();
+            let random = RandomState::new().build_hasher().finish();
+
+            hasher.update(nanos.to_le_bytes());
+            hasher.update(process::id().to_le_bytes());
+            hasher.update(random.to_le_bytes());
+        }
+    }
+    hasher.update([0]);
+    if let Some(name) = output_path.file_name() {
+        hasher.update(name.as_encoded_bytes());
+    }
+    let digest = hasher.finalize();
+
+    // Forty bits, as eight characters in two groups of four
+    let bits = digest[..5]
+        .iter()
+        .fold(0u64, |bits, &byte| (bits << 8) | byte as u64);
+    let code: String = (0..8)
+        .rev()
+        .map(|index| ALPHABET[(bits >> (index * 5)) as usize & 31] as char)
+        .collect();
+
+    format!("{}-{}", &code[..4], &code[4..])
+}
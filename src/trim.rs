@@ -0,0 +1,238 @@
+use lopdf::Document;
+
+use crate::analysis::Analysis;
+use crate::geometry::Rect;
+use crate::i18n::Message;
+use crate::overlay::{self, ContentBox};
+use crate::units::{POINTS_PER_MM, TRIM_PRESETS};
+
+/// How far, in points, a size may be from a standard one and still be taken
+/// for it: about two millimetres, covering rounding in whatever made the PDF.
+const TOLERANCE: f64 = 6.0;
+
+/// The most bleed a page larger than a standard size is taken to allow on
+/// each edge: a quarter of an inch.
+const MAX_BLEED: f64 = 18.0;
+
+/// How far, as a fraction, a page's content may differ in size from most
+/// pages' and still count towards the trim. Title pages, blank pages, and
+/// full bleed images fall outside it.
+const OUTLIER: f64 = 0.1;
+
+/// The trim a manuscript appears to have been made for, and why.
+pub struct Detected {
+    /// The preset's name, if it's a standard size.
+    pub name: Option<&'static str>,
+    pub width: f64,
+    pub height: f64,
+    pub basis: Basis,
+}
+
+pub enum Basis {
+    /// The pages are that size, or that size with this much bleed each side.
+    Page { bleed: f64 },
+    /// The smallest standard size holding the content of the pages whose
+    /// content is of a consistent size: this wide and high, on this many of
+    /// this many pages.
+    Content {
+        width: f64,
+        height: f64,
+        consistent: usize,
+        pages: usize,
+    },
+    /// Nothing standard fits, so the pages' own size.
+    Unknown,
+}
+
+/// Work out a manuscript's trim size from its pages, for when it isn't
+/// known.
+///
+/// Pages the size of a standard trim are taken to be that trim, and pages a
+/// little larger to be that trim with bleed. Otherwise, the manuscript is
+/// taken to be set on a larger page, and the trim is the smallest standard
+/// size which holds the content of its pages, leaving out those whose
+/// content is unlike the rest.
+pub fn detect(doc: &Document, which: ContentBox) -> Result<Detected, String> {
+    let mut analysis = Analysis::new(doc);
+    let mut boxes = Vec::new();
+    let mut contents = Vec::new();
+
+    for page_id in doc.get_pages().into_values() {
+        let page = overlay::content_box(doc, page_id, which).map_err(|e| e.to_string())?;
+        boxes.push((page.width(), page.height()));
+
+        let Some(bounds) = analysis.page(page_id).and_then(|content| content.bounds) else {
+            continue;
+        };
+
+        // Content beyond the page isn't printed, whatever it would have been
+        let clipped = Rect {
+            x1: bounds.x1.max(page.x1),
+            y1: bounds.y1.max(page.y1),
+            x2: bounds.x2.min(page.x2),
+            y2: bounds.y2.min(page.y2),
+        };
+        if clipped.width() > 0.0 && clipped.height() > 0.0 {
+            contents.push((clipped.width(), clipped.height()));
+        }
+    }
+
+    let Some(&(page_width, page_height)) = most_common(&boxes) else {
+        return Err("The manuscript has no pages".to_string());
+    };
+
+    // Pages already at a standard size, or else at one with the same bleed
+    // all round
+    let extra = |&(_, width, height): &(&str, f64, f64)| (page_width - width, page_height - height);
+    let exact = presets(page_width, page_height).find(|preset| {
+        let (width, height) = extra(preset);
+        width.abs() <= TOLERANCE && height.abs() <= TOLERANCE
+    });
+    let bled = presets(page_width, page_height)
+        .filter(|preset| {
+            let (width, height) = extra(preset);
+            (width - height).abs() <= TOLERANCE && width > 0.0 && width / 2.0 <= MAX_BLEED
+        })
+        .min_by(|a, b| extra(a).0.total_cmp(&extra(b).0));
+    if let Some(preset) = exact.or(bled) {
+        let (name, width, height) = preset;
+        let bleed = if exact.is_some() {
+            0.0
+        } else {
+            extra(&preset).0.min(extra(&preset).1) / 2.0
+        };
+
+        return Ok(Detected {
+            name: Some(name),
+            width,
+            height,
+            basis: Basis::Page { bleed },
+        });
+    }
+
+    // Otherwise, the smallest standard size on the page holding the extent of
+    // the content most pages have
+    let consistent = consistent(&contents);
+    if !consistent.is_empty() {
+        let content_width = consistent
+            .iter()
+            .map(|&(width, _)| width)
+            .fold(0.0, f64::max);
+        let content_height = consistent
+            .iter()
+            .map(|&(_, height)| height)
+            .fold(0.0, f64::max);
+
+        let smallest = presets(page_width, page_height)
+            .filter(|&(_, width, height)| {
+                width + TOLERANCE >= content_width
+                    && height + TOLERANCE >= content_height
+                    && width <= page_width + TOLERANCE
+                    && height <= page_height + TOLERANCE
+            })
+            .min_by(|a, b| (a.1 * a.2).total_cmp(&(b.1 * b.2)));
+
+        if let Some((name, width, height)) = smallest {
+            return Ok(Detected {
+                name: Some(name),
+                width,
+                height,
+                basis: Basis::Content {
+                    width: content_width,
+                    height: content_height,
+                    consistent: consistent.len(),
+                    pages: boxes.len(),
+                },
+            });
+        }
+    }
+
+    Ok(Detected {
+        name: None,
+        width: page_width,
+        height: page_height,
+        basis: Basis::Unknown,
+    })
+}
+
+/// The standard trim sizes, once each under its first name, turned to
+/// match the page.
+fn presets(page_width: f64, page_height: f64) -> impl Iterator<Item = (&'static str, f64, f64)> {
+    let landscape = page_width > page_height;
+
+    TRIM_PRESETS
+        .iter()
+        .enumerate()
+        .filter(|&(index, &(_, width, height))| {
+            !TRIM_PRESETS[..index]
+                .iter()
+                .any(|&(_, w, h)| w == width && h == height)
+        })
+        .map(move |(_, &(name, width, height))| {
+            if landscape {
+                (name, height, width)
+            } else {
+                (name, width, height)
+            }
+        })
+}
+
+/// The size which most pages are, within the tolerance.
+fn most_common(sizes: &[(f64, f64)]) -> Option<&(f64, f64)> {
+    let near = |a: &(f64, f64), b: &(f64, f64)| {
+        (a.0 - b.0).abs() <= TOLERANCE && (a.1 - b.1).abs() <= TOLERANCE
+    };
+
+    sizes
+        .iter()
+        .max_by_key(|size| sizes.iter().filter(|other| near(size, other)).count())
+}
+
+/// The content sizes near the median, leaving out pages unlike the rest.
+fn consistent(sizes: &[(f64, f64)]) -> Vec<(f64, f64)> {
+    let median = |mut values: Vec<f64>| {
+        values.sort_by(f64::total_cmp);
+        values.get(values.len() / 2).copied().unwrap_or(0.0)
+    };
+    let width = median(sizes.iter().map(|&(width, _)| width).collect());
+    let height = median(sizes.iter().map(|&(_, height)| height).collect());
+
+    sizes
+        .iter()
+        .filter(|&&(w, h)| {
+            (w - width).abs() <= width * OUTLIER && (h - height).abs() <= height * OUTLIER
+        })
+        .copied()
+        .collect()
+}
+
+impl Detected {
+    /// A report of the trim chosen, and why.
+    pub fn message(&self) -> Message<'_> {
+        let trim = self.name.unwrap_or_default();
+        let mm = |points: f64| points / POINTS_PER_MM;
+
+        match self.basis {
+            Basis::Page { bleed } => Message::TrimFromPage {
+                trim,
+                bleed: mm(bleed),
+            },
+            Basis::Content {
+                width,
+                height,
+                consistent,
+                pages,
+            } => Message::TrimFromContent {
+                trim,
+                width: mm(width),
+                height: mm(height),
+                consistent,
+                pages,
+            },
+            Basis::Unknown => Message::TrimUnknown {
+                width: mm(self.width),
+                height: mm(self.height),
+            },
+        }
+    }
+}
@@ -0,0 +1,169 @@
+/// Points per inch, the PDF user space unit.
+pub const POINTS_PER_INCH: f64 = 72.0;
+
+/// Points per millimetre.
+pub const POINTS_PER_MM: f64 = POINTS_PER_INCH / 25.4;
+
+/// Parse a length given on the command line into points.
+///
+/// Accepts a number followed by one of the units "pt", "mm", "cm", or "in",
+/// for example "3mm" or "0.125in". A bare number is taken to be in points.
+pub fn parse_length(text: &str) -> Result<f64, String> {
+    let text = text.trim();
+
+    let (number, scale) = if let Some(number) = text.strip_suffix("mm") {
+        (number, POINTS_PER_MM)
+    } else if let Some(number) = text.strip_suffix("cm") {
+        (number, POINTS_PER_MM * 10.0)
+    } else if let Some(number) = text.strip_suffix("in") {
+        (number, POINTS_PER_INCH)
+    } else if let Some(number) = text.strip_suffix("pt") {
+        (number, 1.0)
+    } else {
+        (text, 1.0)
+    };
+
+    let value = number
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| format!("'{}' is not a length (e.g. 3mm, 0.125in, 9pt)", text))?;
+
+    if !value.is_finite() || value < 0.0 {
+        return Err(format!("'{}' must be a non-negative length", text));
+    }
+
+    Ok(value * scale)
+}
+
+/// Parse a pair of lengths separated by a comma, such as "20mm,30mm", into
+/// an (x, y) position in points.
+pub fn parse_position(text: &str) -> Result<(f64, f64), String> {
+    let (x, y) = text
+        .split_once(',')
+        .ok_or_else(|| format!("Expected X,Y (e.g. 20mm,30mm), not '{}'", text))?;
+
+    Ok((parse_length(x)?, parse_length(y)?))
+}
+
+/// Parse a width and height separated by an "x", such as "3.5inx2in" or
+/// "88mmx55mm", into points. If only the height carries a unit, as in
+/// "6x9in", it applies to the width as well.
+pub fn parse_size(text: &str) -> Result<(f64, f64), String> {
+    let (width, height) = text
+        .trim()
+        .split_once('x')
+        .ok_or_else(|| format!("Expected WIDTHxHEIGHT (e.g. 6x9in), not '{}'", text))?;
+
+    let width = width.trim();
+    let height = height.trim();
+
+    let is_bare = |s: &str| s.parse::<f64>().is_ok();
+    if is_bare(width) && !is_bare(height) {
+        let unit = height.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+        return Ok((
+            parse_length(&format!("{}{}", width, unit))?,
+            parse_length(height)?,
+        ));
+    }
+
+    Ok((parse_length(width)?, parse_length(height)?))
+}
+
+/// Trim sizes which may be given by name, as width and height in points.
+pub const TRIM_PRESETS: [(&str, f64, f64); 7] = [
+    ("trade", 6.0 * POINTS_PER_INCH, 9.0 * POINTS_PER_INCH),
+    ("6x9", 6.0 * POINTS_PER_INCH, 9.0 * POINTS_PER_INCH),
+    ("digest", 5.5 * POINTS_PER_INCH, 8.5 * POINTS_PER_INCH),
+    ("5.5x8.5", 5.5 * POINTS_PER_INCH, 8.5 * POINTS_PER_INCH),
+    ("5x8", 5.0 * POINTS_PER_INCH, 8.0 * POINTS_PER_INCH),
+    ("pocket", 4.25 * POINTS_PER_INCH, 6.875 * POINTS_PER_INCH),
+    ("a5", 148.0 * POINTS_PER_MM, 210.0 * POINTS_PER_MM),
+];
+
+/// Sheet sizes which may be given by name, as width and height in points.
+pub const SHEET_PRESETS: [(&str, f64, f64); 5] = [
+    ("a4", 595.0, 842.0),       // 210 × 297 mm
+    ("a3", 842.0, 1191.0),      // 297 × 420 mm
+    ("sra3", 907.0, 1276.0),    // 320 × 450 mm
+    ("letter", 612.0, 792.0),   // 8.5 × 11 in
+    ("tabloid", 792.0, 1224.0), // 11 × 17 in
+];
+
+/// The width and height in points of the named sheet, if it's one known.
+pub fn sheet_size(name: &str) -> Option<(f64, f64)> {
+    SHEET_PRESETS
+        .iter()
+        .find(|(preset, _, _)| *preset == name)
+        .map(|&(_, width, height)| (width, height))
+}
+
+/// A trim size as given with `--trim`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trim {
+    /// Width and height in points.
+    Size(f64, f64),
+    /// To be worked out from the manuscript's pages.
+    Auto,
+}
+
+/// Parse the name of a trim size preset, such as "trade" or "a5", into its
+/// width and height in points, or "auto".
+pub fn parse_trim(text: &str) -> Result<Trim, String> {
+    let name = text.trim().to_ascii_lowercase();
+    if name == "auto" {
+        return Ok(Trim::Auto);
+    }
+
+    TRIM_PRESETS
+        .iter()
+        .find(|(preset, _, _)| *preset == name)
+        .map(|&(_, width, height)| Trim::Size(width, height))
+        .ok_or_else(|| {
+            let names: Vec<&str> = TRIM_PRESETS.iter().map(|(preset, _, _)| *preset).collect();
+            format!(
+                "Unknown trim size '{}'. Presets: {}, or auto",
+                text,
+                names.join(", ")
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn parse_length_units() {
+        assert!(close(parse_length("3mm").unwrap(), 3.0 * POINTS_PER_MM));
+        assert!(close(parse_length("0.5cm").unwrap(), 5.0 * POINTS_PER_MM));
+        assert!(close(parse_length("0.125in").unwrap(), 9.0));
+        assert!(close(parse_length("9pt").unwrap(), 9.0));
+        assert!(close(parse_length(" 3 mm ").unwrap(), 3.0 * POINTS_PER_MM));
+    }
+
+    #[test]
+    fn parse_length_bare_number_is_points() {
+        assert!(close(parse_length("9").unwrap(), 9.0));
+        assert!(close(parse_length("0").unwrap(), 0.0));
+    }
+
+    #[test]
+    fn parse_length_negative() {
+        assert!(parse_length("-3mm").is_err());
+        assert!(parse_length("-0.125in").is_err());
+    }
+
+    #[test]
+    fn parse_length_garbage() {
+        assert!(parse_length("").is_err());
+        assert!(parse_length("mm").is_err());
+        assert!(parse_length("three mm").is_err());
+        assert!(parse_length("3ft").is_err());
+        assert!(parse_length("inf").is_err());
+        assert!(parse_length("NaN").is_err());
+    }
+}
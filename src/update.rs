@@ -0,0 +1,212 @@
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::time::Duration;
+
+use minisign_verify::{PublicKey, Signature};
+use toml::Value;
+use tracing::{debug, info};
+
+use crate::config::{self, Config};
+
+/// How long to wait on the release site when updating.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long the passive check waits on the release site, so as not to hold
+/// up a run when the network is slow or down.
+const NOTICE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long the passive check goes between looking for a new release.
+const NOTICE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The file in the user's directory recording the latest release found by
+/// the passive check, whose age is when it last looked.
+const NOTICE_FILE: &str = "latest-release";
+
+/// Where releases are published, and the minisign key they're signed with,
+/// as configured with `update-url` and `update-key`.
+///
+/// The site is laid out as
+///
+/// ```text
+/// latest                              the latest version, such as v0.2.0
+/// v0.2.0/cropped-linux-x86_64         the program for each platform
+/// v0.2.0/cropped-linux-x86_64.minisig and its signature
+/// ```
+#[derive(Debug)]
+pub struct Source {
+    pub url: String,
+    pub key: String,
+}
+
+/// What came of looking for a new release.
+#[derive(Debug)]
+pub enum Update {
+    /// This is the latest release.
+    Current,
+    /// A newer release is available, and wasn't asked to be installed.
+    Available(String),
+    /// A newer release was downloaded, verified, and installed in place of
+    /// this program.
+    Installed(String),
+}
+
+impl Source {
+    /// The release site configured, if both it and its key are.
+    pub fn configured(config: &Config) -> Option<Source> {
+        let url = config.setting("update-url")?.as_str()?;
+        let key = config.setting("update-key")?.as_str()?;
+        Some(Source {
+            url: url.trim_end_matches('/').to_string(),
+            key: key.to_string(),
+        })
+    }
+
+    /// The version of the latest release.
+    fn latest(&self, timeout: Duration) -> io::Result<String> {
+        let latest = fetch(&format!("{}/latest", self.url), timeout)?;
+        let latest = String::from_utf8(latest).map_err(io::Error::other)?;
+        Ok(latest.trim().to_string())
+    }
+}
+
+/// Whether the passive check for new releases is turned on, with
+/// `update-check = true`.
+pub fn checks(config: &Config) -> bool {
+    config.setting("update-check").and_then(Value::as_bool) == Some(true)
+}
+
+/// Look for a release newer than the current version, and if asked,
+/// install it in place of this program.
+///
+/// The new program is only installed once its signature is verified with
+/// the configured key, so that a compromised or spoofed release site can't
+/// replace it with something else.
+pub fn update(source: &Source, current: &str, install: bool) -> io::Result<Update> {
+    let latest = source.latest(TIMEOUT)?;
+    info!("Latest release is {}", latest);
+    if !newer(&latest, current) {
+        return Ok(Update::Current);
+    }
+    if !install {
+        return Ok(Update::Available(latest));
+    }
+
+    let key = PublicKey::from_base64(&source.key).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("The update-key setting isn't a minisign public key: {}", e),
+        )
+    })?;
+
+    let url = format!("{}/{}/{}", source.url, latest, asset());
+    let program = fetch(&url, TIMEOUT)?;
+    let signature = fetch(&format!("{}.minisig", url), TIMEOUT)?;
+    let signature = Signature::decode(&String::from_utf8_lossy(&signature))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    key.verify(&program, &signature, false).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "The signature on {} doesn't verify with the update-key, so it wasn't installed: {}",
+                url, e
+            ),
+        )
+    })?;
+    info!("Verified the signature on {}", url);
+
+    replace(&program)?;
+    Ok(Update::Installed(latest))
+}
+
+/// The latest release, if it's newer than the current version, looking no
+/// more than once a day. Any trouble looking is logged and otherwise
+/// ignored, so as never to get in the way of a run.
+pub fn notice(source: &Source, current: &str) -> Option<String> {
+    let record = config::user_directory()?.join(NOTICE_FILE);
+
+    let recent = fs::metadata(&record)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age < NOTICE_INTERVAL);
+
+    let latest = if recent {
+        fs::read_to_string(&record).ok()?.trim().to_string()
+    } else {
+        // Record the current version if the site can't be reached, so that
+        // it isn't tried again on every run until tomorrow
+        let latest = source.latest(NOTICE_TIMEOUT).unwrap_or_else(|e| {
+            debug!("Couldn't look for a new release: {}", e);
+            current.to_string()
+        });
+        if let Some(directory) = record.parent() {
+            let _ = fs::create_dir_all(directory);
+        }
+        let _ = fs::write(&record, &latest);
+        latest
+    };
+
+    newer(&latest, current).then_some(latest)
+}
+
+/// Whether one version, such as v0.2.0, is later than another.
+fn newer(version: &str, than: &str) -> bool {
+    let parse = |version: &str| -> Option<Vec<u64>> {
+        version
+            .trim_start_matches('v')
+            .split('.')
+            .map(|part| part.parse().ok())
+            .collect()
+    };
+
+    match (parse(version), parse(than)) {
+        (Some(version), Some(than)) => version > than,
+        _ => false,
+    }
+}
+
+/// The name of the program for this platform on the release site.
+fn asset() -> String {
+    format!(
+        "cropped-{}-{}{}",
+        env::consts::OS,
+        env::consts::ARCH,
+        env::consts::EXE_SUFFIX
+    )
+}
+
+fn fetch(url: &str, timeout: Duration) -> io::Result<Vec<u8>> {
+    let response = ureq::get(url)
+        .timeout(timeout)
+        .call()
+        .map_err(|e| io::Error::other(format!("Couldn't fetch {}: {}", url, e)))?;
+
+    let mut body = Vec::new();
+    response.into_reader().read_to_end(&mut body)?;
+    Ok(body)
+}
+
+/// Put a new program in place of the one running, next to it first and
+/// then renamed over it, so that it's never left half written.
+fn replace(program: &[u8]) -> io::Result<()> {
+    let current = env::current_exe()?;
+    let staged = current.with_extension("new");
+
+    fs::write(&staged, program)?;
+    fs::set_permissions(&staged, fs::metadata(&current)?.permissions())?;
+
+    // A running program can't be replaced on Windows, but it can be renamed
+    // out of the way
+    if cfg!(target_os = "windows") {
+        let old = current.with_extension("old");
+        let _ = fs::remove_file(&old);
+        fs::rename(&current, &old)?;
+    }
+
+    fs::rename(&staged, &current).inspect_err(|_| {
+        let _ = fs::remove_file(&staged);
+    })?;
+    info!("Installed {}", current.display());
+    Ok(())
+}
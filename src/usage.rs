@@ -0,0 +1,49 @@
+use std::fmt;
+
+use crate::i18n::Message;
+
+/// Paper consumed by a run, for comparing impositions and quoting jobs.
+#[derive(Debug, Clone, Copy)]
+pub struct Usage {
+    /// Number of sheets printed (single-sided).
+    pub sheets: usize,
+    /// Fraction of the total sheet area occupied by trimmed pages, 0.0 to 1.0.
+    pub utilization: f64,
+    /// Estimated paper cost, if a cost per sheet was given.
+    pub cost: Option<f64>,
+}
+
+/// Estimate paper usage given the number of sheets, the combined area of
+/// all the trimmed pieces placed on them, and the area of one sheet (all in
+/// square points).
+pub fn estimate(
+    sheets: usize,
+    trimmed_area: f64,
+    sheet_area: f64,
+    cost_per_sheet: Option<f64>,
+) -> Usage {
+    let total_area = sheets as f64 * sheet_area;
+    let utilization = if total_area > 0.0 {
+        trimmed_area / total_area
+    } else {
+        0.0
+    };
+
+    Usage {
+        sheets,
+        utilization,
+        cost: cost_per_sheet.map(|cost| cost * sheets as f64),
+    }
+}
+
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = Message::Usage {
+            sheets: self.sheets,
+            utilization: self.utilization,
+            cost: self.cost,
+        };
+
+        write!(f, "{}", message)
+    }
+}
@@ -0,0 +1,249 @@
+use std::io;
+use std::path::Path;
+
+use lopdf::content::Content;
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::checksums;
+use crate::overlay::{self, BleedExtension, Options};
+use crate::provenance::{self, Provenance};
+
+/// How deeply form XObjects are followed into one another, in case of a
+/// malformed file in which one draws itself.
+const MAX_DEPTH: usize = 16;
+
+/// What a page shows of the manuscript: the strings of text it draws, as
+/// they're encoded in the content, and a digest of each image, in the order
+/// they're drawn, including any in the form XObjects it draws.
+#[derive(Debug, Default, PartialEq)]
+pub struct Fingerprint {
+    pub text: Vec<Vec<u8>>,
+    pub images: Vec<String>,
+}
+
+/// Take the fingerprint of a page. On a stamped page, what cropped draws
+/// itself, the overlay and, in legacy output, the text set in its font, is
+/// left out, so that the fingerprint is of the manuscript's content alone.
+pub fn fingerprint(doc: &Document, page_id: ObjectId, stamped: bool) -> lopdf::Result<Fingerprint> {
+    let resources = overlay::inherited(doc, page_id, b"Resources")
+        .and_then(|resources| dictionary(doc, resources));
+    let content = doc.get_page_content(page_id)?;
+
+    let mut fingerprint = Fingerprint::default();
+    walk(doc, &content, resources, stamped, 0, &mut fingerprint)?;
+    Ok(fingerprint)
+}
+
+/// Add what a content stream draws to the fingerprint.
+fn walk(
+    doc: &Document,
+    content: &[u8],
+    resources: Option<&Dictionary>,
+    stamped: bool,
+    depth: usize,
+    fingerprint: &mut Fingerprint,
+) -> lopdf::Result<()> {
+    let content = Content::decode(content)?;
+    let xobjects = resources
+        .and_then(|resources| resources.get(b"XObject").ok())
+        .and_then(|xobjects| dictionary(doc, xobjects));
+
+    // The font is part of the graphics state, saved and restored with it
+    let mut font: Option<Vec<u8>> = None;
+    let mut saved = Vec::new();
+
+    for operation in &content.operations {
+        match operation.operator.as_str() {
+            "q" => saved.push(font.clone()),
+            "Q" => font = saved.pop().unwrap_or_default(),
+            "Tf" => {
+                font = operation
+                    .operands
+                    .first()
+                    .and_then(|name| name.as_name().ok())
+                    .map(<[u8]>::to_vec);
+            }
+            "Tj" | "TJ" | "'" | "\"" => {
+                if stamped && font.as_deref() == Some(overlay::LEGACY_FONT_NAME.as_bytes()) {
+                    continue;
+                }
+                let shown = match operation.operands.last() {
+                    Some(Object::Array(items)) => items.as_slice(),
+                    Some(shown) => std::slice::from_ref(shown),
+                    None => &[],
+                };
+                let text: Vec<u8> = shown
+                    .iter()
+                    .filter_map(|item| item.as_str().ok())
+                    .flatten()
+                    .copied()
+                    .collect();
+                fingerprint.text.push(text);
+            }
+            "Do" => {
+                let Some(name) = operation
+                    .operands
+                    .first()
+                    .and_then(|name| name.as_name().ok())
+                else {
+                    continue;
+                };
+                if stamped && name == b"Overlay" {
+                    continue;
+                }
+                let Some(stream) = xobjects
+                    .and_then(|xobjects| xobjects.get(name).ok())
+                    .and_then(|xobject| xobject.as_reference().ok())
+                    .and_then(|id| doc.get_object(id).ok())
+                    .and_then(|xobject| xobject.as_stream().ok())
+                else {
+                    continue;
+                };
+
+                match stream.dict.get(b"Subtype").and_then(Object::as_name) {
+                    Ok(b"Image") => {
+                        // Compare what the image decodes to where it can be
+                        // decoded, since saving compresses streams which
+                        // weren't already
+                        let data = stream
+                            .decompressed_content()
+                            .unwrap_or_else(|_| stream.content.clone());
+                        fingerprint
+                            .images
+                            .push(format!("{:x}", Sha256::digest(&data)));
+                    }
+                    Ok(b"Form") if depth < MAX_DEPTH => {
+                        let own = stream
+                            .dict
+                            .get(b"Resources")
+                            .ok()
+                            .and_then(|resources| dictionary(doc, resources));
+                        let content = stream
+                            .decompressed_content()
+                            .unwrap_or_else(|_| stream.content.clone());
+                        walk(
+                            doc,
+                            &content,
+                            own.or(resources),
+                            stamped,
+                            depth + 1,
+                            fingerprint,
+                        )?;
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that stamping left a manuscript's content alone: that each page of
+/// the stamped PDF draws the same text and images as the manuscript page it
+/// was made from. Returns the number of pages compared, and a description
+/// of each difference found.
+///
+/// Pages are matched up by the settings recorded in the stamped PDF, so that
+/// reordered and selected pages are compared with the right ones. Where
+/// bleed was made up by mirroring, a page draws its content once for each
+/// edge as well, and those copies are allowed for.
+pub fn verify(manuscript_path: &Path, stamped_path: &Path) -> lopdf::Result<(usize, Vec<String>)> {
+    let manuscript = Document::load(manuscript_path)?;
+    let stamped = Document::load(stamped_path)?;
+
+    let Ok(Object::String(json, _)) = stamped.catalog()?.get(provenance::KEY.as_bytes()) else {
+        return Err(lopdf::Error::IO(io::Error::other(format!(
+            "{} has no provenance record; it wasn't stamped by cropped",
+            stamped_path.display()
+        ))));
+    };
+    let provenance: Provenance = serde_json::from_slice(json).map_err(provenance::json_error)?;
+    let options: Options =
+        serde_json::from_value(provenance.settings).map_err(provenance::json_error)?;
+
+    let digest = checksums::sha256_file(manuscript_path).map_err(lopdf::Error::IO)?;
+    if digest != provenance.source_sha256 {
+        return Ok((
+            0,
+            vec![format!(
+                "{} isn't the manuscript {} was stamped from, which was {}",
+                manuscript_path.display(),
+                stamped_path.display(),
+                provenance.source
+            )],
+        ));
+    }
+
+    let manuscript_pages: Vec<ObjectId> = manuscript.page_iter().collect();
+    let stamped_pages: Vec<ObjectId> = stamped.page_iter().collect();
+    let order = overlay::output_order(&options, manuscript_pages.len())?
+        .unwrap_or_else(|| (0..manuscript_pages.len()).collect());
+    if order.len() != stamped_pages.len() {
+        return Ok((
+            0,
+            vec![format!(
+                "The stamped PDF has {} pages, but {} were expected from the manuscript",
+                stamped_pages.len(),
+                order.len()
+            )],
+        ));
+    }
+
+    let mut problems = Vec::new();
+
+    let copies: &[usize] = match options.extend_bleed {
+        Some(BleedExtension::Mirror) => &[1, 5],
+        _ => &[1],
+    };
+
+    for (position, (&index, &stamped_id)) in order.iter().zip(&stamped_pages).enumerate() {
+        let before = fingerprint(&manuscript, manuscript_pages[index], false)?;
+        let after = fingerprint(&stamped, stamped_id, true)?;
+        let (page_num, output_page) = (index + 1, position + 1);
+
+        if !copies
+            .iter()
+            .any(|&n| repeats(&after.text, &before.text, n))
+        {
+            problems.push(format!(
+                "Page {} of the output doesn't draw the same text as page {} of the manuscript",
+                output_page, page_num
+            ));
+        }
+        if !copies
+            .iter()
+            .any(|&n| repeats(&after.images, &before.images, n))
+        {
+            problems.push(format!(
+                "Page {} of the output doesn't draw the same images as page {} of the manuscript",
+                output_page, page_num
+            ));
+        }
+    }
+
+    info!(
+        "Compared {} pages, {} problem(s)",
+        stamped_pages.len(),
+        problems.len()
+    );
+    Ok((stamped_pages.len(), problems))
+}
+
+/// Whether `after` is `before` over again, `count` times.
+fn repeats<T: PartialEq>(after: &[T], before: &[T], count: usize) -> bool {
+    after.len() == before.len() * count
+        && (before.is_empty() || after.chunks(before.len()).all(|chunk| chunk == before))
+}
+
+/// Resolve an object to the dictionary it is or refers to.
+fn dictionary<'a>(doc: &'a Document, object: &'a Object) -> Option<&'a Dictionary> {
+    match object {
+        Object::Dictionary(dict) => Some(dict),
+        Object::Reference(id) => doc.get_dictionary(*id).ok(),
+        _ => None,
+    }
+}
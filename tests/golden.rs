@@ -0,0 +1,193 @@
+//! Render stamped fixtures with Ghostscript and compare them against the
+//! reference images in tests/golden, catching the kind of mistake, such as
+//! transforms applied in the wrong order or marks clipped away, which the
+//! snapshot tests of the operator streams can't see.
+//!
+//! Run with `cargo test --features golden`, with Ghostscript and Inconsolata
+//! installed. After an intended change to the output, set CROPPED_BLESS=1 to
+//! write new references, and check them by eye before committing. Where gs
+//! isn't installed, or a case has no references yet, the case says so and is
+//! skipped rather than failed.
+
+#![cfg(feature = "golden")]
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use image::GrayImage;
+
+/// Resolution the pages are rendered at.
+const RESOLUTION: u32 = 72;
+
+/// Height of the band along the bottom of the sheet which is ignored, as
+/// the slug there carries the time of stamping.
+const SLUG_BAND: u32 = 48;
+
+/// Difference in gray level, after blurring, beyond which a pixel counts as
+/// changed. Blurring first means antialiasing differences between versions
+/// of Ghostscript don't count, while a mark moved by a point still does.
+const PIXEL_THRESHOLD: u8 = 48;
+
+/// Fraction of pixels which may change before the render fails.
+const CHANGED_LIMIT: f64 = 0.001;
+
+fn scratch(case: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("cropped-golden-{}-{}", case, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Whether Ghostscript is there to render with.
+fn ghostscript() -> bool {
+    Command::new("gs")
+        .arg("--version")
+        .output()
+        .is_ok_and(|output| output.status.success())
+}
+
+fn run(program: &str, args: &[&str]) {
+    let status = Command::new(program)
+        .args(args)
+        .status()
+        .unwrap_or_else(|e| panic!("Unable to run {}: {}", program, e));
+    assert!(status.success(), "{} {:?} failed", program, args);
+}
+
+/// Generate a fixture, stamp it with the given arguments, and render each
+/// page of the result, returning the paths of the images.
+fn render(case: &str, fixture_args: &[&str], stamp_args: &[&str], pages: usize) -> Vec<PathBuf> {
+    let dir = scratch(case);
+    let fixture = dir.join("fixture.pdf");
+    let stamped = dir.join("stamped.pdf");
+    let cropped = env!("CARGO_BIN_EXE_cropped");
+
+    let pages_text = pages.to_string();
+    let mut args = vec!["gen-fixture", "-o", fixture.to_str().unwrap()];
+    args.extend(fixture_args);
+    args.extend(["--pages", &pages_text]);
+    run(cropped, &args);
+
+    let mut args = vec![
+        "stamp",
+        fixture.to_str().unwrap(),
+        "-o",
+        stamped.to_str().unwrap(),
+    ];
+    args.extend(stamp_args);
+    run(cropped, &args);
+
+    let pattern = dir.join("page-%d.png");
+    run(
+        "gs",
+        &[
+            "-q",
+            "-dNOPAUSE",
+            "-dBATCH",
+            "-dSAFER",
+            "-sDEVICE=pnggray",
+            &format!("-r{}", RESOLUTION),
+            &format!("-sOutputFile={}", pattern.display()),
+            stamped.to_str().unwrap(),
+        ],
+    );
+
+    (1..=pages)
+        .map(|page| dir.join(format!("page-{}.png", page)))
+        .collect()
+}
+
+/// Average each pixel with its neighbours.
+fn blur(image: &GrayImage) -> GrayImage {
+    let (width, height) = image.dimensions();
+    GrayImage::from_fn(width, height, |x, y| {
+        let mut sum = 0u32;
+        let mut count = 0u32;
+        for ny in y.saturating_sub(1)..=(y + 1).min(height - 1) {
+            for nx in x.saturating_sub(1)..=(x + 1).min(width - 1) {
+                sum += image.get_pixel(nx, ny)[0] as u32;
+                count += 1;
+            }
+        }
+        image::Luma([(sum / count) as u8])
+    })
+}
+
+/// Compare a render against its reference, writing the reference instead
+/// when blessing.
+fn compare(actual: &Path, reference: &Path, bless: bool) {
+    if bless {
+        fs::copy(actual, reference).unwrap();
+        return;
+    }
+
+    let actual = image::open(actual).unwrap().to_luma8();
+    let expected = image::open(reference).unwrap().to_luma8();
+    assert_eq!(actual.dimensions(), expected.dimensions());
+
+    let (actual, expected) = (blur(&actual), blur(&expected));
+    let (width, height) = actual.dimensions();
+    let compared = height.saturating_sub(SLUG_BAND);
+
+    let changed = (0..compared)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .filter(|&(x, y)| {
+            actual.get_pixel(x, y)[0].abs_diff(expected.get_pixel(x, y)[0]) > PIXEL_THRESHOLD
+        })
+        .count();
+    let fraction = changed as f64 / (width * compared) as f64;
+
+    assert!(
+        fraction <= CHANGED_LIMIT,
+        "{:.2}% of pixels differ from {}",
+        fraction * 100.0,
+        reference.display()
+    );
+}
+
+fn check(case: &str, fixture_args: &[&str], stamp_args: &[&str], pages: usize) {
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let references: Vec<PathBuf> = (1..=pages)
+        .map(|page| golden.join(format!("{}-{}.png", case, page)))
+        .collect();
+    let bless = env::var_os("CROPPED_BLESS").is_some();
+
+    if !ghostscript() {
+        eprintln!("Skipping {}: Ghostscript (gs) isn't installed", case);
+        return;
+    }
+    if !bless && let Some(missing) = references.iter().find(|reference| !reference.exists()) {
+        eprintln!(
+            "Skipping {}: no reference image {}; run with CROPPED_BLESS=1 to create it",
+            case,
+            missing.display()
+        );
+        return;
+    }
+
+    let actual = render(case, fixture_args, stamp_args, pages);
+    for (actual, reference) in actual.iter().zip(&references) {
+        compare(actual, reference, bless);
+    }
+}
+
+#[test]
+fn trade_with_bleed() {
+    check(
+        "trade-with-bleed",
+        &["--bleed", "3mm"],
+        &["--bleed", "3mm"],
+        2,
+    );
+}
+
+#[test]
+fn trade_mirrored_bleed() {
+    check(
+        "trade-mirrored-bleed",
+        &["--bleed", "0"],
+        &["--bleed", "3mm", "--extend-bleed", "mirror"],
+        2,
+    );
+}